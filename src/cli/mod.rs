@@ -0,0 +1,128 @@
+mod render;
+pub use self::render::render;
+
+mod split_atlas;
+pub use self::split_atlas::split_atlas;
+
+use crate::entities::TilePatternError;
+use crate::world::DatabaseError;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// The world shown when no other config file is given
+pub const DEFAULT_CONFIG_FILE: &str = "worlds/tellene/config.toml";
+
+/// Where the viewer's settings are kept when no other settings file is given
+pub const DEFAULT_SETTINGS_FILE: &str = "settings.toml";
+
+pub const USAGE: &str = "Usage:
+  hm_world [--regen-textures] [--globe-mesh <kind>:<n>] [--settings <settings file>]
+      Shows the world's globe, regenerating all of its textures if asked. Maps without a
+      tile pyramid are drawn on a globe mesh of uv-sphere:<degrees per quad> (the default,
+      uv-sphere:1), icosphere:<subdivisions>, cube-sphere:<subdivisions>, or model:<name> for
+      one of the world's [models]. Input bindings and viewer settings are loaded from the
+      settings file (default settings.toml), and saved back to it on exit
+  hm_world render <output png> [--lat <degrees>] [--long <degrees>] [--distance <radii>]
+                  [--size <width>x<height>] [--config <config file>] [--globe-mesh <kind>:<n>]
+                  [--renderer <gl|software>] [--settings <settings file>]
+      Draws the globe offscreen into a PNG, from a camera over lat/long (default 0, 0) at
+      distance world radii from its center (default 2). Images are size pixels (default
+      1024x768). Needs no GPU or display server: the gl renderer (the default) only needs
+      EGL's surfaceless platform, which Mesa provides with its software renderer, and the
+      software renderer draws on the CPU without GL at all. Viewer settings like the field of
+      view are only loaded from a settings file if one is given
+  hm_world split-atlas <atlas image> <output dir> --bounds <min lat>,<max lat>,<min long>,<max long>
+                       [--tile-deg <degrees>] [--size <pixels>] [--background <RRGGBB[AA]>]
+                       [--pattern <file name pattern>]
+      Splits an equirectangular atlas into square tiles, tile-deg degrees (default 1) and size
+      pixels (default 512) wide, filling anything outside the atlas with background (default
+      ff00ff). Tiles are named by pattern, which defaults to the textures/tellene_1 layout of
+      tellene_{max_lat}_{min_long}.png";
+
+#[derive(Debug)]
+pub enum CliError {
+	Usage(String),
+	BadValue(&'static str, String),
+	ImageError(image::ImageError),
+	IOError(std::io::Error),
+	TilePatternError(TilePatternError),
+	WorldError(DatabaseError),
+	ViewError(String),
+}
+impl fmt::Display for CliError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			CliError::Usage(e) => write!(f, "{}", e),
+			CliError::BadValue(name, value) => write!(f, "bad value {:?} for {}", value, name),
+			CliError::ImageError(e) => write!(f, "{}", e),
+			CliError::IOError(e) => write!(f, "{}", e),
+			CliError::TilePatternError(e) => write!(f, "{}", e),
+			CliError::WorldError(e) => write!(f, "{}", e),
+			CliError::ViewError(e) => write!(f, "{}", e),
+		}
+	}
+}
+
+/// Runs the subcommand named by the first of args. None if there isn't one, so the globe
+/// should be shown as usual.
+pub fn run_subcommand(args: &[String]) -> Option<Result<(), CliError>> {
+	let (subcommand, args) = args.split_first()?;
+	match subcommand.as_str() {
+		"render" => Some(render(args)),
+		"split-atlas" => Some(split_atlas(args)),
+		_ => None,
+	}
+}
+
+/// The arguments after a subcommand: positional ones, and --name value options
+struct Args {
+	positional: Vec<String>,
+	options: HashMap<String, String>,
+}
+impl Args {
+	fn parse(args: &[String], known_options: &[&str]) -> Result<Args, CliError> {
+		let mut positional = Vec::new();
+		let mut options = HashMap::new();
+		let mut args = args.iter();
+		while let Some(arg) = args.next() {
+			if let Some(name) = arg.strip_prefix("--") {
+				if !known_options.contains(&name) {
+					return Err(CliError::Usage(format!("unknown option {}", arg)));
+				}
+				let value = args
+					.next()
+					.ok_or_else(|| CliError::Usage(format!("{} needs a value", arg)))?;
+				options.insert(name.to_string(), value.clone());
+			} else {
+				positional.push(arg.clone());
+			}
+		}
+		Ok(Args {
+			positional,
+			options,
+		})
+	}
+
+	fn positional(&self, index: usize, name: &str) -> Result<&str, CliError> {
+		self.positional
+			.get(index)
+			.map(String::as_str)
+			.ok_or_else(|| CliError::Usage(format!("missing {}", name)))
+	}
+
+	fn option(&self, name: &str) -> Option<&str> {
+		self.options.get(name).map(String::as_str)
+	}
+
+	fn parse_option<T: FromStr>(&self, name: &'static str) -> Result<Option<T>, CliError> {
+		match self.option(name) {
+			Some(value) => value
+				.parse::<T>()
+				.map(Some)
+				.map_err(|_| CliError::BadValue(name, value.to_string())),
+			None => Ok(None),
+		}
+	}
+}
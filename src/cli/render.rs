@@ -0,0 +1,83 @@
+use super::{Args, CliError, DEFAULT_CONFIG_FILE};
+use crate::database::FileDatabase;
+use crate::entities::LatLong;
+use crate::presenter::View;
+use crate::presenter::{GLPresenter, GlobeMesh};
+use crate::view::{HeadlessView, SoftwareView};
+use crate::world::ConcreteWorld;
+
+use cgmath::Deg;
+
+const DEFAULT_DISTANCE: f64 = 2_f64;
+const DEFAULT_SIZE: (u32, u32) = (1024, 768);
+
+// Parses an image size as <width>x<height>
+fn parse_size(value: &str) -> Result<(u32, u32), CliError> {
+	let bad_value = || CliError::BadValue("size", value.to_string());
+	let mut dims = value.splitn(2, 'x').map(|dim| dim.parse::<u32>());
+	match (dims.next(), dims.next()) {
+		(Some(Ok(width)), Some(Ok(height))) if width > 0 && height > 0 => Ok((width, height)),
+		_ => Err(bad_value()),
+	}
+}
+
+/// Draws the world from a camera over a lat/long into a PNG, without a window, so it works
+/// without a GPU or display server. See USAGE for the arguments.
+pub fn render(args: &[String]) -> Result<(), CliError> {
+	let args = Args::parse(
+		args,
+		&[
+			"lat",
+			"long",
+			"distance",
+			"size",
+			"config",
+			"globe-mesh",
+			"renderer",
+			"settings",
+		],
+	)?;
+	let out_file = args.positional(0, "output image")?;
+	let lat: f64 = args.parse_option("lat")?.unwrap_or(0_f64);
+	let long: f64 = args.parse_option("long")?.unwrap_or(0_f64);
+	let distance = args.parse_option("distance")?.unwrap_or(DEFAULT_DISTANCE);
+	let (width, height) = match args.option("size") {
+		Some(size) => parse_size(size)?,
+		None => DEFAULT_SIZE,
+	};
+	let globe_mesh: Option<GlobeMesh> = args.parse_option("globe-mesh")?;
+	if !(-90_f64..=90_f64).contains(&lat) {
+		return Err(CliError::BadValue("lat", lat.to_string()));
+	}
+	if distance.is_nan() || distance <= 1_f64 {
+		return Err(CliError::Usage(
+			"distance must be outside the world, over 1".to_string(),
+		));
+	}
+
+	let renderer = args.option("renderer").unwrap_or("gl");
+	if renderer != "gl" && renderer != "software" {
+		return Err(CliError::BadValue("renderer", renderer.to_string()));
+	}
+
+	let database = FileDatabase::new(args.option("config").unwrap_or(DEFAULT_CONFIG_FILE), false);
+	let world = ConcreteWorld::new(Box::new(database)).map_err(CliError::WorldError)?;
+	let view: Box<dyn View> = match renderer {
+		"software" => Box::new(SoftwareView::new(width, height)),
+		_ => Box::new(HeadlessView::new(width, height).map_err(CliError::ViewError)?),
+	};
+
+	let mut presenter = GLPresenter::new(view, Box::new(world));
+	if let Some(settings_file) = args.option("settings") {
+		presenter.load_settings(settings_file);
+	}
+	if let Some(globe_mesh) = globe_mesh {
+		presenter.set_globe_mesh(globe_mesh);
+	}
+	let image = presenter
+		.render_still(&LatLong::new(Deg(lat), Deg(long)), distance)
+		.ok_or_else(|| CliError::ViewError("couldn't read back the image".to_string()))?;
+	image.save(out_file).map_err(CliError::IOError)?;
+	info!("Rendered {}x{} image {}", width, height, out_file);
+	Ok(())
+}
@@ -0,0 +1,104 @@
+use super::{Args, CliError};
+use crate::entities::{Atlas, Equirectangular, Map, MapBounds, TilePattern};
+
+use std::path::Path;
+use std::sync::Arc;
+
+use image::{DynamicImage, Rgba, RgbaImage};
+
+const DEFAULT_TILE_SIZE_DEG: u32 = 1;
+const DEFAULT_TILE_SIZE_PX: u32 = 512;
+const DEFAULT_BACKGROUND: Rgba<u8> = Rgba([255, 0, 255, 255]);
+
+// Parses min lat, max lat, min long and max long, separated by commas
+fn parse_bounds(value: &str) -> Result<MapBounds, CliError> {
+	let bad_value = || CliError::BadValue("bounds", value.to_string());
+	let degs = value
+		.split(',')
+		.map(|deg| deg.trim().parse::<f64>().map_err(|_| bad_value()))
+		.collect::<Result<Vec<f64>, CliError>>()?;
+	match degs.as_slice() {
+		&[min_lat, max_lat, min_long, max_long] if min_lat < max_lat && min_long < max_long => {
+			Ok(MapBounds::new(min_lat, max_lat, min_long, max_long))
+		}
+		_ => Err(bad_value()),
+	}
+}
+
+// Parses a colour as hex RRGGBB or RRGGBBAA, optionally starting with #
+fn parse_colour(value: &str) -> Result<Rgba<u8>, CliError> {
+	let bad_value = || CliError::BadValue("background", value.to_string());
+	let hex = value.trim_start_matches('#');
+	if !(hex.len() == 6 || hex.len() == 8) || !hex.is_ascii() {
+		return Err(bad_value());
+	}
+
+	let mut colour = DEFAULT_BACKGROUND;
+	for (i, channel) in colour.0.iter_mut().enumerate().take(hex.len() / 2) {
+		*channel = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| bad_value())?;
+	}
+	Ok(colour)
+}
+
+/// Splits an equirectangular atlas into a directory of square tiles, filling anything the
+/// atlas doesn't cover with a background colour. See USAGE for the arguments.
+pub fn split_atlas(args: &[String]) -> Result<(), CliError> {
+	let args = Args::parse(
+		args,
+		&["bounds", "tile-deg", "size", "background", "pattern"],
+	)?;
+	let atlas_file = args.positional(0, "atlas image")?;
+	let out_dir = Path::new(args.positional(1, "output dir")?);
+	let bounds = parse_bounds(
+		args.option("bounds")
+			.ok_or_else(|| CliError::Usage("missing --bounds".to_string()))?,
+	)?;
+	let tile_size_deg = args
+		.parse_option("tile-deg")?
+		.unwrap_or(DEFAULT_TILE_SIZE_DEG);
+	let tile_size_px = args.parse_option("size")?.unwrap_or(DEFAULT_TILE_SIZE_PX);
+	let background = match args.option("background") {
+		Some(background) => parse_colour(background)?,
+		None => DEFAULT_BACKGROUND,
+	};
+	let pattern = TilePattern::new(args.option("pattern").unwrap_or(TilePattern::LEGACY))
+		.map_err(CliError::TilePatternError)?;
+	if tile_size_deg == 0 || tile_size_px == 0 {
+		return Err(CliError::Usage("tiles can't be empty".to_string()));
+	}
+
+	let image = image::open(atlas_file).map_err(CliError::ImageError)?;
+	info!("Loaded atlas {}", atlas_file);
+	let atlas = Atlas::new(Some(image), bounds, Arc::new(Equirectangular), 0).ok_or_else(|| {
+		CliError::Usage("bounds can't be shown in the atlas' projection".to_string())
+	})?;
+
+	// The background is the missing image, which every tile starts out as
+	let background = DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+		tile_size_px,
+		tile_size_px,
+		background,
+	));
+	let map = Map::new("split-atlas", vec![atlas], background, tile_size_deg);
+
+	// Tiles are saved as they're made, so the whole atlas never has to fit in memory
+	std::fs::create_dir_all(out_dir).map_err(CliError::IOError)?;
+	let mut saved: Result<usize, CliError> = Ok(0);
+	map.generate_each_texture(&map.texture_keys(), &mut |key, tile, progress| {
+		eprint!("\rGenerating tiles: {}    ", progress);
+		if progress.is_done() {
+			eprintln!();
+		}
+
+		// After the first failure there's no point saving the rest
+		if let (Ok(count), Some(tile)) = (&mut saved, tile) {
+			let tile_path = out_dir.join(pattern.file_name(&key, tile_size_deg));
+			match tile.save(&tile_path) {
+				Ok(()) => *count += 1,
+				Err(err) => saved = Err(CliError::IOError(err)),
+			}
+		}
+	});
+	info!("Wrote {} tiles to {:?}", saved?, out_dir);
+	Ok(())
+}
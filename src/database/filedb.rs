@@ -1,63 +1,850 @@
-use crate::entities::{Map, MapBounds};
+use super::pyramid_cache::{pyramid_tile_path, PyramidManifest};
+use super::texture_cache::{texture_file_name, TextureManifest};
+use super::tile_dir::load_tile_dir;
+use crate::entities::{
+	edit_set, read_cities, read_obj, write_cities, Atlas, Bookmark, CameraView, City,
+	ContentHasher, Equirectangular, LambertConformalConic, LatLong, Map, MapBounds, Mercator,
+	Model, PolarStereographic, Progress, Projection, TileId, TilePattern, TilePyramid,
+};
 use crate::world::{Database, DatabaseError, WorldState};
 
+use std::collections::HashMap;
 use std::fs::File;
+use std::hash::Hasher;
 use std::io::{BufReader, Read};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
+use cgmath::{Deg, Rad};
 use image::DynamicImage;
+use rusttype::Font;
 use toml::Value;
+use toml_edit::DocumentMut;
 
-fn get_image_from_file(file_name: &str) -> Result<DynamicImage, DatabaseError> {
-	let file = File::open(file_name).map_err(|e| DatabaseError::IOError(e))?;
-	Ok(
-		image::load(BufReader::with_capacity(8192, file), image::PNG)
-			.map_err(|e| DatabaseError::ImageError(e))?,
-	)
+// Where cities get saved if the config doesn't name a file for them yet
+const DEFAULT_CITIES_FILENAME: &str = "cities.csv";
+
+// Size of each tile in a pyramid, and the gutter around it, if the config doesn't say
+const DEFAULT_PYRAMID_TILE_PX: i64 = 256;
+const DEFAULT_PYRAMID_GUTTER_PX: i64 = 4;
+
+// What the world's radius is measured in, if the config gives it without saying
+const DEFAULT_DISTANCE_UNIT: &str = "km";
+
+// Where the camera was left is kept beside the config, in its own file so the config's only
+// written when the world itself changes. It's the user's own, so git ignores it.
+const LAST_VIEW_FILENAME: &str = "last_view.toml";
+
+pub(super) fn get_image_from_file(file_name: &str) -> Result<DynamicImage, DatabaseError> {
+	let file = File::open(file_name).map_err(DatabaseError::IOError)?;
+	image::load(BufReader::with_capacity(8192, file), image::PNG).map_err(DatabaseError::ImageError)
+}
+
+fn get_file_hash(file_name: &str) -> Result<u64, DatabaseError> {
+	let mut file = BufReader::new(File::open(file_name).map_err(DatabaseError::IOError)?);
+	let mut hasher = ContentHasher::new();
+	let mut buf = [0_u8; 8192];
+	loop {
+		match file.read(&mut buf).map_err(DatabaseError::IOError)? {
+			0 => break,
+			len => hasher.write(&buf[..len]),
+		}
+	}
+	Ok(hasher.finish())
+}
+
+/// An atlas as described in the config, before its image is loaded
+struct AtlasConfig {
+	file_path: String,
+	atlas: Atlas,
+}
+
+/// How a map's tile pyramid should be laid out, from the config's [map.pyramid] table
+struct PyramidConfig {
+	tile_px: u32,
+	gutter_px: u32,
 }
 
 pub struct FileDatabase {
 	config_file: String,
+	regen_textures: bool, // Ignore any cached textures, and generate them all again
 }
 impl FileDatabase {
-	pub fn new(config_file: &str) -> FileDatabase {
+	pub fn new(config_file: &str, regen_textures: bool) -> FileDatabase {
 		FileDatabase {
 			config_file: config_file.to_string(),
+			regen_textures,
+		}
+	}
+
+	/// Loads the map, using the textures cached in textures_dir if they were generated from
+	/// the same inputs. Otherwise, only the textures whose part of the atlases changed are
+	/// generated again, and the cache is updated.
+	///
+	/// Maps with a pyramid_config use a tile pyramid instead of textures.
+	fn load_map(
+		&self,
+		name: &str,
+		atlas_configs: &[AtlasConfig],
+		missing_file_path: &str,
+		texture_size_deg: u32,
+		pyramid_config: Option<&PyramidConfig>,
+	) -> Result<Map, DatabaseError> {
+		// Anything about the atlases changing could change the textures
+		let mut source_hasher = ContentHasher::new();
+		for atlas_config in atlas_configs {
+			let atlas = &atlas_config.atlas;
+			source_hasher.write_u64(get_file_hash(&atlas_config.file_path)?);
+			let bounds = atlas.bounds();
+			for deg in &[
+				bounds.min_lat,
+				bounds.max_lat,
+				bounds.min_long,
+				bounds.max_long,
+			] {
+				source_hasher.write_u64(deg.to_bits());
+			}
+			source_hasher.write(format!("{:?}", atlas.projection()).as_bytes());
+			source_hasher.write_i32(atlas.priority);
+		}
+		let atlases_without_images: Vec<Atlas> = atlas_configs
+			.iter()
+			.map(|atlas_config| atlas_config.atlas.clone())
+			.collect();
+
+		let missing_image = get_image_from_file(missing_file_path)?;
+		if let Some(pyramid_config) = pyramid_config {
+			let map = Map::new(
+				name,
+				atlases_without_images,
+				missing_image,
+				texture_size_deg,
+			);
+			return self.load_pyramid(
+				map,
+				atlas_configs,
+				pyramid_config,
+				source_hasher.finish(),
+				get_file_hash(missing_file_path)?,
+			);
+		}
+
+		let config_path = Path::new(&self.config_file);
+		let textures_dir = config_path.with_file_name(format!("tex_{}", texture_size_deg));
+		if let Err(err) = std::fs::create_dir_all(&textures_dir) {
+			warn!("Couldn't create texture dir {:?}: {}", textures_dir, err);
+		}
+		let mut manifest = TextureManifest::new(
+			source_hasher.finish(),
+			get_file_hash(missing_file_path)?,
+			MapBounds::covering(atlases_without_images.iter().map(Atlas::coverage)),
+			texture_size_deg,
+		);
+		let old_manifest = if self.regen_textures {
+			info!("Regenerating all textures");
+			None
+		} else {
+			match TextureManifest::read(&textures_dir) {
+				Ok(old_manifest) => Some(old_manifest),
+				Err(err) => {
+					info!("No usable texture cache in {:?}: {}", textures_dir, err);
+					None
+				}
+			}
+		};
+
+		// Nothing changed, so the atlas images don't even need to be opened
+		if let Some(ref old_manifest) = old_manifest {
+			if old_manifest.same_source_as(&manifest) {
+				let mut textures = HashMap::new();
+				for key in old_manifest.textures.keys() {
+					let texture_file_path = textures_dir.join(texture_file_name(key));
+					match get_image_from_file(&texture_file_path.to_string_lossy()) {
+						Ok(texture) => {
+							textures.insert(*key, texture);
+						}
+						Err(err) => {
+							warn!("Cached texture {:?} unusable: {}", texture_file_path, err);
+							break;
+						}
+					}
+				}
+				if textures.len() == old_manifest.textures.len() {
+					info!("Loaded {} cached textures", textures.len());
+					return Ok(Map::from_textures(
+						name,
+						atlases_without_images,
+						textures,
+						missing_image,
+						texture_size_deg,
+					));
+				}
+			}
+		}
+
+		let mut atlases = atlases_without_images;
+		for (atlas, atlas_config) in atlases.iter_mut().zip(atlas_configs.iter()) {
+			atlas.image = Some(get_image_from_file(&atlas_config.file_path)?);
+		}
+		let mut map = Map::new(name, atlases, missing_image, texture_size_deg);
+		info!("Loaded map!");
+
+		// Reuse any cached textures made from the same parts of the atlases
+		let mut stale_keys = Vec::new();
+		for key in map.texture_keys() {
+			let hash = match map.texture_hash(&key) {
+				Some(hash) => hash,
+				None => continue,
+			};
+			manifest.textures.insert(key, hash);
+
+			let old_hash = old_manifest
+				.as_ref()
+				.and_then(|old_manifest| old_manifest.textures.get(&key));
+			if old_hash == Some(&hash) {
+				let texture_file_path = textures_dir.join(texture_file_name(&key));
+				if let Ok(texture) = get_image_from_file(&texture_file_path.to_string_lossy()) {
+					map.textures.insert(key, texture);
+					continue;
+				}
+			}
+			stale_keys.push(key);
+		}
+		info!(
+			"{} of {} textures need generating",
+			stale_keys.len(),
+			manifest.textures.len()
+		);
+
+		// Log every 10%, so there's some sign of life while the textures are generated
+		let mut logged_percent = 0;
+		let mut log_progress = |progress: &Progress| {
+			if progress.percent() >= logged_percent + 10 {
+				logged_percent = progress.percent();
+				info!("Generating textures: {}", progress);
+			}
+		};
+		for (map_piece_key, texture) in map.generate_textures(&stale_keys, &mut log_progress) {
+			let texture_file_path = textures_dir.join(texture_file_name(&map_piece_key));
+			if texture
+				.save_with_format(&texture_file_path, image::ImageFormat::PNG)
+				.is_err()
+			{
+				warn!("Texture {:?} could not be saved", texture_file_path);
+				manifest.textures.remove(&map_piece_key);
+			}
+			map.textures.insert(map_piece_key, texture);
+		}
+		if let Err(err) = manifest.write(&textures_dir) {
+			warn!("Texture manifest could not be saved: {}", err);
+		}
+
+		// Everything's in the textures now, no need to keep the atlas images around
+		for atlas in map.atlases.iter_mut() {
+			atlas.image = None;
+		}
+		Ok(map)
+	}
+
+	fn pyramid_dir(&self, tile_px: u32) -> PathBuf {
+		Path::new(&self.config_file).with_file_name(format!("pyramid_{}", tile_px))
+	}
+
+	/// Gives map (whose atlases aren't loaded yet) the tile pyramid in pyramid_dir, which
+	/// the presenter streams tiles in from as it needs them. The atlases are only loaded to
+	/// generate the pyramid again if anything it's made from changed.
+	fn load_pyramid(
+		&self,
+		mut map: Map,
+		atlas_configs: &[AtlasConfig],
+		pyramid_config: &PyramidConfig,
+		source_hash: u64,
+		missing_hash: u64,
+	) -> Result<Map, DatabaseError> {
+		let pyramid_dir = self.pyramid_dir(pyramid_config.tile_px);
+		if let Err(err) = std::fs::create_dir_all(&pyramid_dir) {
+			warn!("Couldn't create pyramid dir {:?}: {}", pyramid_dir, err);
+		}
+		let mut manifest = PyramidManifest {
+			source_hash,
+			missing_hash,
+			pyramid: TilePyramid::new(
+				pyramid_config.tile_px,
+				pyramid_config.gutter_px,
+				map.bounds.clone(),
+				0_f64,
+			),
+		};
+
+		if self.regen_textures {
+			info!("Regenerating tile pyramid");
+		} else {
+			match PyramidManifest::read(&pyramid_dir) {
+				Ok(ref old_manifest) if old_manifest.same_source_as(&manifest) => {
+					info!(
+						"Using the {} level tile pyramid in {:?}",
+						old_manifest.pyramid.max_level + 1,
+						pyramid_dir
+					);
+					map.pyramid = Some(old_manifest.pyramid.clone());
+					return Ok(map);
+				}
+				Ok(_) => info!("Tile pyramid in {:?} is out of date", pyramid_dir),
+				Err(err) => info!("No usable tile pyramid in {:?}: {}", pyramid_dir, err),
+			}
+		}
+
+		for (atlas, atlas_config) in map.atlases.iter_mut().zip(atlas_configs.iter()) {
+			atlas.image = Some(get_image_from_file(&atlas_config.file_path)?);
+		}
+		// Deep enough to show the most detailed atlas at its full resolution
+		let px_per_deg = map
+			.atlases
+			.iter()
+			.filter_map(Atlas::px_per_deg)
+			.fold(0_f64, f64::max);
+		manifest.pyramid = TilePyramid::new(
+			pyramid_config.tile_px,
+			pyramid_config.gutter_px,
+			map.bounds.clone(),
+			px_per_deg,
+		);
+		let ids = manifest.pyramid.tile_ids();
+		info!(
+			"Tile pyramid needs {} levels, {} tiles",
+			manifest.pyramid.max_level + 1,
+			ids.len()
+		);
+
+		// Tiles are saved as they're made, so the whole pyramid never has to fit in memory
+		let mut all_saved = true;
+		let mut logged_percent = 0;
+		map.generate_pyramid_tiles(&manifest.pyramid, &ids, &mut |id, tile, progress| {
+			let tile_path = pyramid_tile_path(&pyramid_dir, &id);
+			let saved = match (tile, tile_path.parent()) {
+				(Some(tile), Some(level_dir)) => {
+					std::fs::create_dir_all(level_dir).is_ok()
+						&& tile
+							.save_with_format(&tile_path, image::ImageFormat::PNG)
+							.is_ok()
+				}
+				_ => false,
+			};
+			if !saved {
+				warn!("Pyramid tile {:?} could not be saved", tile_path);
+				all_saved = false;
+			}
+
+			if progress.percent() >= logged_percent + 10 {
+				logged_percent = progress.percent();
+				info!("Generating pyramid tiles: {}", progress);
+			}
+		});
+		// Otherwise the missing tiles would be taken as up to date next time
+		if !all_saved {
+			warn!("Tile pyramid is incomplete, it'll be generated again next time");
+		} else if let Err(err) = manifest.write(&pyramid_dir) {
+			warn!("Pyramid manifest could not be saved: {}", err);
+		}
+
+		for atlas in map.atlases.iter_mut() {
+			atlas.image = None;
+		}
+		map.pyramid = Some(manifest.pyramid);
+		Ok(map)
+	}
+
+	/// Reads the [map.pyramid] table, whose tile_px and gutter_px are both optional
+	fn load_pyramid_config(pyramid: &Value) -> Result<PyramidConfig, DatabaseError> {
+		let get_px = |key: &str, default: i64| match pyramid.get(key) {
+			Some(_) => Self::value_get_int(pyramid, key),
+			None => Ok(default),
+		};
+		let tile_px = get_px("tile_px", DEFAULT_PYRAMID_TILE_PX)?;
+		let gutter_px = get_px("gutter_px", DEFAULT_PYRAMID_GUTTER_PX)?;
+		if tile_px <= 0 || gutter_px < 0 {
+			error!(
+				"Bad pyramid tile size {}px with a {}px gutter",
+				tile_px, gutter_px
+			);
+			return Err(DatabaseError::ConfigValueWrongType);
+		}
+		Ok(PyramidConfig {
+			tile_px: tile_px as u32,
+			gutter_px: gutter_px as u32,
+		})
+	}
+
+	/// Loads a map made from a directory of tiles, described by a [map.tiles] table naming
+	/// the dir and the pattern its tiles are named by. Tiles are texture_size_deg wide.
+	fn load_tiles(
+		&self,
+		name: &str,
+		tiles: &Value,
+		missing_file_path: &str,
+		texture_size_deg: u32,
+	) -> Result<Map, DatabaseError> {
+		let dir = self.sibling_file_path(Self::value_get_str(tiles, "dir")?)?;
+		let pattern = Self::value_get_str(tiles, "pattern")?;
+		let pattern = TilePattern::new(pattern).map_err(|e| {
+			error!("Bad tile pattern {:?}: {}", pattern, e);
+			DatabaseError::ConfigValueWrongType
+		})?;
+
+		let missing_image = get_image_from_file(missing_file_path)?;
+		let textures = load_tile_dir(Path::new(&dir), &pattern, texture_size_deg, &missing_image)?;
+		Ok(Map::from_tiles(
+			name,
+			textures,
+			missing_image,
+			texture_size_deg,
+		))
+	}
+
+	/// Reads the map's atlases, either from a list of [[map.atlas]] tables, or if there
+	/// are none, a single atlas described in [map] itself
+	fn load_atlas_configs(&self, map: &Value) -> Result<Vec<AtlasConfig>, DatabaseError> {
+		match map.get("atlas") {
+			Some(atlases) => atlases
+				.as_array()
+				.ok_or(DatabaseError::ConfigValueWrongType)?
+				.iter()
+				.map(|atlas| self.load_atlas_config(atlas))
+				.collect(),
+			None => Ok(vec![self.load_atlas_config(map)?]),
+		}
+	}
+
+	fn load_atlas_config(&self, atlas: &Value) -> Result<AtlasConfig, DatabaseError> {
+		let filename = Self::value_get_str(atlas, "filename")?;
+		let min_lat = Self::value_get_float(atlas, "min_lat")?;
+		let max_lat = Self::value_get_float(atlas, "max_lat")?;
+		let min_long = Self::value_get_float(atlas, "min_long")?;
+		let max_long = Self::value_get_float(atlas, "max_long")?;
+		// Priority is optional, atlases default to being ordered by detail
+		let priority = match atlas.get("priority") {
+			Some(_) => Self::value_get_int(atlas, "priority")? as i32,
+			None => 0_i32,
+		};
+		let projection = match atlas.get("projection") {
+			Some(projection) => Self::load_projection(projection)?,
+			None => Arc::new(Equirectangular),
+		};
+
+		let bounds = MapBounds::new(min_lat, max_lat, min_long, max_long);
+		let atlas = Atlas::new(None, bounds, projection, priority).ok_or_else(|| {
+			error!(
+				"Atlas {}'s corners can't be shown in its projection",
+				filename
+			);
+			DatabaseError::ConfigValueWrongType
+		})?;
+		Ok(AtlasConfig {
+			file_path: self.sibling_file_path(filename)?,
+			atlas,
+		})
+	}
+
+	/// Reads an atlas' projection, either just a name like "mercator", or a table with a type
+	/// and the projection's parameters, like
+	/// { type = "lambert_conformal_conic", standard_parallels = [33.0, 45.0], central_long = -96.0 }
+	fn load_projection(value: &Value) -> Result<Arc<dyn Projection>, DatabaseError> {
+		let name = match value.as_str() {
+			Some(name) => name,
+			None => Self::value_get_str(value, "type")?,
+		};
+		// Parameters default to 0, or north for the pole
+		let get_float = |key: &str| match value.get(key) {
+			Some(_) => Self::value_get_float(value, key),
+			None => Ok(0_f64),
+		};
+
+		Ok(match name {
+			"equirectangular" => Arc::new(Equirectangular),
+			"mercator" => Arc::new(Mercator),
+			"lambert_conformal_conic" => {
+				let parallels = Self::value_get(value, "standard_parallels")?
+					.as_array()
+					.ok_or(DatabaseError::ConfigValueWrongType)?;
+				let parallels = parallels
+					.iter()
+					.map(Self::value_as_float)
+					.collect::<Result<Vec<f64>, DatabaseError>>()?;
+				let projection = match parallels.as_slice() {
+					[parallel] => {
+						LambertConformalConic::new(*parallel, *parallel, get_float("central_long")?)
+					}
+					[parallel_1, parallel_2] => LambertConformalConic::new(
+						*parallel_1,
+						*parallel_2,
+						get_float("central_long")?,
+					),
+					_ => None,
+				};
+				Arc::new(projection.ok_or_else(|| {
+					error!("Bad standard parallels for projection: {:?}", parallels);
+					DatabaseError::ConfigValueWrongType
+				})?)
+			}
+			"polar_stereographic" => {
+				let north = match value.get("pole").and_then(Value::as_str) {
+					None | Some("north") => true,
+					Some("south") => false,
+					Some(pole) => {
+						error!("Unknown pole for projection: {}", pole);
+						return Err(DatabaseError::ConfigValueWrongType);
+					}
+				};
+				Arc::new(PolarStereographic::new(north, get_float("central_long")?))
+			}
+			_ => {
+				error!("Unknown projection: {}", name);
+				return Err(DatabaseError::ConfigValueWrongType);
+			}
+		})
+	}
+
+	fn save_atlas(table: &mut toml_edit::Table, atlas: &Atlas) {
+		let bounds = atlas.bounds();
+		edit_set(table, "min_lat", bounds.min_lat.into());
+		edit_set(table, "max_lat", bounds.max_lat.into());
+		edit_set(table, "min_long", bounds.min_long.into());
+		edit_set(table, "max_long", bounds.max_long.into());
+		if atlas.priority != 0 || table.contains_key("priority") {
+			edit_set(table, "priority", (atlas.priority as i64).into());
 		}
 	}
 
 	fn load_config(&self) -> Result<Value, DatabaseError> {
-		let mut file = File::open(&self.config_file).map_err(|e| DatabaseError::IOError(e))?;
+		let mut file = File::open(&self.config_file).map_err(DatabaseError::IOError)?;
 
 		let mut config = String::new();
 		file.read_to_string(&mut config)
-			.map_err(|e| DatabaseError::IOError(e))?;
+			.map_err(DatabaseError::IOError)?;
 
 		config
 			.parse::<Value>()
 			.map_err(|e| DatabaseError::ConfigParseError(Box::new(e)))
 	}
 
-	fn value_get<'a>(value: &'a Value, key: &str) -> Result<&'a Value, DatabaseError> {
-		Ok(value.get(key).ok_or(DatabaseError::ConfigMissingValue)?)
+	/// Path of a file named in the config, which lives next to the config file
+	fn sibling_file_path(&self, file_name: &str) -> Result<String, DatabaseError> {
+		Ok(Path::new(&self.config_file)
+			.with_file_name(file_name)
+			.to_str()
+			.ok_or(DatabaseError::IOError(std::io::Error::new(
+				std::io::ErrorKind::NotFound,
+				"Parent dir of config",
+			)))?
+			.to_string())
+	}
+
+	fn load_cities(&self, config: &Value) -> Result<Vec<City>, DatabaseError> {
+		// Cities are optional, not every world has them
+		let cities = match config.get("cities") {
+			Some(cities) => cities,
+			None => return Ok(Vec::new()),
+		};
+		let cities_filename = Self::value_get_str(cities, "filename")?;
+
+		let file =
+			File::open(self.sibling_file_path(cities_filename)?).map_err(DatabaseError::IOError)?;
+		let (cities, line_errors) =
+			read_cities(BufReader::new(file)).map_err(DatabaseError::CitiesParseError)?;
+		if !line_errors.is_empty() {
+			warn!(
+				"Skipped {} lines of {} that couldn't be read as cities",
+				line_errors.len(),
+				cities_filename
+			);
+		}
+		Ok(cities)
+	}
+
+	/// Models named in the [models] table, which maps each name to an OBJ file
+	fn load_models(&self, config: &Value) -> Result<HashMap<String, Model>, DatabaseError> {
+		let models = match config.get("models") {
+			Some(models) => models,
+			None => return Ok(HashMap::new()),
+		};
+		let models = models.as_table().ok_or_else(|| {
+			error!("[models] should be a table of names to OBJ files");
+			DatabaseError::ConfigValueWrongType
+		})?;
+
+		let mut loaded = HashMap::new();
+		for (name, file_name) in models {
+			let file_name = file_name.as_str().ok_or_else(|| {
+				error!("Model {} should be the name of an OBJ file", name);
+				DatabaseError::ConfigValueWrongType
+			})?;
+			let file =
+				File::open(self.sibling_file_path(file_name)?).map_err(DatabaseError::IOError)?;
+			let model = read_obj(name, BufReader::new(file))
+				.map_err(|e| DatabaseError::ModelParseError(file_name.to_string(), e))?;
+			loaded.insert(name.clone(), model);
+		}
+		Ok(loaded)
+	}
+
+	/// Fonts named in the [fonts] table, which maps each name to a TrueType or OpenType file
+	fn load_fonts(&self, config: &Value) -> Result<HashMap<String, Font<'static>>, DatabaseError> {
+		let fonts = match config.get("fonts") {
+			Some(fonts) => fonts,
+			None => return Ok(HashMap::new()),
+		};
+		let fonts = fonts.as_table().ok_or_else(|| {
+			error!("[fonts] should be a table of names to font files");
+			DatabaseError::ConfigValueWrongType
+		})?;
+
+		let mut loaded = HashMap::new();
+		for (name, file_name) in fonts {
+			let file_name = file_name.as_str().ok_or_else(|| {
+				error!("Font {} should be the name of a font file", name);
+				DatabaseError::ConfigValueWrongType
+			})?;
+			let bytes = std::fs::read(self.sibling_file_path(file_name)?)
+				.map_err(DatabaseError::IOError)?;
+			let font = Font::from_bytes(bytes)
+				.map_err(|e| DatabaseError::FontParseError(file_name.to_string(), e))?;
+			info!("Loaded font {} from {}", name, file_name);
+			loaded.insert(name.clone(), font);
+		}
+		Ok(loaded)
+	}
+
+	/// Bookmarks from the [[bookmarks]] tables, each a camera view with a name and maybe a slot
+	fn load_bookmarks(config: &Value) -> Result<Vec<Bookmark>, DatabaseError> {
+		let bookmarks = match config.get("bookmarks") {
+			Some(bookmarks) => bookmarks,
+			None => return Ok(Vec::new()),
+		};
+		let bookmarks = bookmarks.as_array().ok_or_else(|| {
+			error!("[[bookmarks]] should be tables of camera views");
+			DatabaseError::ConfigValueWrongType
+		})?;
+
+		bookmarks
+			.iter()
+			.map(|bookmark| {
+				let slot = match bookmark.get("slot") {
+					Some(slot) => Some(
+						slot.as_integer()
+							.filter(|&slot| slot >= 0 && slot <= u32::MAX as i64)
+							.ok_or(DatabaseError::ConfigValueWrongType)? as u32,
+					),
+					None => None,
+				};
+				Ok(Bookmark {
+					name: Self::value_get_str(bookmark, "name")?.to_string(),
+					slot,
+					view: Self::load_camera_view(bookmark)?,
+				})
+			})
+			.collect()
+	}
+
+	/// A camera view from a table with its lat, long, distance, and whether it's orbiting,
+	/// along with its heading and tilt if it is. Angles are in degrees.
+	fn load_camera_view(value: &Value) -> Result<CameraView, DatabaseError> {
+		let orbit = match value.get("orbit") {
+			Some(orbit) => orbit.as_bool().ok_or(DatabaseError::ConfigValueWrongType)?,
+			None => false,
+		};
+		let orbit_angle = |key: &str| -> Result<Rad<f64>, DatabaseError> {
+			match value.get(key) {
+				Some(_) if orbit => Ok(Deg(Self::value_get_float(value, key)?).into()),
+				_ => Ok(Rad(0_f64)),
+			}
+		};
+		Ok(CameraView {
+			target: LatLong::new(
+				Deg(Self::value_get_float(value, "lat")?),
+				Deg(Self::value_get_float(value, "long")?),
+			)
+			.normalize(),
+			distance: Self::value_get_float(value, "distance")?,
+			orbit,
+			heading: orbit_angle("heading")?,
+			tilt: orbit_angle("tilt")?,
+		})
+	}
+
+	/// The camera view in the [camera] table of the last view file, None if there's no file.
+	/// A file that can't be read is only warned about, since it's not part of the world.
+	fn load_last_view(&self) -> Result<Option<CameraView>, DatabaseError> {
+		let path = self.sibling_file_path(LAST_VIEW_FILENAME)?;
+		let contents = match std::fs::read_to_string(&path) {
+			Ok(contents) => contents,
+			Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+			Err(e) => return Err(DatabaseError::IOError(e)),
+		};
+		let view = contents
+			.parse::<Value>()
+			.ok()
+			.and_then(|value| value.get("camera").cloned())
+			.and_then(|camera| Self::load_camera_view(&camera).ok());
+		if view.is_none() {
+			warn!(
+				"Couldn't read the camera view in {}, starting from the default",
+				path
+			);
+		}
+		Ok(view)
+	}
+
+	/// Saves view to the last view file, if it's not already there
+	fn save_last_view(&self, view: &CameraView) -> Result<(), DatabaseError> {
+		let path = self.sibling_file_path(LAST_VIEW_FILENAME)?;
+		let contents = match std::fs::read_to_string(&path) {
+			Ok(contents) => contents,
+			Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+			Err(e) => return Err(DatabaseError::IOError(e)),
+		};
+		// A file that won't parse is replaced rather than kept, it's only ever written here
+		let mut doc = contents.parse::<DocumentMut>().unwrap_or_default();
+		Self::save_camera_view(Self::edit_get_table(&mut doc, "camera")?, view);
+		let new_contents = doc.to_string();
+		if new_contents == contents {
+			return Ok(());
+		}
+		Self::write_file(&path, new_contents.as_bytes())
+	}
+
+	/// Writes contents to a temp file next to file_name and then moves it over file_name, so
+	/// a failed save never leaves a half-written file behind
+	fn write_file(file_name: &str, contents: &[u8]) -> Result<(), DatabaseError> {
+		let tmp_file_name = format!("{}.tmp", file_name);
+		std::fs::write(&tmp_file_name, contents).map_err(DatabaseError::IOError)?;
+		std::fs::rename(&tmp_file_name, file_name).map_err(DatabaseError::IOError)
+	}
+
+	fn edit_get_table<'a>(
+		doc: &'a mut DocumentMut,
+		key: &str,
+	) -> Result<&'a mut toml_edit::Table, DatabaseError> {
+		doc.entry(key)
+			.or_insert(toml_edit::table())
+			.as_table_mut()
+			.ok_or(DatabaseError::ConfigValueWrongType)
+	}
+
+	fn save_cities(&self, doc: &mut DocumentMut, cities: &[City]) -> Result<(), DatabaseError> {
+		if cities.is_empty() && doc.get("cities").is_none() {
+			return Ok(());
+		}
+		let cities_table = Self::edit_get_table(doc, "cities")?;
+		let cities_filename = match cities_table.get("filename") {
+			Some(filename) => filename
+				.as_str()
+				.ok_or(DatabaseError::ConfigValueWrongType)?
+				.to_string(),
+			None => DEFAULT_CITIES_FILENAME.to_string(),
+		};
+		edit_set(cities_table, "filename", cities_filename.as_str().into());
+
+		// The file's only rewritten if it doesn't already read back as the same cities, and
+		// keeps the line endings it had
+		let cities_path = self.sibling_file_path(&cities_filename)?;
+		let old_contents = std::fs::read(&cities_path).ok();
+		let line_ending = match old_contents {
+			Some(ref old_contents) if old_contents.windows(2).any(|pair| pair == b"\r\n") => "\r\n",
+			_ => "\n",
+		};
+		let write = |cities: &[City]| {
+			let mut contents: Vec<u8> = Vec::new();
+			write_cities(&mut contents, cities, line_ending).map_err(DatabaseError::IOError)?;
+			Ok(contents)
+		};
+		let contents = write(cities)?;
+		if let Some(old_contents) = old_contents {
+			if let Ok((old_cities, _)) = read_cities(old_contents.as_slice()) {
+				if write(&old_cities)? == contents {
+					return Ok(());
+				}
+			}
+		}
+		Self::write_file(&cities_path, &contents)
+	}
+
+	fn save_bookmarks(doc: &mut DocumentMut, bookmarks: &[Bookmark]) -> Result<(), DatabaseError> {
+		if bookmarks.is_empty() {
+			doc.remove("bookmarks");
+			return Ok(());
+		}
+		let tables = doc
+			.entry("bookmarks")
+			.or_insert(toml_edit::Item::ArrayOfTables(
+				toml_edit::ArrayOfTables::new(),
+			))
+			.as_array_of_tables_mut()
+			.ok_or(DatabaseError::ConfigValueWrongType)?;
+		// The tables already there are kept for the bookmarks in their place, with their
+		// comments, and the rest are added or dropped
+		while tables.len() > bookmarks.len() {
+			tables.remove(tables.len() - 1);
+		}
+		while tables.len() < bookmarks.len() {
+			tables.push(toml_edit::Table::new());
+		}
+		for (table, bookmark) in tables.iter_mut().zip(bookmarks.iter()) {
+			edit_set(table, "name", bookmark.name.as_str().into());
+			match bookmark.slot {
+				Some(slot) => edit_set(table, "slot", (slot as i64).into()),
+				None => {
+					table.remove("slot");
+				}
+			}
+			Self::save_camera_view(table, &bookmark.view);
+		}
+		Ok(())
+	}
+
+	fn save_camera_view(table: &mut toml_edit::Table, view: &CameraView) {
+		// Rounded to a billionth of a degree, so angles that went to radians and back are
+		// saved just as they were written
+		let degrees = |angle: Rad<f64>| ((Deg::from(angle).0 * 1e9_f64).round() / 1e9_f64).into();
+		edit_set(table, "lat", degrees(view.target.lat));
+		edit_set(table, "long", degrees(view.target.long));
+		edit_set(table, "distance", view.distance.into());
+		if view.orbit {
+			edit_set(table, "orbit", true.into());
+			edit_set(table, "heading", degrees(view.heading));
+			edit_set(table, "tilt", degrees(view.tilt));
+		} else {
+			for key in &["orbit", "heading", "tilt"] {
+				table.remove(key);
+			}
+		}
 	}
 
-	fn value_get_str<'a>(value: &'a Value, key: &str) -> Result<&'a str, DatabaseError> {
-		Ok(Self::value_get(value, key)?
+	pub(super) fn value_get<'a>(value: &'a Value, key: &str) -> Result<&'a Value, DatabaseError> {
+		value.get(key).ok_or(DatabaseError::ConfigMissingValue)
+	}
+
+	pub(super) fn value_get_str<'a>(value: &'a Value, key: &str) -> Result<&'a str, DatabaseError> {
+		Self::value_get(value, key)?
 			.as_str()
-			.ok_or(DatabaseError::ConfigValueWrongType)?)
+			.ok_or(DatabaseError::ConfigValueWrongType)
 	}
 
-	fn value_get_int(value: &Value, key: &str) -> Result<i64, DatabaseError> {
-		Ok(Self::value_get(value, key)?
+	pub(super) fn value_get_int(value: &Value, key: &str) -> Result<i64, DatabaseError> {
+		Self::value_get(value, key)?
 			.as_integer()
-			.ok_or(DatabaseError::ConfigValueWrongType)?)
+			.ok_or(DatabaseError::ConfigValueWrongType)
+	}
+
+	/// The number at key in value, which may be written as an integer, like min_lat = 10
+	pub(super) fn value_get_float(value: &Value, key: &str) -> Result<f64, DatabaseError> {
+		Self::value_as_float(Self::value_get(value, key)?)
 	}
 
-	fn value_get_float(value: &Value, key: &str) -> Result<f64, DatabaseError> {
-		Ok(Self::value_get(value, key)?
+	fn value_as_float(value: &Value) -> Result<f64, DatabaseError> {
+		value
 			.as_float()
-			.ok_or(DatabaseError::ConfigValueWrongType)?)
+			.or_else(|| value.as_integer().map(|value| value as f64))
+			.ok_or(DatabaseError::ConfigValueWrongType)
 	}
 }
 impl Database for FileDatabase {
@@ -70,73 +857,484 @@ impl Database for FileDatabase {
 
 		// World table info
 		let world = Self::value_get(&value, "world")?;
-		let name = Self::value_get_str(&world, "name")?;
+		let name = Self::value_get_str(world, "name")?;
+		let radius = match world.get("radius") {
+			Some(_) => Some(Self::value_get_float(world, "radius")?),
+			None => None,
+		};
+		let distance_unit = match world.get("distance_unit") {
+			Some(_) => Self::value_get_str(world, "distance_unit")?,
+			None => DEFAULT_DISTANCE_UNIT,
+		};
 
 		// Map table info
 		let map = Self::value_get(&value, "map")?;
-		let map_filename = Self::value_get_str(&map, "filename")?;
-		let min_lat = Self::value_get_float(&map, "min_lat")?;
-		let max_lat = Self::value_get_float(&map, "max_lat")?;
-		let min_long = Self::value_get_float(&map, "min_long")?;
-		let max_long = Self::value_get_float(&map, "max_long")?;
-		let missing_texture_file = Self::value_get_str(&map, "missing_texture")?;
-		let texture_size_deg = Self::value_get_int(&map, "texture_size_deg")?;
-
-		let map_bounds = MapBounds::new(min_lat, max_lat, min_long, max_long);
-		let config_path = Path::new(&self.config_file);
-		let map_file_path = config_path
-			.with_file_name(map_filename)
-			.to_str()
-			.ok_or(DatabaseError::IOError(std::io::Error::new(
-				std::io::ErrorKind::NotFound,
-				"Parent dir of config",
-			)))?
-			.to_string();
-		let map_image = get_image_from_file(&map_file_path)?;
-		let missing_file_path = config_path
-			.with_file_name(missing_texture_file)
-			.to_str()
-			.ok_or(DatabaseError::IOError(std::io::Error::new(
-				std::io::ErrorKind::NotFound,
-				"Parent dir of config",
-			)))?
-			.to_string();
-		let missing_image = get_image_from_file(&missing_file_path)?;
-		let mut map = Map::new(&name, map_image, missing_image, map_bounds);
-		info!("Loaded map!");
+		let missing_texture_file = Self::value_get_str(map, "missing_texture")?;
+		let texture_size_deg = Self::value_get_int(map, "texture_size_deg")?;
 
-		let textures_dir = config_path.with_file_name(&format!("tex_{}", texture_size_deg));
-		let dir_builder = std::fs::DirBuilder::new();
-		if dir_builder.create(&textures_dir).is_err() {
-			warn!("Dir builder couldn't create {:?}", textures_dir);
-		}
-		info!("foo");
-
-		// TODO: Skip generating textures if textures_dir exists, generate a Map based on
-		// the images in that folder instead of the large image (which we shouldn't open)
-		// Add a CLI option in main to bypass this and force regen the textures
-		let map_textures = map.generate_textures(texture_size_deg as u32);
-		for (map_piece_key, texture) in map_textures.iter() {
-			let texture_filename = format!(
-				"{:+04}_{:+04}.png",
-				map_piece_key.min_long, map_piece_key.min_lat
-			);
-			let texture_file_path = textures_dir.join(texture_filename);
-			if texture
-				.save_with_format(&texture_file_path, image::ImageFormat::PNG)
-				.is_err()
-			{
-				warn!("Texture {:?} could not be saved", texture_file_path);
+		let missing_file_path = self.sibling_file_path(missing_texture_file)?;
+		let pyramid_config = match map.get("pyramid") {
+			Some(pyramid) => Some(Self::load_pyramid_config(pyramid)?),
+			None => None,
+		};
+		// Maps come either from tiles split up ahead of time, or atlases
+		let map = match map.get("tiles") {
+			Some(tiles) => {
+				if pyramid_config.is_some() {
+					warn!("Tile pyramids are generated from atlases, ignoring [map.pyramid]");
+				}
+				self.load_tiles(name, tiles, &missing_file_path, texture_size_deg as u32)?
 			}
-		}
+			None => self.load_map(
+				name,
+				&self.load_atlas_configs(map)?,
+				&missing_file_path,
+				texture_size_deg as u32,
+				pyramid_config.as_ref(),
+			)?,
+		};
+
+		let cities = self.load_cities(&value)?;
+		info!("Loaded {} cities!", cities.len());
+
+		let models = self.load_models(&value)?;
+		let fonts = self.load_fonts(&value)?;
+
+		let bookmarks = Self::load_bookmarks(&value)?;
+		let camera = self.load_last_view()?;
 
 		Ok(WorldState {
 			name: name.to_string(),
+			radius,
+			distance_unit: distance_unit.to_string(),
 			map: Box::new(map),
+			cities,
+			models,
+			fonts,
+			bookmarks,
+			camera,
 		})
 	}
 
-	fn save(&self) {
-		error!("Database save unimplemented!");
+	fn save(&self, state: &WorldState) -> Result<(), DatabaseError> {
+		// Edit the config as it is on disk, so keys and comments we don't know about survive
+		let mut config = String::new();
+		File::open(&self.config_file)
+			.and_then(|mut file| file.read_to_string(&mut config))
+			.map_err(DatabaseError::IOError)?;
+		let mut doc = config
+			.parse::<DocumentMut>()
+			.map_err(|e| DatabaseError::ConfigParseError(Box::new(e)))?;
+
+		// World table info
+		let world = Self::edit_get_table(&mut doc, "world")?;
+		edit_set(world, "name", state.name.as_str().into());
+		if let Some(radius) = state.radius {
+			edit_set(world, "radius", radius.into());
+			// Left out while it's the default the config left out
+			if world.contains_key("distance_unit") || state.distance_unit != DEFAULT_DISTANCE_UNIT {
+				edit_set(world, "distance_unit", state.distance_unit.as_str().into());
+			}
+		}
+
+		// Map table info (the map images themselves never change, so aren't rewritten)
+		let map = Self::edit_get_table(&mut doc, "map")?;
+		edit_set(
+			map,
+			"texture_size_deg",
+			(state.map.texture_size_deg as i64).into(),
+		);
+		let atlases = &state.map.atlases;
+		if map.contains_key("atlas") {
+			let atlas_tables = map
+				.get_mut("atlas")
+				.and_then(|atlas| atlas.as_array_of_tables_mut())
+				.ok_or(DatabaseError::ConfigValueWrongType)?;
+			if atlas_tables.len() != atlases.len() {
+				return Err(DatabaseError::ConfigValueWrongType);
+			}
+			for (atlas_table, atlas) in atlas_tables.iter_mut().zip(atlases.iter()) {
+				Self::save_atlas(atlas_table, atlas);
+			}
+		} else if !map.contains_key("tiles") {
+			// A single atlas described in [map] itself
+			match atlases.as_slice() {
+				[atlas] => Self::save_atlas(map, atlas),
+				_ => return Err(DatabaseError::ConfigValueWrongType),
+			}
+		}
+
+		self.save_cities(&mut doc, &state.cities)?;
+		// Bookmarks are only written when they're not the ones already saved, so views that
+		// went to radians and back are never rewritten as they were
+		let saved_bookmarks = config
+			.parse::<Value>()
+			.ok()
+			.and_then(|value| Self::load_bookmarks(&value).ok());
+		if saved_bookmarks.as_ref() != Some(&state.bookmarks) {
+			Self::save_bookmarks(&mut doc, &state.bookmarks)?;
+		}
+		if let Some(ref camera) = state.camera {
+			self.save_last_view(camera)?;
+		}
+
+		let new_config = doc.to_string();
+		if new_config == config {
+			return Ok(());
+		}
+		Self::write_file(&self.config_file, new_config.as_bytes())?;
+		info!("Saved world {} to {}", state.name, self.config_file);
+		Ok(())
+	}
+
+	fn load_pyramid_tile(
+		&self,
+		pyramid: &TilePyramid,
+		id: &TileId,
+	) -> Result<DynamicImage, DatabaseError> {
+		let tile_path = pyramid_tile_path(&self.pyramid_dir(pyramid.tile_px), id);
+		get_image_from_file(&tile_path.to_string_lossy())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::entities::MapPieceKey;
+
+	use cgmath::Deg;
+	use image::{GenericImageView, Rgba, RgbaImage};
+	use std::path::PathBuf;
+
+	const CONFIG: &str = r#"# A world just big enough to load
+[world]
+name = "Scratch"
+radius = 6371
+
+[map]
+missing_texture = "missing.png"
+texture_size_deg = 2
+filename = "atlas.png"
+min_lat = 10.0
+max_lat = 12.0
+min_long = -2.0
+max_long = 0.0
+
+[cities]
+filename = "cities.csv"
+"#;
+
+	// Two textures' worth of atlas either side of the prime meridian
+	const TWO_ATLASES_CONFIG: &str = r#"[world]
+name = "Scratch"
+
+[map]
+missing_texture = "missing.png"
+texture_size_deg = 2
+
+[[map.atlas]]
+filename = "west.png"
+min_lat = 10.0
+max_lat = 12.0
+min_long = -4.0
+max_long = 0.0
+
+[[map.atlas]]
+filename = "east.png"
+min_lat = 10.0
+max_lat = 12.0
+min_long = 0.0
+max_long = 4.0
+"#;
+
+	const CITIES: &str = "Town,   Population,  Country,  Page,  Lat_Deg,  Lat_Min,  Lat_Dir,  Lon_Deg,  Lon_Min,  Lon_Dir\r\n\
+		Alpha,  1200,        Kalamar,  12,    11,       30,       N,        1,        15,       W\r\n\
+		Gamma,  RUINS,       Tokis,    ,      10,       5,        N,        0,        45,       W\r\n";
+
+	// A directory with CONFIG and CITIES in it, and the images they name, emptied first
+	fn scratch_world(name: &str) -> PathBuf {
+		let dir = std::env::temp_dir().join(format!("hm_world_{}_{}", name, std::process::id()));
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir_all(&dir).unwrap();
+		std::fs::write(dir.join("config.toml"), CONFIG).unwrap();
+		std::fs::write(dir.join("cities.csv"), CITIES).unwrap();
+		for &(file_name, size) in &[("missing.png", 4), ("atlas.png", 8)] {
+			RgbaImage::from_pixel(size, size, Rgba([40, 80, 120, 255]))
+				.save(dir.join(file_name))
+				.unwrap();
+		}
+		dir
+	}
+
+	fn database(dir: &Path) -> FileDatabase {
+		FileDatabase::new(dir.join("config.toml").to_str().unwrap(), false)
+	}
+
+	#[test]
+	fn saving_unchanged_world_leaves_files_alone() {
+		let dir = scratch_world("unchanged");
+		let db = database(&dir);
+		let state = db.load().unwrap();
+		db.save(&state).unwrap();
+
+		assert_eq!(
+			std::fs::read_to_string(dir.join("config.toml")).unwrap(),
+			CONFIG
+		);
+		assert_eq!(
+			std::fs::read_to_string(dir.join("cities.csv")).unwrap(),
+			CITIES
+		);
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+
+	#[test]
+	fn world_and_cities_round_trip() {
+		let dir = scratch_world("round_trip");
+		let db = database(&dir);
+		let mut state = db.load().unwrap();
+		assert_eq!(state.name, "Scratch");
+		assert_eq!(state.radius, Some(6371_f64));
+		assert_eq!(state.cities.len(), 2);
+
+		state.name = "Renamed".to_string();
+		state.cities[0].population = Some(1300);
+		state.cities.push(City {
+			name: "Delta".to_string(),
+			population: Some(90),
+			country: "Pekal".to_string(),
+			page: None,
+			coords: LatLong::new(Deg(-10.5_f64), Deg(3.25_f64)),
+		});
+		db.save(&state).unwrap();
+
+		let config = std::fs::read_to_string(dir.join("config.toml")).unwrap();
+		assert!(config.starts_with("# A world just big enough to load\n"));
+		let cities_file = std::fs::read_to_string(dir.join("cities.csv")).unwrap();
+		assert_eq!(cities_file.matches("\r\n").count(), 4);
+		assert_eq!(cities_file.matches('\n').count(), 4);
+
+		let reloaded = db.load().unwrap();
+		assert_eq!(reloaded.name, "Renamed");
+		assert_eq!(reloaded.radius, Some(6371_f64));
+		assert_eq!(reloaded.map.texture_size_deg, 2);
+		assert_eq!(reloaded.cities.len(), state.cities.len());
+		for (reloaded, city) in reloaded.cities.iter().zip(state.cities.iter()) {
+			assert_eq!(reloaded.name, city.name);
+			assert_eq!(reloaded.population, city.population);
+			assert_eq!(reloaded.country, city.country);
+			assert_eq!(reloaded.page, city.page);
+			assert!((reloaded.coords.lat.0 - city.coords.lat.0).abs() < 1e-9_f64);
+			assert!((reloaded.coords.long.0 - city.coords.long.0).abs() < 1e-9_f64);
+		}
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+
+	#[test]
+	fn last_view_is_kept_apart_from_config() {
+		let dir = scratch_world("last_view");
+		let db = database(&dir);
+		let mut state = db.load().unwrap();
+		assert!(state.camera.is_none());
+
+		let view = CameraView {
+			target: LatLong::new(Deg(11.5_f64), Deg(-1.25_f64)),
+			distance: 0.5_f64,
+			orbit: true,
+			heading: Deg(30_f64).into(),
+			tilt: Deg(45_f64).into(),
+		};
+		state.camera = Some(view.clone());
+		db.save(&state).unwrap();
+		assert_eq!(
+			std::fs::read_to_string(dir.join("config.toml")).unwrap(),
+			CONFIG
+		);
+
+		let last_view_path = dir.join(LAST_VIEW_FILENAME);
+		let last_view = std::fs::read_to_string(&last_view_path).unwrap();
+		let reloaded = db.load().unwrap().camera.unwrap();
+		assert!((reloaded.target.lat.0 - view.target.lat.0).abs() < 1e-9_f64);
+		assert!((reloaded.target.long.0 - view.target.long.0).abs() < 1e-9_f64);
+		assert_eq!(reloaded.distance, view.distance);
+		assert!(reloaded.orbit);
+		assert!((reloaded.heading.0 - view.heading.0).abs() < 1e-9_f64);
+		assert!((reloaded.tilt.0 - view.tilt.0).abs() < 1e-9_f64);
+
+		// Saving the same view again leaves the file as it was
+		state.camera = Some(reloaded);
+		db.save(&state).unwrap();
+		assert_eq!(std::fs::read_to_string(&last_view_path).unwrap(), last_view);
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+
+	#[test]
+	fn bookmarks_are_only_written_when_they_change() {
+		let dir = scratch_world("bookmarks");
+		let config = format!(
+			"{}\n# Where the old capital was\n[[bookmarks]]\nname = \"Ruins\"\nslot = 1\n\
+			 lat = 10.0833333333\nlong = -0.75\ndistance = 0.01\n",
+			CONFIG
+		);
+		std::fs::write(dir.join("config.toml"), &config).unwrap();
+		let db = database(&dir);
+		let mut state = db.load().unwrap();
+		assert_eq!(state.bookmarks.len(), 1);
+
+		db.save(&state).unwrap();
+		assert_eq!(
+			std::fs::read_to_string(dir.join("config.toml")).unwrap(),
+			config
+		);
+
+		state.bookmarks[0].name = "Old capital".to_string();
+		state.bookmarks.push(Bookmark {
+			name: "Alpha".to_string(),
+			slot: None,
+			view: CameraView {
+				target: LatLong::new(Deg(11.5_f64), Deg(-1.25_f64)),
+				distance: 0.1_f64,
+				orbit: false,
+				heading: Rad(0_f64),
+				tilt: Rad(0_f64),
+			},
+		});
+		db.save(&state).unwrap();
+		let saved = std::fs::read_to_string(dir.join("config.toml")).unwrap();
+		assert!(saved.contains("# Where the old capital was\n"));
+
+		let reloaded = db.load().unwrap().bookmarks;
+		assert_eq!(reloaded.len(), 2);
+		assert_eq!(reloaded[0].name, "Old capital");
+		assert_eq!(reloaded[0].slot, Some(1));
+		assert_eq!(reloaded[1].name, "Alpha");
+		assert_eq!(reloaded[1].slot, None);
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+
+	#[test]
+	fn whole_numbers_can_be_written_as_integers() {
+		let dir = scratch_world("integers");
+		let config = CONFIG
+			.replace("min_lat = 10.0", "min_lat = 10")
+			.replace("max_long = 0.0", "max_long = 0");
+		let config = format!(
+			"{}\n[[bookmarks]]\nname = \"Alpha\"\nlat = 11\nlong = -1\ndistance = 1\n",
+			config
+		);
+		std::fs::write(dir.join("config.toml"), &config).unwrap();
+		let db = database(&dir);
+		let state = db.load().unwrap();
+		assert_eq!(state.radius, Some(6371_f64));
+		let view = &state.bookmarks[0].view;
+		assert!((view.target.lat.0 - Rad::from(Deg(11_f64)).0).abs() < 1e-9_f64);
+		assert!((view.target.long.0 - Rad::from(Deg(-1_f64)).0).abs() < 1e-9_f64);
+		assert_eq!(view.distance, 1_f64);
+		let projection: Value =
+			"type = \"lambert_conformal_conic\"\nstandard_parallels = [33, 45]\ncentral_long = -96\n"
+				.parse()
+				.unwrap();
+		assert!(FileDatabase::load_projection(&projection).is_ok());
+
+		// And they're still integers once saved
+		db.save(&state).unwrap();
+		assert_eq!(
+			std::fs::read_to_string(dir.join("config.toml")).unwrap(),
+			config
+		);
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+
+	#[test]
+	fn changing_one_atlas_only_regenerates_its_textures() {
+		let dir = scratch_world("partial_regen");
+		std::fs::write(dir.join("config.toml"), TWO_ATLASES_CONFIG).unwrap();
+		let atlas = |file_name: &str, colour: Rgba<u8>| {
+			RgbaImage::from_pixel(8, 4, colour)
+				.save(dir.join(file_name))
+				.unwrap();
+		};
+		let (west, east, new_east) = (
+			Rgba([200, 0, 0, 255]),
+			Rgba([0, 200, 0, 255]),
+			Rgba([0, 0, 200, 255]),
+		);
+		atlas("west.png", west);
+		atlas("east.png", east);
+		let key = |min_long| MapPieceKey {
+			min_lat: 10,
+			min_long,
+		};
+		let colour = |map: &Map, min_long| map.textures[&key(min_long)].get_pixel(1, 1);
+
+		let db = database(&dir);
+		let map = db.load().unwrap().map;
+		assert_eq!(map.textures.len(), 4);
+		assert_eq!(colour(&map, -4), west);
+		assert_eq!(colour(&map, 2), east);
+
+		// Marking every cached texture shows which ones are loaded rather than generated
+		let marked = Rgba([1, 2, 3, 255]);
+		for &min_long in &[-4, -2, 0, 2] {
+			RgbaImage::from_pixel(4, 4, marked)
+				.save(dir.join("tex_2").join(texture_file_name(&key(min_long))))
+				.unwrap();
+		}
+		atlas("east.png", new_east);
+
+		let map = db.load().unwrap().map;
+		assert_eq!(colour(&map, -4), marked);
+		assert_eq!(colour(&map, -2), marked);
+		assert_eq!(colour(&map, 0), new_east);
+		assert_eq!(colour(&map, 2), new_east);
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+
+	#[test]
+	fn tiles_load_from_their_dir() {
+		let dir = scratch_world("tiles");
+		let config = |tiles: &str| {
+			let config = format!(
+				"[world]\nname = \"Tiles\"\n\n[map]\nmissing_texture = \"missing.png\"\n\
+				texture_size_deg = 1\n\n[map.tiles]\n{}\n",
+				tiles
+			);
+			std::fs::write(dir.join("config.toml"), config).unwrap();
+		};
+		std::fs::create_dir_all(dir.join("tiles")).unwrap();
+		for file_name in &["tile_12_-2.png", "tile_12_west.png"] {
+			RgbaImage::from_pixel(4, 4, Rgba([10, 20, 30, 255]))
+				.save_with_format(dir.join("tiles").join(file_name), image::ImageFormat::PNG)
+				.unwrap();
+		}
+		let db = database(&dir);
+
+		config("dir = \"tiles\"\npattern = \"tile_{max_lat}_{min_long}.png\"");
+		let map = db.load().unwrap().map;
+		let keys: Vec<&MapPieceKey> = map.textures.keys().collect();
+		assert_eq!(
+			keys,
+			[&MapPieceKey {
+				min_lat: 11,
+				min_long: -2
+			}]
+		);
+
+		config("dir = \"no_tiles\"\npattern = \"tile_{max_lat}_{min_long}.png\"");
+		assert!(db.load().is_err());
+		config("dir = \"tiles\"\npattern = \"tile_{north}_{min_long}.png\"");
+		assert!(db.load().is_err());
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+
+	#[test]
+	fn tellene_cities_write_back_byte_identical() {
+		let contents = std::fs::read("worlds/tellene/TelleneCities.csv").unwrap();
+		let (cities, line_errors) = read_cities(contents.as_slice()).unwrap();
+		assert!(line_errors.is_empty());
+
+		let mut written: Vec<u8> = Vec::new();
+		write_cities(&mut written, &cities, "\r\n").unwrap();
+		assert!(written == contents);
 	}
 }
@@ -1,2 +1,8 @@
 mod filedb;
 pub use self::filedb::FileDatabase;
+
+mod pyramid_cache;
+
+mod texture_cache;
+
+mod tile_dir;
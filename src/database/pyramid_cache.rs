@@ -0,0 +1,114 @@
+use super::filedb::FileDatabase;
+use crate::entities::{MapBounds, TileId, TilePyramid};
+use crate::world::DatabaseError;
+
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use toml::Value;
+
+const MANIFEST_FILENAME: &str = "manifest.toml";
+
+/// Where the tile for id lives in a pyramid dir: <level>/<x>_<y>.png
+pub fn pyramid_tile_path(dir: &Path, id: &TileId) -> PathBuf {
+	dir.join(id.level.to_string())
+		.join(format!("{}_{}.png", id.x, id.y))
+}
+
+// Hashes are stored as hex strings, because TOML integers are signed
+fn format_hash(hash: u64) -> Value {
+	Value::String(format!("{:016x}", hash))
+}
+
+fn parse_hash(value: &Value) -> Result<u64, DatabaseError> {
+	let hash = value.as_str().ok_or(DatabaseError::ConfigValueWrongType)?;
+	u64::from_str_radix(hash, 16).map_err(|_| DatabaseError::ConfigValueWrongType)
+}
+
+/// Records what the tiles in a pyramid_<N> dir were generated from, so the atlases only have
+/// to be loaded when they change. Unlike a texture cache, a pyramid is always generated
+/// whole, since every level depends on all of the atlases it covers.
+#[derive(Debug)]
+pub struct PyramidManifest {
+	pub source_hash: u64, // Of the atlases' image files, bounds, projections and priorities
+	pub missing_hash: u64, // Of the missing texture file
+	pub pyramid: TilePyramid,
+}
+impl PyramidManifest {
+	fn path(dir: &Path) -> PathBuf {
+		dir.join(MANIFEST_FILENAME)
+	}
+
+	pub fn read(dir: &Path) -> Result<PyramidManifest, DatabaseError> {
+		let mut file = File::open(Self::path(dir)).map_err(DatabaseError::IOError)?;
+		let mut manifest = String::new();
+		file.read_to_string(&mut manifest)
+			.map_err(DatabaseError::IOError)?;
+		let value = manifest
+			.parse::<Value>()
+			.map_err(|e| DatabaseError::ConfigParseError(Box::new(e)))?;
+
+		let bounds = FileDatabase::value_get(&value, "bounds")?;
+		Ok(PyramidManifest {
+			source_hash: parse_hash(FileDatabase::value_get(&value, "source_hash")?)?,
+			missing_hash: parse_hash(FileDatabase::value_get(&value, "missing_hash")?)?,
+			pyramid: TilePyramid {
+				tile_px: FileDatabase::value_get_int(&value, "tile_px")? as u32,
+				gutter_px: FileDatabase::value_get_int(&value, "gutter_px")? as u32,
+				max_level: FileDatabase::value_get_int(&value, "max_level")? as u32,
+				bounds: MapBounds::new(
+					FileDatabase::value_get_float(bounds, "min_lat")?,
+					FileDatabase::value_get_float(bounds, "max_lat")?,
+					FileDatabase::value_get_float(bounds, "min_long")?,
+					FileDatabase::value_get_float(bounds, "max_long")?,
+				),
+			},
+		})
+	}
+
+	pub fn write(&self, dir: &Path) -> Result<(), DatabaseError> {
+		let pyramid = &self.pyramid;
+		let mut bounds = toml::value::Table::new();
+		bounds.insert("min_lat".to_string(), Value::Float(pyramid.bounds.min_lat));
+		bounds.insert("max_lat".to_string(), Value::Float(pyramid.bounds.max_lat));
+		bounds.insert(
+			"min_long".to_string(),
+			Value::Float(pyramid.bounds.min_long),
+		);
+		bounds.insert(
+			"max_long".to_string(),
+			Value::Float(pyramid.bounds.max_long),
+		);
+
+		let mut manifest = toml::value::Table::new();
+		manifest.insert("source_hash".to_string(), format_hash(self.source_hash));
+		manifest.insert("missing_hash".to_string(), format_hash(self.missing_hash));
+		manifest.insert(
+			"tile_px".to_string(),
+			Value::Integer(pyramid.tile_px as i64),
+		);
+		manifest.insert(
+			"gutter_px".to_string(),
+			Value::Integer(pyramid.gutter_px as i64),
+		);
+		manifest.insert(
+			"max_level".to_string(),
+			Value::Integer(pyramid.max_level as i64),
+		);
+		manifest.insert("bounds".to_string(), Value::Table(bounds));
+
+		std::fs::write(Self::path(dir), Value::Table(manifest).to_string())
+			.map_err(DatabaseError::IOError)
+	}
+
+	/// Whether the tiles recorded in self were made from the same inputs as other's. How
+	/// deep the pyramid goes depends on the atlases' images, so isn't compared.
+	pub fn same_source_as(&self, other: &PyramidManifest) -> bool {
+		self.source_hash == other.source_hash
+			&& self.missing_hash == other.missing_hash
+			&& self.pyramid.tile_px == other.pyramid.tile_px
+			&& self.pyramid.gutter_px == other.pyramid.gutter_px
+			&& self.pyramid.bounds == other.pyramid.bounds
+	}
+}
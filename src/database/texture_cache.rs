@@ -0,0 +1,206 @@
+use super::filedb::FileDatabase;
+use crate::entities::{MapBounds, MapPieceKey};
+use crate::world::DatabaseError;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use toml::Value;
+
+const MANIFEST_FILENAME: &str = "manifest.toml";
+
+fn manifest_key(key: &MapPieceKey) -> String {
+	format!("{:+04}_{:+04}", key.min_long, key.min_lat)
+}
+
+pub fn texture_file_name(key: &MapPieceKey) -> String {
+	format!("{}.png", manifest_key(key))
+}
+
+fn parse_manifest_key(manifest_key: &str) -> Option<MapPieceKey> {
+	let mut parts = manifest_key.split('_');
+	let min_long = parts.next()?.parse::<i32>().ok()?;
+	let min_lat = parts.next()?.parse::<i32>().ok()?;
+	if parts.next().is_some() {
+		return None;
+	}
+	Some(MapPieceKey { min_lat, min_long })
+}
+
+// Hashes are stored as hex strings, because TOML integers are signed
+fn format_hash(hash: u64) -> Value {
+	Value::String(format!("{:016x}", hash))
+}
+
+fn parse_hash(value: &Value) -> Result<u64, DatabaseError> {
+	let hash = value.as_str().ok_or(DatabaseError::ConfigValueWrongType)?;
+	u64::from_str_radix(hash, 16).map_err(|_| DatabaseError::ConfigValueWrongType)
+}
+
+/// Records what the textures in a tex_<N> dir were generated from, so that they can be
+/// used instead of decoding the large image and generating them all again.
+#[derive(Debug)]
+pub struct TextureManifest {
+	pub source_hash: u64, // Of the atlases' image files, bounds, projections and priorities
+	pub missing_hash: u64, // Of the missing texture file
+	pub bounds: MapBounds, // Covering every atlas
+	pub texture_size_deg: u32,
+	pub textures: HashMap<MapPieceKey, u64>, // From Map::texture_hash
+}
+impl TextureManifest {
+	pub fn new(
+		source_hash: u64,
+		missing_hash: u64,
+		bounds: MapBounds,
+		texture_size_deg: u32,
+	) -> TextureManifest {
+		TextureManifest {
+			source_hash,
+			missing_hash,
+			bounds,
+			texture_size_deg,
+			textures: HashMap::new(),
+		}
+	}
+
+	fn path(dir: &Path) -> PathBuf {
+		dir.join(MANIFEST_FILENAME)
+	}
+
+	pub fn read(dir: &Path) -> Result<TextureManifest, DatabaseError> {
+		let mut file = File::open(Self::path(dir)).map_err(DatabaseError::IOError)?;
+		let mut manifest = String::new();
+		file.read_to_string(&mut manifest)
+			.map_err(DatabaseError::IOError)?;
+		let value = manifest
+			.parse::<Value>()
+			.map_err(|e| DatabaseError::ConfigParseError(Box::new(e)))?;
+
+		let bounds = FileDatabase::value_get(&value, "bounds")?;
+		let mut textures = HashMap::new();
+		let textures_table = FileDatabase::value_get(&value, "textures")?
+			.as_table()
+			.ok_or(DatabaseError::ConfigValueWrongType)?;
+		for (key, hash) in textures_table.iter() {
+			let key = parse_manifest_key(key).ok_or(DatabaseError::ConfigValueWrongType)?;
+			textures.insert(key, parse_hash(hash)?);
+		}
+
+		Ok(TextureManifest {
+			source_hash: parse_hash(FileDatabase::value_get(&value, "source_hash")?)?,
+			missing_hash: parse_hash(FileDatabase::value_get(&value, "missing_hash")?)?,
+			bounds: MapBounds::new(
+				FileDatabase::value_get_float(bounds, "min_lat")?,
+				FileDatabase::value_get_float(bounds, "max_lat")?,
+				FileDatabase::value_get_float(bounds, "min_long")?,
+				FileDatabase::value_get_float(bounds, "max_long")?,
+			),
+			texture_size_deg: FileDatabase::value_get_int(&value, "texture_size_deg")? as u32,
+			textures,
+		})
+	}
+
+	pub fn write(&self, dir: &Path) -> Result<(), DatabaseError> {
+		let mut bounds = toml::value::Table::new();
+		bounds.insert("min_lat".to_string(), Value::Float(self.bounds.min_lat));
+		bounds.insert("max_lat".to_string(), Value::Float(self.bounds.max_lat));
+		bounds.insert("min_long".to_string(), Value::Float(self.bounds.min_long));
+		bounds.insert("max_long".to_string(), Value::Float(self.bounds.max_long));
+
+		let textures: toml::value::Table = self
+			.textures
+			.iter()
+			.map(|(key, &hash)| (manifest_key(key), format_hash(hash)))
+			.collect();
+
+		let mut manifest = toml::value::Table::new();
+		manifest.insert("source_hash".to_string(), format_hash(self.source_hash));
+		manifest.insert("missing_hash".to_string(), format_hash(self.missing_hash));
+		manifest.insert(
+			"texture_size_deg".to_string(),
+			Value::Integer(self.texture_size_deg as i64),
+		);
+		manifest.insert("bounds".to_string(), Value::Table(bounds));
+		manifest.insert("textures".to_string(), Value::Table(textures));
+
+		std::fs::write(Self::path(dir), Value::Table(manifest).to_string())
+			.map_err(DatabaseError::IOError)
+	}
+
+	/// Whether the textures recorded in self were made from the same inputs as other's
+	pub fn same_source_as(&self, other: &TextureManifest) -> bool {
+		self.source_hash == other.source_hash
+			&& self.missing_hash == other.missing_hash
+			&& self.bounds == other.bounds
+			&& self.texture_size_deg == other.texture_size_deg
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn manifest() -> TextureManifest {
+		let mut manifest = TextureManifest::new(
+			0x0123_4567_89ab_cdef,
+			0xfedc_ba98_7654_3210,
+			MapBounds::new(-10.5_f64, 12_f64, -4_f64, 4.25_f64),
+			2,
+		);
+		for &(min_lat, min_long, hash) in &[(-12, -4, 1_u64), (10, 2, u64::MAX)] {
+			manifest
+				.textures
+				.insert(MapPieceKey { min_lat, min_long }, hash);
+		}
+		manifest
+	}
+
+	#[test]
+	fn manifest_round_trips() {
+		let dir = std::env::temp_dir().join(format!("hm_world_manifest_{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let manifest = manifest();
+		manifest.write(&dir).unwrap();
+		let read = TextureManifest::read(&dir).unwrap();
+		let _ = std::fs::remove_dir_all(&dir);
+
+		assert!(read.same_source_as(&manifest));
+		assert_eq!(read.textures, manifest.textures);
+		assert_eq!(
+			texture_file_name(&MapPieceKey {
+				min_lat: -12,
+				min_long: 2
+			}),
+			"+002_-012.png"
+		);
+	}
+
+	#[test]
+	fn changed_sources_invalidate_the_manifest() {
+		let old = manifest();
+		assert!(manifest().same_source_as(&old));
+
+		let mut new = manifest();
+		new.source_hash += 1;
+		assert!(!new.same_source_as(&old));
+
+		let mut new = manifest();
+		new.missing_hash += 1;
+		assert!(!new.same_source_as(&old));
+
+		let mut new = manifest();
+		new.texture_size_deg = 1;
+		assert!(!new.same_source_as(&old));
+
+		let mut new = manifest();
+		new.bounds.max_long += 1_f64;
+		assert!(!new.same_source_as(&old));
+
+		// The textures' own hashes are checked one by one, so don't invalidate the rest
+		let mut new = manifest();
+		new.textures.clear();
+		assert!(new.same_source_as(&old));
+	}
+}
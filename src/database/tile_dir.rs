@@ -0,0 +1,122 @@
+use super::filedb::get_image_from_file;
+use crate::entities::{MapPieceKey, TilePattern};
+use crate::world::DatabaseError;
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use image::{DynamicImage, FilterType, GenericImageView};
+
+/// Loads every tile in dir whose file name matches pattern, like the ones split-atlas
+/// writes. Tiles that aren't the same size as missing_image are resized to match, so they
+/// can all go in one texture array.
+pub fn load_tile_dir(
+	dir: &Path,
+	pattern: &TilePattern,
+	tile_size_deg: u32,
+	missing_image: &DynamicImage,
+) -> Result<HashMap<MapPieceKey, DynamicImage>, DatabaseError> {
+	let (width, height) = missing_image.dimensions();
+	let mut tiles = HashMap::new();
+
+	for entry in std::fs::read_dir(dir).map_err(DatabaseError::IOError)? {
+		let path = entry.map_err(DatabaseError::IOError)?.path();
+		let key = match path
+			.file_name()
+			.and_then(|file_name| file_name.to_str())
+			.and_then(|file_name| pattern.parse_file_name(file_name, tile_size_deg))
+		{
+			Some(key) => key,
+			None => continue,
+		};
+
+		let mut tile = get_image_from_file(&path.to_string_lossy())?;
+		if tile.dimensions() != (width, height) {
+			warn!(
+				"Tile {:?} is {:?}, resizing to {:?}",
+				path,
+				tile.dimensions(),
+				(width, height)
+			);
+			tile = tile.resize_exact(width, height, FilterType::Lanczos3);
+		}
+		tiles.insert(key, tile);
+	}
+
+	if tiles.is_empty() {
+		warn!("No tiles in {:?} are named like {:?}", dir, pattern);
+	}
+	info!("Loaded {} tiles from {:?}", tiles.len(), dir);
+	Ok(tiles)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use image::{Rgba, RgbaImage};
+	use std::path::PathBuf;
+
+	fn scratch_dir(name: &str) -> PathBuf {
+		let dir = std::env::temp_dir().join(format!("hm_world_{}_{}", name, std::process::id()));
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir_all(&dir).unwrap();
+		dir
+	}
+
+	fn missing_image() -> DynamicImage {
+		DynamicImage::ImageRgba8(RgbaImage::new(4, 4))
+	}
+
+	#[test]
+	fn only_tiles_named_like_the_pattern_are_loaded() {
+		let dir = scratch_dir("tile_dir");
+		for &(file_name, size) in &[
+			("tellene_12_-2.png", 4),
+			("tellene_11_-2.png", 8),
+			("tellene_twelve_-2.png", 4),
+			("tellene_12_-2.png.bak", 4),
+			("other_12_-2.png", 4),
+		] {
+			RgbaImage::from_pixel(size, size, Rgba([10, 20, 30, 255]))
+				.save_with_format(dir.join(file_name), image::ImageFormat::PNG)
+				.unwrap();
+		}
+		std::fs::write(dir.join("README.txt"), "Not a tile").unwrap();
+
+		let pattern = TilePattern::new(TilePattern::LEGACY).unwrap();
+		let tiles = load_tile_dir(&dir, &pattern, 1, &missing_image()).unwrap();
+		let _ = std::fs::remove_dir_all(&dir);
+
+		let mut keys: Vec<&MapPieceKey> = tiles.keys().collect();
+		keys.sort();
+		assert_eq!(
+			keys,
+			[
+				&MapPieceKey {
+					min_lat: 10,
+					min_long: -2
+				},
+				&MapPieceKey {
+					min_lat: 11,
+					min_long: -2
+				},
+			]
+		);
+		// Resized to match the missing image
+		assert!(tiles.values().all(|tile| tile.dimensions() == (4, 4)));
+	}
+
+	#[test]
+	fn missing_dir_is_an_error() {
+		let dir = std::env::temp_dir().join(format!("hm_world_no_tiles_{}", std::process::id()));
+		let pattern = TilePattern::new(TilePattern::LEGACY).unwrap();
+		match load_tile_dir(&dir, &pattern, 1, &missing_image()) {
+			Err(DatabaseError::IOError(_)) => (),
+			other => panic!(
+				"expected an IO error, got {:?}",
+				other.map(|tiles| tiles.len())
+			),
+		}
+	}
+}
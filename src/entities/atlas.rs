@@ -0,0 +1,496 @@
+use image::imageops;
+use image::{DynamicImage, FilterType, GenericImage, GenericImageView, Pixel, Rgba};
+
+use std::hash::Hash;
+use std::sync::Arc;
+
+use super::content_hash::ContentHasher;
+use super::map::{MapBounds, MapPieceKey};
+use super::projection::Projection;
+
+// Samples taken along each edge of an image when finding the lat/long it covers
+const COVERAGE_EDGE_SAMPLES: u32 = 64;
+
+// Samples taken along each edge of a texture when finding the part of an image it's made from
+const SOURCE_EDGE_SAMPLES: u32 = 16;
+
+// Most samples taken along each axis of a texture's pixel when reprojecting an image into it
+const MAX_SAMPLES_PER_PX: u32 = 8;
+
+// Finds the lat/long covered by the part of projection inside extent, by unprojecting points
+// along its edges
+fn find_coverage(projection: &dyn Projection, extent: (f64, f64, f64, f64)) -> MapBounds {
+	let (min_x, min_y, max_x, max_y) = extent;
+	let mut coverage = MapBounds::new(
+		f64::INFINITY,
+		f64::NEG_INFINITY,
+		f64::INFINITY,
+		f64::NEG_INFINITY,
+	);
+	for i in 0..=COVERAGE_EDGE_SAMPLES {
+		let t = i as f64 / COVERAGE_EDGE_SAMPLES as f64;
+		let x = min_x + t * (max_x - min_x);
+		let y = min_y + t * (max_y - min_y);
+		for &(x, y) in &[(x, min_y), (x, max_y), (min_x, y), (max_x, y)] {
+			if let Some((lat, long)) = projection.unproject(x, y) {
+				coverage.min_lat = coverage.min_lat.min(lat);
+				coverage.max_lat = coverage.max_lat.max(lat);
+				coverage.min_long = coverage.min_long.min(long);
+				coverage.max_long = coverage.max_long.max(long);
+			}
+		}
+	}
+
+	// A pole inside the image has every longitude around it
+	for &pole_lat in &[90_f64, -90_f64] {
+		if let Some((x, y)) = projection.project(pole_lat, 0_f64) {
+			if x >= min_x.min(max_x)
+				&& x <= min_x.max(max_x)
+				&& y >= min_y.min(max_y)
+				&& y <= min_y.max(max_y)
+			{
+				coverage.min_lat = coverage.min_lat.min(pole_lat);
+				coverage.max_lat = coverage.max_lat.max(pole_lat);
+				coverage.min_long = -180_f64;
+				coverage.max_long = 180_f64;
+			}
+		}
+	}
+	coverage
+}
+
+// The part of the source image a texture is cut from, in image-space pixels. Offsets are
+// how far past each edge of the image the texture would extend, if the image were bigger.
+#[derive(Hash, Debug)]
+struct SourceRect {
+	min_x: u32,
+	min_y: u32,
+	width: u32,
+	height: u32,
+	offset_min_x: u32,
+	offset_max_x: u32,
+	offset_min_y: u32,
+	offset_max_y: u32,
+}
+
+/// One source image for a map, covering part of the world
+#[derive(Clone)]
+pub struct Atlas {
+	pub image: Option<DynamicImage>, // Only loaded when textures need generating
+	// For equirectangular images, the lat/long of the image's edges. Otherwise, the lat/long
+	// of its bottom left (min) and top right (max) corners.
+	bounds: MapBounds,
+	projection: Arc<dyn Projection>,
+	extent: (f64, f64, f64, f64), // Projected min x, min y, max x, max y of the image
+	coverage: MapBounds,          // Lat/long of everything the image shows
+	// Where atlases overlap, the one with the highest priority is drawn on top. Between
+	// atlases of the same priority, the most detailed one wins.
+	pub priority: i32,
+}
+impl Atlas {
+	/// None if projection can't show the corners given by bounds
+	pub fn new(
+		image: Option<DynamicImage>,
+		bounds: MapBounds,
+		projection: Arc<dyn Projection>,
+		priority: i32,
+	) -> Option<Atlas> {
+		let (min_x, min_y) = projection.project(bounds.min_lat, bounds.min_long)?;
+		let (max_x, max_y) = projection.project(bounds.max_lat, bounds.max_long)?;
+		let extent = (min_x, min_y, max_x, max_y);
+		let coverage = if projection.is_equirectangular() {
+			bounds.clone()
+		} else {
+			find_coverage(&*projection, extent)
+		};
+
+		Some(Atlas {
+			image,
+			bounds,
+			projection,
+			extent,
+			coverage,
+			priority,
+		})
+	}
+
+	pub fn bounds(&self) -> &MapBounds {
+		&self.bounds
+	}
+
+	pub fn projection(&self) -> &dyn Projection {
+		&*self.projection
+	}
+
+	/// The smallest lat/long bounds containing everything the atlas shows
+	pub fn coverage(&self) -> &MapBounds {
+		&self.coverage
+	}
+
+	/// Image pixels per degree of latitude, None if the image isn't loaded
+	pub fn px_per_deg(&self) -> Option<f64> {
+		let image = self.image.as_ref()?;
+		Some(image.height() as f64 / (self.coverage.max_lat - self.coverage.min_lat))
+	}
+
+	fn get_img_x_by_long(&self, image: &DynamicImage, long_deg: f64) -> i32 {
+		let px_per_deg_long =
+			(image.width() as f64 - 1_f64) / (self.bounds.max_long - self.bounds.min_long);
+		((long_deg - self.bounds.min_long) * px_per_deg_long).round() as i32
+	}
+
+	fn get_img_y_by_lat(&self, image: &DynamicImage, lat_deg: f64) -> i32 {
+		let px_per_deg_lat =
+			(image.height() as f64 - 1_f64) / (self.bounds.max_lat - self.bounds.min_lat);
+		image.height() as i32
+			- 1_i32 - ((lat_deg - self.bounds.min_lat) * px_per_deg_lat).round() as i32
+	}
+
+	/// Keys of every texture needed to cover the atlas' bounds
+	pub fn texture_keys(&self, tex_size_deg: u32) -> Vec<MapPieceKey> {
+		// "Increments" of tex_size_deg, set to hold whole image.
+		// E.g. if the atlas goes from long -13.7 to 12.0, with tex_size of 2 deg,
+		// Min long incr would be -7, max would be 6
+		let min_long_incr = (self.coverage.min_long / tex_size_deg as f64).floor() as i32;
+		let max_long_incr = (self.coverage.max_long / tex_size_deg as f64).ceil() as i32 - 1_i32;
+		let min_lat_incr = (self.coverage.min_lat / tex_size_deg as f64).floor() as i32;
+		let max_lat_incr = (self.coverage.max_lat / tex_size_deg as f64).ceil() as i32 - 1_i32;
+
+		let mut keys = Vec::new();
+		for long_incr in min_long_incr..=max_long_incr {
+			for lat_incr in min_lat_incr..=max_lat_incr {
+				keys.push(MapPieceKey {
+					min_lat: lat_incr * tex_size_deg as i32,
+					min_long: long_incr * tex_size_deg as i32,
+				});
+			}
+		}
+		keys
+	}
+
+	/// Whether any of the atlas falls inside a texture covering tex_bounds
+	pub fn covers(&self, tex_bounds: &MapBounds) -> bool {
+		self.coverage.intersects(tex_bounds)
+	}
+
+	fn get_source_rect(&self, image: &DynamicImage, tex_bounds: &MapBounds) -> SourceRect {
+		// Get x dimensions (in image-space pixels) for the subimage we'll grab
+		let subimage_min_long = tex_bounds.min_long;
+		// If min x coord would be < 0, set to 0 and store the offset
+		let (subimage_min_x, offset_min_x) = {
+			let min = self.get_img_x_by_long(image, subimage_min_long);
+			if min < 0 {
+				(0_u32, (-min) as u32)
+			} else {
+				(min as u32, 0_u32)
+			}
+		};
+
+		let subimage_max_long = tex_bounds.max_long;
+		// Max coords non-inclusive
+		// If max x coord would be > width, set to width and store the offset
+		let (subimage_max_x, offset_max_x) = {
+			let max = self.get_img_x_by_long(image, subimage_max_long);
+			if max > image.width() as i32 {
+				(image.width(), (max as u32 - image.width()))
+			} else {
+				(max as u32, 0_u32)
+			}
+		};
+
+		// Get y dimensions ...
+		let subimage_min_lat = tex_bounds.min_lat;
+		// If min y coord would be < 0, set to 0 and store the offset
+		// Also, min lat -> max y, because image coords are weird!
+		let (subimage_max_y, offset_max_y) = {
+			let max = self.get_img_y_by_lat(image, subimage_min_lat);
+			if max > image.height() as i32 {
+				(image.height(), (max as u32 - image.height()))
+			} else {
+				(max as u32, 0_u32)
+			}
+		};
+
+		let subimage_max_lat = tex_bounds.max_lat;
+		// If max y coord would be > height, set to height and store the offset
+		// Also, max lat -> min y, because image coords are weird!
+		let (subimage_min_y, offset_min_y) = {
+			let min = self.get_img_y_by_lat(image, subimage_max_lat);
+			if min < 0 {
+				(0_u32, (-min) as u32)
+			} else {
+				(min as u32, 0_u32)
+			}
+		};
+
+		SourceRect {
+			min_x: subimage_min_x,
+			min_y: subimage_min_y,
+			width: subimage_max_x - subimage_min_x,
+			height: subimage_max_y - subimage_min_y,
+			offset_min_x,
+			offset_max_x,
+			offset_min_y,
+			offset_max_y,
+		}
+	}
+
+	// Finds the bounding rect of the part of image a texture covering tex_bounds is made
+	// from, by projecting points along the texture's edges into it
+	fn get_reprojected_source_rect(
+		&self,
+		image: &DynamicImage,
+		tex_bounds: &MapBounds,
+	) -> SourceRect {
+		let (mut min_x, mut min_y) = (f64::INFINITY, f64::INFINITY);
+		let (mut max_x, mut max_y) = (f64::NEG_INFINITY, f64::NEG_INFINITY);
+		let MapBounds {
+			min_lat,
+			max_lat,
+			min_long,
+			max_long,
+		} = *tex_bounds;
+		for i in 0..=SOURCE_EDGE_SAMPLES {
+			let t = i as f64 / SOURCE_EDGE_SAMPLES as f64;
+			let lat = min_lat + t * (max_lat - min_lat);
+			let long = min_long + t * (max_long - min_long);
+			for &(lat, long) in &[
+				(lat, min_long),
+				(lat, max_long),
+				(min_lat, long),
+				(max_lat, long),
+			] {
+				if let Some((x, y)) = self.get_img_coords(image, lat, long) {
+					min_x = min_x.min(x);
+					min_y = min_y.min(y);
+					max_x = max_x.max(x);
+					max_y = max_y.max(y);
+				}
+			}
+		}
+
+		// Pad for the pixels bilinear sampling reads either side, then clamp to the image
+		let clamp = |v: f64, max: u32| v.max(0_f64).min(max as f64) as u32;
+		let (min_x, max_x) = (
+			clamp(min_x.floor() - 1_f64, image.width()),
+			clamp(max_x.ceil() + 2_f64, image.width()),
+		);
+		let (min_y, max_y) = (
+			clamp(min_y.floor() - 1_f64, image.height()),
+			clamp(max_y.ceil() + 2_f64, image.height()),
+		);
+		SourceRect {
+			min_x,
+			min_y,
+			width: max_x.saturating_sub(min_x),
+			height: max_y.saturating_sub(min_y),
+			offset_min_x: 0_u32,
+			offset_max_x: 0_u32,
+			offset_min_y: 0_u32,
+			offset_max_y: 0_u32,
+		}
+	}
+
+	// Projects lat/long into image-space pixels, where pixel centres are at whole coords. None
+	// if the projection can't show lat/long, but may be outside the image.
+	fn get_img_coords(
+		&self,
+		image: &DynamicImage,
+		lat_deg: f64,
+		long_deg: f64,
+	) -> Option<(f64, f64)> {
+		let (x, y) = self.projection.project(lat_deg, long_deg)?;
+		let (min_x, min_y, max_x, max_y) = self.extent;
+		Some((
+			(x - min_x) / (max_x - min_x) * image.width() as f64 - 0.5_f64,
+			(max_y - y) / (max_y - min_y) * image.height() as f64 - 0.5_f64,
+		))
+	}
+
+	// Bilinearly samples image at lat/long, as premultiplied RGBA. None if lat/long is outside
+	// the image.
+	fn sample(&self, image: &DynamicImage, lat_deg: f64, long_deg: f64) -> Option<[f64; 4]> {
+		let (x, y) = self.get_img_coords(image, lat_deg, long_deg)?;
+		let (max_x, max_y) = (
+			image.width() as f64 - 0.5_f64,
+			image.height() as f64 - 0.5_f64,
+		);
+		if !(x >= -0.5_f64 && x < max_x && y >= -0.5_f64 && y < max_y) {
+			return None;
+		}
+
+		// Edge pixels are stretched out to the image's edges
+		let x = x.max(0_f64).min(image.width() as f64 - 1_f64);
+		let y = y.max(0_f64).min(image.height() as f64 - 1_f64);
+		let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+		let (x1, y1) = (
+			(x0 + 1).min(image.width() - 1),
+			(y0 + 1).min(image.height() - 1),
+		);
+		let (fx, fy) = (x - x0 as f64, y - y0 as f64);
+
+		let mut sampled = [0_f64; 4];
+		for &(px, py, weight) in &[
+			(x0, y0, (1_f64 - fx) * (1_f64 - fy)),
+			(x1, y0, fx * (1_f64 - fy)),
+			(x0, y1, (1_f64 - fx) * fy),
+			(x1, y1, fx * fy),
+		] {
+			let pixel = image.get_pixel(px, py).0;
+			let alpha = pixel[3] as f64 / 255_f64;
+			for channel in 0..3 {
+				sampled[channel] += weight * alpha * pixel[channel] as f64;
+			}
+			sampled[3] += weight * alpha;
+		}
+		Some(sampled)
+	}
+
+	// How many samples to take along each axis of a texture pixel, so that every image pixel
+	// it covers contributes
+	fn samples_per_px(
+		&self,
+		image: &DynamicImage,
+		texture: &DynamicImage,
+		tex_bounds: &MapBounds,
+	) -> u32 {
+		let deg_per_px = ((tex_bounds.max_lat - tex_bounds.min_lat) / texture.height() as f64)
+			.min((tex_bounds.max_long - tex_bounds.min_long) / texture.width() as f64);
+		let centre_lat = (tex_bounds.min_lat + tex_bounds.max_lat) / 2_f64;
+		let centre_long = (tex_bounds.min_long + tex_bounds.max_long) / 2_f64;
+
+		let img_px_per_px = match (
+			self.get_img_coords(image, centre_lat, centre_long),
+			self.get_img_coords(image, centre_lat + deg_per_px, centre_long),
+			self.get_img_coords(image, centre_lat, centre_long + deg_per_px),
+		) {
+			(Some(centre), Some(north), Some(east)) => {
+				let dist =
+					|(x, y): (f64, f64)| ((x - centre.0).powi(2) + (y - centre.1).powi(2)).sqrt();
+				dist(north).max(dist(east))
+			}
+			_ => 1_f64,
+		};
+		(img_px_per_px.ceil() as u32).clamp(1, MAX_SAMPLES_PER_PX)
+	}
+
+	// Draws the atlas onto texture by projecting each of texture's pixels into image,
+	// averaging several samples per pixel where image is more detailed than texture
+	fn reproject_texture(
+		&self,
+		image: &DynamicImage,
+		texture: &mut DynamicImage,
+		tex_bounds: &MapBounds,
+	) {
+		let samples = self.samples_per_px(image, texture, tex_bounds);
+		let (tex_width, tex_height) = texture.dimensions();
+		let deg_per_sample_x =
+			(tex_bounds.max_long - tex_bounds.min_long) / (tex_width * samples) as f64;
+		let deg_per_sample_y =
+			(tex_bounds.max_lat - tex_bounds.min_lat) / (tex_height * samples) as f64;
+
+		for tex_y in 0..tex_height {
+			for tex_x in 0..tex_width {
+				let mut sum = [0_f64; 4];
+				for sample_y in 0..samples {
+					let lat = tex_bounds.max_lat
+						- ((tex_y * samples + sample_y) as f64 + 0.5_f64) * deg_per_sample_y;
+					for sample_x in 0..samples {
+						let long = tex_bounds.min_long
+							+ ((tex_x * samples + sample_x) as f64 + 0.5_f64) * deg_per_sample_x;
+						if let Some(sampled) = self.sample(image, lat, long) {
+							for channel in 0..4 {
+								sum[channel] += sampled[channel];
+							}
+						}
+					}
+				}
+				if sum[3] <= 0_f64 {
+					continue;
+				}
+
+				// Samples outside the image count as transparent, so its edges blend in
+				let alpha = sum[3] / (samples * samples) as f64;
+				let mut pixel = Rgba([0_u8; 4]);
+				for channel in 0..3 {
+					pixel.0[channel] = (sum[channel] / sum[3]).round().clamp(0_f64, 255_f64) as u8;
+				}
+				pixel.0[3] = (alpha * 255_f64).round().min(255_f64) as u8;
+				let mut blended = texture.get_pixel(tex_x, tex_y);
+				blended.blend(&pixel);
+				texture.put_pixel(tex_x, tex_y, blended);
+			}
+		}
+	}
+
+	/// Hashes the part of the atlas a texture covering tex_bounds is made from. None if the
+	/// image isn't loaded.
+	pub fn hash_texture_source(
+		&self,
+		hasher: &mut ContentHasher,
+		tex_bounds: &MapBounds,
+	) -> Option<()> {
+		let image = self.image.as_ref()?;
+		let rect = if self.projection.is_equirectangular() {
+			self.get_source_rect(image, tex_bounds)
+		} else {
+			// The rect alone doesn't say how the image is laid over the texture
+			format!("{:?}{:?}", self.projection, self.bounds).hash(hasher);
+			self.get_reprojected_source_rect(image, tex_bounds)
+		};
+
+		rect.hash(hasher);
+		self.priority.hash(hasher);
+		hasher.write_image(&image.view(rect.min_x, rect.min_y, rect.width, rect.height));
+		Some(())
+	}
+
+	/// Draws the part of the atlas inside tex_bounds onto texture, scaled to texture's size.
+	/// Returns false if the image isn't loaded.
+	pub fn overlay_texture(&self, texture: &mut DynamicImage, tex_bounds: &MapBounds) -> bool {
+		let image = match self.image {
+			Some(ref image) => image,
+			None => return false,
+		};
+		if !self.projection.is_equirectangular() {
+			self.reproject_texture(image, texture, tex_bounds);
+			return true;
+		}
+
+		let rect = self.get_source_rect(image, tex_bounds);
+		if rect.width == 0 || rect.height == 0 {
+			return true;
+		}
+
+		// Grab a view into image based on dimensions above
+		debug!(
+			"Subimage for {:?}: minx={}+{}, miny={}+{}, w={}, h={}",
+			tex_bounds,
+			rect.min_x,
+			rect.offset_min_x,
+			rect.min_y,
+			rect.offset_min_y,
+			rect.width,
+			rect.height
+		);
+		let subimage = image.view(rect.min_x, rect.min_y, rect.width, rect.height);
+
+		// Get the multipliers needed to bring subimage up to texture's size
+		let resize_x_mult =
+			texture.width() as f64 / (rect.width + rect.offset_min_x + rect.offset_max_x) as f64;
+		let resize_y_mult =
+			texture.height() as f64 / (rect.height + rect.offset_min_y + rect.offset_max_y) as f64;
+
+		// Resize subimage
+		let resize_to_x = (rect.width as f64 * resize_x_mult).round() as u32;
+		let resize_to_y = (rect.height as f64 * resize_y_mult).round() as u32;
+		let resized_subimage =
+			imageops::resize(&subimage, resize_to_x, resize_to_y, FilterType::Lanczos3);
+
+		// Get offsets of subimage and overlay
+		let tex_x_offset = (rect.offset_min_x as f64 * resize_x_mult).round() as u32;
+		let tex_y_offset = (rect.offset_min_y as f64 * resize_y_mult).round() as u32;
+		imageops::overlay(texture, &resized_subimage, tex_x_offset, tex_y_offset);
+		true
+	}
+}
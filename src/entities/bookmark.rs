@@ -0,0 +1,25 @@
+use super::coords::LatLong;
+
+use cgmath::Rad;
+
+/// Where the camera's looking from, so it can be put back there. Distances are in radii of
+/// the world's sphere, so they don't depend on how big it's drawn.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CameraView {
+	pub target: LatLong, // The point on the surface the camera's over, or orbiting
+	pub distance: f64,   // From target
+	pub orbit: bool,     // Orbiting target, rather than the whole globe from straight over it
+	// Which way the camera's looking, clockwise from north, and how far it's tilted over
+	// from looking straight down. Only orbiting views have them.
+	pub heading: Rad<f64>,
+	pub tilt: Rad<f64>,
+}
+
+/// A camera view the user named, to go back to. Bookmarks with a slot are set and gone to
+/// with that slot's keys.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bookmark {
+	pub name: String,
+	pub slot: Option<u32>,
+	pub view: CameraView,
+}
@@ -1,56 +1,449 @@
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, Write};
 
-use cgmath::Rad;
+use cgmath::{Deg, Rad};
 
 use super::coords::LatLong;
 
+#[derive(Clone)]
 pub struct City {
 	pub name: String,
-	pub population: u32,
+	pub population: Option<u32>, // None if the city is in ruins
 	pub country: String,
+	pub page: Option<u32>, // Sourcebook page the city is described on, if known
 	pub coords: LatLong,
 }
 impl std::fmt::Debug for City {
 	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
 		write!(
 			f,
-			"{}: pop {}, country {}, lat {:?}, long {:?}",
-			self.name, self.population, self.country, self.coords.lat, self.coords.long
+			"{}: pop {:?}, country {}, page {:?}, lat {:?}, long {:?}",
+			self.name, self.population, self.country, self.page, self.coords.lat, self.coords.long
 		)?;
 		Ok(())
 	}
 }
 
-pub fn read_cities_file(file_name: &str) -> Vec<City> {
+/// How big a settlement is, from its population. The sizes follow the Dungeon Master's
+/// Guide's, with hamlets counted as thorps and small and large towns as towns.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum SettlementTier {
+	Ruins,
+	Thorp,   // Up to 400 people
+	Village, // Up to 900
+	Town,    // Up to 5,000
+	City,
+}
+impl City {
+	pub fn tier(&self) -> SettlementTier {
+		match self.population {
+			None => SettlementTier::Ruins,
+			Some(population) if population <= 400 => SettlementTier::Thorp,
+			Some(population) if population <= 900 => SettlementTier::Village,
+			Some(population) if population <= 5000 => SettlementTier::Town,
+			Some(_) => SettlementTier::City,
+		}
+	}
+}
+
+/// Something wrong with a single line of a cities file. Line numbers start at 1.
+#[derive(Debug)]
+pub enum CityParseError {
+	IOError(usize, std::io::Error),
+	MissingHeaderColumn(&'static str),
+	MissingColumn(usize, &'static str),
+	BadValue(usize, &'static str, String),
+}
+impl fmt::Display for CityParseError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			CityParseError::IOError(line, e) => write!(f, "line {}: {}", line, e),
+			CityParseError::MissingHeaderColumn(column) => {
+				write!(f, "header is missing column {}", column)
+			}
+			CityParseError::MissingColumn(line, column) => {
+				write!(f, "line {}: missing column {}", line, column)
+			}
+			CityParseError::BadValue(line, column, value) => {
+				write!(
+					f,
+					"line {}: bad value {:?} for column {}",
+					line, value, column
+				)
+			}
+		}
+	}
+}
+
+const POPULATION_RUINS: &str = "RUINS";
+
+// Column names for files with a header, matched case-insensitively
+const COL_NAME: &str = "Town";
+const COL_POPULATION: &str = "Population";
+const COL_COUNTRY: &str = "Country";
+const COL_PAGE: &str = "Page";
+const COL_LAT_DEG: &str = "Lat_Deg";
+const COL_LAT_MIN: &str = "Lat_Min";
+const COL_LAT_DIR: &str = "Lat_Dir";
+const COL_LONG_DEG: &str = "Lon_Deg";
+const COL_LONG_MIN: &str = "Lon_Min";
+const COL_LONG_DIR: &str = "Lon_Dir";
+
+// Column order used when writing files
+const HEADER_COLUMNS: [&str; 10] = [
+	COL_NAME,
+	COL_POPULATION,
+	COL_COUNTRY,
+	COL_PAGE,
+	COL_LAT_DEG,
+	COL_LAT_MIN,
+	COL_LAT_DIR,
+	COL_LONG_DEG,
+	COL_LONG_MIN,
+	COL_LONG_DIR,
+];
+
+// Lat/long columns for headerless files, which are in radians
+const COL_LAT_RAD: &str = "Lat";
+const COL_LONG_RAD: &str = "Long";
+
+// Column order for headerless files
+const HEADERLESS_COLUMNS: [&str; 5] = [
+	COL_NAME,
+	COL_POPULATION,
+	COL_COUNTRY,
+	COL_LAT_RAD,
+	COL_LONG_RAD,
+];
+
+/// The two layouts of cities file we know about
+enum CitiesLayout {
+	/// No header, columns are name, population, country, lat, long, with lat/long in radians
+	Headerless,
+	/// A header naming each column, with lat/long split into degrees, minutes and N/S/E/W
+	Header(HashMap<String, usize>),
+}
+
+/// Splits a line on commas, dropping the padding around each column
+fn split_columns(line: &str) -> Vec<&str> {
+	line.split(',').map(str::trim).collect()
+}
+
+fn is_header(columns: &[&str]) -> bool {
+	columns
+		.iter()
+		.any(|column| column.eq_ignore_ascii_case(COL_POPULATION))
+}
+
+struct LineParser<'a> {
+	line_num: usize,
+	columns: Vec<&'a str>,
+	layout: &'a CitiesLayout,
+}
+impl<'a> LineParser<'a> {
+	fn get(&self, column: &'static str) -> Result<&'a str, CityParseError> {
+		let index = match self.layout {
+			CitiesLayout::Headerless => HEADERLESS_COLUMNS.iter().position(|&c| c == column),
+			CitiesLayout::Header(indices) => indices.get(&column.to_lowercase()).cloned(),
+		}
+		.ok_or(CityParseError::MissingHeaderColumn(column))?;
+		self.columns
+			.get(index)
+			.cloned()
+			.ok_or(CityParseError::MissingColumn(self.line_num, column))
+	}
+
+	fn parse<T: std::str::FromStr>(
+		&self,
+		column: &'static str,
+		value: &str,
+	) -> Result<T, CityParseError> {
+		value
+			.parse::<T>()
+			.map_err(|_| CityParseError::BadValue(self.line_num, column, value.to_string()))
+	}
+
+	fn population(&self, column: &'static str) -> Result<Option<u32>, CityParseError> {
+		let value = self.get(column)?;
+		if value.eq_ignore_ascii_case(POPULATION_RUINS) {
+			Ok(None)
+		} else {
+			self.parse(column, value).map(Some)
+		}
+	}
+
+	// Degrees and minutes, made negative for S or W
+	fn deg_min_dir(
+		&self,
+		columns: (&'static str, &'static str, &'static str),
+		negative_dir: &str,
+		positive_dir: &str,
+	) -> Result<Deg<f64>, CityParseError> {
+		let (deg_col, min_col, dir_col) = columns;
+		let deg: f64 = self.parse(deg_col, self.get(deg_col)?)?;
+		let min: f64 = self.parse(min_col, self.get(min_col)?)?;
+		let dir = self.get(dir_col)?;
+
+		let sign = if dir.eq_ignore_ascii_case(positive_dir) {
+			1_f64
+		} else if dir.eq_ignore_ascii_case(negative_dir) {
+			-1_f64
+		} else {
+			return Err(CityParseError::BadValue(
+				self.line_num,
+				dir_col,
+				dir.to_string(),
+			));
+		};
+		Ok(Deg(sign * (deg + min / 60_f64)))
+	}
+
+	fn city(&self) -> Result<City, CityParseError> {
+		let (page, coords) = match self.layout {
+			CitiesLayout::Headerless => {
+				let lat = Rad(self.parse(COL_LAT_RAD, self.get(COL_LAT_RAD)?)?);
+				let long = Rad(self.parse(COL_LONG_RAD, self.get(COL_LONG_RAD)?)?);
+				(None, LatLong::new(lat, long))
+			}
+			CitiesLayout::Header(indices) => {
+				// Page is optional, either as a whole column or for a single city
+				let page = if indices.contains_key(&COL_PAGE.to_lowercase()) {
+					match self.get(COL_PAGE)? {
+						"" => None,
+						page => Some(self.parse(COL_PAGE, page)?),
+					}
+				} else {
+					None
+				};
+				let lat = self.deg_min_dir((COL_LAT_DEG, COL_LAT_MIN, COL_LAT_DIR), "S", "N")?;
+				let long =
+					self.deg_min_dir((COL_LONG_DEG, COL_LONG_MIN, COL_LONG_DIR), "W", "E")?;
+				(page, LatLong::new(Rad::from(lat), Rad::from(long)))
+			}
+		};
+
+		Ok(City {
+			name: self.get(COL_NAME)?.to_string(),
+			population: self.population(COL_POPULATION)?,
+			country: self.get(COL_COUNTRY)?.to_string(),
+			page,
+			coords,
+		})
+	}
+}
+
+/// Reads cities from a CSV file in either of the layouts we know about:
+///
+/// * Headerless, with name, population, country, lat and long (in radians) columns
+/// * With a header like TelleneCities.csv, naming Town, Population, Country, Page, and
+///   Lat/Lon _Deg, _Min and _Dir columns. Page is optional.
+///
+/// In both, padding around columns is ignored, blank lines are skipped, and a population
+/// of RUINS is read as no population.
+///
+/// Lines that can't be read as a city are skipped with a warning, and their errors returned
+/// along with the cities that could be. Only a file that can't be read at all, or a header
+/// missing a column cities need, is an error.
+pub fn read_cities<R: BufRead>(
+	reader: R,
+) -> Result<(Vec<City>, Vec<CityParseError>), CityParseError> {
 	let mut cities: Vec<City> = Vec::new();
+	let mut line_errors: Vec<CityParseError> = Vec::new();
+	let mut layout: Option<CitiesLayout> = None;
+
+	for (line_idx, line) in reader.lines().enumerate() {
+		let line_num = line_idx + 1;
+		let line = line.map_err(|e| CityParseError::IOError(line_num, e))?;
+		let columns = split_columns(&line);
+		if columns.iter().all(|column| column.is_empty()) {
+			continue;
+		}
 
-	let f = File::open(file_name).unwrap();
-	let f = BufReader::new(f);
-
-	for line in f.lines() {
-		let line = line.unwrap();
-		let columns: Vec<&str> = line.split(',').collect();
-		let city = City {
-			name: String::from(columns[0]),
-			population: columns[1].parse::<u32>().unwrap(),
-			country: String::from(columns[2]),
-			coords: LatLong::new(
-				Rad(columns[3].parse::<f64>().unwrap()),
-				Rad(columns[4].parse::<f64>().unwrap()),
-			),
+		// The first line decides the layout of the rest of the file
+		if layout.is_none() && is_header(&columns) {
+			let indices: HashMap<String, usize> = columns
+				.iter()
+				.enumerate()
+				.map(|(index, column)| (column.to_lowercase(), index))
+				.collect();
+			if let Some(&column) = HEADER_COLUMNS
+				.iter()
+				.filter(|&&column| column != COL_PAGE)
+				.find(|column| !indices.contains_key(&column.to_lowercase()))
+			{
+				return Err(CityParseError::MissingHeaderColumn(column));
+			}
+			layout = Some(CitiesLayout::Header(indices));
+			continue;
+		}
+		let layout = layout.get_or_insert(CitiesLayout::Headerless);
+
+		let parser = LineParser {
+			line_num,
+			columns,
+			layout,
 		};
-		cities.push(city);
+		match parser.city() {
+			Ok(city) => cities.push(city),
+			Err(e) => {
+				warn!("Skipped a city that couldn't be read, {}", e);
+				line_errors.push(e);
+			}
+		}
+	}
+	info!("Read {} cities", cities.len());
+	Ok((cities, line_errors))
+}
+
+// Splits an angle into whole degrees, minutes and a direction, the inverse of deg_min_dir
+fn to_deg_min_dir<'a>(
+	angle: Rad<f64>,
+	negative_dir: &'a str,
+	positive_dir: &'a str,
+) -> (f64, f64, &'a str) {
+	let Deg(deg) = Deg::from(angle);
+	// Sign rather than < 0, so 0° W reads back as W
+	let dir = if deg.is_sign_negative() {
+		negative_dir
+	} else {
+		positive_dir
+	};
+
+	// Round to a millionth of a minute, so whole minutes read from a file stay whole
+	let round = |x: f64| (x * 1_000_000_f64).round() / 1_000_000_f64;
+	let total_min = round(deg.abs() * 60_f64);
+	let whole_deg = (total_min / 60_f64).floor();
+	(whole_deg, round(total_min - whole_deg * 60_f64), dir)
+}
+
+fn city_columns(city: &City) -> Vec<String> {
+	let (lat_deg, lat_min, lat_dir) = to_deg_min_dir(city.coords.lat, "S", "N");
+	let (long_deg, long_min, long_dir) = to_deg_min_dir(city.coords.long, "W", "E");
+	vec![
+		city.name.clone(),
+		match city.population {
+			Some(population) => population.to_string(),
+			None => POPULATION_RUINS.to_string(),
+		},
+		city.country.clone(),
+		city.page.map(|page| page.to_string()).unwrap_or_default(),
+		lat_deg.to_string(),
+		lat_min.to_string(),
+		lat_dir.to_string(),
+		long_deg.to_string(),
+		long_min.to_string(),
+		long_dir.to_string(),
+	]
+}
+
+/// Writes cities as a CSV file with a header, in the same padded layout as
+/// TelleneCities.csv, ending each line with line_ending. read_cities will read the output
+/// back into the same cities.
+pub fn write_cities<W: Write>(
+	mut writer: W,
+	cities: &[City],
+	line_ending: &str,
+) -> std::io::Result<()> {
+	let header: Vec<String> = HEADER_COLUMNS
+		.iter()
+		.map(|column| column.to_string())
+		.collect();
+	let rows: Vec<Vec<String>> = cities.iter().map(city_columns).collect();
+
+	// Every column (and its comma) is padded to line up with the widest value in it
+	let mut widths = vec![0_usize; HEADER_COLUMNS.len()];
+	for row in std::iter::once(&header).chain(rows.iter()) {
+		for (width, column) in widths.iter_mut().zip(row.iter()) {
+			*width = (*width).max(column.len() + 2);
+		}
+	}
+
+	for row in std::iter::once(&header).chain(rows.iter()) {
+		let last = row.len() - 1;
+		let line: String = row
+			.iter()
+			.enumerate()
+			.map(|(i, column)| {
+				if i == last {
+					column.clone()
+				} else {
+					format!("{:<width$}", format!("{},", column), width = widths[i])
+				}
+			})
+			.collect();
+		write!(writer, "{}{}", line, line_ending)?;
 	}
-	println!("{:?}", cities);
-	cities
+	Ok(())
 }
 
-pub fn closest_city_to<'a>(coords: &LatLong, cities: &'a Vec<City>) -> Option<&'a City> {
+pub fn closest_city_to<'a>(coords: &LatLong, cities: &'a [City]) -> Option<&'a City> {
 	let cmp_cities = |city1: &&City, city2: &&City| {
 		let city1_dist = coords.great_circle_distance(&city1.coords);
 		let city2_dist = coords.great_circle_distance(&city2.coords);
 		city1_dist.partial_cmp(&city2_dist).unwrap()
 	};
-	cities.into_iter().min_by(cmp_cities)
+	cities.iter().min_by(cmp_cities)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const HEADER: &str =
+		"Town, Population, Country, Page, Lat_Deg, Lat_Min, Lat_Dir, Lon_Deg, Lon_Min, Lon_Dir";
+
+	#[test]
+	fn bad_lines_are_skipped_and_reported() {
+		let file = format!(
+			"{}\n{}\n{}\n{}\n",
+			HEADER,
+			"Alpha, 1200, Kalamar, 12, 35, 30, N, 5, 15, W",
+			"Beta, lots, Kalamar, , 36, 0, N, 6, 0, E",
+			"Gamma, RUINS, Tokis, , 30, 0, S, 0, 45, E",
+		);
+		let (cities, line_errors) = read_cities(file.as_bytes()).unwrap();
+
+		let names: Vec<&str> = cities.iter().map(|city| city.name.as_str()).collect();
+		assert_eq!(names, ["Alpha", "Gamma"]);
+		assert_eq!(cities[1].population, None);
+		match line_errors.as_slice() {
+			[CityParseError::BadValue(3, COL_POPULATION, value)] => assert_eq!(value, "lots"),
+			other => panic!("expected a bad population on line 3, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn header_missing_a_column_is_an_error() {
+		let file = "Town, Population, Country, Lat_Deg, Lat_Min, Lat_Dir\nAlpha, 1, K, 1, 0, N\n";
+		match read_cities(file.as_bytes()) {
+			Err(CityParseError::MissingHeaderColumn(COL_LONG_DEG)) => (),
+			other => panic!("expected a missing Lon_Deg column, got {:?}", other.err()),
+		}
+	}
+
+	#[test]
+	fn tiers_include_their_largest_population() {
+		let tier = |population| {
+			City {
+				name: String::from("Alpha"),
+				population,
+				country: String::from("Kalamar"),
+				page: None,
+				coords: LatLong {
+					lat: Rad(0_f64),
+					long: Rad(0_f64),
+				},
+			}
+			.tier()
+		};
+		assert_eq!(tier(None), SettlementTier::Ruins);
+		assert_eq!(tier(Some(0)), SettlementTier::Thorp);
+		assert_eq!(tier(Some(400)), SettlementTier::Thorp);
+		assert_eq!(tier(Some(401)), SettlementTier::Village);
+		assert_eq!(tier(Some(900)), SettlementTier::Village);
+		assert_eq!(tier(Some(901)), SettlementTier::Town);
+		assert_eq!(tier(Some(5000)), SettlementTier::Town);
+		assert_eq!(tier(Some(5001)), SettlementTier::City);
+	}
 }
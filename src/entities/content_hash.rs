@@ -0,0 +1,47 @@
+use image::GenericImageView;
+
+use std::hash::Hasher;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// A 64-bit FNV-1a hasher, for telling whether files and images have changed.
+///
+/// Unlike std's DefaultHasher, the output is stable across Rust versions and runs, so it's
+/// safe to write to disk and compare against later.
+pub struct ContentHasher {
+	state: u64,
+}
+impl ContentHasher {
+	pub fn new() -> ContentHasher {
+		ContentHasher {
+			state: FNV_OFFSET_BASIS,
+		}
+	}
+
+	/// Hashes the dimensions and every pixel (as RGBA) of image
+	pub fn write_image<I: GenericImageView>(&mut self, image: &I)
+	where
+		I::Pixel: image::Pixel<Subpixel = u8>,
+	{
+		use image::Pixel;
+
+		self.write_u32(image.width());
+		self.write_u32(image.height());
+		for (_, _, pixel) in image.pixels() {
+			self.write(&pixel.to_rgba().0);
+		}
+	}
+}
+impl Hasher for ContentHasher {
+	fn finish(&self) -> u64 {
+		self.state
+	}
+
+	fn write(&mut self, bytes: &[u8]) {
+		for &byte in bytes {
+			self.state ^= byte as u64;
+			self.state = self.state.wrapping_mul(FNV_PRIME);
+		}
+	}
+}
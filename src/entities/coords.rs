@@ -2,8 +2,9 @@
 // TODO clean up dead code annotations
 
 use cgmath::prelude::*;
-use cgmath::{Point3, Rad, Vector3};
+use cgmath::{Deg, Point3, Rad, Vector3};
 
+use std::fmt;
 use std::ops::{Add, Sub};
 
 // All conventions used here are from
@@ -19,16 +20,12 @@ pub struct SphericalPoint {
 }
 impl SphericalPoint {
 	pub fn new(radius: f64, theta: Rad<f64>, phi: Rad<f64>) -> SphericalPoint {
-		SphericalPoint {
-			radius: radius,
-			theta: theta,
-			phi: phi,
-		}
+		SphericalPoint { radius, theta, phi }
 	}
 	pub fn from_point(point: &Point3<f64>) -> SphericalPoint {
 		let radius: f64 = point.to_vec().magnitude();
 		SphericalPoint {
-			radius: radius,
+			radius,
 			theta: Rad::acos(point.y / radius),
 			phi: Rad(point.x.atan2(point.z)),
 		}
@@ -37,7 +34,7 @@ impl SphericalPoint {
 	pub fn from_vec(vec: &Vector3<f64>) -> SphericalPoint {
 		let radius: f64 = vec.magnitude();
 		SphericalPoint {
-			radius: radius,
+			radius,
 			theta: Rad::acos(vec.y / radius),
 			phi: Rad(vec.x.atan2(vec.z)),
 		}
@@ -57,18 +54,20 @@ impl SphericalPoint {
 		Vector3::new(x, y, z)
 	}
 
+	// 0 phi is 0 longitude, and phi increases eastwards, so that looking at the globe from
+	// outside with north up, east is to the right.
 	// TODO: TESTME
 	pub fn from_lat_long(radius: f64, lat: Rad<f64>, long: Rad<f64>) -> SphericalPoint {
 		SphericalPoint {
-			radius: radius,
+			radius,
 			theta: (Rad::turn_div_4() - lat),
-			phi: (Rad::turn_div_2() - long), // TODO change so 0 phi is 0 longitude
+			phi: long,
 		}
 	}
 	pub fn as_lat_long(&self) -> LatLong {
 		let lat = Rad::turn_div_4() - self.theta;
-		let long = Rad::turn_div_2() - self.phi; //TODO change so 0 phi is 0 longitude
-		LatLong::new(lat, long)
+		let long = self.phi;
+		LatLong::new(lat, long).normalize()
 	}
 
 	pub fn is_ok(&self) -> bool {
@@ -78,7 +77,7 @@ impl SphericalPoint {
 
 // lat is in radians, [-PI/2 (S), PI/2 (N)]
 // long is in radians, [-PI (W), PI (E))
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct LatLong {
 	pub lat: Rad<f64>,
 	pub long: Rad<f64>,
@@ -93,25 +92,19 @@ impl LatLong {
 
 	pub fn normalize(&self) -> LatLong {
 		let Rad(lat) = self.lat;
-		let new_lat = Rad(lat
-			.min(std::f64::consts::FRAC_PI_2)
-			.max(-std::f64::consts::FRAC_PI_2));
+		let new_lat = Rad(lat.clamp(-std::f64::consts::FRAC_PI_2, std::f64::consts::FRAC_PI_2));
 
-		// TODO: doesn't cover cases where long is < -2*pi
 		let Rad(long) = self.long;
 		let new_long = Rad(
-			(long + std::f64::consts::PI) % (2_f64 * std::f64::consts::PI) - std::f64::consts::PI,
+			(long + std::f64::consts::PI).rem_euclid(2_f64 * std::f64::consts::PI)
+				- std::f64::consts::PI,
 		);
 
 		LatLong::new(new_lat, new_long)
 	}
 
 	pub fn as_sph_point(&self, radius: f64) -> SphericalPoint {
-		SphericalPoint {
-			radius: radius,
-			theta: (Rad::turn_div_4() - self.lat),
-			phi: (Rad::turn_div_2() - self.long),
-		}
+		SphericalPoint::from_lat_long(radius, self.lat, self.long)
 	}
 
 	// Returns the great circle distance in radians between self and other
@@ -144,3 +137,20 @@ impl Sub for LatLong {
 		LatLong::new(self.lat - other.lat, self.long - other.long).normalize()
 	}
 }
+// Degrees to two decimal places, north or south then east or west, like "35.12°N 5.03°W"
+impl fmt::Display for LatLong {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let Deg(lat) = self.lat.into();
+		let Deg(long) = self.long.into();
+		let north_south = if lat < 0_f64 { 'S' } else { 'N' };
+		let east_west = if long < 0_f64 { 'W' } else { 'E' };
+		write!(
+			f,
+			"{:.2}°{} {:.2}°{}",
+			lat.abs(),
+			north_south,
+			long.abs(),
+			east_west
+		)
+	}
+}
@@ -1,10 +1,20 @@
-use image::imageops;
-use image::{DynamicImage, FilterType, GenericImage, GenericImageView};
+use image::{DynamicImage, FilterType};
 
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::fmt;
+use std::hash::Hasher;
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Instant;
 
-#[derive(Clone, Debug)]
+use super::atlas::Atlas;
+use super::content_hash::ContentHasher;
+use super::progress::Progress;
+use super::tile_pyramid::{TileId, TilePyramid};
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct MapBounds {
 	pub min_lat: f64, // All in degrees
 	pub max_lat: f64,
@@ -14,186 +24,380 @@ pub struct MapBounds {
 impl MapBounds {
 	pub fn new(min_lat: f64, max_lat: f64, min_long: f64, max_long: f64) -> MapBounds {
 		MapBounds {
-			min_lat: min_lat,
-			max_lat: max_lat,
-			min_long: min_long,
-			max_long: max_long,
+			min_lat,
+			max_lat,
+			min_long,
+			max_long,
 		}
 	}
+
+	/// The smallest bounds covering all of bounds
+	pub fn covering<'a, I: Iterator<Item = &'a MapBounds>>(bounds: I) -> MapBounds {
+		bounds.fold(
+			MapBounds::new(
+				f64::INFINITY,
+				f64::NEG_INFINITY,
+				f64::INFINITY,
+				f64::NEG_INFINITY,
+			),
+			|acc, b| {
+				MapBounds::new(
+					acc.min_lat.min(b.min_lat),
+					acc.max_lat.max(b.max_lat),
+					acc.min_long.min(b.min_long),
+					acc.max_long.max(b.max_long),
+				)
+			},
+		)
+	}
+
+	/// Whether any of other falls inside self
+	pub fn intersects(&self, other: &MapBounds) -> bool {
+		self.min_lat < other.max_lat
+			&& self.max_lat > other.min_lat
+			&& self.min_long < other.max_long
+			&& self.max_long > other.min_long
+	}
 }
 
-#[derive(Eq, PartialEq, Hash, Debug)]
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
 pub struct MapPieceKey {
 	pub min_lat: i32, // In degrees
 	pub min_long: i32,
 }
+impl MapPieceKey {
+	/// Key of the texture, texture_size_deg degrees wide and high, that covers lat/long
+	pub fn containing(lat_deg: f64, long_deg: f64, texture_size_deg: u32) -> MapPieceKey {
+		let tex_size_deg = texture_size_deg as f64;
+		MapPieceKey {
+			min_lat: ((lat_deg / tex_size_deg).floor() * tex_size_deg) as i32,
+			min_long: ((long_deg / tex_size_deg).floor() * tex_size_deg) as i32,
+		}
+	}
+
+	/// Lat/long covered by the texture for self, which is texture_size_deg degrees wide and high
+	pub fn bounds(&self, texture_size_deg: u32) -> MapBounds {
+		let (min_lat, min_long) = (self.min_lat as f64, self.min_long as f64);
+		MapBounds::new(
+			min_lat,
+			min_lat + texture_size_deg as f64,
+			min_long,
+			min_long + texture_size_deg as f64,
+		)
+	}
+}
+
+// Generates an image for each of keys with generate, spread across every core. Each image is
+// handed to done on this thread as soon as it's made, along with the progress so far. Threads
+// wait while done is busy, so only about one image per thread is ever held at once. what says
+// what the images are, for logging.
+fn generate_in_parallel<K, G>(
+	what: &str,
+	keys: &[K],
+	generate: G,
+	done: &mut dyn FnMut(K, Option<DynamicImage>, &Progress),
+) where
+	K: Copy + Send + Sync,
+	G: Fn(&K) -> Option<DynamicImage> + Sync,
+{
+	let threads = thread::available_parallelism()
+		.map(|threads| threads.get())
+		.unwrap_or(1)
+		.min(keys.len());
+	info!(
+		"Generating {} {} on {} threads (may take a while)",
+		keys.len(),
+		what,
+		threads
+	);
+
+	// Each thread takes the next key nobody's started on, until there are none left.
+	// Every image only depends on its key, so which thread makes it doesn't matter.
+	let start = Instant::now();
+	let next_key = AtomicUsize::new(0);
+	let (sender, receiver) = mpsc::sync_channel(threads);
+	thread::scope(|scope| {
+		for _ in 0..threads {
+			let sender = sender.clone();
+			let (next_key, generate) = (&next_key, &generate);
+			scope.spawn(move || {
+				while let Some(key) = keys.get(next_key.fetch_add(1, atomic::Ordering::Relaxed)) {
+					if sender.send((*key, generate(key))).is_err() {
+						break;
+					}
+				}
+			});
+		}
+		drop(sender);
+
+		for (done_count, (key, image)) in receiver.iter().enumerate() {
+			done(
+				key,
+				image,
+				&Progress {
+					done: done_count + 1,
+					total: keys.len(),
+					elapsed: start.elapsed(),
+				},
+			);
+		}
+	});
+}
 
 #[derive(Clone)]
 pub struct Map {
 	world_name: String,
-	pub image: DynamicImage,
+	pub atlases: Vec<Atlas>,
 	pub missing_image: DynamicImage,
-	pub bounds: MapBounds,
+	pub bounds: MapBounds, // Covers every atlas
+	pub texture_size_deg: u32,
+	pub textures: HashMap<MapPieceKey, DynamicImage>,
+	pub pyramid: Option<TilePyramid>, // Streamed in tile by tile instead of using textures
 }
 impl Map {
 	pub fn new(
 		world_name: &str,
-		large_image: DynamicImage,
+		atlases: Vec<Atlas>,
 		missing_image: DynamicImage,
-		bounds: MapBounds,
+		texture_size_deg: u32,
 	) -> Map {
 		Map {
 			world_name: world_name.to_string(),
-			image: large_image,
-			missing_image: missing_image,
-			bounds: bounds,
+			bounds: MapBounds::covering(atlases.iter().map(Atlas::coverage)),
+			atlases,
+			missing_image,
+			texture_size_deg,
+			textures: HashMap::new(),
+			pyramid: None,
 		}
 	}
 
-	fn get_img_x_by_long(&self, long_deg: f64) -> i32 {
-		let px_per_deg_long =
-			(self.image.width() as f64 - 1_f64) / (self.bounds.max_long - self.bounds.min_long);
-		((long_deg - self.bounds.min_long) * px_per_deg_long).round() as i32
+	/// Makes a map out of already generated textures, so the atlases' images aren't needed
+	pub fn from_textures(
+		world_name: &str,
+		atlases: Vec<Atlas>,
+		textures: HashMap<MapPieceKey, DynamicImage>,
+		missing_image: DynamicImage,
+		texture_size_deg: u32,
+	) -> Map {
+		let mut map = Map::new(world_name, atlases, missing_image, texture_size_deg);
+		map.textures = textures;
+		map
 	}
 
-	fn get_img_y_by_lat(&self, lat_deg: f64) -> i32 {
-		let px_per_deg_lat =
-			(self.image.height() as f64 - 1_f64) / (self.bounds.max_lat - self.bounds.min_lat);
-		self.image.height() as i32
-			- 1_i32 - ((lat_deg - self.bounds.min_lat) * px_per_deg_lat).round() as i32
+	/// Makes a map out of textures that were split up ahead of time, without any atlases
+	pub fn from_tiles(
+		world_name: &str,
+		textures: HashMap<MapPieceKey, DynamicImage>,
+		missing_image: DynamicImage,
+		texture_size_deg: u32,
+	) -> Map {
+		let tile_bounds: Vec<MapBounds> = textures
+			.keys()
+			.map(|key| key.bounds(texture_size_deg))
+			.collect();
+
+		let mut map = Map::new(world_name, Vec::new(), missing_image, texture_size_deg);
+		map.bounds = MapBounds::covering(tile_bounds.iter());
+		map.textures = textures;
+		map
 	}
 
-	pub fn generate_textures(&mut self, tex_size_deg: u32) -> HashMap<MapPieceKey, DynamicImage> {
-		// "Increments" of tex_size_deg, set to hold whole image.
-		// E.g. if self.image goes from long -13.7 to 12.0, with tex_size of 2 deg,
-		// Min long incr would be -7, max would be 6
-		let min_long_incr = (self.bounds.min_long / tex_size_deg as f64).floor() as i32;
-		let max_long_incr = (self.bounds.max_long / tex_size_deg as f64).ceil() as i32 - 1_i32;
-		let min_lat_incr = (self.bounds.min_lat / tex_size_deg as f64).floor() as i32;
-		let max_lat_incr = (self.bounds.max_lat / tex_size_deg as f64).ceil() as i32 - 1_i32;
+	/// Keys of every texture needed to cover the map's atlases
+	pub fn texture_keys(&self) -> Vec<MapPieceKey> {
+		let mut keys: Vec<MapPieceKey> = self
+			.atlases
+			.iter()
+			.flat_map(|atlas| atlas.texture_keys(self.texture_size_deg))
+			.collect();
+		keys.sort();
+		keys.dedup();
+		keys
+	}
 
-		let mut textures: HashMap<MapPieceKey, DynamicImage> = HashMap::new();
+	// Atlases covering tex_bounds, in the order they should be drawn: lowest priority first,
+	// and least detailed first among the same priority
+	fn atlases_for(&self, tex_bounds: &MapBounds) -> Vec<&Atlas> {
+		let mut atlases: Vec<&Atlas> = self
+			.atlases
+			.iter()
+			.filter(|atlas| atlas.covers(tex_bounds))
+			.collect();
+		atlases.sort_by(|a, b| {
+			a.priority.cmp(&b.priority).then(
+				a.px_per_deg()
+					.partial_cmp(&b.px_per_deg())
+					.unwrap_or(Ordering::Equal),
+			)
+		});
+		atlases
+	}
 
-		// Goal in each iteration of the inner loop is to get a texture which is
-		// tex_size_deg degrees wide and high, the size of self.missing_image
-		debug!("Image w={}, h={}", self.image.width(), self.image.height());
-		info!("Generating textures (may take a while)");
-		for long_incr in min_long_incr..=max_long_incr {
-			// Get x dimensions (in image-space pixels) for the subimage we'll grab
-			let subimage_min_long = (long_incr * tex_size_deg as i32) as f64;
-			// If min x coord would be < 0, set to 0 and store the offset
-			let (subimage_min_x, offset_min_x) = {
-				let min = self.get_img_x_by_long(subimage_min_long);
-				if min < 0 {
-					(0_u32, (-min) as u32)
-				} else {
-					(min as u32, 0_u32)
-				}
-			};
-
-			let subimage_max_long = subimage_min_long + tex_size_deg as f64;
-			// Max coords non-inclusive
-			// If max x coord would be > width, set to width and store the offset
-			let (subimage_max_x, offset_max_x) = {
-				let max = self.get_img_x_by_long(subimage_max_long);
-				if max > self.image.width() as i32 {
-					(self.image.width(), (max as u32 - self.image.width()))
-				} else {
-					(max as u32, 0_u32)
-				}
-			};
-			let subimage_width_px = subimage_max_x - subimage_min_x;
-			debug!(
-				"long incr={}, minlong x={}+{}, maxlong x={}, subimage w={}, img width={}",
-				long_incr,
-				subimage_min_x,
-				offset_min_x,
-				subimage_max_long,
-				subimage_width_px,
-				self.image.width()
-			);
+	/// Hashes everything the texture for key is generated from, so a cached copy of the
+	/// texture can be checked against it. None if an atlas' image isn't loaded.
+	pub fn texture_hash(&self, key: &MapPieceKey) -> Option<u64> {
+		let tex_bounds = key.bounds(self.texture_size_deg);
+		let mut hasher = ContentHasher::new();
+		for atlas in self.atlases_for(&tex_bounds) {
+			atlas.hash_texture_source(&mut hasher, &tex_bounds)?;
+		}
+		hasher.write_image(&self.missing_image);
+		Some(hasher.finish())
+	}
 
-			for lat_incr in min_lat_incr..=max_lat_incr {
-				// Get y dimensions ...
-				let subimage_min_lat = (lat_incr * tex_size_deg as i32) as f64;
-				// If min y coord would be < 0, set to 0 and store the offset
-				// Also, min lat -> max y, because image coords are weird!
-				let (subimage_max_y, offset_max_y) = {
-					let max = self.get_img_y_by_lat(subimage_min_lat);
-					if max > self.image.height() as i32 {
-						(self.image.height(), (max as u32 - self.image.height()))
-					} else {
-						(max as u32, 0_u32)
-					}
-				};
-
-				let subimage_max_lat = subimage_min_lat + tex_size_deg as f64;
-				// If max y coord would be > height, set to height and store the offset
-				// Also, max lat -> min y, because image coords are weird!
-				let (subimage_min_y, offset_min_y) = {
-					let min = self.get_img_y_by_lat(subimage_max_lat);
-					if min < 0 {
-						(0_u32, (-min) as u32)
-					} else {
-						(min as u32, 0_u32)
-					}
-				};
-				let subimage_height_px = subimage_max_y - subimage_min_y;
-
-				// Grab a view into image based on dimensions above
-				debug!(
-					"Subimage: minx={}, miny={}, w={}, h={}",
-					subimage_min_x, subimage_min_y, subimage_width_px, subimage_height_px
-				);
-				let subimage = self.image.sub_image(
-					subimage_min_x,
-					subimage_min_y,
-					subimage_width_px,
-					subimage_height_px,
-				);
-
-				// Set up missing_img (bottom layer)
-				let mut missing_img = self.missing_image.clone();
-
-				// Get the multipliers needed to bring subimage up to missing_img's size
-				let resize_x_mult = missing_img.width() as f64
-					/ (subimage_width_px + offset_min_x + offset_max_x) as f64;
-				let resize_y_mult = missing_img.height() as f64
-					/ (subimage_height_px + offset_min_y + offset_max_y) as f64;
-
-				// Resize subimage
-				let resize_to_x = (subimage_width_px as f64 * resize_x_mult).round() as u32;
-				let resize_to_y = (subimage_height_px as f64 * resize_y_mult).round() as u32;
-				let resized_subimage =
-					imageops::resize(&subimage, resize_to_x, resize_to_y, FilterType::Lanczos3);
-
-				// Get offsets of subimage and overlay
-				let tex_x_offset = (offset_min_x as f64 * resize_x_mult).round() as u32;
-				let tex_y_offset = (offset_min_y as f64 * resize_y_mult).round() as u32;
-				imageops::overlay(
-					&mut missing_img,
-					&resized_subimage,
-					tex_x_offset,
-					tex_y_offset,
-				);
-
-				// Store it and continue!
-				let map_piece_key = MapPieceKey {
-					min_lat: lat_incr * tex_size_deg as i32,
-					min_long: long_incr * tex_size_deg as i32,
-				};
-				textures.insert(map_piece_key, missing_img);
+	/// Generates the texture for key, which is texture_size_deg degrees wide and high and
+	/// the size of self.missing_image. None if an atlas' image isn't loaded.
+	pub fn generate_texture(&self, key: &MapPieceKey) -> Option<DynamicImage> {
+		self.render(
+			&key.bounds(self.texture_size_deg),
+			self.missing_image.clone(),
+		)
+	}
+
+	// Draws every atlas inside tex_bounds over background, scaled to background's size. None
+	// if an atlas' image isn't loaded.
+	fn render(&self, tex_bounds: &MapBounds, background: DynamicImage) -> Option<DynamicImage> {
+		// Background is the bottom layer, then each atlas in turn
+		let mut texture = background;
+		for atlas in self.atlases_for(tex_bounds) {
+			if !atlas.overlay_texture(&mut texture, tex_bounds) {
+				return None;
 			}
 		}
+		Some(texture)
+	}
+
+	/// Generates the textures for keys from the atlases, spread across every core. Textures
+	/// that need an atlas whose image isn't loaded are left out. progress is called from this
+	/// thread after each texture is done.
+	pub fn generate_textures(
+		&self,
+		keys: &[MapPieceKey],
+		progress: &mut dyn FnMut(&Progress),
+	) -> HashMap<MapPieceKey, DynamicImage> {
+		let mut textures: HashMap<MapPieceKey, DynamicImage> = HashMap::new();
+		self.generate_each_texture(keys, &mut |key, texture, done| {
+			if let Some(texture) = texture {
+				textures.insert(key, texture);
+			}
+			progress(done);
+		});
 		textures
 	}
+
+	/// Generates the textures for keys from the atlases, spread across every core. Each
+	/// texture is handed to done on this thread as soon as it's made, along with the progress
+	/// so far, so they don't all have to fit in memory at once. Textures that need an atlas
+	/// whose image isn't loaded are None.
+	pub fn generate_each_texture(
+		&self,
+		keys: &[MapPieceKey],
+		done: &mut dyn FnMut(MapPieceKey, Option<DynamicImage>, &Progress),
+	) {
+		generate_in_parallel("textures", keys, |key| self.generate_texture(key), done);
+	}
+
+	/// Generates the tiles ids of pyramid from the atlases, spread across every core. Each
+	/// tile is handed to done on this thread as soon as it's made, along with the progress so
+	/// far, so they don't all have to fit in memory at once. Tiles that need an atlas whose
+	/// image isn't loaded are None.
+	pub fn generate_pyramid_tiles(
+		&self,
+		pyramid: &TilePyramid,
+		ids: &[TileId],
+		done: &mut dyn FnMut(TileId, Option<DynamicImage>, &Progress),
+	) {
+		// Wherever there's no atlas, the tile is the missing image stretched over it
+		let image_px = pyramid.image_px();
+		let background = self
+			.missing_image
+			.resize_exact(image_px, image_px, FilterType::Lanczos3);
+		generate_in_parallel(
+			"pyramid tiles",
+			ids,
+			|id| self.render(&pyramid.image_bounds(id), background.clone()),
+			done,
+		);
+	}
 }
 impl fmt::Debug for Map {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		write!(
 			f,
-			"Map{{image: <image from {}>, bounds: {:?}}}",
-			self.world_name, self.bounds
+			"Map{{atlases: <{} images from {}>, bounds: {:?}, texture_size_deg: {}}}",
+			self.atlases.len(),
+			self.world_name,
+			self.bounds,
+			self.texture_size_deg
+		)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::entities::{Equirectangular, Mercator};
+
+	use image::{GenericImageView, Rgba, RgbaImage};
+	use std::sync::Arc;
+
+	// A gradient no two pixels of which are the same, so any misplaced pixel shows
+	fn gradient(width: u32, height: u32, seed: u32) -> DynamicImage {
+		DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |x, y| {
+			Rgba([
+				(x * 7 + seed) as u8,
+				(y * 5 + seed) as u8,
+				((x + y) * 3) as u8,
+				255,
+			])
+		}))
+	}
+
+	// Two overlapping atlases, one needing reprojecting, over part of a plain background
+	fn synthetic_map() -> Map {
+		let plain = Atlas::new(
+			Some(gradient(40, 30, 0)),
+			MapBounds::new(0_f64, 3_f64, 0_f64, 4_f64),
+			Arc::new(Equirectangular),
+			0,
+		)
+		.unwrap();
+		let reprojected = Atlas::new(
+			Some(gradient(25, 25, 100)),
+			MapBounds::new(1.5_f64, 4.5_f64, 2.5_f64, 5.5_f64),
+			Arc::new(Mercator),
+			1,
 		)
+		.unwrap();
+		let missing =
+			DynamicImage::ImageRgba8(RgbaImage::from_pixel(16, 16, Rgba([255, 0, 255, 255])));
+		Map::new("Synthetic", vec![plain, reprojected], missing, 1)
+	}
+
+	#[test]
+	fn parallel_textures_match_serial_ones() {
+		let map = synthetic_map();
+		let keys = map.texture_keys();
+		assert!(keys.len() > 1);
+
+		let mut progress_calls = 0;
+		let parallel = map.generate_textures(&keys, &mut |progress| {
+			progress_calls += 1;
+			assert_eq!(progress.done, progress_calls);
+			assert_eq!(progress.total, keys.len());
+		});
+		assert_eq!(progress_calls, keys.len());
+		assert_eq!(parallel.len(), keys.len());
+
+		for key in &keys {
+			let serial = map.generate_texture(key).unwrap();
+			let parallel = &parallel[key];
+			assert_eq!(serial.dimensions(), parallel.dimensions());
+			assert!(
+				serial.raw_pixels() == parallel.raw_pixels(),
+				"{:?} differs",
+				key
+			);
+		}
 	}
 }
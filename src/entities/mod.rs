@@ -1,7 +1,39 @@
-//pub mod city;
+mod atlas;
+pub use self::atlas::Atlas;
+
+mod bookmark;
+pub use self::bookmark::{Bookmark, CameraView};
+
+mod city;
+pub use self::city::{
+	closest_city_to, read_cities, write_cities, City, CityParseError, SettlementTier,
+};
+
+mod content_hash;
+pub use self::content_hash::ContentHasher;
 
 mod coords;
 pub use self::coords::{LatLong, SphericalPoint};
 
 mod map;
-pub use self::map::{Map, MapBounds};
+pub use self::map::{Map, MapBounds, MapPieceKey};
+
+mod model;
+pub use self::model::{read_obj, Model, ObjParseError};
+
+mod progress;
+pub use self::progress::Progress;
+
+mod projection;
+pub use self::projection::{
+	Equirectangular, LambertConformalConic, Mercator, PolarStereographic, Projection,
+};
+
+mod tile_pattern;
+pub use self::tile_pattern::{TilePattern, TilePatternError};
+
+mod tile_pyramid;
+pub use self::tile_pyramid::{TileId, TilePyramid};
+
+mod toml_edits;
+pub use self::toml_edits::{edit_set, same_value};
@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::BufRead;
+
+use cgmath::prelude::*;
+use cgmath::Vector3;
+
+/// A corner of a model's triangles
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ModelVertex {
+	pub position: [f64; 3],
+	pub normal: [f64; 3],
+	pub tex_coords: [f64; 2],
+}
+
+/// A triangle mesh made outside the app, which the globe can be drawn with. Triangles are
+/// counter-clockwise seen from the front, as in OBJ files.
+#[derive(Clone, Debug)]
+pub struct Model {
+	pub vertices: Vec<ModelVertex>,
+	pub triangles: Vec<u32>, // Indices into vertices, three per triangle
+}
+
+/// Something wrong with a single line of an OBJ file. Line numbers start at 1.
+#[derive(Debug)]
+pub enum ObjParseError {
+	IOError(usize, std::io::Error),
+	MissingValue(usize, &'static str),
+	BadValue(usize, &'static str, String),
+	BadIndex(usize, &'static str, i64), // Past the end of what's been defined so far, or 0
+	TooFewCorners(usize),
+	NoFaces,
+}
+impl fmt::Display for ObjParseError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ObjParseError::IOError(line, e) => write!(f, "line {}: {}", line, e),
+			ObjParseError::MissingValue(line, what) => {
+				write!(f, "line {}: missing {}", line, what)
+			}
+			ObjParseError::BadValue(line, what, value) => {
+				write!(f, "line {}: bad value {:?} for {}", line, value, what)
+			}
+			ObjParseError::BadIndex(line, what, index) => {
+				write!(f, "line {}: no {} with index {}", line, what, index)
+			}
+			ObjParseError::TooFewCorners(line) => {
+				write!(f, "line {}: face has fewer than 3 corners", line)
+			}
+			ObjParseError::NoFaces => write!(f, "no faces"),
+		}
+	}
+}
+
+// What kind of thing each index in a face corner refers to
+const WHAT_VERTEX: &str = "vertex";
+const WHAT_TEX_COORDS: &str = "texture coords";
+const WHAT_NORMAL: &str = "normal";
+
+fn parse_float(
+	line_num: usize,
+	what: &'static str,
+	value: Option<&str>,
+) -> Result<f64, ObjParseError> {
+	let value = value.ok_or(ObjParseError::MissingValue(line_num, what))?;
+	value
+		.parse::<f64>()
+		.map_err(|_| ObjParseError::BadValue(line_num, what, value.to_string()))
+}
+
+// An index from a face corner, which counts from 1, or back from the end of what's been
+// defined so far if negative
+fn parse_index(
+	line_num: usize,
+	what: &'static str,
+	value: &str,
+	defined: usize,
+) -> Result<usize, ObjParseError> {
+	let index = value
+		.parse::<i64>()
+		.map_err(|_| ObjParseError::BadValue(line_num, what, value.to_string()))?;
+	let resolved = if index < 0 {
+		defined as i64 + index
+	} else {
+		index - 1
+	};
+	if resolved < 0 || resolved >= defined as i64 {
+		return Err(ObjParseError::BadIndex(line_num, what, index));
+	}
+	Ok(resolved as usize)
+}
+
+/// Reads a model from a Wavefront OBJ file. Vertices (v), texture coords (vt), normals (vn)
+/// and faces (f) are read, faces with more than 3 corners being split into triangles.
+/// Anything else, like groups and materials, is skipped.
+///
+/// Corners without texture coords get (0, 0). Corners without a normal get the average of
+/// the normals of the faces around their vertex, so smooth shading still works.
+pub fn read_obj<R: BufRead>(name: &str, reader: R) -> Result<Model, ObjParseError> {
+	let mut positions: Vec<[f64; 3]> = Vec::new();
+	let mut tex_coords: Vec<[f64; 2]> = Vec::new();
+	let mut normals: Vec<[f64; 3]> = Vec::new();
+	// Each corner of each triangle, by its position, tex coords and normal indices
+	let mut corners: Vec<(usize, Option<usize>, Option<usize>)> = Vec::new();
+
+	for (line_idx, line) in reader.lines().enumerate() {
+		let line_num = line_idx + 1;
+		let line = line.map_err(|e| ObjParseError::IOError(line_num, e))?;
+		let line = line.split('#').next().unwrap_or("");
+		let mut values = line.split_whitespace();
+		let float = |values: &mut std::str::SplitWhitespace, what: &'static str| {
+			parse_float(line_num, what, values.next())
+		};
+
+		match values.next() {
+			Some("v") => {
+				let (x, y, z) = (
+					float(&mut values, "x")?,
+					float(&mut values, "y")?,
+					float(&mut values, "z")?,
+				);
+				// w is optional, and scales the rest down
+				let w = match values.next() {
+					Some(w) => parse_float(line_num, "w", Some(w))?,
+					None => 1_f64,
+				};
+				positions.push([x / w, y / w, z / w]);
+			}
+			Some("vt") => {
+				let u = float(&mut values, "u")?;
+				let v = match values.next() {
+					Some(v) => parse_float(line_num, "v", Some(v))?,
+					None => 0_f64,
+				};
+				tex_coords.push([u, v]);
+			}
+			Some("vn") => {
+				normals.push([
+					float(&mut values, "normal x")?,
+					float(&mut values, "normal y")?,
+					float(&mut values, "normal z")?,
+				]);
+			}
+			Some("f") => {
+				let mut face = Vec::new();
+				for corner in values {
+					// v, v/vt, v//vn or v/vt/vn
+					let mut indices = corner.split('/');
+					let position = parse_index(
+						line_num,
+						WHAT_VERTEX,
+						indices.next().unwrap_or(""),
+						positions.len(),
+					)?;
+					let tex_coord = match indices.next() {
+						None | Some("") => None,
+						Some(index) => Some(parse_index(
+							line_num,
+							WHAT_TEX_COORDS,
+							index,
+							tex_coords.len(),
+						)?),
+					};
+					let normal = match indices.next() {
+						None | Some("") => None,
+						Some(index) => {
+							Some(parse_index(line_num, WHAT_NORMAL, index, normals.len())?)
+						}
+					};
+					if indices.next().is_some() {
+						return Err(ObjParseError::BadValue(
+							line_num,
+							"face corner",
+							corner.to_string(),
+						));
+					}
+					face.push((position, tex_coord, normal));
+				}
+				if face.len() < 3 {
+					return Err(ObjParseError::TooFewCorners(line_num));
+				}
+				for i in 1..face.len() - 1 {
+					corners.extend_from_slice(&[face[0], face[i], face[i + 1]]);
+				}
+			}
+			_ => continue,
+		}
+	}
+	if corners.is_empty() {
+		return Err(ObjParseError::NoFaces);
+	}
+
+	// Normals for corners without one, from the faces around each vertex weighted by area
+	let mut face_normals = vec![Vector3::zero(); positions.len()];
+	for triangle in corners.chunks(3) {
+		let corner = |i: usize| Vector3::from(positions[triangle[i].0]);
+		let cross = (corner(1) - corner(0)).cross(corner(2) - corner(0));
+		for &(position, _, _) in triangle {
+			face_normals[position] += cross;
+		}
+	}
+
+	// Corners with the same indices share a vertex
+	let mut vertex_indices = HashMap::new();
+	let mut vertices = Vec::new();
+	let mut triangles = Vec::with_capacity(corners.len());
+	for corner in corners {
+		let index = *vertex_indices.entry(corner).or_insert_with(|| {
+			let (position, tex_coord, normal) = corner;
+			vertices.push(ModelVertex {
+				position: positions[position],
+				normal: match normal {
+					Some(normal) => normals[normal],
+					// Left as zero if every face around the vertex is flat
+					None if face_normals[position].is_zero() => [0_f64; 3],
+					None => face_normals[position].normalize().into(),
+				},
+				tex_coords: tex_coord.map_or([0_f64, 0_f64], |t| tex_coords[t]),
+			});
+			vertices.len() as u32 - 1
+		});
+		triangles.push(index);
+	}
+
+	info!(
+		"Read model {} with {} vertices and {} triangles",
+		name,
+		vertices.len(),
+		triangles.len() / 3
+	);
+	Ok(Model {
+		vertices,
+		triangles,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const SQUARE: &str = "# A unit square, as one quad\n\
+		v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+		vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+		f 1/1 2/2 3/3 4/4\n";
+
+	fn read(obj: &str) -> Result<Model, ObjParseError> {
+		read_obj("test", obj.as_bytes())
+	}
+
+	#[test]
+	fn quads_are_split_into_triangles() {
+		let model = read(SQUARE).unwrap();
+		assert_eq!(model.vertices.len(), 4);
+		assert_eq!(model.triangles, [0, 1, 2, 0, 2, 3]);
+		// Without normals in the file, they're worked out from the faces
+		for vertex in &model.vertices {
+			assert_eq!(vertex.normal, [0_f64, 0_f64, 1_f64]);
+			assert_eq!(vertex.tex_coords, [vertex.position[0], vertex.position[1]]);
+		}
+	}
+
+	#[test]
+	fn bad_face_indices_are_errors() {
+		// Three vertices and one texture coord, then the face on line 5
+		let cases: &[(&str, &str, i64)] = &[
+			("f 1 2 4", WHAT_VERTEX, 4),
+			("f 0 1 2", WHAT_VERTEX, 0),
+			("f 1 2 -4", WHAT_VERTEX, -4),
+			("f 1/1 2/2 3/1", WHAT_TEX_COORDS, 2),
+			("f 1//1 2//1 3//1", WHAT_NORMAL, 1),
+		];
+		for &(face, what, index) in cases {
+			match read(&format!("v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\n{}\n", face)) {
+				Err(ObjParseError::BadIndex(5, w, i)) if (w, i) == (what, index) => (),
+				other => panic!("expected a bad {} index in {}, got {:?}", what, face, other),
+			}
+		}
+	}
+
+	#[test]
+	fn missing_and_non_numeric_values_are_errors() {
+		match read("v 0 0 0\nv 1 0\n") {
+			Err(ObjParseError::MissingValue(2, "z")) => (),
+			other => panic!("expected a missing z, got {:?}", other),
+		}
+		match read("v 0 0 0\nv 1 north 0\n") {
+			Err(ObjParseError::BadValue(2, "y", value)) => assert_eq!(value, "north"),
+			other => panic!("expected a bad y, got {:?}", other),
+		}
+		match read("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 three\n") {
+			Err(ObjParseError::BadValue(4, WHAT_VERTEX, value)) => assert_eq!(value, "three"),
+			other => panic!("expected a bad vertex index, got {:?}", other),
+		}
+		match read("v 0 0 0\nv 1 0 0\nf 1 2\n") {
+			Err(ObjParseError::TooFewCorners(3)) => (),
+			other => panic!("expected too few corners, got {:?}", other),
+		}
+		match read("v 0 0 0\nv 1 0 0\nv 0 1 0\n") {
+			Err(ObjParseError::NoFaces) => (),
+			other => panic!("expected no faces, got {:?}", other),
+		}
+	}
+}
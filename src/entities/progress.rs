@@ -0,0 +1,39 @@
+use std::fmt;
+use std::time::Duration;
+
+/// How far through a long job, like generating textures, something is
+#[derive(Clone, Copy, Debug)]
+pub struct Progress {
+	pub done: usize,
+	pub total: usize,
+	pub elapsed: Duration,
+}
+impl Progress {
+	/// Estimated time until the job's done, assuming the rest goes as fast as it has so far.
+	/// None until something's been done.
+	pub fn eta(&self) -> Option<Duration> {
+		if self.done == 0 {
+			return None;
+		}
+		let remaining = self.total.saturating_sub(self.done) as u32;
+		Some(self.elapsed / self.done as u32 * remaining)
+	}
+
+	pub fn percent(&self) -> usize {
+		(self.done * 100).checked_div(self.total).unwrap_or(100)
+	}
+
+	pub fn is_done(&self) -> bool {
+		self.done >= self.total
+	}
+}
+impl fmt::Display for Progress {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}/{} ({}%)", self.done, self.total, self.percent())?;
+		if let Some(eta) = self.eta() {
+			let secs = eta.as_secs();
+			write!(f, ", ETA {}m {:02}s", secs / 60, secs % 60)?;
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,255 @@
+use std::f64::consts::{FRAC_PI_2, FRAC_PI_4};
+use std::fmt;
+
+/// A way of flattening the globe onto a map, like the one an atlas' image was drawn in.
+///
+/// Projected x increases eastwards and y northwards, in whatever units the projection likes;
+/// only the ratios between projected points matter when placing them on an image.
+pub trait Projection: fmt::Debug + Send + Sync {
+	/// Projects lat/long (in degrees) onto the map. None if the point can't be shown on it,
+	/// e.g. the poles on a Mercator map.
+	fn project(&self, lat_deg: f64, long_deg: f64) -> Option<(f64, f64)>;
+
+	/// The inverse of project, giving lat/long in degrees. Longitudes aren't normalized, so
+	/// stay continuous across the map.
+	fn unproject(&self, x: f64, y: f64) -> Option<(f64, f64)>;
+
+	/// Whether x is linear in longitude and y in latitude, so that a map's image can be cut
+	/// up into textures without reprojecting it
+	fn is_equirectangular(&self) -> bool {
+		false
+	}
+}
+
+/// The plate carrée projection: x is longitude and y is latitude
+#[derive(Debug)]
+pub struct Equirectangular;
+impl Projection for Equirectangular {
+	fn project(&self, lat_deg: f64, long_deg: f64) -> Option<(f64, f64)> {
+		Some((long_deg, lat_deg))
+	}
+
+	fn unproject(&self, x: f64, y: f64) -> Option<(f64, f64)> {
+		Some((y, x))
+	}
+
+	fn is_equirectangular(&self) -> bool {
+		true
+	}
+}
+
+/// The (spherical) Mercator projection, which can't show the poles
+#[derive(Debug)]
+pub struct Mercator;
+impl Projection for Mercator {
+	fn project(&self, lat_deg: f64, long_deg: f64) -> Option<(f64, f64)> {
+		if lat_deg.abs() >= 90_f64 {
+			return None;
+		}
+		let y = (FRAC_PI_4 + lat_deg.to_radians() / 2_f64).tan().ln();
+		Some((long_deg.to_radians(), y))
+	}
+
+	fn unproject(&self, x: f64, y: f64) -> Option<(f64, f64)> {
+		let lat = 2_f64 * y.exp().atan() - FRAC_PI_2;
+		Some((lat.to_degrees(), x.to_degrees()))
+	}
+}
+
+/// The Lambert conformal conic projection, true to scale along its two standard parallels.
+/// Its origin is the cone's apex, at the pole nearest the standard parallels.
+#[derive(Debug)]
+pub struct LambertConformalConic {
+	n: f64, // Cone constant, negative for southern cones
+	f: f64,
+	central_long_deg: f64,
+}
+impl LambertConformalConic {
+	/// None if the standard parallels don't make a cone, i.e. are on the equator, a pole, or
+	/// either side of the equator from each other
+	pub fn new(
+		standard_parallel_1_deg: f64,
+		standard_parallel_2_deg: f64,
+		central_long_deg: f64,
+	) -> Option<LambertConformalConic> {
+		let lat_1 = standard_parallel_1_deg.to_radians();
+		let lat_2 = standard_parallel_2_deg.to_radians();
+		let t = |lat: f64| (FRAC_PI_4 + lat / 2_f64).tan();
+
+		let n = if (lat_1 - lat_2).abs() < 1e-10 {
+			lat_1.sin()
+		} else {
+			(lat_1.cos() / lat_2.cos()).ln() / (t(lat_2) / t(lat_1)).ln()
+		};
+		if !n.is_finite() || n.abs() < 1e-10 || n.abs() >= 1_f64 {
+			return None;
+		}
+
+		Some(LambertConformalConic {
+			n,
+			f: lat_1.cos() * t(lat_1).powf(n) / n,
+			central_long_deg,
+		})
+	}
+}
+impl Projection for LambertConformalConic {
+	fn project(&self, lat_deg: f64, long_deg: f64) -> Option<(f64, f64)> {
+		// The pole opposite the apex is infinitely far away
+		if lat_deg * self.n.signum() <= -90_f64 {
+			return None;
+		}
+		let rho = self.f
+			/ (FRAC_PI_4 + lat_deg.to_radians() / 2_f64)
+				.tan()
+				.powf(self.n);
+		let theta = self.n * (long_deg - self.central_long_deg).to_radians();
+		Some((rho * theta.sin(), -rho * theta.cos()))
+	}
+
+	fn unproject(&self, x: f64, y: f64) -> Option<(f64, f64)> {
+		let sign = self.n.signum();
+		let rho = sign * (x * x + y * y).sqrt();
+		let theta = (sign * x).atan2(-sign * y);
+
+		let lat = if rho == 0_f64 {
+			sign * FRAC_PI_2
+		} else {
+			2_f64 * (self.f / rho).powf(1_f64 / self.n).atan() - FRAC_PI_2
+		};
+		Some((
+			lat.to_degrees(),
+			self.central_long_deg + (theta / self.n).to_degrees(),
+		))
+	}
+}
+
+/// The polar stereographic projection, centred on either pole, with central_long pointing
+/// straight down from the north pole (or up from the south pole)
+#[derive(Debug)]
+pub struct PolarStereographic {
+	north: bool,
+	central_long_deg: f64,
+}
+impl PolarStereographic {
+	pub fn new(north: bool, central_long_deg: f64) -> PolarStereographic {
+		PolarStereographic {
+			north,
+			central_long_deg,
+		}
+	}
+
+	fn sign(&self) -> f64 {
+		if self.north {
+			1_f64
+		} else {
+			-1_f64
+		}
+	}
+}
+impl Projection for PolarStereographic {
+	fn project(&self, lat_deg: f64, long_deg: f64) -> Option<(f64, f64)> {
+		let sign = self.sign();
+		// The opposite pole is infinitely far away
+		if lat_deg * sign <= -90_f64 {
+			return None;
+		}
+		let rho = 2_f64 * (FRAC_PI_4 - sign * lat_deg.to_radians() / 2_f64).tan();
+		let theta = (long_deg - self.central_long_deg).to_radians();
+		Some((rho * theta.sin(), -sign * rho * theta.cos()))
+	}
+
+	fn unproject(&self, x: f64, y: f64) -> Option<(f64, f64)> {
+		let sign = self.sign();
+		let rho = (x * x + y * y).sqrt();
+		let lat = sign * (FRAC_PI_2 - 2_f64 * (rho / 2_f64).atan());
+		let theta = x.atan2(-sign * y);
+		Some((lat.to_degrees(), self.central_long_deg + theta.to_degrees()))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const LATS: [f64; 7] = [-80_f64, -45_f64, -10_f64, 0_f64, 10_f64, 45_f64, 80_f64];
+	const LONGS: [f64; 5] = [-170_f64, -90_f64, 0_f64, 30_f64, 170_f64];
+
+	// Projects and unprojects every lat/long, checking it comes back where it started
+	fn assert_round_trips(projection: &dyn Projection, lats: &[f64]) {
+		for &lat in lats {
+			for &long in &LONGS {
+				let (x, y) = projection
+					.project(lat, long)
+					.unwrap_or_else(|| panic!("{:?} can't show {}, {}", projection, lat, long));
+				let (back_lat, back_long) = projection.unproject(x, y).unwrap();
+				// Longitudes can come back a whole turn away
+				let long_error = (back_long - long + 180_f64).rem_euclid(360_f64) - 180_f64;
+				assert!(
+					(back_lat - lat).abs() < 1e-9_f64 && long_error.abs() < 1e-9_f64,
+					"{:?} took {}, {} to {}, {}",
+					projection,
+					lat,
+					long,
+					back_lat,
+					back_long
+				);
+			}
+		}
+	}
+
+	#[test]
+	fn mercator_round_trips() {
+		assert_round_trips(&Mercator, &LATS);
+		assert_round_trips(&Mercator, &[-89.9_f64, 89.9_f64]);
+		assert_eq!(Mercator.project(90_f64, 0_f64), None);
+		assert_eq!(Mercator.project(-90_f64, 45_f64), None);
+	}
+
+	#[test]
+	fn lambert_conformal_conic_round_trips() {
+		// The apex is at the pole nearest the standard parallels, and the other can't be shown
+		let north = LambertConformalConic::new(30_f64, 60_f64, 10_f64).unwrap();
+		assert!(north.n > 0_f64);
+		assert_round_trips(&north, &LATS);
+		assert_eq!(north.project(-90_f64, 0_f64), None);
+		assert_eq!(
+			north.unproject(0_f64, 0_f64).map(|(lat, _)| lat),
+			Some(90_f64)
+		);
+
+		let south = LambertConformalConic::new(-20_f64, -50_f64, -60_f64).unwrap();
+		assert!(south.n < 0_f64);
+		assert_round_trips(&south, &LATS);
+		assert_eq!(south.project(90_f64, 0_f64), None);
+		assert_eq!(
+			south.unproject(0_f64, 0_f64).map(|(lat, _)| lat),
+			Some(-90_f64)
+		);
+
+		// Parallels that don't make a cone
+		assert!(LambertConformalConic::new(0_f64, 0_f64, 0_f64).is_none());
+		assert!(LambertConformalConic::new(-30_f64, 30_f64, 0_f64).is_none());
+		assert!(LambertConformalConic::new(90_f64, 90_f64, 0_f64).is_none());
+	}
+
+	#[test]
+	fn polar_stereographic_round_trips() {
+		let north = PolarStereographic::new(true, 10_f64);
+		assert_round_trips(&north, &LATS);
+		assert_eq!(north.project(-90_f64, 0_f64), None);
+		// The pole itself is the middle of the map, whatever its longitude
+		assert_eq!(north.project(90_f64, 123_f64), Some((0_f64, 0_f64)));
+		assert_eq!(
+			north.unproject(0_f64, 0_f64).map(|(lat, _)| lat),
+			Some(90_f64)
+		);
+
+		let south = PolarStereographic::new(false, -45_f64);
+		assert_round_trips(&south, &LATS);
+		assert_eq!(south.project(90_f64, 0_f64), None);
+		assert_eq!(
+			south.unproject(0_f64, 0_f64).map(|(lat, _)| lat),
+			Some(-90_f64)
+		);
+	}
+}
@@ -0,0 +1,225 @@
+use std::fmt;
+
+use super::map::MapPieceKey;
+
+#[derive(Debug)]
+pub enum TilePatternError {
+	UnclosedBrace(String),
+	UnknownField(String),
+	BadFormat(String),
+}
+impl fmt::Display for TilePatternError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			TilePatternError::UnclosedBrace(pattern) => write!(f, "unclosed {{ in {:?}", pattern),
+			TilePatternError::UnknownField(field) => write!(f, "unknown field {:?}", field),
+			TilePatternError::BadFormat(format) => write!(f, "bad format {:?}", format),
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Field {
+	MinLat,
+	MaxLat,
+	MinLong,
+	MaxLong,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+	Literal(String),
+	Field {
+		field: Field,
+		sign: bool,   // Always write a sign, even for positive numbers
+		width: usize, // Zero padded to at least this many characters, including the sign
+	},
+}
+
+/// How the files for a directory of tiles are named, e.g. "tellene_{max_lat}_{min_long}.png".
+///
+/// Fields in braces are replaced by the tile's edges in whole degrees: min_lat, max_lat,
+/// min_long and max_long. Like Rust's format!, a field can be followed by :+ to always
+/// write a sign, and/or :0<width> to zero pad it, e.g. {min_long:+04} gives -025 or +012.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TilePattern {
+	parts: Vec<Part>,
+}
+impl TilePattern {
+	/// The layout of textures/tellene_1, named by each tile's northwest corner
+	pub const LEGACY: &'static str = "tellene_{max_lat}_{min_long}.png";
+
+	pub fn new(pattern: &str) -> Result<TilePattern, TilePatternError> {
+		let mut parts = Vec::new();
+		let mut rest = pattern;
+		while let Some(open) = rest.find('{') {
+			if open > 0 {
+				parts.push(Part::Literal(rest[..open].to_string()));
+			}
+			let close = rest[open..]
+				.find('}')
+				.ok_or_else(|| TilePatternError::UnclosedBrace(pattern.to_string()))?;
+			parts.push(Self::parse_field(&rest[open + 1..open + close])?);
+			rest = &rest[open + close + 1..];
+		}
+		if !rest.is_empty() {
+			parts.push(Part::Literal(rest.to_string()));
+		}
+		Ok(TilePattern { parts })
+	}
+
+	// Parses what's between the braces of a field, like min_lat or min_lat:+04
+	fn parse_field(field: &str) -> Result<Part, TilePatternError> {
+		let (name, format) = match field.find(':') {
+			Some(colon) => (&field[..colon], &field[colon + 1..]),
+			None => (field, ""),
+		};
+		let field = match name {
+			"min_lat" => Field::MinLat,
+			"max_lat" => Field::MaxLat,
+			"min_long" => Field::MinLong,
+			"max_long" => Field::MaxLong,
+			_ => return Err(TilePatternError::UnknownField(name.to_string())),
+		};
+
+		let (sign, width) = match format.strip_prefix('+') {
+			Some(width) => (true, width),
+			None => (false, format),
+		};
+		let width = match width {
+			"" => 0_usize,
+			width => width
+				.strip_prefix('0')
+				.and_then(|width| width.parse::<usize>().ok())
+				.ok_or_else(|| TilePatternError::BadFormat(format.to_string()))?,
+		};
+
+		Ok(Part::Field { field, sign, width })
+	}
+
+	/// The file name of the tile for key, which is tile_size_deg degrees wide and high
+	pub fn file_name(&self, key: &MapPieceKey, tile_size_deg: u32) -> String {
+		let mut file_name = String::new();
+		for part in &self.parts {
+			match part {
+				Part::Literal(literal) => file_name.push_str(literal),
+				Part::Field { field, sign, width } => {
+					let deg = match field {
+						Field::MinLat => key.min_lat,
+						Field::MaxLat => key.min_lat + tile_size_deg as i32,
+						Field::MinLong => key.min_long,
+						Field::MaxLong => key.min_long + tile_size_deg as i32,
+					};
+					if *sign {
+						file_name.push_str(&format!("{:+0width$}", deg, width = width));
+					} else {
+						file_name.push_str(&format!("{:0width$}", deg, width = width));
+					}
+				}
+			}
+		}
+		file_name
+	}
+
+	/// The key of the tile named file_name, which is tile_size_deg degrees wide and high. None
+	/// if file_name doesn't match the pattern, or the pattern doesn't say where the tile is.
+	pub fn parse_file_name(&self, file_name: &str, tile_size_deg: u32) -> Option<MapPieceKey> {
+		let (mut min_lat, mut min_long) = (None, None);
+		let mut rest = file_name;
+		for part in &self.parts {
+			match part {
+				Part::Literal(literal) => rest = rest.strip_prefix(literal.as_str())?,
+				Part::Field { field, .. } => {
+					// An optional sign, then as many digits as there are
+					let sign_len = if rest.starts_with(['+', '-']) { 1 } else { 0 };
+					let len = sign_len
+						+ rest[sign_len..]
+							.find(|c: char| !c.is_ascii_digit())
+							.unwrap_or(rest.len() - sign_len);
+					let deg = rest[..len].parse::<i32>().ok()?;
+					rest = &rest[len..];
+
+					let (edge, min_deg) = match field {
+						Field::MinLat => (&mut min_lat, deg),
+						Field::MaxLat => (&mut min_lat, deg - tile_size_deg as i32),
+						Field::MinLong => (&mut min_long, deg),
+						Field::MaxLong => (&mut min_long, deg - tile_size_deg as i32),
+					};
+					// Both edges of a tile have to agree on where it is
+					if edge.get_or_insert(min_deg) != &min_deg {
+						return None;
+					}
+				}
+			}
+		}
+		if !rest.is_empty() {
+			return None;
+		}
+
+		let key = MapPieceKey {
+			min_lat: min_lat?,
+			min_long: min_long?,
+		};
+		// Make sure it's named exactly as it should be, including any padding
+		if self.file_name(&key, tile_size_deg) == file_name {
+			Some(key)
+		} else {
+			None
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn key(min_lat: i32, min_long: i32) -> MapPieceKey {
+		MapPieceKey { min_lat, min_long }
+	}
+
+	#[test]
+	fn names_round_trip() {
+		let legacy = TilePattern::new(TilePattern::LEGACY).unwrap();
+		let padded = TilePattern::new("{min_lat:+03}{min_long:+04}_{max_long}.jpg").unwrap();
+		for &(min_lat, min_long) in &[(0, 0), (35, 5), (-5, -12), (-90, 179), (89, -180)] {
+			for pattern in &[&legacy, &padded] {
+				let name = pattern.file_name(&key(min_lat, min_long), 1);
+				assert_eq!(
+					pattern.parse_file_name(&name, 1),
+					Some(key(min_lat, min_long)),
+					"{}",
+					name
+				);
+			}
+		}
+
+		assert_eq!(legacy.file_name(&key(-5, -12), 1), "tellene_-4_-12.png");
+		assert_eq!(padded.file_name(&key(-5, -12), 5), "-05-012_-7.jpg");
+		assert_eq!(padded.file_name(&key(5, 12), 5), "+05+012_17.jpg");
+	}
+
+	#[test]
+	fn names_not_matching_the_pattern_arent_tiles() {
+		let legacy = TilePattern::new(TilePattern::LEGACY).unwrap();
+		for name in &[
+			"tellene_4.png",
+			"tellene_4_12.jpg",
+			"other_4_12.png",
+			"tellene_4_12.png.bak",
+			"tellene_north_12.png",
+			"tellene_04_12.png", // Padded where the pattern isn't
+			"tellene_+4_12.png", // Signed where the pattern isn't
+		] {
+			assert_eq!(legacy.parse_file_name(name, 1), None, "{}", name);
+		}
+
+		// Both edges have to agree on where the tile is
+		let both_edges = TilePattern::new("{min_lat}_{max_lat}_{min_long}.png").unwrap();
+		assert_eq!(both_edges.parse_file_name("3_4_7.png", 1), Some(key(3, 7)));
+		assert_eq!(both_edges.parse_file_name("3_5_7.png", 1), None);
+
+		// Nor is it a tile if the pattern doesn't say where it is
+		let no_long = TilePattern::new("tile_{min_lat}.png").unwrap();
+		assert_eq!(no_long.parse_file_name("tile_3.png", 1), None);
+	}
+}
@@ -0,0 +1,237 @@
+use super::map::MapBounds;
+
+// Deepest level a pyramid can go to, where tiles are under 0.0002° across
+const MAX_LEVEL: u32 = 20;
+
+/// A tile in a pyramid, by its level and its column and row within that level.
+///
+/// Level 0 is two tiles, the western and eastern hemispheres, and each level after splits
+/// every tile of the one before into four. Columns count east from 180°W, and rows count
+/// south from the north pole.
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct TileId {
+	pub level: u32,
+	pub x: u32,
+	pub y: u32,
+}
+impl TileId {
+	/// The tiles of level 0, which cover the whole world between them
+	pub fn roots() -> [TileId; 2] {
+		[
+			TileId {
+				level: 0,
+				x: 0,
+				y: 0,
+			},
+			TileId {
+				level: 0,
+				x: 1,
+				y: 0,
+			},
+		]
+	}
+
+	/// Degrees every tile of level is wide and high
+	pub fn size_deg(level: u32) -> f64 {
+		180_f64 / 2_f64.powi(level as i32)
+	}
+
+	/// Lat/long covered by the tile
+	pub fn bounds(&self) -> MapBounds {
+		let size_deg = Self::size_deg(self.level);
+		let max_lat = 90_f64 - self.y as f64 * size_deg;
+		let min_long = -180_f64 + self.x as f64 * size_deg;
+		MapBounds::new(max_lat - size_deg, max_lat, min_long, min_long + size_deg)
+	}
+
+	/// The tile of the level before that this tile is part of, None for level 0
+	pub fn parent(&self) -> Option<TileId> {
+		if self.level == 0 {
+			return None;
+		}
+		Some(TileId {
+			level: self.level - 1,
+			x: self.x / 2,
+			y: self.y / 2,
+		})
+	}
+
+	/// The four tiles of the next level that this tile splits into
+	pub fn children(&self) -> [TileId; 4] {
+		let child = |dx, dy| TileId {
+			level: self.level + 1,
+			x: self.x * 2 + dx,
+			y: self.y * 2 + dy,
+		};
+		[child(0, 0), child(1, 0), child(0, 1), child(1, 1)]
+	}
+}
+
+/// A quadtree of tiles generated from a map's atlases, each level twice as detailed as the
+/// one before, down to the level that shows the atlases at their full resolution.
+///
+/// Every tile is tile_px square, with a gutter of gutter_px around it that's drawn from the
+/// map around the tile, so that filtering near its edges blends into its neighbours.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TilePyramid {
+	pub tile_px: u32,
+	pub gutter_px: u32,
+	pub max_level: u32,
+	pub bounds: MapBounds, // Only tiles overlapping these exist
+}
+impl TilePyramid {
+	/// A pyramid over bounds, deep enough that its last level has at least px_per_deg
+	pub fn new(tile_px: u32, gutter_px: u32, bounds: MapBounds, px_per_deg: f64) -> TilePyramid {
+		let mut max_level = 0_u32;
+		while max_level < MAX_LEVEL && (tile_px as f64) / TileId::size_deg(max_level) < px_per_deg {
+			max_level += 1;
+		}
+		TilePyramid {
+			tile_px,
+			gutter_px,
+			max_level,
+			bounds,
+		}
+	}
+
+	/// Whether the pyramid has a tile for id
+	pub fn contains(&self, id: &TileId) -> bool {
+		id.level <= self.max_level && id.bounds().intersects(&self.bounds)
+	}
+
+	/// Every tile in the pyramid, least detailed first
+	pub fn tile_ids(&self) -> Vec<TileId> {
+		let mut ids = Vec::new();
+		for level in 0..=self.max_level {
+			let size_deg = TileId::size_deg(level);
+			let (columns, rows) = (2_u32 << level, 1_u32 << level);
+			let column = |long: f64| {
+				(((long + 180_f64) / size_deg).floor().max(0_f64) as u32).min(columns - 1)
+			};
+			let row =
+				|lat: f64| (((90_f64 - lat) / size_deg).floor().max(0_f64) as u32).min(rows - 1);
+			for x in column(self.bounds.min_long)..=column(self.bounds.max_long) {
+				for y in row(self.bounds.max_lat)..=row(self.bounds.min_lat) {
+					let id = TileId { level, x, y };
+					if self.contains(&id) {
+						ids.push(id);
+					}
+				}
+			}
+		}
+		ids
+	}
+
+	/// Width and height of every tile's image, gutter included
+	pub fn image_px(&self) -> u32 {
+		self.tile_px + 2 * self.gutter_px
+	}
+
+	/// Lat/long drawn into the image for id, gutter included
+	pub fn image_bounds(&self, id: &TileId) -> MapBounds {
+		let bounds = id.bounds();
+		let gutter_deg = TileId::size_deg(id.level) * self.gutter_px as f64 / self.tile_px as f64;
+		MapBounds::new(
+			bounds.min_lat - gutter_deg,
+			bounds.max_lat + gutter_deg,
+			bounds.min_long - gutter_deg,
+			bounds.max_long + gutter_deg,
+		)
+	}
+
+	/// Maps coords from 0 to 1 across a tile onto its image, skipping over the gutter
+	pub fn inset_tex_coords(&self, u: f64, v: f64) -> (f64, f64) {
+		let (tile_px, gutter_px) = (self.tile_px as f64, self.gutter_px as f64);
+		let image_px = self.image_px() as f64;
+		(
+			(gutter_px + u * tile_px) / image_px,
+			(gutter_px + v * tile_px) / image_px,
+		)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn tile(level: u32, x: u32, y: u32) -> TileId {
+		TileId { level, x, y }
+	}
+
+	#[test]
+	fn children_split_their_parent_in_four() {
+		for id in &[
+			tile(0, 0, 0),
+			tile(0, 1, 0),
+			tile(3, 5, 2),
+			tile(7, 255, 127),
+		] {
+			let bounds = id.bounds();
+			let (mid_lat, mid_long) = (
+				(bounds.min_lat + bounds.max_lat) / 2_f64,
+				(bounds.min_long + bounds.max_long) / 2_f64,
+			);
+			let children = id.children();
+			assert_eq!(
+				children
+					.iter()
+					.map(TileId::bounds)
+					.collect::<Vec<MapBounds>>(),
+				[
+					MapBounds::new(mid_lat, bounds.max_lat, bounds.min_long, mid_long),
+					MapBounds::new(mid_lat, bounds.max_lat, mid_long, bounds.max_long),
+					MapBounds::new(bounds.min_lat, mid_lat, bounds.min_long, mid_long),
+					MapBounds::new(bounds.min_lat, mid_lat, mid_long, bounds.max_long),
+				]
+			);
+			for child in &children {
+				assert_eq!(child.parent(), Some(*id));
+			}
+		}
+
+		for root in &TileId::roots() {
+			assert_eq!(root.parent(), None);
+		}
+		assert_eq!(
+			tile(0, 0, 0).bounds(),
+			MapBounds::new(-90_f64, 90_f64, -180_f64, 0_f64)
+		);
+		assert_eq!(
+			tile(0, 1, 0).bounds(),
+			MapBounds::new(-90_f64, 90_f64, 0_f64, 180_f64)
+		);
+	}
+
+	#[test]
+	fn pyramid_goes_deep_enough_and_no_deeper() {
+		let world = MapBounds::new(-90_f64, 90_f64, -180_f64, 180_f64);
+		let max_level = |px_per_deg| TilePyramid::new(256, 0, world.clone(), px_per_deg).max_level;
+		// Level 3 tiles are 22.5° across, so 256 pixels is 11.38 per degree
+		assert_eq!(max_level(0_f64), 0);
+		assert_eq!(max_level(10_f64), 3);
+		assert_eq!(max_level(11.37_f64), 3);
+		assert_eq!(max_level(11.39_f64), 4);
+		assert_eq!(max_level(1e12_f64), MAX_LEVEL);
+	}
+
+	#[test]
+	fn only_tiles_over_the_bounds_are_in_the_pyramid() {
+		let bounds = MapBounds::new(10_f64, 20_f64, -50_f64, -5_f64);
+		let pyramid = TilePyramid::new(64, 2, bounds.clone(), 1_f64);
+		let ids = pyramid.tile_ids();
+
+		assert_eq!(pyramid.max_level, 2);
+		assert_eq!(
+			ids,
+			[tile(0, 0, 0), tile(1, 1, 0), tile(2, 2, 1), tile(2, 3, 1)]
+		);
+		for id in &ids {
+			assert!(id.bounds().intersects(&bounds), "{:?}", id);
+			if let Some(parent) = id.parent() {
+				assert!(ids.contains(&parent), "{:?}", id);
+			}
+		}
+		assert!(!pyramid.contains(&tile(0, 1, 0)));
+		assert!(!pyramid.contains(&tile(3, 5, 3)));
+	}
+}
@@ -0,0 +1,43 @@
+use toml_edit::{Array, Table, Value};
+
+/// Whether old and new are the same value, however they're written. An integer is the same
+/// as a float equal to it, and arrays of strings are the same in any order.
+pub fn same_value(old: &Value, new: &Value) -> bool {
+	match (old, new) {
+		(Value::String(old), Value::String(new)) => old.value() == new.value(),
+		(Value::Integer(old), Value::Integer(new)) => old.value() == new.value(),
+		(Value::Float(old), Value::Float(new)) => old.value() == new.value(),
+		(Value::Boolean(old), Value::Boolean(new)) => old.value() == new.value(),
+		(Value::Integer(old), Value::Float(new)) => *old.value() as f64 == *new.value(),
+		(Value::Array(old), Value::Array(new)) => {
+			let strings = |array: &Array| {
+				let mut strings: Vec<Option<String>> = array
+					.iter()
+					.map(|value| value.as_str().map(str::to_string))
+					.collect();
+				strings.sort();
+				strings
+			};
+			strings(old) == strings(new)
+		}
+		_ => false,
+	}
+}
+
+/// Sets key in table to new_value. Values that are already the same as new_value are left
+/// alone, and changed ones keep their comments, so saving what was loaded leaves the file
+/// exactly as the user wrote it.
+pub fn edit_set(table: &mut Table, key: &str, new_value: Value) {
+	match table.get_mut(key).and_then(|item| item.as_value_mut()) {
+		Some(old_value) => {
+			if !same_value(old_value, &new_value) {
+				let decor = old_value.decor().clone();
+				*old_value = new_value;
+				*old_value.decor_mut() = decor;
+			}
+		}
+		None => {
+			table.insert(key, toml_edit::value(new_value));
+		}
+	}
+}
@@ -1,17 +1,21 @@
 extern crate cgmath;
 #[macro_use]
 extern crate glium;
+extern crate glutin_egl_sys;
 extern crate image;
+extern crate libloading;
 
 #[macro_use]
 extern crate log;
 use log::{Level, LevelFilter, Metadata, Record};
 
 extern crate rand;
+extern crate rusttype;
 extern crate toml;
+extern crate toml_edit;
 
 mod presenter;
-use presenter::GLPresenter;
+use presenter::{GLPresenter, GlobeMesh};
 
 mod view;
 use view::GLView;
@@ -24,6 +28,8 @@ use database::FileDatabase;
 
 mod entities;
 
+mod cli;
+
 static LOGGER: SimpleLogger = SimpleLogger;
 struct SimpleLogger;
 impl log::Log for SimpleLogger {
@@ -44,7 +50,45 @@ fn main() {
 	log::set_logger(&LOGGER).unwrap();
 	log::set_max_level(LevelFilter::Info);
 
-	let database = FileDatabase::new("worlds/tellene/config.toml");
+	let args: Vec<String> = std::env::args().skip(1).collect();
+	if let Some(result) = cli::run_subcommand(&args) {
+		if let Err(err) = result {
+			error!("Main: {}", err);
+			if let cli::CliError::Usage(_) = err {
+				println!("{}", cli::USAGE);
+			}
+			std::process::exit(1);
+		}
+		return;
+	}
+
+	let regen_textures = args.iter().any(|arg| arg == "--regen-textures");
+	let globe_mesh = match args.iter().position(|arg| arg == "--globe-mesh") {
+		Some(i) => match args.get(i + 1).map(|arg| arg.parse::<GlobeMesh>()) {
+			Some(Ok(globe_mesh)) => Some(globe_mesh),
+			Some(Err(err)) => {
+				error!("Main: {}", err);
+				println!("{}", cli::USAGE);
+				std::process::exit(1);
+			}
+			None => {
+				println!("{}", cli::USAGE);
+				std::process::exit(1);
+			}
+		},
+		None => None,
+	};
+	let settings_file = match args.iter().position(|arg| arg == "--settings") {
+		Some(i) => match args.get(i + 1) {
+			Some(settings_file) => settings_file.as_str(),
+			None => {
+				println!("{}", cli::USAGE);
+				std::process::exit(1);
+			}
+		},
+		None => cli::DEFAULT_SETTINGS_FILE,
+	};
+	let database = FileDatabase::new(cli::DEFAULT_CONFIG_FILE, regen_textures);
 
 	let world = match ConcreteWorld::new(Box::new(database)) {
 		Ok(world) => world,
@@ -63,6 +107,10 @@ fn main() {
 	};
 
 	let mut presenter = GLPresenter::new(Box::new(view), Box::new(world));
+	presenter.load_settings(settings_file);
+	if let Some(globe_mesh) = globe_mesh {
+		presenter.set_globe_mesh(globe_mesh);
+	}
 	presenter.event_loop();
 
 	info!("Main is returning");
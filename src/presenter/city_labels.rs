@@ -0,0 +1,270 @@
+use super::city_markers::{marker_opacity, marker_radius_px};
+use super::glyph_atlas::{GlyphAtlas, TextLayout};
+use super::label_placement::{place_labels, LabelCandidate};
+use super::{Mesh, MeshObject, Shading, Vertex, View, ViewParams};
+use crate::entities::{City, SettlementTier};
+
+use cgmath::prelude::*;
+use cgmath::{Point2, Vector2, Vector3};
+
+use rusttype::Font;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+// Offsets the dark halo behind each label is drawn at, so it can be read on any terrain
+const HALO_OFFSETS_PX: [(f64, f64); 4] = [
+	(-1_f64, -1_f64),
+	(1_f64, -1_f64),
+	(-1_f64, 1_f64),
+	(1_f64, 1_f64),
+];
+const HALO_COLOR: [f64; 3] = [0.05_f64, 0.05_f64, 0.05_f64];
+
+/// How labels for a tier of settlement look
+#[derive(Clone, Debug)]
+pub struct LabelStyle {
+	pub font: String, // Name of one of the world's fonts
+	pub size_px: f64,
+	pub color: [f64; 3],
+}
+
+struct LabelledCity {
+	name: String,
+	tier: SettlementTier,
+	priority: u32,
+	position: Vector3<f64>,     // Unit vector from the world's center
+	layout: Option<TextLayout>, // Laid out the first time it's wanted
+}
+
+/// Labels with the names of the settlements on the globe, drawn on the screen beside their
+/// markers. They're only drawn for settlements on the near side of the globe whose markers
+/// are showing, and never overlap: when they would, the more populous settlement's label
+/// wins.
+///
+/// Labels are laid out with the glyph atlas they're given, which must be the same one every
+/// time.
+pub struct CityLabels {
+	cities: Vec<LabelledCity>,
+	styles: BTreeMap<SettlementTier, LabelStyle>, // Tiers without one aren't labelled
+	missing_fonts: HashSet<String>,               // Already warned about
+}
+impl CityLabels {
+	pub fn new(cities: &[City], styles: BTreeMap<SettlementTier, LabelStyle>) -> CityLabels {
+		CityLabels {
+			cities: cities
+				.iter()
+				.map(|city| LabelledCity {
+					name: city.name.clone(),
+					tier: city.tier(),
+					priority: city.population.unwrap_or(0),
+					position: city.coords.as_sph_point(1_f64).to_vec(),
+					layout: None,
+				})
+				.collect(),
+			styles,
+			missing_fonts: HashSet::new(),
+		}
+	}
+
+	/// The objects to draw the labels that fit on screen with, as seen with params. Any
+	/// glyphs they need that weren't in atlas are added to it.
+	pub fn update(
+		&mut self,
+		view: &dyn View,
+		atlas: &mut GlyphAtlas,
+		fonts: &HashMap<String, Font<'static>>,
+		params: &ViewParams,
+	) -> Vec<MeshObject> {
+		let altitude = params.altitude();
+		let screen_size = Vector2::new(params.viewport.0 as f64, params.viewport.1 as f64);
+
+		let mut candidates = Vec::new();
+		let mut candidate_cities = Vec::new();
+		for i in 0..self.cities.len() {
+			let (tier, position) = (self.cities[i].tier, self.cities[i].position);
+			if marker_opacity(tier, altitude) <= 0_f64 {
+				continue;
+			}
+			let anchor = match screen_pos(params, position, screen_size) {
+				Some(anchor) => anchor,
+				None => continue,
+			};
+			let size = match self.layout(i, atlas, fonts) {
+				Some(layout) => layout.size,
+				None => continue,
+			};
+			candidates.push(LabelCandidate {
+				anchor,
+				size,
+				margin: marker_radius_px(tier),
+				priority: self.cities[i].priority,
+			});
+			candidate_cities.push(i);
+		}
+
+		// A mesh for each tier, since they fade separately
+		let mut tier_vertices: BTreeMap<SettlementTier, (Vec<Vertex>, Vec<u32>)> = BTreeMap::new();
+		for placed in place_labels(&candidates, screen_size) {
+			let city = &self.cities[candidate_cities[placed.candidate]];
+			let (layout, style) = match (&city.layout, self.styles.get(&city.tier)) {
+				(Some(layout), Some(style)) => (layout, style),
+				_ => continue,
+			};
+			let (vertices, triangles) = tier_vertices
+				.entry(city.tier)
+				.or_insert_with(|| (Vec::new(), Vec::new()));
+			add_text(
+				vertices,
+				triangles,
+				layout,
+				placed.top_left,
+				style.color,
+				screen_size,
+			);
+		}
+
+		tier_vertices
+			.iter()
+			.map(|(&tier, (vertices, triangles))| {
+				let mut object = MeshObject::new(Mesh::new(view.get_facade(), vertices, triangles));
+				object.shading = Shading::Overlay {
+					opacity: marker_opacity(tier, altitude),
+				};
+				object
+			})
+			.collect()
+	}
+
+	// City i's label laid out in its tier's style, None if the tier isn't labelled or its
+	// font's missing
+	fn layout(
+		&mut self,
+		i: usize,
+		atlas: &mut GlyphAtlas,
+		fonts: &HashMap<String, Font<'static>>,
+	) -> Option<&TextLayout> {
+		if self.cities[i].layout.is_none() {
+			let style = self.styles.get(&self.cities[i].tier)?;
+			let font = match fonts.get(&style.font) {
+				Some(font) => font,
+				None => {
+					if self.missing_fonts.insert(style.font.clone()) {
+						warn!(
+							"No font {} for labels, the world's [fonts] should name it",
+							style.font
+						);
+					}
+					return None;
+				}
+			};
+			let layout = atlas.layout(&style.font, font, style.size_px, &self.cities[i].name);
+			self.cities[i].layout = Some(layout);
+		}
+		self.cities[i].layout.as_ref()
+	}
+}
+
+/// Where a point on the surface straight out along dir, a unit vector, is on a screen of
+/// screen_size pixels seen with params, in pixels from the top left. None if it's on the far
+/// side of the world, or off screen.
+pub fn screen_pos(
+	params: &ViewParams,
+	dir: Vector3<f64>,
+	screen_size: Vector2<f64>,
+) -> Option<Point2<f64>> {
+	let (world_pos, normal) = params.surface_pos(dir);
+	if (params.cam_pos - world_pos).dot(normal) <= 0_f64 {
+		return None;
+	}
+	let clip_pos = params.view_proj_mat * world_pos.to_homogeneous();
+	if clip_pos.w <= 0_f64 {
+		return None;
+	}
+	let ndc = clip_pos.truncate() / clip_pos.w;
+	if ndc.x.abs() > 1_f64 || ndc.y.abs() > 1_f64 {
+		return None;
+	}
+	Some(Point2::new(
+		(ndc.x + 1_f64) / 2_f64 * screen_size.x,
+		(1_f64 - ndc.y) / 2_f64 * screen_size.y,
+	))
+}
+
+/// Adds layout's text with its top left at top_left on a screen of screen_size pixels, over
+/// a dark halo so it can be read on any terrain, as overlay vertices in clip space
+pub fn add_text(
+	vertices: &mut Vec<Vertex>,
+	triangles: &mut Vec<u32>,
+	layout: &TextLayout,
+	top_left: Point2<f64>,
+	color: [f64; 3],
+	screen_size: Vector2<f64>,
+) {
+	for &(dx, dy) in &HALO_OFFSETS_PX {
+		let top_left = top_left + Vector2::new(dx, dy);
+		add_quads(
+			vertices,
+			triangles,
+			layout,
+			top_left,
+			HALO_COLOR,
+			screen_size,
+		);
+	}
+	add_quads(vertices, triangles, layout, top_left, color, screen_size);
+}
+
+// Adds a quad for each of layout's glyphs, with its top left at top_left on screen
+fn add_quads(
+	vertices: &mut Vec<Vertex>,
+	triangles: &mut Vec<u32>,
+	layout: &TextLayout,
+	top_left: Point2<f64>,
+	color: [f64; 3],
+	screen_size: Vector2<f64>,
+) {
+	for quad in &layout.quads {
+		add_quad(
+			vertices,
+			triangles,
+			(top_left + quad.min.to_vec(), top_left + quad.max.to_vec()),
+			(quad.tex_min, quad.tex_max),
+			color,
+			screen_size,
+		);
+	}
+}
+
+/// Adds a quad from the min to the max corner of rect, in pixels from the top left of a
+/// screen of screen_size pixels, textured from the min to the max corner of tex_rect, as
+/// overlay vertices in clip space
+pub fn add_quad(
+	vertices: &mut Vec<Vertex>,
+	triangles: &mut Vec<u32>,
+	rect: (Point2<f64>, Point2<f64>),
+	tex_rect: (Point2<f64>, Point2<f64>),
+	color: [f64; 3],
+	screen_size: Vector2<f64>,
+) {
+	let vertex = |x: f64, y: f64, u: f64, v: f64| Vertex {
+		position: [
+			x / screen_size.x * 2_f64 - 1_f64,
+			1_f64 - y / screen_size.y * 2_f64,
+			0_f64,
+			1_f64,
+		],
+		color,
+		tex_coords: [u, v, 0_f64],
+		..Default::default()
+	};
+	let ((min, max), (tex_min, tex_max)) = (rect, tex_rect);
+	let first = vertices.len() as u32;
+	vertices.extend_from_slice(&[
+		vertex(min.x, min.y, tex_min.x, tex_min.y),
+		vertex(max.x, min.y, tex_max.x, tex_min.y),
+		vertex(max.x, max.y, tex_max.x, tex_max.y),
+		vertex(min.x, max.y, tex_min.x, tex_max.y),
+	]);
+	// Clockwise on screen, so they're front facing
+	triangles.extend_from_slice(&[first, first + 1, first + 2, first, first + 2, first + 3]);
+}
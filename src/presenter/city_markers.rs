@@ -0,0 +1,277 @@
+use super::{MapProjection, Mesh, MeshObject, Shading, Vertex, View, ViewParams};
+use crate::entities::{City, SettlementTier};
+
+use cgmath::prelude::*;
+use cgmath::Vector3;
+
+use std::collections::BTreeMap;
+use std::f64::consts::PI;
+use std::rc::Rc;
+
+// Steps per doubling of the camera's altitude that markers are resized at. Between steps
+// they're left as they are, so the meshes aren't rebuilt every frame while zooming.
+const SIZE_STEPS_PER_DOUBLING: f64 = 8_f64;
+
+// Pixels of dark outline around every marker, so they stand out on any terrain
+const OUTLINE_PX: f64 = 1.5_f64;
+const OUTLINE_COLOR: [f64; 3] = [0.1_f64, 0.1_f64, 0.1_f64];
+
+/// How markers for a tier of settlement look
+#[derive(Clone, Copy, Debug)]
+struct MarkerStyle {
+	color: [f64; 3],
+	corners: u32,               // Corners of the polygon, or points of the star
+	star: bool,                 // Whether it's a star rather than a polygon
+	size_px: f64,               // From the middle to the corners, on screen
+	fade_altitude: Option<f64>, // World radii above the surface it's gone by, if it fades
+}
+
+fn marker_style(tier: SettlementTier) -> MarkerStyle {
+	match tier {
+		SettlementTier::Ruins => MarkerStyle {
+			color: [0.6_f64, 0.6_f64, 0.6_f64],
+			corners: 3,
+			star: false,
+			size_px: 4_f64,
+			fade_altitude: Some(0.1_f64),
+		},
+		SettlementTier::Thorp => MarkerStyle {
+			color: [1_f64, 1_f64, 0.7_f64],
+			corners: 8,
+			star: false,
+			size_px: 2.5_f64,
+			fade_altitude: Some(0.1_f64),
+		},
+		SettlementTier::Village => MarkerStyle {
+			color: [1_f64, 0.8_f64, 0.3_f64],
+			corners: 8,
+			star: false,
+			size_px: 3.5_f64,
+			fade_altitude: Some(0.3_f64),
+		},
+		SettlementTier::Town => MarkerStyle {
+			color: [1_f64, 0.5_f64, 0.1_f64],
+			corners: 4,
+			star: false,
+			size_px: 5_f64,
+			fade_altitude: Some(1_f64),
+		},
+		SettlementTier::City => MarkerStyle {
+			color: [0.9_f64, 0.1_f64, 0.1_f64],
+			corners: 5,
+			star: true,
+			size_px: 7_f64,
+			fade_altitude: None,
+		},
+	}
+}
+
+/// How opaque tier's markers are at altitude world radii above the surface
+pub fn marker_opacity(tier: SettlementTier, altitude: f64) -> f64 {
+	marker_style(tier).opacity(altitude)
+}
+
+/// How far tier's markers reach out from their middle on screen, outline and all
+pub fn marker_radius_px(tier: SettlementTier) -> f64 {
+	marker_style(tier).size_px + OUTLINE_PX
+}
+
+impl MarkerStyle {
+	// How opaque markers are at altitude. They fade out over the last half of the way up to
+	// their fade altitude.
+	fn opacity(&self, altitude: f64) -> f64 {
+		match self.fade_altitude {
+			Some(fade_altitude) => {
+				((fade_altitude - altitude) / (0.5_f64 * fade_altitude)).clamp(0_f64, 1_f64)
+			}
+			None => 1_f64,
+		}
+	}
+
+	// The marker's outline, as angles around its middle and distances out from it in
+	// multiples of size_px
+	fn outline(&self) -> Vec<(f64, f64)> {
+		let points = if self.star {
+			self.corners * 2
+		} else {
+			self.corners
+		};
+		(0..points)
+			.map(|i| {
+				// Pointing north
+				let angle = PI / 2_f64 + 2_f64 * PI * i as f64 / points as f64;
+				let radius = if self.star && i % 2 == 1 {
+					0.45_f64
+				} else {
+					1_f64
+				};
+				(angle, radius)
+			})
+			.collect()
+	}
+}
+
+/// One marker for every settlement on the globe, lying flat on the surface and sized to
+/// stay the same size on screen. Each tier of settlement is batched into a single mesh, so
+/// thousands of markers only take a draw per tier.
+///
+/// Markers face away from the world's center with their back faces culled, so the ones
+/// over the horizon aren't drawn. On the flat map they lie on it, facing up.
+pub struct CityMarkers {
+	positions: BTreeMap<SettlementTier, Vec<Vector3<f64>>>, // Unit vectors, by tier
+	meshes: BTreeMap<SettlementTier, Rc<Mesh>>,
+	size_step: Option<i64>,     // Which altitude step the meshes were built for
+	map: Option<MapProjection>, // Which flat map they were laid out on, if any
+}
+impl CityMarkers {
+	pub fn new(cities: &[City]) -> CityMarkers {
+		let mut positions = BTreeMap::new();
+		for city in cities {
+			positions
+				.entry(city.tier())
+				.or_insert_with(Vec::new)
+				.push(city.coords.as_sph_point(1_f64).to_vec());
+		}
+		info!("Marking {} cities on the globe", cities.len());
+
+		CityMarkers {
+			positions,
+			meshes: BTreeMap::new(),
+			size_step: None,
+			map: None,
+		}
+	}
+
+	/// The objects to draw the markers with as seen with params, least important first so
+	/// the rest are drawn over them. Tiers that have faded out entirely are left out.
+	pub fn update(&mut self, view: &dyn View, params: &ViewParams) -> Vec<MeshObject> {
+		let altitude = params.altitude();
+		let size_step = (altitude.max(f64::EPSILON).log2() * SIZE_STEPS_PER_DOUBLING).round();
+		if self.size_step != Some(size_step as i64) || self.map != params.map {
+			self.size_step = Some(size_step as i64);
+			self.map = params.map;
+			// World units per pixel, at the surface right below the camera
+			let step_altitude = (size_step / SIZE_STEPS_PER_DOUBLING).exp2() * params.world_radius;
+			let world_per_px = step_altitude / params.px_per_rad;
+			self.meshes = self
+				.positions
+				.iter()
+				.map(|(&tier, positions)| {
+					let mesh =
+						marker_mesh(view, params, &marker_style(tier), positions, world_per_px);
+					(tier, Rc::new(mesh))
+				})
+				.collect();
+		}
+
+		self.meshes
+			.iter()
+			.filter_map(|(&tier, mesh)| {
+				let opacity = marker_opacity(tier, altitude);
+				if opacity <= 0_f64 {
+					return None;
+				}
+				let mut object = MeshObject::from_shared(mesh.clone());
+				object.shading = Shading::Flat { opacity };
+				Some(object)
+			})
+			.collect()
+	}
+}
+
+// A single mesh of markers in style at positions, world_per_px world units to a pixel.
+// Each is its outline with the marker itself over it, both fans of triangles.
+fn marker_mesh(
+	view: &dyn View,
+	params: &ViewParams,
+	style: &MarkerStyle,
+	positions: &[Vector3<f64>],
+	world_per_px: f64,
+) -> Mesh {
+	let outline = style.outline();
+	let mut vertices = Vec::with_capacity(positions.len() * 2 * (outline.len() + 1));
+	let mut triangles = Vec::with_capacity(positions.len() * 6 * outline.len());
+
+	for &dir in positions {
+		let (middle, up) = params.surface_pos(dir);
+		let middle = middle.to_vec();
+		// East and north along the surface, or any two directions across it at the poles
+		let east = match Vector3::unit_y().cross(up) {
+			east if east.magnitude2() > f64::EPSILON => east.normalize(),
+			_ => Vector3::unit_x(),
+		};
+		let north = up.cross(east);
+
+		for &(size_px, color) in &[
+			(style.size_px + OUTLINE_PX, OUTLINE_COLOR),
+			(style.size_px, style.color),
+		] {
+			let vertex = |position: Vector3<f64>| Vertex {
+				position: position.extend(1_f64).into(),
+				color,
+				normal: up.into(),
+				..Default::default()
+			};
+			let first = vertices.len() as u32;
+			vertices.push(vertex(middle));
+			for &(angle, radius) in &outline {
+				let across = (east * angle.cos() + north * angle.sin()) * radius;
+				vertices.push(vertex(middle + across * size_px * world_per_px));
+			}
+			// The outline goes anticlockwise seen from above, and front faces are clockwise
+			let points = outline.len() as u32;
+			for i in 0..points {
+				triangles.extend_from_slice(&[first, first + 1 + (i + 1) % points, first + 1 + i]);
+			}
+		}
+	}
+	Mesh::new(view.get_facade(), &vertices, &triangles)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn markers_fade_over_the_last_half_of_the_way_up() {
+		let close = |a: f64, b: f64| (a - b).abs() < 1e-9_f64;
+		// Towns fade at 1 world radius
+		let town = |altitude| marker_opacity(SettlementTier::Town, altitude);
+		assert!(close(town(0_f64), 1_f64));
+		assert!(close(town(0.5_f64), 1_f64));
+		assert!(close(town(0.75_f64), 0.5_f64));
+		assert!(close(town(0.9_f64), 0.2_f64));
+		assert!(close(town(1_f64), 0_f64));
+		assert!(close(town(2_f64), 0_f64));
+		// Villages at 0.3
+		let village = |altitude| marker_opacity(SettlementTier::Village, altitude);
+		assert!(close(village(0.15_f64), 1_f64));
+		assert!(close(village(0.225_f64), 0.5_f64));
+		assert!(close(village(0.3_f64), 0_f64));
+	}
+
+	#[test]
+	fn smaller_settlements_fade_sooner_and_cities_never_do() {
+		let tiers = [
+			SettlementTier::Ruins,
+			SettlementTier::Thorp,
+			SettlementTier::Village,
+			SettlementTier::Town,
+		];
+		for &altitude in &[0.05_f64, 0.2_f64, 0.5_f64, 0.9_f64] {
+			let opacities: Vec<f64> = tiers
+				.iter()
+				.map(|&tier| marker_opacity(tier, altitude))
+				.collect();
+			assert!(
+				opacities.windows(2).all(|pair| pair[0] <= pair[1]),
+				"at {}: {:?}",
+				altitude,
+				opacities
+			);
+		}
+		for &altitude in &[0_f64, 1_f64, 100_f64] {
+			assert_eq!(marker_opacity(SettlementTier::City, altitude), 1_f64);
+		}
+	}
+}
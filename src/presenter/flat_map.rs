@@ -0,0 +1,518 @@
+use super::{Mesh, Vertex};
+use crate::entities::{LatLong, SphericalPoint};
+
+use cgmath::prelude::*;
+use cgmath::{Matrix4, Point2, Point3, Rad, Vector2, Vector3};
+use glium::backend::Facade;
+
+use std::f64::consts::{FRAC_PI_2, FRAC_PI_4, PI};
+
+// Furthest north and south Mercator maps go, 85.05°, where they're as tall as they are wide
+const MERCATOR_MAX_LAT: f64 = 1.484_422_229_745_332_4_f64;
+
+// How near the poles points are taken to be on them, as the sine of the angle off them.
+// Cylindrical maps stretch the poles out along their top and bottom edges.
+const POLE_EPSILON: f64 = 1e-9_f64;
+
+// How far past ±180° corners can be and still be on that edge, in radians
+const EDGE_EPSILON: f64 = 1e-9_f64;
+
+// Longest a triangle's edges can be on azimuthal maps, in world radii, before it's taken to
+// have been torn apart around the point opposite the map's center
+const MAX_AZIMUTHAL_EDGE: f64 = FRAC_PI_2;
+
+// East and north along the surface at up, a unit vector, or any two directions across it at
+// the poles
+fn east_north(up: Vector3<f64>) -> (Vector3<f64>, Vector3<f64>) {
+	let east = match Vector3::unit_y().cross(up) {
+		east if east.magnitude2() > 1e-18_f64 => east.normalize(),
+		_ => Vector3::unit_x(),
+	};
+	(east, up.cross(east))
+}
+
+/// How the globe's laid out on a flat map. Map coords are in world radii, with x going east
+/// and y going north, and (0, 0) in the middle of the map.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MapProjection {
+	Equirectangular, // Longitude across and latitude up
+	Mercator,        // Keeps shapes, stretched towards the poles, which it stops short of
+	// Keeps distances and directions from center, a unit vector from the world's center, with
+	// the point opposite it all the way around the edge
+	Azimuthal { center: Vector3<f64> },
+}
+impl MapProjection {
+	/// The projection with name, as settings files give it. Azimuthal maps are centred on
+	/// lat/long 0 until they're centred somewhere else.
+	pub fn from_name(name: &str) -> Option<MapProjection> {
+		match name {
+			"equirectangular" => Some(MapProjection::Equirectangular),
+			"mercator" => Some(MapProjection::Mercator),
+			"azimuthal" => Some(MapProjection::Azimuthal {
+				center: Vector3::unit_z(),
+			}),
+			_ => None,
+		}
+	}
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			MapProjection::Equirectangular => "equirectangular",
+			MapProjection::Mercator => "mercator",
+			MapProjection::Azimuthal { .. } => "azimuthal",
+		}
+	}
+
+	/// The projection to switch to after this one
+	pub fn next(&self) -> MapProjection {
+		match self {
+			MapProjection::Equirectangular => MapProjection::Mercator,
+			MapProjection::Mercator => MapProjection::Azimuthal {
+				center: Vector3::unit_z(),
+			},
+			MapProjection::Azimuthal { .. } => MapProjection::Equirectangular,
+		}
+	}
+
+	/// This projection centred on center, a unit vector, if it's azimuthal
+	pub fn centred_on(&self, center: Vector3<f64>) -> MapProjection {
+		match self {
+			MapProjection::Azimuthal { .. } => MapProjection::Azimuthal { center },
+			projection => *projection,
+		}
+	}
+
+	/// How far the map goes across and up from its middle, in world radii. Azimuthal maps are
+	/// the disc inside that.
+	pub fn extent(&self) -> Vector2<f64> {
+		match self {
+			MapProjection::Equirectangular => Vector2::new(PI, FRAC_PI_2),
+			_ => Vector2::new(PI, PI),
+		}
+	}
+
+	/// Where the point on the surface straight out along dir, a unit vector, is on the map
+	pub fn project(&self, dir: Vector3<f64>) -> Point2<f64> {
+		match *self {
+			MapProjection::Azimuthal { center } => {
+				let (east, north) = east_north(center);
+				let across = Vector2::new(dir.dot(east), dir.dot(north));
+				let across_len = across.magnitude();
+				let angle = across_len.atan2(dir.dot(center));
+				if across_len < f64::EPSILON {
+					// Either the middle, or the point opposite it, put at the bottom of the edge
+					return Point2::new(0_f64, if angle < FRAC_PI_2 { 0_f64 } else { -PI });
+				}
+				Point2::from_vec(across * (angle / across_len))
+			}
+			_ => self.project_lat_long(&SphericalPoint::from_vec(&dir).as_lat_long()),
+		}
+	}
+
+	/// Where lat_long is on the map. On cylindrical maps, longitudes of ±180° are on the edge
+	/// they're given as.
+	pub fn project_lat_long(&self, lat_long: &LatLong) -> Point2<f64> {
+		match self {
+			MapProjection::Equirectangular => Point2::new(lat_long.long.0, lat_long.lat.0),
+			MapProjection::Mercator => {
+				let lat = lat_long.lat.0.clamp(-MERCATOR_MAX_LAT, MERCATOR_MAX_LAT);
+				Point2::new(lat_long.long.0, (FRAC_PI_4 + lat / 2_f64).tan().ln())
+			}
+			MapProjection::Azimuthal { .. } => self.project(lat_long.as_sph_point(1_f64).to_vec()),
+		}
+	}
+
+	/// The unit vector out to the point on the surface at point on the map. None if it's off
+	/// the map.
+	pub fn unproject(&self, point: Point2<f64>) -> Option<Vector3<f64>> {
+		let extent = self.extent();
+		let lat_long_dir = |lat: f64, long: f64| {
+			LatLong::new(Rad(lat), Rad(long))
+				.as_sph_point(1_f64)
+				.to_vec()
+		};
+		match *self {
+			MapProjection::Azimuthal { center } => {
+				let angle = point.to_vec().magnitude();
+				if angle > PI {
+					return None;
+				}
+				if angle < f64::EPSILON {
+					return Some(center);
+				}
+				let (east, north) = east_north(center);
+				let across = (east * point.x + north * point.y) / angle;
+				Some(center * angle.cos() + across * angle.sin())
+			}
+			_ if point.x.abs() > extent.x || point.y.abs() > extent.y => None,
+			MapProjection::Equirectangular => Some(lat_long_dir(point.y, point.x)),
+			MapProjection::Mercator => Some(lat_long_dir(point.y.sinh().atan(), point.x)),
+		}
+	}
+
+	/// Keeps point on the map, moving it to the nearest edge if it's off
+	pub fn clamp(&self, point: Point2<f64>) -> Point2<f64> {
+		let extent = self.extent();
+		match self {
+			MapProjection::Azimuthal { .. } if point.to_vec().magnitude() > PI => {
+				Point2::from_vec(point.to_vec().normalize_to(PI))
+			}
+			_ => Point2::new(
+				point.x.clamp(-extent.x, extent.x),
+				point.y.clamp(-extent.y, extent.y),
+			),
+		}
+	}
+
+	/// How many world radii north to south the map is around dir, a unit vector, for each world
+	/// radius of ground
+	pub fn scale_at(&self, dir: Vector3<f64>) -> f64 {
+		match self {
+			MapProjection::Mercator => {
+				let lat = dir.y.clamp(-1_f64, 1_f64).asin();
+				1_f64 / lat.clamp(-MERCATOR_MAX_LAT, MERCATOR_MAX_LAT).cos()
+			}
+			_ => 1_f64,
+		}
+	}
+
+	/// Where the point on the surface straight out along dir, a unit vector, is on the map laid
+	/// flat facing +z, with its middle at the world's center and scaled up by its radius
+	pub fn map_pos(&self, dir: Vector3<f64>, center: Point3<f64>, radius: f64) -> Point3<f64> {
+		let point = self.project(dir);
+		center + Vector3::new(point.x, point.y, 0_f64) * radius
+	}
+
+	/// The unit vector out to the point on the surface at pos on the map laid flat around the
+	/// world's center, as map_pos lays it. None if it's off the map.
+	pub fn map_dir(
+		&self,
+		pos: Point3<f64>,
+		center: Point3<f64>,
+		radius: f64,
+	) -> Option<Vector3<f64>> {
+		let disp = (pos - center) / radius;
+		self.unproject(Point2::new(disp.x, disp.y))
+	}
+
+	/// Lays mesh, on the surface of the sphere around center, flat on the map as map_pos does,
+	/// uploaded to facade if there is one. Triangles around a pole are stretched out along the
+	/// edge of cylindrical maps, and those across ±180° drawn at both edges. On azimuthal maps,
+	/// those torn apart around the edge are left out.
+	pub fn flatten<F>(
+		&self,
+		facade: Option<&F>,
+		mesh: &Mesh,
+		center: Point3<f64>,
+		radius: f64,
+	) -> Mesh
+	where
+		F: ?Sized + Facade,
+	{
+		let dirs: Vec<Vector3<f64>> = mesh
+			.vertices
+			.iter()
+			.map(|vertex| {
+				let [x, y, z, _] = vertex.position;
+				(Point3::new(x, y, z) - center).normalize()
+			})
+			.collect();
+		let flat_vertex = |index: usize, point: Point2<f64>| Vertex {
+			position: (center + Vector3::new(point.x, point.y, 0_f64) * radius)
+				.to_homogeneous()
+				.into(),
+			normal: [0_f64, 0_f64, 1_f64],
+			..mesh.vertices[index]
+		};
+
+		// Every vertex where it's projected to, with copies added wherever triangles need them
+		// somewhere else
+		let points: Vec<Point2<f64>> = dirs.iter().map(|&dir| self.project(dir)).collect();
+		let mut vertices: Vec<Vertex> = (0..points.len())
+			.map(|index| flat_vertex(index, points[index]))
+			.collect();
+		let mut triangles = Vec::with_capacity(mesh.triangles_list.len());
+
+		for triangle in mesh.triangles_list.chunks(3) {
+			let corners: Vec<(usize, Point2<f64>)> = triangle
+				.iter()
+				.map(|&index| (index as usize, points[index as usize]))
+				.collect();
+			let polygons = match self {
+				MapProjection::Azimuthal { .. } => {
+					// Torn triangles come out stretched, or turned over so they're anticlockwise
+					let (a, b, c) = (corners[0].1, corners[1].1, corners[2].1);
+					let turned_over = (b - a).perp_dot(c - a) > 0_f64;
+					let torn = turned_over
+						|| (0..3).any(|i| {
+							(corners[(i + 1) % 3].1 - corners[i].1).magnitude() > MAX_AZIMUTHAL_EDGE
+						});
+					if torn {
+						Vec::new()
+					} else {
+						vec![corners]
+					}
+				}
+				_ => mend_cylindrical(&corners, &dirs),
+			};
+
+			for polygon in polygons {
+				let indices: Vec<u32> = polygon
+					.iter()
+					.map(|&(index, point)| {
+						if point == points[index] {
+							index as u32
+						} else {
+							vertices.push(flat_vertex(index, point));
+							vertices.len() as u32 - 1
+						}
+					})
+					.collect();
+				// A fan, which keeps the triangle's winding
+				for i in 1..indices.len().saturating_sub(1) {
+					triangles.extend_from_slice(&[indices[0], indices[i], indices[i + 1]]);
+				}
+			}
+		}
+		Mesh::new(facade, &vertices, &triangles)
+	}
+}
+
+// The polygons a triangle with corners, as vertex indices and where they're projected to, is
+// drawn with on a cylindrical map. A corner on a pole, which could be at any longitude, is
+// stretched out along the edge between the longitudes of the corners either side of it. A
+// triangle across ±180° is drawn once with its corners moved east of the edge, and again west.
+fn mend_cylindrical(
+	corners: &[(usize, Point2<f64>)],
+	dirs: &[Vector3<f64>],
+) -> Vec<Vec<(usize, Point2<f64>)>> {
+	let on_pole = |index: usize| dirs[index].y.abs() > 1_f64 - POLE_EPSILON;
+	let mut corners = corners.to_vec();
+	let off_pole: Vec<f64> = corners
+		.iter()
+		.filter(|&&(index, _)| !on_pole(index))
+		.map(|&(_, point)| point.x)
+		.collect();
+	if off_pole.len() < 2 {
+		return Vec::new(); // All along a pole's edge, so there's nothing to draw
+	}
+
+	let min_x = off_pole.iter().cloned().fold(f64::INFINITY, f64::min);
+	let max_x = off_pole.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+	let across_edge = max_x - min_x > PI;
+	if across_edge {
+		for corner in corners.iter_mut() {
+			if corner.1.x < 0_f64 && !on_pole(corner.0) {
+				corner.1.x += 2_f64 * PI;
+			}
+		}
+	}
+
+	let mut polygon = Vec::with_capacity(4);
+	for i in 0..corners.len() {
+		let (index, point) = corners[i];
+		if on_pole(index) {
+			let before = corners[(i + corners.len() - 1) % corners.len()].1;
+			let after = corners[(i + 1) % corners.len()].1;
+			polygon.push((index, Point2::new(before.x, point.y)));
+			polygon.push((index, Point2::new(after.x, point.y)));
+		} else {
+			polygon.push((index, point));
+		}
+	}
+
+	if !across_edge {
+		return vec![polygon];
+	}
+	// Either copy can be left out if it's all past its edge, as tiles ending at ±180° are when
+	// their corners there come out on the wrong side
+	let xs = polygon.iter().map(|&(_, point)| point.x);
+	let (min_x, max_x) = (
+		xs.clone().fold(f64::INFINITY, f64::min),
+		xs.fold(f64::NEG_INFINITY, f64::max),
+	);
+	let mut polygons = Vec::with_capacity(2);
+	if max_x > PI + EDGE_EPSILON {
+		let west = polygon
+			.iter()
+			.map(|&(index, point)| (index, Point2::new(point.x - 2_f64 * PI, point.y)))
+			.collect();
+		polygons.push(west);
+	}
+	if min_x < PI - EDGE_EPSILON {
+		polygons.push(polygon);
+	}
+	polygons
+}
+
+/// The camera over the flat map, looking straight down on it with north up. It's kept over
+/// the map, and between a min and max altitude over it.
+#[derive(Clone, Debug)]
+pub struct FlatMap {
+	projection: MapProjection,
+	center: Point2<f64>, // Map coords straight under the camera
+	altitude: f64,       // World radii the camera's over the map
+	min_altitude: f64,
+	max_altitude: f64,
+}
+impl FlatMap {
+	/// The map laid out with projection, seen from over focus, a unit vector, with as much
+	/// ground around it in view as the globe would have altitude world radii over it.
+	/// Azimuthal maps are centred on focus.
+	pub fn new(
+		projection: MapProjection,
+		focus: Vector3<f64>,
+		altitude: f64,
+		min_altitude: f64,
+		max_altitude: f64,
+	) -> FlatMap {
+		let mut map = FlatMap {
+			projection: projection.centred_on(focus),
+			center: Point2::origin(),
+			altitude,
+			min_altitude,
+			max_altitude,
+		};
+		map.look_at(focus, altitude);
+		map
+	}
+
+	pub fn projection(&self) -> MapProjection {
+		self.projection
+	}
+
+	/// Lays the map out with projection instead, still over the same point with as much
+	/// ground around it in view. Azimuthal maps are centred on that point.
+	pub fn set_projection(&mut self, projection: MapProjection) {
+		let (focus, altitude) = (self.focus(), self.ground_altitude());
+		self.projection = projection.centred_on(focus);
+		self.look_at(focus, altitude);
+	}
+
+	/// Moves the camera over focus, a unit vector, with as much ground around it in view as
+	/// the globe would have altitude world radii over it, as far as its limits let it
+	pub fn look_at(&mut self, focus: Vector3<f64>, altitude: f64) {
+		self.center = self.projection.clamp(self.projection.project(focus));
+		self.altitude = altitude * self.projection.scale_at(focus);
+		self.zoom(1_f64);
+	}
+
+	/// The unit vector out to the point on the surface in the middle of the screen
+	pub fn focus(&self) -> Vector3<f64> {
+		self.projection
+			.unproject(self.center)
+			.unwrap_or_else(Vector3::unit_z)
+	}
+
+	/// How high over the globe the camera would have as much ground around the focus in view
+	pub fn ground_altitude(&self) -> f64 {
+		self.altitude / self.projection.scale_at(self.focus())
+	}
+
+	/// Moves the camera across the map by by, in world radii, no further than its edges
+	pub fn pan(&mut self, by: Vector2<f64>) {
+		self.center = self.projection.clamp(self.center + by);
+	}
+
+	/// Divides the camera's altitude by factor, so more than 1 zooms in. It stops at its
+	/// limits.
+	pub fn zoom(&mut self, factor: f64) {
+		self.altitude = (self.altitude / factor).clamp(self.min_altitude, self.max_altitude);
+	}
+
+	/// Pans and zooms the way a locked Camera's move_cam does: +vec.x = east, +vec.y = in,
+	/// +vec.z = south. Panning is slower the lower the camera is, so the ground moves by about
+	/// as fast on screen.
+	pub fn move_map(&mut self, vec: Vector3<f64>, elapsed: f64, pan_speed: f64, zoom_speed: f64) {
+		let pan = elapsed * pan_speed * self.altitude;
+		self.pan(Vector2::new(vec.x, -vec.z) * pan);
+		if vec.y.abs() > 0.01_f64 {
+			let zoom_factor = (zoom_speed * vec.y.abs()).powf(elapsed);
+			self.zoom(if vec.y < 0_f64 {
+				1_f64 / zoom_factor
+			} else {
+				zoom_factor
+			});
+		}
+	}
+
+	/// Where the camera is, over the map laid flat around the world's center as
+	/// MapProjection::map_pos lays it
+	pub fn cam_pos(&self, center: Point3<f64>, radius: f64) -> Point3<f64> {
+		center + Vector3::new(self.center.x, self.center.y, self.altitude) * radius
+	}
+
+	pub fn view_mat(&self, center: Point3<f64>, radius: f64) -> Matrix4<f64> {
+		let pos = self.cam_pos(center, radius);
+		Matrix4::look_at(pos, pos - Vector3::unit_z(), Vector3::unit_y())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use cgmath::Deg;
+
+	fn lat_long_dir(lat: f64, long: f64) -> Vector3<f64> {
+		LatLong::new(Deg(lat), Deg(long))
+			.as_sph_point(1_f64)
+			.to_vec()
+	}
+
+	// Points laid out on the map with projection come back to where they were, through
+	// world coords as well as map coords
+	fn assert_round_trips(projection: MapProjection, lats: &[f64]) {
+		let (center, radius) = (Point3::new(1_f64, -2_f64, 3_f64), 2.5_f64);
+		for &lat in lats {
+			for &long in &[-179_f64, -90_f64, -30_f64, 0_f64, 45_f64, 120_f64, 179_f64] {
+				let dir = lat_long_dir(lat, long);
+				let back = projection.unproject(projection.project(dir)).unwrap();
+				assert!(
+					(back - dir).magnitude() < 1e-9_f64,
+					"{:?} at {}, {}: {:?}",
+					projection,
+					lat,
+					long,
+					back
+				);
+				let pos = projection.map_pos(dir, center, radius);
+				let back = projection.map_dir(pos, center, radius).unwrap();
+				assert!((back - dir).magnitude() < 1e-9_f64);
+			}
+		}
+	}
+
+	#[test]
+	fn cylindrical_maps_round_trip() {
+		let lats = [-80_f64, -45_f64, 0_f64, 10_f64, 60_f64, 85_f64];
+		assert_round_trips(MapProjection::Equirectangular, &lats);
+		assert_round_trips(MapProjection::Mercator, &lats);
+	}
+
+	#[test]
+	fn azimuthal_maps_round_trip() {
+		// Everywhere but the point opposite the center, which is all the way around the edge
+		let lats = [-89_f64, -45_f64, 0_f64, 30_f64, 89_f64];
+		for &(lat, long) in &[(0_f64, 0_f64), (35_f64, 5_f64), (-60_f64, 150_f64)] {
+			let center = lat_long_dir(lat, long);
+			assert_round_trips(MapProjection::Azimuthal { center }, &lats);
+		}
+	}
+
+	#[test]
+	fn points_off_the_map_arent_anywhere() {
+		let off = [
+			(MapProjection::Equirectangular, Point2::new(0_f64, 1.6_f64)),
+			(MapProjection::Equirectangular, Point2::new(3.2_f64, 0_f64)),
+			(MapProjection::Mercator, Point2::new(-3.2_f64, 0_f64)),
+			(MapProjection::Mercator, Point2::new(0_f64, 3.2_f64)),
+			(
+				MapProjection::from_name("azimuthal").unwrap(),
+				Point2::new(2.3_f64, 2.3_f64),
+			),
+		];
+		for &(projection, point) in &off {
+			assert_eq!(projection.unproject(point), None, "{:?}", projection);
+		}
+	}
+}
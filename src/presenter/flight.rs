@@ -0,0 +1,201 @@
+use cgmath::prelude::*;
+use cgmath::Vector3;
+
+use std::f64::consts::PI;
+
+// How long flights take: a minimum, then more for each radian flown around the globe and
+// each doubling or halving of the altitude, up to a maximum
+const MIN_SECS: f64 = 0.75_f64;
+const SECS_PER_RAD: f64 = 0.75_f64;
+const SECS_PER_DOUBLING: f64 = 0.15_f64;
+const MAX_SECS: f64 = 4_f64;
+
+// How high flights climb partway, in sphere radii per radian flown, so the way there can be
+// seen. Flights that start or end higher than that don't climb at all.
+const CLIMB_PER_RAD: f64 = 0.5_f64;
+
+/// A camera's flight around a sphere from one point over it to another, along the great
+/// circle between them. It eases in and out, and climbs partway through long flights.
+///
+/// Points over the sphere are a unit vector from its center and an altitude above its
+/// surface, in world units.
+#[derive(Clone, Debug)]
+pub struct Flight {
+	from_dir: Vector3<f64>,
+	from_altitude: f64,
+	to_dir: Vector3<f64>,
+	to_altitude: f64,
+	angle: f64, // Between the directions, in radians
+	climb: f64, // Extra altitude halfway through
+	duration: f64,
+}
+impl Flight {
+	pub fn new(
+		from_dir: Vector3<f64>,
+		from_altitude: f64,
+		to_dir: Vector3<f64>,
+		to_altitude: f64,
+		radius: f64,
+	) -> Flight {
+		let (from_dir, to_dir) = (from_dir.normalize(), to_dir.normalize());
+		let angle = from_dir.dot(to_dir).clamp(-1_f64, 1_f64).acos();
+		let climb = (angle * CLIMB_PER_RAD * radius - from_altitude.max(to_altitude)).max(0_f64);
+		let doublings = (to_altitude / from_altitude).log2().abs();
+		let duration = MIN_SECS + angle * SECS_PER_RAD + doublings * SECS_PER_DOUBLING;
+		Flight {
+			from_dir,
+			from_altitude,
+			to_dir,
+			to_altitude,
+			angle,
+			climb,
+			duration: if duration.is_finite() {
+				duration.min(MAX_SECS)
+			} else {
+				MAX_SECS
+			},
+		}
+	}
+
+	/// How long the flight takes, in seconds
+	pub fn duration(&self) -> f64 {
+		self.duration
+	}
+
+	/// How far through the flight it is secs seconds in, from 0 to 1, eased in and out just
+	/// like it moves
+	pub fn progress(&self, secs: f64) -> f64 {
+		let t = (secs / self.duration).clamp(0_f64, 1_f64);
+		(1_f64 - (PI * t).cos()) / 2_f64
+	}
+
+	/// Where the flight is secs seconds in, as a direction and altitude. Before it starts
+	/// it's at the start, and after it ends it's at the end.
+	pub fn sample(&self, secs: f64) -> (Vector3<f64>, f64) {
+		let t = (secs / self.duration).clamp(0_f64, 1_f64);
+		let eased = self.progress(secs);
+
+		let dir = if self.angle < 1e-9_f64 {
+			self.to_dir
+		} else {
+			// Around the great circle, or any of them when flying to the other side
+			let across = self.to_dir - self.from_dir * self.from_dir.dot(self.to_dir);
+			let across = match across.magnitude2() {
+				len2 if len2 > 1e-18_f64 => across.normalize(),
+				_ => match self.from_dir.cross(Vector3::unit_y()) {
+					axis if axis.magnitude2() > 1e-18_f64 => axis.normalize(),
+					_ => Vector3::unit_x(),
+				},
+			};
+			let angle = self.angle * eased;
+			self.from_dir * angle.cos() + across * angle.sin()
+		};
+
+		// Evenly in and out between the altitudes, in how many times higher it is
+		let altitude = if self.from_altitude > 0_f64 && self.to_altitude > 0_f64 {
+			self.from_altitude * (self.to_altitude / self.from_altitude).powf(eased)
+		} else {
+			self.from_altitude + (self.to_altitude - self.from_altitude) * eased
+		};
+		(dir, altitude + self.climb * (PI * t).sin())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn assert_dir_near(a: Vector3<f64>, b: Vector3<f64>) {
+		assert!((a - b).magnitude() < 1e-9_f64, "{:?} isn't {:?}", a, b);
+	}
+
+	#[test]
+	fn starts_and_ends_where_its_told() {
+		let (from, to) = (
+			Vector3::new(1_f64, 0_f64, 0_f64),
+			Vector3::new(0_f64, 3_f64, 4_f64),
+		);
+		let flight = Flight::new(from, 0.5_f64, to, 0.01_f64, 1_f64);
+
+		for &secs in &[-1_f64, 0_f64] {
+			let (dir, altitude) = flight.sample(secs);
+			assert_dir_near(dir, from);
+			assert!((altitude - 0.5_f64).abs() < 1e-9_f64);
+		}
+		for &secs in &[flight.duration(), flight.duration() + 1_f64] {
+			let (dir, altitude) = flight.sample(secs);
+			assert_dir_near(dir, to.normalize());
+			assert!((altitude - 0.01_f64).abs() < 1e-9_f64);
+		}
+	}
+
+	#[test]
+	fn midpoint_is_on_the_great_circle() {
+		let (from, to) = (
+			Vector3::new(1_f64, 0_f64, 0_f64),
+			Vector3::new(1_f64, 1_f64, 1_f64),
+		);
+		let flight = Flight::new(from, 0.1_f64, to, 0.1_f64, 1_f64);
+		let (dir, altitude) = flight.sample(flight.duration() / 2_f64);
+
+		assert!((dir.magnitude() - 1_f64).abs() < 1e-9_f64);
+		assert!(dir.dot(from.cross(to)).abs() < 1e-9_f64);
+		assert_dir_near(dir, (from + to.normalize()).normalize());
+		assert!(altitude >= 0.1_f64);
+	}
+
+	#[test]
+	fn easing_increases_and_stops_at_the_end() {
+		let flight = Flight::new(
+			Vector3::unit_x(),
+			0.1_f64,
+			Vector3::unit_y(),
+			0.2_f64,
+			1_f64,
+		);
+		let steps = 100;
+		let mut last = flight.progress(0_f64);
+		assert_eq!(last, 0_f64);
+		for step in 1..=steps {
+			let progress = flight.progress(flight.duration() * step as f64 / steps as f64);
+			assert!(progress > last);
+			last = progress;
+		}
+		assert!((last - 1_f64).abs() < 1e-12_f64);
+		assert_eq!(flight.progress(flight.duration() * 2_f64), 1_f64);
+		assert_eq!(flight.progress(-1_f64), 0_f64);
+	}
+
+	#[test]
+	fn antipodal_flight_stays_on_the_sphere() {
+		let from = Vector3::new(0_f64, 0_f64, 1_f64);
+		let flight = Flight::new(from, 0.1_f64, -from, 0.1_f64, 1_f64);
+		assert!(flight.duration() <= MAX_SECS);
+
+		let steps = 20;
+		for step in 0..=steps {
+			let (dir, altitude) = flight.sample(flight.duration() * step as f64 / steps as f64);
+			assert!((dir.magnitude() - 1_f64).abs() < 1e-9_f64);
+			assert!(altitude.is_finite() && altitude >= 0.1_f64 - 1e-9_f64);
+		}
+		assert_dir_near(flight.sample(flight.duration()).0, -from);
+	}
+
+	#[test]
+	fn zero_distance_flight_stays_put() {
+		let dir = Vector3::new(0_f64, 1_f64, 0_f64);
+		let flight = Flight::new(dir, 0.1_f64, dir, 0.1_f64, 1_f64);
+		assert!((flight.duration() - MIN_SECS).abs() < 1e-12_f64);
+		for &secs in &[0_f64, flight.duration() / 2_f64, flight.duration()] {
+			let (sampled_dir, altitude) = flight.sample(secs);
+			assert_dir_near(sampled_dir, dir);
+			assert!((altitude - 0.1_f64).abs() < 1e-9_f64);
+		}
+
+		// Only changing altitude, from and to the surface
+		let flight = Flight::new(dir, 0_f64, dir, 0.2_f64, 1_f64);
+		assert!(flight.duration().is_finite());
+		let (_, altitude) = flight.sample(flight.duration() / 2_f64);
+		assert!((altitude - 0.1_f64).abs() < 1e-9_f64);
+	}
+}
@@ -2,7 +2,7 @@
 
 in vec3 v_position;
 in vec3 v_normal;
-//in vec4 v_color;
+in vec4 v_color;
 in vec3 v_tex_coords;
 
 out vec4 color;
@@ -11,12 +11,25 @@ uniform dvec3 u_light_pos;
 uniform dvec4 u_light_color;
 uniform dvec4 u_light_ambient;
 uniform sampler2DArray u_tex;
+uniform sampler2D u_overlay_tex;
+uniform bool u_flat;
+uniform bool u_overlay;
+uniform double u_opacity;
 
 void main() {
-	dvec4 v_color = texture(u_tex, v_tex_coords);
+	if (u_overlay) {
+		color = vec4(v_color.rgb, texture(u_overlay_tex, v_tex_coords.xy).a * u_opacity);
+		return;
+	}
+	if (u_flat) {
+		color = vec4(v_color.rgb, v_color.a * u_opacity);
+		return;
+	}
+
+	dvec4 tex_color = texture(u_tex, v_tex_coords);
 	dvec3 vert_to_light = normalize(u_light_pos - v_position);
-	dvec4 i_diffuse = v_color * u_light_color * max(dot(v_normal, vert_to_light), 0.0);
-	dvec4 i_ambient = v_color * u_light_ambient;
+	dvec4 i_diffuse = tex_color * u_light_color * max(dot(v_normal, vert_to_light), 0.0);
+	dvec4 i_ambient = tex_color * u_light_ambient;
 
 	color = vec4(clamp(i_diffuse + i_ambient, 0.0, 1.0));
 }
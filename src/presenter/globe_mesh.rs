@@ -0,0 +1,332 @@
+use super::{TextureLayers, Vertex};
+use crate::entities::{LatLong, MapPieceKey, Model, SphericalPoint};
+
+use cgmath::prelude::*;
+use cgmath::{Deg, Vector3};
+
+// How close to a pole a corner has to be to count as on it, as a unit vector's y
+const POLE_EPSILON: f64 = 1e-12;
+
+/// Which mesh the globe is drawn with, for maps without a tile pyramid
+#[derive(Clone, Debug, PartialEq)]
+pub enum GlobeMesh {
+	/// A quad between every pair of parallels and meridians deg_resolution degrees apart
+	UvSphere { deg_resolution: u32 },
+	/// An icosahedron with every triangle split into four, subdivisions times over
+	Icosphere { subdivisions: u32 },
+	/// A cube with every face split into a 2^subdivisions by 2^subdivisions grid of quads
+	CubeSphere { subdivisions: u32 },
+	/// One of the world's models, pushed out onto the sphere
+	Model { name: String },
+}
+
+impl std::str::FromStr for GlobeMesh {
+	type Err = String;
+
+	/// Parses uv-sphere:<deg_resolution>, icosphere:<subdivisions>,
+	/// cube-sphere:<subdivisions> or model:<name>
+	fn from_str(s: &str) -> Result<GlobeMesh, String> {
+		let mut parts = s.splitn(2, ':');
+		let kind = parts.next().unwrap_or("");
+		let value = parts
+			.next()
+			.ok_or_else(|| format!("globe mesh {:?} is missing :<value>", s))?;
+		if kind == "model" {
+			return Ok(GlobeMesh::Model {
+				name: value.to_string(),
+			});
+		}
+		let n = value
+			.parse::<u32>()
+			.map_err(|_| format!("globe mesh {:?} doesn't end in :<number>", s))?;
+		match kind {
+			"uv-sphere" if n > 0 && 180 % n == 0 => Ok(GlobeMesh::UvSphere { deg_resolution: n }),
+			"uv-sphere" => Err(format!("UV sphere resolution {} doesn't divide 180°", n)),
+			"icosphere" => Ok(GlobeMesh::Icosphere { subdivisions: n }),
+			"cube-sphere" => Ok(GlobeMesh::CubeSphere { subdivisions: n }),
+			_ => Err(format!("unknown globe mesh {:?}", kind)),
+		}
+	}
+}
+
+type Triangle = [Vector3<f64>; 3];
+
+// Unit vector from the world's center through lat/long
+fn lat_long_dir(lat_deg: f64, long_deg: f64) -> Vector3<f64> {
+	LatLong::new(Deg(lat_deg), Deg(long_deg))
+		.as_sph_point(1_f64)
+		.to_vec()
+}
+
+// Splits each triangle into four, with new corners on the sphere halfway along each edge
+fn subdivide(triangles: Vec<Triangle>) -> Vec<Triangle> {
+	let mut split = Vec::with_capacity(triangles.len() * 4);
+	for [a, b, c] in triangles {
+		let (ab, bc, ca) = (
+			(a + b).normalize(),
+			(b + c).normalize(),
+			(c + a).normalize(),
+		);
+		split.extend_from_slice(&[[a, ab, ca], [ab, b, bc], [ca, bc, c], [ab, bc, ca]]);
+	}
+	split
+}
+
+/// The triangles of a unit icosphere. The icosahedron has a corner at each pole, so no
+/// triangle has a pole inside it.
+pub fn icosphere(subdivisions: u32) -> Vec<Triangle> {
+	let ring_lat = 0.5_f64.atan().to_degrees();
+	let north = Vector3::unit_y();
+	let south = -north;
+	let upper = |i: u32| lat_long_dir(ring_lat, (i % 5) as f64 * 72_f64);
+	let lower = |i: u32| lat_long_dir(-ring_lat, (i % 5) as f64 * 72_f64 + 36_f64);
+
+	let mut triangles = Vec::new();
+	for i in 0..5 {
+		triangles.push([north, upper(i), upper(i + 1)]);
+		triangles.push([upper(i), lower(i), upper(i + 1)]);
+		triangles.push([upper(i + 1), lower(i), lower(i + 1)]);
+		triangles.push([south, lower(i + 1), lower(i)]);
+	}
+	for _ in 0..subdivisions {
+		triangles = subdivide(triangles);
+	}
+	triangles
+}
+
+/// The triangles of a unit cube-sphere. Grid lines are spaced by angle rather than along the
+/// cube's faces, so quads near the middle of a face aren't much bigger than near its corners.
+pub fn cube_sphere(subdivisions: u32) -> Vec<Triangle> {
+	let divs = 1_u32 << subdivisions;
+	let (x, y, z) = (Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z());
+	// The middle of each face, and the directions its grid goes in
+	let faces = [
+		(x, y, z),
+		(-x, z, y),
+		(y, z, x),
+		(-y, x, z),
+		(z, x, y),
+		(-z, y, x),
+	];
+
+	let mut triangles = Vec::new();
+	for &(normal, across, down) in faces.iter() {
+		let point = |column: u32, row: u32| {
+			let angle = |i: u32| {
+				(std::f64::consts::FRAC_PI_4 * (2_f64 * i as f64 / divs as f64 - 1_f64)).tan()
+			};
+			(normal + across * angle(column) + down * angle(row)).normalize()
+		};
+		for row in 0..divs {
+			for column in 0..divs {
+				let (a, b) = (point(column, row), point(column + 1, row));
+				let (c, d) = (point(column, row + 1), point(column + 1, row + 1));
+				triangles.push([a, b, c]);
+				triangles.push([b, d, c]);
+			}
+		}
+	}
+	triangles
+}
+
+/// The triangles of model, with every corner moved onto the unit sphere
+pub fn model_triangles(model: &Model) -> Vec<Triangle> {
+	let corner = |index: u32| Vector3::from(model.vertices[index as usize].position).normalize();
+	model
+		.triangles
+		.chunks(3)
+		.map(|triangle| {
+			[
+				corner(triangle[0]),
+				corner(triangle[1]),
+				corner(triangle[2]),
+			]
+		})
+		.filter(|triangle| triangle.iter().all(|corner| corner.magnitude() > 0.5_f64))
+		.collect()
+}
+
+fn at_pole(point: &Vector3<f64>) -> bool {
+	point.y.abs() >= 1_f64 - POLE_EPSILON
+}
+
+// The triangle split into three that meet at a pole, if it has a pole inside it or on an
+// edge, since its longitudes can't be laid out without a gap otherwise
+fn split_at_poles(triangle: Triangle) -> Vec<Triangle> {
+	for &pole in [Vector3::unit_y(), -Vector3::unit_y()].iter() {
+		if triangle.iter().any(at_pole) {
+			break;
+		}
+		let sides: Vec<f64> = (0..3)
+			.map(|i| pole.dot(triangle[i].cross(triangle[(i + 1) % 3])))
+			.collect();
+		// On the inner side of every edge, and not on the far side of the world
+		let inside = (sides.iter().all(|&side| side > -POLE_EPSILON)
+			|| sides.iter().all(|&side| side < POLE_EPSILON))
+			&& pole.dot(triangle[0] + triangle[1] + triangle[2]) > 0_f64;
+		if inside {
+			// If the pole's on an edge, the piece along that edge would be flat, so is left out
+			let [a, b, c] = triangle;
+			return [[pole, a, b], [pole, b, c], [pole, c, a]]
+				.iter()
+				.zip(sides)
+				.filter(|&(_, side)| side.abs() > POLE_EPSILON)
+				.map(|(&piece, _)| piece)
+				.collect();
+		}
+	}
+	vec![triangle]
+}
+
+// The triangle as a polygon of [lat, long] in degrees, with longitudes unwrapped so it
+// doesn't jump across 180°. A corner at a pole becomes an edge along it, between the
+// longitudes of the corners either side, since that's where the meridians to them meet it.
+fn lat_long_polygon(triangle: &Triangle) -> Option<Vec<[f64; 2]>> {
+	let lat_long: Vec<Option<[f64; 2]>> = triangle
+		.iter()
+		.map(|point| {
+			if at_pole(point) {
+				return None;
+			}
+			let lat_long = SphericalPoint::from_vec(point).as_lat_long();
+			Some([Deg::from(lat_long.lat).0, Deg::from(lat_long.long).0])
+		})
+		.collect();
+	let reference_long = lat_long
+		.iter()
+		.filter_map(|corner| corner.map(|c| c[1]))
+		.next()?;
+	let lat_long: Vec<Option<[f64; 2]>> = lat_long
+		.into_iter()
+		.map(|corner| {
+			corner.map(|[lat, long]| {
+				[
+					lat,
+					long + 360_f64 * ((reference_long - long) / 360_f64).round(),
+				]
+			})
+		})
+		.collect();
+
+	let mut polygon = Vec::new();
+	for i in 0..3 {
+		match lat_long[i] {
+			Some(corner) => polygon.push(corner),
+			None => {
+				let pole_lat = 90_f64.copysign(triangle[i].y);
+				let before = lat_long[(i + 2) % 3]?;
+				let after = lat_long[(i + 1) % 3]?;
+				polygon.push([pole_lat, before[1]]);
+				polygon.push([pole_lat, after[1]]);
+			}
+		}
+	}
+	Some(polygon)
+}
+
+// The part of polygon where coord axis is at least value, or at most it if !keep_above
+fn clip(polygon: &[[f64; 2]], axis: usize, value: f64, keep_above: bool) -> Vec<[f64; 2]> {
+	let inside = |point: &[f64; 2]| (point[axis] >= value) == keep_above;
+	let mut clipped = Vec::new();
+	for i in 0..polygon.len() {
+		let (from, to) = (polygon[i], polygon[(i + 1) % polygon.len()]);
+		if inside(&from) {
+			clipped.push(from);
+		}
+		if inside(&from) != inside(&to) {
+			let t = (value - from[axis]) / (to[axis] - from[axis]);
+			let mut crossing = [
+				from[0] + t * (to[0] - from[0]),
+				from[1] + t * (to[1] - from[1]),
+			];
+			crossing[axis] = value;
+			clipped.push(crossing);
+		}
+	}
+	clipped
+}
+
+/// Cuts triangles on the unit sphere along the edges of the map's textures, so each piece
+/// lies on one texture, and gives every vertex the coords of its lat/long in that texture's
+/// layer. Between the cuts, pieces are flat in lat/long rather than in space, so vertices
+/// are where they'd be on the sphere and the textures line up exactly.
+pub fn texture_triangles(
+	triangles: Vec<Triangle>,
+	radius: f64,
+	texture_layers: &TextureLayers,
+) -> (Vec<Vertex>, Vec<u32>) {
+	let tex_size_deg = texture_layers.texture_size_deg();
+	let tex_size = tex_size_deg as f64;
+	let mut vertices = Vec::new();
+	let mut triangles_list = Vec::new();
+
+	for triangle in triangles.into_iter().flat_map(split_at_poles) {
+		let polygon = match lat_long_polygon(&triangle) {
+			Some(polygon) => polygon,
+			None => continue,
+		};
+		let min = |axis: usize| {
+			polygon
+				.iter()
+				.map(|p| p[axis])
+				.fold(f64::INFINITY, f64::min)
+		};
+		let max = |axis: usize| {
+			polygon
+				.iter()
+				.map(|p| p[axis])
+				.fold(f64::NEG_INFINITY, f64::max)
+		};
+		let cells = |axis: usize| {
+			((min(axis) / tex_size).floor() as i32)..((max(axis) / tex_size).ceil() as i32)
+		};
+
+		for cell_lat in cells(0) {
+			for cell_long in cells(1) {
+				let (min_lat, min_long) = (cell_lat as f64 * tex_size, cell_long as f64 * tex_size);
+				let mut piece = clip(&polygon, 0, min_lat, true);
+				piece = clip(&piece, 0, min_lat + tex_size, false);
+				piece = clip(&piece, 1, min_long, true);
+				piece = clip(&piece, 1, min_long + tex_size, false);
+				if piece.len() < 3 {
+					continue;
+				}
+
+				let key = MapPieceKey {
+					min_lat: cell_lat * tex_size_deg as i32,
+					// Longitude wraps around the globe
+					min_long: (cell_long * tex_size_deg as i32 + 180).rem_euclid(360) - 180,
+				};
+				let layer = texture_layers.get_layer(&key) as f64;
+				let first = vertices.len() as u32;
+				for &[lat, long] in &piece {
+					let pos = lat_long_dir(lat, long) * radius;
+					let (u, v) = texture_layers
+						.inset_tex_coords((long - min_long) / tex_size, (lat - min_lat) / tex_size);
+					vertices.push(Vertex {
+						position: [pos.x, pos.y, pos.z, 1_f64],
+						normal: pos.normalize().into(),
+						tex_coords: [u, v, layer],
+						..Default::default()
+					});
+				}
+
+				// Front faces are clockwise seen from outside, with north up and east right
+				let twice_area: f64 = (0..piece.len())
+					.map(|i| {
+						let (a, b) = (piece[i], piece[(i + 1) % piece.len()]);
+						a[1] * b[0] - b[1] * a[0]
+					})
+					.sum();
+				for i in 1..piece.len() as u32 - 1 {
+					if twice_area > 0_f64 {
+						triangles_list.extend_from_slice(&[first, first + i + 1, first + i]);
+					} else {
+						triangles_list.extend_from_slice(&[first, first + i, first + i + 1]);
+					}
+				}
+			}
+		}
+	}
+	(vertices, triangles_list)
+}
@@ -1,117 +1,41 @@
 extern crate log;
 
-use super::{AmbientLight, Camera, Mesh, MeshObject, View, WorldLight};
+use super::settings::{
+	ActionType, AnalogAction, ContinualAction, InputType, InstantAction, Settings,
+};
+use super::{
+	pick_lat_long, pick_point, AmbientLight, Camera, CameraLimits, CityLabels, CityMarkers,
+	FlatMap, GlobeMesh, GlyphAtlas, Graticule, MapProjection, Mesh, MeshObject, ScaleBar,
+	TextureLayers, TileStreamer, View, ViewParams, WorldLight,
+};
+use crate::entities::{closest_city_to, Bookmark, CameraView, City, LatLong, SphericalPoint};
 use crate::world::{World, WorldState};
 
 use glium::glutin::dpi::LogicalPosition;
-use glium::glutin::{MouseButton, VirtualKeyCode, WindowEvent};
+use glium::glutin::{MouseButton, WindowEvent};
 
 use cgmath::prelude::*;
-use cgmath::{Deg, Point3, Vector3};
+use cgmath::{Matrix4, Point2, Point3, Rad, Vector2, Vector3, Vector4};
+
+use image::RgbaImage;
 
 use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
 use std::time::{Duration, Instant};
 
 const VERT_SHADER: &str = include_str!["vertex.glsl"];
 const FRAG_SHADER: &str = include_str!["fragment.glsl"];
 
-#[derive(Hash, Eq, PartialEq, Clone, Copy, Debug)]
-enum InputType {
-	Key(VirtualKeyCode),
-	Mouse(MouseButton),
-}
-
-#[derive(Hash, Eq, PartialEq, Clone, Copy, Debug)]
-enum ContinualAction {
-	MoveForward,
-	MoveBackward,
-	MoveLeft,
-	MoveRight,
-	MoveUp,
-	MoveDown,
-	MoveFast,
-}
-
-#[derive(Clone, Copy, Debug)]
-enum InstantAction {
-	Quit,
-	//ToggleDebug,
-	MoveLight,
-	//ToggleCursorGrab,
-	ToggleCameraLock,
-	Log,
-}
-
-#[derive(Clone, Copy, Debug)]
-enum ActionType {
-	Continual(ContinualAction),
-	Instant(InstantAction),
-}
-
-#[derive(Debug)]
-struct Settings {
-	bindings: HashMap<InputType, ActionType>,
-	max_fps: f64,
-
-	world_center: Point3<f64>,
-	world_radius: f64,
+// Most frames render_still waits for tiles to load over
+const MAX_STILL_FRAMES: u32 = 1000;
 
-	light_frac_ambient: f64,
-	light_distance: f64,
+// Times dragging the globe is refined, each getting the grabbed point closer to the cursor.
+// Rotating by latitude and longitude is only exact under the middle of the screen.
+const DRAG_ITERATIONS: u32 = 4;
 
-	move_speed: f64,
-	pan_speed: f64,
-	zoom_speed: f64,
-	fov: Deg<f64>,
-
-	quitting: bool,
-}
-impl Default for Settings {
-	fn default() -> Settings {
-		use self::ContinualAction::*;
-		use self::InputType::{Key, Mouse};
-		use self::InstantAction::*;
-
-		let bindings: HashMap<InputType, ActionType> = [
-			(Key(VirtualKeyCode::W), ActionType::Continual(MoveForward)),
-			(Key(VirtualKeyCode::S), ActionType::Continual(MoveBackward)),
-			(Key(VirtualKeyCode::A), ActionType::Continual(MoveLeft)),
-			(Key(VirtualKeyCode::D), ActionType::Continual(MoveRight)),
-			(Key(VirtualKeyCode::R), ActionType::Continual(MoveUp)),
-			(Key(VirtualKeyCode::F), ActionType::Continual(MoveDown)),
-			(Key(VirtualKeyCode::LShift), ActionType::Continual(MoveFast)),
-			(Key(VirtualKeyCode::Escape), ActionType::Instant(Quit)),
-			//(Key(VirtualKeyCode::Q), ActionType::Instant(ToggleDebug)),
-			(Key(VirtualKeyCode::L), ActionType::Instant(MoveLight)),
-			//(Key(VirtualKeyCode::G), ActionType::Instant(ToggleCursorGrab)),
-			(Key(VirtualKeyCode::C), ActionType::Instant(ToggleCameraLock)),
-			(Mouse(MouseButton::Left), ActionType::Instant(Log)),
-			(Mouse(MouseButton::Middle), ActionType::Instant(Log)),
-			(Mouse(MouseButton::Right), ActionType::Instant(Log)),
-		]
-		.iter()
-		.cloned()
-		.collect();
-
-		Settings {
-			bindings: bindings,
-			max_fps: 60_f64,
-
-			world_center: Point3::new(0_f64, 0_f64, 0_f64),
-			world_radius: 1_f64,
-
-			light_frac_ambient: 0.05_f64,
-			light_distance: 10_000_f64,
-
-			move_speed: 1_f64,
-			pan_speed: 1_f64,
-			zoom_speed: 2_f64,
-			fov: Deg(90_f64),
-
-			quitting: false,
-		}
-	}
-}
+// Furthest the cursor can move between pressing a mouse button and releasing it for it to
+// still be a click, and not a drag
+const CLICK_MAX_MOVE_PX: f64 = 4_f64;
 
 fn duration_to_secs(duration: &Duration) -> f64 {
 	let secs: f64 = duration.as_secs() as f64;
@@ -119,64 +43,140 @@ fn duration_to_secs(duration: &Duration) -> f64 {
 	secs + subsec
 }
 
-fn conv_image_to_raw_image(image: &image::DynamicImage) -> glium::texture::RawImage2d<'static, u8> {
-	let image_rgba = image.to_rgba();
+fn conv_image_to_raw_image(
+	image_rgba: image::RgbaImage,
+) -> glium::texture::RawImage2d<'static, u8> {
 	let image_dimensions = image_rgba.dimensions();
 	glium::texture::RawImage2d::from_raw_rgba_reversed(&image_rgba.into_raw(), image_dimensions)
 }
 
 pub struct GLPresenter {
-	view: Box<View>,
-	world: Box<World>,
+	view: Box<dyn View>,
+	world: Box<dyn World>,
 
 	settings: Settings,
+	settings_path: Option<String>, // Where the settings are saved on exit, if anywhere
 	inputs_held: HashSet<InputType>,
+	clicks: HashMap<MouseButton, Point2<f64>>, // Where buttons that may still be clicks were pressed
 	objects: Vec<MeshObject>,
+	tile_streamer: Option<TileStreamer>, // Replaces objects every frame, if there is one
+	map_objects: Option<(MapProjection, Vec<MeshObject>)>, // Objects laid flat, without a streamer
+	graticule: Option<Graticule>,
+	graticule_objects: Vec<MeshObject>, // Drawn over objects
+	city_markers: Option<CityMarkers>,
+	marker_objects: Vec<MeshObject>, // Drawn over the graticule
+	city_labels: Option<CityLabels>,
+	scale_bar: Option<ScaleBar>,
+	label_objects: Vec<MeshObject>, // Drawn over everything else, on the screen
+	glyph_atlas: GlyphAtlas,        // Every label's glyphs, for the view's overlay texture
+
+	cursor_pos: Option<Point2<f64>>, // In pixels from the top left, if it's over the view
+	selected_city: Option<City>,
+	title: String, // Last shown, with what's under the cursor
 
 	ambient_light: AmbientLight,
 	world_light: WorldLight,
 
 	camera: Camera,
+	flat_map: Option<FlatMap>, // Looked down on instead of the globe, while it's open
 }
 
 impl GLPresenter {
-	pub fn new(view: Box<View>, world: Box<World>) -> GLPresenter {
+	pub fn new(view: Box<dyn View>, world: Box<dyn World>) -> GLPresenter {
 		let settings: Settings = Default::default();
 		let mut camera = Camera::new(
-				settings.world_center + Vector3::unit_z() * 2_f64 * settings.world_radius,
-				-Vector3::unit_z(),
-				Vector3::unit_y(),
-				settings.move_speed,
-				settings.pan_speed,
-				settings.zoom_speed);
+			settings.world_center + Vector3::unit_z() * 2_f64 * settings.world_radius,
+			-Vector3::unit_z(),
+			Vector3::unit_y(),
+			settings.move_speed,
+			settings.pan_speed,
+			settings.zoom_speed,
+		);
 		camera.lock(settings.world_center, settings.world_radius);
 
-		GLPresenter {
-			view: view,
-			world: world,
+		let mut presenter = GLPresenter {
+			view,
+			world,
+			settings_path: None,
 			inputs_held: HashSet::new(),
+			clicks: HashMap::new(),
 			objects: Vec::new(),
+			tile_streamer: None,
+			map_objects: None,
+			graticule: None,
+			graticule_objects: Vec::new(),
+			city_markers: None,
+			marker_objects: Vec::new(),
+			city_labels: None,
+			scale_bar: None,
+			label_objects: Vec::new(),
+			glyph_atlas: GlyphAtlas::new(settings.glyph_atlas_px),
+			cursor_pos: None,
+			selected_city: None,
+			title: String::new(),
 			ambient_light: AmbientLight {
-				color: (Vector3::new(1_f64, 1_f64, 1_f64) * settings.light_frac_ambient).extend(1_f64),
+				color: Vector4::zero(),
 			},
 			world_light: WorldLight {
 				pos: settings.world_center + Vector3::unit_z() * settings.light_distance,
-				color: (Vector3::new(1_f64, 1_f64, 1_f64) * (1_f64 - settings.light_frac_ambient)).extend(1_f64),
+				color: Vector4::zero(),
 			},
-			camera: camera,
-			settings: settings,
+			camera,
+			flat_map: None,
+			settings,
+		};
+		presenter.apply_settings();
+		presenter
+	}
+
+	/// Replaces the settings with the ones in the settings file at path, and saves them back
+	/// there on exit. Only settings the file already has or that differ from the defaults are
+	/// written, so a missing file is only made once something's been changed. A file that
+	/// can't be loaded is left alone, and the settings are left as they were.
+	pub fn load_settings(&mut self, path: &str) {
+		let globe_mesh = self.settings.globe_mesh.clone();
+		match Settings::load(path) {
+			Ok(settings) => {
+				self.settings = settings;
+				self.settings.globe_mesh = globe_mesh; // Not kept in the file
+				self.settings_path = Some(path.to_string());
+				self.apply_settings();
+			}
+			Err(err) => error!("Presenter: Error loading settings from {}: {}", path, err),
 		}
 	}
 
-	pub fn event_loop(&mut self) {
-		let world_state = self.update_from_world().clone();
-		self.init_view(&world_state);
+	// Passes the settings on to what's made from them, for when they change
+	fn apply_settings(&mut self) {
+		let frac_ambient = self.settings.light_frac_ambient;
+		self.ambient_light.color = (Vector3::new(1_f64, 1_f64, 1_f64) * frac_ambient).extend(1_f64);
+		self.world_light.color =
+			(Vector3::new(1_f64, 1_f64, 1_f64) * (1_f64 - frac_ambient)).extend(1_f64);
+		self.camera.set_speeds(
+			self.settings.move_speed,
+			self.settings.pan_speed,
+			self.settings.zoom_speed,
+		);
+		self.camera.set_limits(CameraLimits {
+			world_center: self.settings.world_center,
+			world_radius: self.settings.world_radius,
+			min_altitude: self.settings.min_altitude * self.settings.world_radius,
+			max_altitude: self.settings.max_altitude * self.settings.world_radius,
+			max_tilt: self.settings.max_orbit_tilt.into(),
+		});
+	}
 
-		self.objects.push(MeshObject::new(Mesh::gen_sphere_mesh(
-			self.view.get_facade(),
-			1_u32,
-			self.settings.world_radius,
-		)));
+	/// Which mesh to draw the globe with, if the map has no tile pyramid
+	pub fn set_globe_mesh(&mut self, globe_mesh: GlobeMesh) {
+		self.settings.globe_mesh = globe_mesh;
+	}
+
+	pub fn event_loop(&mut self) {
+		self.init_world();
+		if let Some(view) = self.world.get_state().camera.clone() {
+			info!("Presenter: Camera back over {}", view.target);
+			self.camera.set_view(&view);
+		}
 
 		let mut fps_track_start = Instant::now();
 		let mut frame_count = 0_u32;
@@ -189,11 +189,25 @@ impl GLPresenter {
 
 			self.update_from_view();
 			if self.settings.quitting {
-				self.world.save();
+				self.world.get_state_mut().camera = Some(self.camera_view());
+				if let Err(err) = self.world.save() {
+					error!("Presenter: Error saving world: {}", err);
+				}
+				if let Some(ref settings_path) = self.settings_path {
+					if let Err(err) = self.settings.save(settings_path) {
+						error!(
+							"Presenter: Error saving settings to {}: {}",
+							settings_path, err
+						);
+					}
+				}
 				break;
 			}
 			self.process_held_inputs(secs_since_last_frame);
+			self.camera.update_flight(secs_since_last_frame);
 
+			self.update_objects();
+			self.update_title();
 			self.draw();
 
 			// FPS tracker
@@ -218,6 +232,71 @@ impl GLPresenter {
 		info!("exiting presenter event loop");
 	}
 
+	/// Draws a single frame of the world and returns it, seen from over lat_long, distance
+	/// world radii from the world's center, and lit from behind the camera. Maps with a tile
+	/// pyramid have every tile the camera wants loaded first.
+	pub fn render_still(&mut self, lat_long: &LatLong, distance: f64) -> Option<RgbaImage> {
+		self.init_world();
+		let disp = lat_long
+			.as_sph_point(distance * self.settings.world_radius)
+			.to_vec();
+		self.camera.look_from(self.settings.world_center, disp);
+		self.process_instant_action(&InstantAction::MoveLight);
+
+		for _ in 0..MAX_STILL_FRAMES {
+			self.update_objects();
+			if self
+				.tile_streamer
+				.as_ref()
+				.is_none_or(TileStreamer::is_settled)
+			{
+				break;
+			}
+		}
+		self.draw();
+		self.view.read_image()
+	}
+
+	// Sets the view up to draw the world
+	fn init_world(&mut self) {
+		let world_state = self.update_from_world().clone();
+		self.init_view(&world_state);
+		self.glyph_atlas = GlyphAtlas::new(self.settings.glyph_atlas_px);
+		self.graticule = Some(Graticule::new(self.settings.graticule_label_style.clone()));
+		self.city_markers = Some(CityMarkers::new(&world_state.cities));
+		self.city_labels = Some(CityLabels::new(
+			&world_state.cities,
+			self.settings.label_styles.clone(),
+		));
+		self.scale_bar = Some(ScaleBar::new(self.settings.scale_bar_label_style.clone()));
+		match world_state.map.pyramid {
+			Some(ref pyramid) => {
+				self.tile_streamer = TileStreamer::new(
+					&mut *self.view,
+					pyramid,
+					&world_state.map.missing_image,
+					self.settings.world_radius,
+					self.settings.tile_cache_layers,
+					self.settings.tile_loads_per_frame,
+					self.settings.texture_anisotropy,
+				);
+				if self.tile_streamer.is_none() {
+					error!("Presenter: Couldn't set up tile streaming");
+				}
+			}
+			None => {
+				let texture_layers = self.init_textures(&world_state);
+				self.objects.push(MeshObject::new(Mesh::gen_globe_mesh(
+					self.view.get_facade(),
+					&self.settings.globe_mesh,
+					self.settings.world_radius,
+					&texture_layers,
+					&world_state.models,
+				)));
+			}
+		}
+	}
+
 	fn update_from_world(&mut self) -> &WorldState {
 		self.world.get_state()
 	}
@@ -257,55 +336,198 @@ impl GLPresenter {
 				}
 			}
 			WindowEvent::MouseInput { state, button, .. } => {
+				// Buttons fire their actions when they're released, if they were clicked rather
+				// than dragged, so dragging the globe doesn't also click on it
 				let input = InputType::Mouse(button);
 				debug!("MouseInput: {:?} {:?}", button, state);
 				match state {
 					ElementState::Pressed => {
-						self.process_input(&input);
+						if let Some(cursor_pos) = self.cursor_pos {
+							self.clicks.insert(button, cursor_pos);
+						}
 						self.inputs_held.insert(input);
 					}
 					ElementState::Released => {
 						self.inputs_held.remove(&input);
+						if self.clicks.remove(&button).is_some() {
+							self.process_input(&input);
+						}
 					}
 				}
 			}
-			// TODO: Shouldn't skip over the process_input pipeline but...
-			//       Need a more generic way to implement zoom for scroll wheel.
-			//       No other input has an "amount"... and I want to preserve the delta
 			WindowEvent::MouseWheel { delta, .. } => {
-				let dist = match delta {
-					MouseScrollDelta::LineDelta(_, vert) => vert as f64 * 15_f64,
-					MouseScrollDelta::PixelDelta(LogicalPosition { y, .. }) => y,
+				// Trackpads scroll by pixels, and mice by lines
+				let amount = match delta {
+					MouseScrollDelta::LineDelta(horiz, vert) => {
+						Vector2::new(horiz as f64, vert as f64) * self.settings.scroll_line_px
+					}
+					MouseScrollDelta::PixelDelta(LogicalPosition { x, y }) => Vector2::new(x, y),
 				};
-				let zoom_factor = 1_f64 - dist / 300_f64; // TODO calibrate, add config
-				info!("MouseWheel: {:?} ({}, {})", delta, dist, zoom_factor);
-				//TODO call camera move for the zoom
+				debug!("MouseWheel: {:?} ({:?})", delta, amount);
+				self.process_analog_input(&InputType::Scroll, amount);
 			}
-			//TODO WindowEvent::CursorMoved { position, .. } => {
-			//...
-			//}
+			WindowEvent::CursorMoved { position, .. } => {
+				let position = position.to_physical(self.view.get_hidpi_factor());
+				let cursor_pos = Point2::new(position.x, position.y);
+				self.clicks.retain(|_, pressed_pos| {
+					(cursor_pos - *pressed_pos).magnitude() <= CLICK_MAX_MOVE_PX
+				});
+				if let Some(last_pos) = self.cursor_pos.replace(cursor_pos) {
+					let held: Vec<InputType> = self.inputs_held.iter().cloned().collect();
+					for input in held {
+						if let InputType::Mouse(button) = input {
+							self.process_analog_input(
+								&InputType::Drag(button),
+								cursor_pos - last_pos,
+							);
+						}
+					}
+				}
+			}
+			WindowEvent::CursorLeft { .. } => self.cursor_pos = None,
 			_ => (),
 		}
 	}
 
-	// Fires when an input is first pressed. Used for actions that don't happen continually,
-	// like switching camera modes.
+	// Every action input is bound to, which can be more than one
+	fn bound_actions(&self, input: &InputType) -> Vec<ActionType> {
+		self.settings
+			.bindings
+			.get(input)
+			.cloned()
+			.unwrap_or_default()
+	}
+
+	// Fires when a key is first pressed, or a mouse button's clicked. Used for actions that
+	// don't happen continually, like switching camera modes.
 	fn process_input(&mut self, input: &InputType) {
-		if let Some(&ActionType::Instant(action)) = self.settings.bindings.get(input) {
-			self.process_instant_action(&action);
+		let actions = self.bound_actions(input);
+		if !actions.is_empty() {
+			self.camera.cancel_flight(); // The user's taking over
+		}
+		for action in actions {
+			if let ActionType::Instant(action) = action {
+				self.process_instant_action(&action);
+			}
 		}
 	}
 	fn process_instant_action(&mut self, action: &InstantAction) {
 		info!("Instant action fired: {:?}", action);
 		match action {
 			InstantAction::Quit => self.settings.quitting = true,
-			InstantAction::MoveLight => self.world_light.pos = self.settings.world_center +
-				(self.camera.get_pos() - self.settings.world_center)
-				.normalize_to(self.settings.light_distance),
+			InstantAction::MoveLight => {
+				self.world_light.pos = self.settings.world_center
+					+ (self.camera.get_pos() - self.settings.world_center)
+						.normalize_to(self.settings.light_distance)
+			}
+			InstantAction::SelectCity => self.select_city(),
+			InstantAction::OrbitSelectedCity => {
+				if let Some(ref city) = self.selected_city {
+					let altitude = self.settings.orbit_altitude * self.settings.world_radius;
+					self.flat_map = None; // Cities are only orbited on the globe
+					self.camera.lock_to_surface(&city.coords, altitude);
+				}
+			}
+			InstantAction::GoToBookmark(slot) => {
+				match self.held_actions().contains(&ContinualAction::SetBookmark) {
+					true => self.set_bookmark(*slot),
+					false => self.go_to_bookmark(*slot),
+				}
+			}
+			InstantAction::ToggleFlatMap => self.toggle_flat_map(),
+			InstantAction::CycleMapProjection => {
+				self.settings.map_projection = self.settings.map_projection.next();
+				info!("Flat maps are {} now", self.settings.map_projection.name());
+				if let Some(ref mut flat_map) = self.flat_map {
+					flat_map.set_projection(self.settings.map_projection);
+				}
+			}
+			InstantAction::ToggleGraticule => {
+				self.settings.show_graticule = !self.settings.show_graticule;
+				info!(
+					"Graticule {}",
+					if self.settings.show_graticule {
+						"shown"
+					} else {
+						"hidden"
+					}
+				);
+			}
+			InstantAction::ToggleScaleBar => {
+				self.settings.show_scale_bar = !self.settings.show_scale_bar;
+				info!(
+					"Scale bar {}",
+					if self.settings.show_scale_bar {
+						"shown"
+					} else {
+						"hidden"
+					}
+				);
+			}
 			InstantAction::Log => (),
 			InstantAction::ToggleCameraLock => match self.camera.is_locked() {
 				true => self.camera.unlock(),
-				false => self.camera.lock(self.settings.world_center, self.settings.world_radius),
+				false => self
+					.camera
+					.lock(self.settings.world_center, self.settings.world_radius),
+			},
+		}
+	}
+
+	// Fires whenever an input with an amount happens, like scrolling, with how much it
+	// happened by. Used for actions that follow the mouse, like dragging the globe around.
+	fn process_analog_input(&mut self, input: &InputType, amount: Vector2<f64>) {
+		for action in self.bound_actions(input) {
+			if let ActionType::Analog(action) = action {
+				self.process_analog_action(&action, amount);
+			}
+		}
+	}
+	fn process_analog_action(&mut self, action: &AnalogAction, amount: Vector2<f64>) {
+		debug!("Analog action fired: {:?} ({:?})", action, amount);
+		self.camera.cancel_flight();
+		match action {
+			AnalogAction::Zoom => {
+				let factor = (amount.y / self.settings.scroll_zoom_px).exp2();
+				match self.flat_map {
+					Some(ref mut flat_map) => flat_map.zoom(factor),
+					None => self.camera.zoom(factor),
+				}
+			}
+			// On the flat map, moves it as far as the cursor moved, keeping the point grabbed
+			// under it
+			AnalogAction::DragGlobe if self.flat_map.is_some() => {
+				let view_params = self.view_params();
+				let map_per_px = view_params.altitude() / view_params.px_per_rad;
+				if let Some(ref mut flat_map) = self.flat_map {
+					flat_map.pan(Vector2::new(-amount.x, amount.y) * map_per_px);
+				}
+			}
+			// Orbiting a city, turns and tilts the camera around it as far as the cursor moved
+			AnalogAction::DragGlobe if self.camera.is_orbiting_surface() => {
+				let px_per_rad = self.view_params().px_per_rad;
+				self.camera.orbit(Point2::new(
+					Rad(-amount.x / px_per_rad),
+					Rad(amount.y / px_per_rad),
+				));
+			}
+			// Otherwise keeps the point on the globe the cursor was over under it, as long as
+			// the cursor stays over the globe
+			AnalogAction::DragGlobe => {
+				let cursor_pos = match self.cursor_pos {
+					Some(cursor_pos) => cursor_pos,
+					None => return,
+				};
+				let grabbed = match pick_point(&self.view_params(), cursor_pos - amount) {
+					Some(grabbed) => grabbed,
+					None => return,
+				};
+				for _ in 0..DRAG_ITERATIONS {
+					match pick_point(&self.view_params(), cursor_pos) {
+						Some(under) => self.camera.drag_locked(grabbed, under),
+						None => return,
+					}
+				}
 			}
 		}
 	}
@@ -313,15 +535,22 @@ impl GLPresenter {
 	// Fires every frame an input is held (incl the first!), with the time since the last
 	// frame. Used for actions that happen continually, like moving the camera.
 	fn process_held_inputs(&mut self, frame_secs: f64) {
-		let inputs_vec: Vec<InputType> = self.inputs_held.iter().map(|&it| it).collect();
+		let actions = self.held_actions();
+		self.process_continual_actions(&actions, frame_secs)
+	}
+	// The continual actions the inputs held are bound to
+	fn held_actions(&self) -> HashSet<ContinualAction> {
+		let inputs_vec: Vec<InputType> = self.inputs_held.iter().copied().collect();
 		let mut actions: HashSet<ContinualAction> = HashSet::new();
 
-		for &input in inputs_vec.iter() {
-			if let Some(&ActionType::Continual(action)) = self.settings.bindings.get(&input) {
-				actions.insert(action);
+		for input in inputs_vec.iter() {
+			for action in self.bound_actions(input) {
+				if let ActionType::Continual(action) = action {
+					actions.insert(action);
+				}
 			}
 		}
-		self.process_continual_actions(&actions, frame_secs)
+		actions
 	}
 	fn process_continual_actions(&mut self, actions: &HashSet<ContinualAction>, frame_secs: f64) {
 		let mut move_vec: Vector3<f64> = Vector3::zero();
@@ -337,35 +566,357 @@ impl GLPresenter {
 				ContinualAction::MoveUp => move_vec += Vector3::unit_y(),
 				ContinualAction::MoveDown => move_vec -= Vector3::unit_y(),
 				ContinualAction::MoveFast => move_mult *= 2_f64,
+				ContinualAction::SetBookmark => (), // Only changes what bookmark keys do
 			};
 		}
 		if move_vec.magnitude2() >= 0.01_f64 {
-			self.camera.move_cam(move_vec.normalize_to(move_mult), frame_secs);
+			self.camera.cancel_flight();
+			match self.flat_map {
+				Some(ref mut flat_map) => flat_map.move_map(
+					move_vec.normalize_to(move_mult),
+					frame_secs,
+					self.settings.pan_speed,
+					self.settings.zoom_speed,
+				),
+				None => self
+					.camera
+					.move_cam(move_vec.normalize_to(move_mult), frame_secs),
+			}
 		}
 	}
 
-	fn draw(&self) {
-		let view_mat = self.camera.view_mat();
-
+	fn proj_mat(&self) -> Matrix4<f64> {
 		let aspect_ratio = self.view.get_aspect_ratio();
-		let proj_mat =
-			cgmath::perspective(self.settings.fov, aspect_ratio, 0.00001_f64, 100000_f64);
+		cgmath::perspective(self.settings.fov, aspect_ratio, 0.00001_f64, 100000_f64)
+	}
+
+	// Over the globe, or the flat map while it's open
+	fn view_mat(&self) -> Matrix4<f64> {
+		match self.flat_map {
+			Some(ref flat_map) => {
+				flat_map.view_mat(self.settings.world_center, self.settings.world_radius)
+			}
+			None => self.camera.view_mat(),
+		}
+	}
+	fn cam_pos(&self) -> Point3<f64> {
+		match self.flat_map {
+			Some(ref flat_map) => {
+				flat_map.cam_pos(self.settings.world_center, self.settings.world_radius)
+			}
+			None => self.camera.get_pos(),
+		}
+	}
+
+	// What the camera can see, for picking which tiles to stream in and where labels go
+	fn view_params(&self) -> ViewParams {
+		let (width, height) = self.view.get_dimensions();
+		let half_fov: Rad<f64> = (self.settings.fov / 2_f64).into();
+		ViewParams {
+			cam_pos: self.cam_pos(),
+			view_proj_mat: self.proj_mat() * self.view_mat(),
+			world_center: self.settings.world_center,
+			world_radius: self.settings.world_radius,
+			px_per_rad: height as f64 / 2_f64 / half_fov.0.tan(),
+			viewport: (width, height),
+			map: self.flat_map.as_ref().map(FlatMap::projection),
+		}
+	}
 
+	// Streams in the tiles the camera can now see, if the map has a tile pyramid, and
+	// resizes and fades the city markers for how far away the camera is, and places the
+	// city labels that fit on screen. The graticule and scale bar are kept up with the
+	// camera too, while they're shown.
+	fn update_objects(&mut self) {
+		let view_params = self.view_params();
+		if let Some(ref mut tile_streamer) = self.tile_streamer {
+			self.objects = tile_streamer.update(&*self.view, &*self.world, &view_params);
+		} else if let Some(projection) = view_params.map {
+			if self.map_objects.as_ref().map(|&(laid_out, _)| laid_out) != Some(projection) {
+				let (center, radius) = (self.settings.world_center, self.settings.world_radius);
+				let map_objects = self
+					.objects
+					.iter()
+					.map(|object| {
+						let mut map_object = object.clone();
+						map_object.mesh = Rc::new(projection.flatten(
+							self.view.get_facade(),
+							&object.mesh,
+							center,
+							radius,
+						));
+						map_object
+					})
+					.collect();
+				self.map_objects = Some((projection, map_objects));
+			}
+		}
+		let fonts = &self.world.get_state().fonts;
+		self.graticule_objects.clear();
+		self.label_objects.clear();
+		if let (true, Some(ref mut graticule)) = (self.settings.show_graticule, &mut self.graticule)
+		{
+			let (lines, labels) =
+				graticule.update(&*self.view, &mut self.glyph_atlas, fonts, &view_params);
+			self.graticule_objects = lines;
+			self.label_objects = labels;
+		}
+		if let Some(ref mut city_markers) = self.city_markers {
+			self.marker_objects = city_markers.update(&*self.view, &view_params);
+		}
+		if let Some(ref mut city_labels) = self.city_labels {
+			let label_objects =
+				city_labels.update(&*self.view, &mut self.glyph_atlas, fonts, &view_params);
+			self.label_objects.extend(label_objects);
+		}
+		if let (true, Some(ref mut scale_bar)) = (self.settings.show_scale_bar, &mut self.scale_bar)
+		{
+			// Without the world's size, distances are measured in its radius
+			let state = self.world.get_state();
+			let (radius, unit) = match state.radius {
+				Some(radius) => (radius, state.distance_unit.as_str()),
+				None => (1_f64, "radii"),
+			};
+			let scale_objects = scale_bar.update(
+				&*self.view,
+				&mut self.glyph_atlas,
+				fonts,
+				&view_params,
+				radius,
+				unit,
+			);
+			self.label_objects.extend(scale_objects);
+		}
+		if let Some(image) = self.glyph_atlas.take_changed() {
+			if !self.view.set_overlay_texture(image) {
+				error!("Presenter: Couldn't set the glyph atlas");
+			}
+		}
+	}
+
+	// The flat map is lit from straight over it, wherever the light is over the globe
+	fn draw(&self) {
+		let map_light = WorldLight {
+			pos: self.cam_pos() + Vector3::unit_z() * self.settings.light_distance,
+			color: self.world_light.color,
+		};
+		let (globe_objects, world_light) = match (&self.flat_map, &self.map_objects) {
+			(Some(_), &Some((_, ref map_objects))) => (map_objects, &map_light),
+			(Some(_), &None) => (&self.objects, &map_light),
+			(None, _) => (&self.objects, &self.world_light),
+		};
+		let objects: Vec<MeshObject> = globe_objects
+			.iter()
+			.chain(&self.graticule_objects)
+			.chain(&self.marker_objects)
+			.chain(&self.label_objects)
+			.cloned()
+			.collect();
 		self.view.draw(
-			view_mat,
-			proj_mat,
+			self.view_mat(),
+			self.proj_mat(),
 			&self.ambient_light,
-			&self.world_light,
-			&self.objects,
+			world_light,
+			&objects,
 		);
 	}
 
+	// Where on the globe the cursor is over, if it's over the globe
+	fn cursor_lat_long(&self) -> Option<LatLong> {
+		self.cursor_pos
+			.and_then(|cursor_pos| pick_lat_long(&self.view_params(), cursor_pos))
+	}
+
+	// Selects the city closest to where on the globe the cursor is over, if it's near
+	// enough to the cursor to have been clicked on, and flies over to it unless it's orbiting
+	// a point on the surface. On the flat map, it's moved straight over it.
+	fn select_city(&mut self) {
+		let (cursor_pos, lat_long) = match (self.cursor_pos, self.cursor_lat_long()) {
+			(Some(cursor_pos), Some(lat_long)) => (cursor_pos, lat_long),
+			_ => return,
+		};
+		let view_params = self.view_params();
+		let cam_dist = (view_params.cam_pos - view_params.world_center).magnitude();
+		let altitude = cam_dist - view_params.world_radius;
+		// How far around the globe select_radius_px is, near the cursor
+		let max_angle = match pick_point(&view_params, cursor_pos) {
+			Some(point) => {
+				self.settings.select_radius_px * (point - view_params.cam_pos).magnitude()
+					/ view_params.px_per_rad
+					/ view_params.world_radius
+			}
+			None => return,
+		};
+
+		let city = match closest_city_to(&lat_long, &self.world.get_state().cities) {
+			Some(city) if lat_long.great_circle_distance(&city.coords).0 <= max_angle => city,
+			_ => return,
+		};
+		info!(
+			"Selected {} ({:?}) at {}",
+			city.name,
+			city.tier(),
+			city.coords
+		);
+		self.selected_city = Some(city.clone());
+		if let Some(ref mut flat_map) = self.flat_map {
+			let altitude = flat_map.ground_altitude().min(self.settings.city_altitude);
+			flat_map.look_at(city.coords.as_sph_point(1_f64).to_vec(), altitude);
+			return;
+		}
+		if self.camera.is_orbiting_surface() {
+			// It stays orbiting where it is, until it's told to orbit this city
+			info!("Orbiting, so staying put until told to orbit {}", city.name);
+			return;
+		}
+		let fly_altitude = self.settings.city_altitude * self.settings.world_radius;
+		self.camera.fly_to(&city.coords, altitude.min(fly_altitude));
+	}
+
+	// Sets the bookmark in slot to where the camera's looking from, keeping its name if it
+	// was already set
+	fn set_bookmark(&mut self, slot: u32) {
+		let view = self.camera_view();
+		let bookmarks = &mut self.world.get_state_mut().bookmarks;
+		match bookmarks
+			.iter_mut()
+			.find(|bookmark| bookmark.slot == Some(slot))
+		{
+			Some(bookmark) => {
+				info!("Bookmark {} set to {}", bookmark.name, view.target);
+				bookmark.view = view;
+			}
+			None => {
+				let name = format!("Bookmark {}", slot);
+				info!("{} set to {}", name, view.target);
+				bookmarks.push(Bookmark {
+					name,
+					slot: Some(slot),
+					view,
+				});
+			}
+		}
+	}
+
+	// Flies the camera to the bookmark in slot, if it's set, or moves the flat map over it
+	fn go_to_bookmark(&mut self, slot: u32) {
+		let bookmarks = &self.world.get_state().bookmarks;
+		match bookmarks
+			.iter()
+			.find(|bookmark| bookmark.slot == Some(slot))
+		{
+			Some(bookmark) => {
+				info!("Going to bookmark {}", bookmark.name);
+				match self.flat_map {
+					Some(ref mut flat_map) => {
+						let target = bookmark.view.target.as_sph_point(1_f64).to_vec();
+						flat_map.look_at(target, bookmark.view.distance);
+					}
+					None => self.camera.fly_to_view(&bookmark.view),
+				}
+			}
+			None => info!(
+				"No bookmark in slot {} yet, hold the SetBookmark keys to set it",
+				slot
+			),
+		}
+	}
+
+	// Where the camera's looking from, to be saved. Over the flat map, it's over the point in
+	// the middle of the screen, as high as it'd have as much around it in view on the globe.
+	fn camera_view(&self) -> CameraView {
+		match self.flat_map {
+			Some(ref flat_map) => CameraView {
+				target: SphericalPoint::from_vec(&flat_map.focus()).as_lat_long(),
+				distance: flat_map.ground_altitude(),
+				orbit: false,
+				heading: Rad(0_f64),
+				tilt: Rad(0_f64),
+			},
+			None => self.camera.view(),
+		}
+	}
+
+	// Switches between the globe and the flat map, keeping the point in the middle of the
+	// screen there, with about as much ground around it in view
+	fn toggle_flat_map(&mut self) {
+		if self.flat_map.is_some() {
+			let view = self.camera_view();
+			info!("Back to the globe, over {}", view.target);
+			self.camera.set_view(&view);
+			self.flat_map = None;
+			return;
+		}
+
+		// Where the camera's looking on the globe, or straight down if it's looking past it
+		let view_params = self.view_params();
+		let (width, height) = view_params.viewport;
+		let middle = Point2::new(width as f64 / 2_f64, height as f64 / 2_f64);
+		let (focus, altitude) = match pick_point(&view_params, middle) {
+			Some(point) => (
+				(point - view_params.world_center).normalize(),
+				(point - view_params.cam_pos).magnitude() / view_params.world_radius,
+			),
+			None => (
+				(view_params.cam_pos - view_params.world_center).normalize(),
+				view_params.altitude(),
+			),
+		};
+		self.camera.cancel_flight();
+		let flat_map = FlatMap::new(
+			self.settings.map_projection,
+			focus,
+			altitude,
+			self.settings.min_altitude,
+			self.settings.max_altitude,
+		);
+		info!(
+			"Flat {} map, over {}",
+			flat_map.projection().name(),
+			SphericalPoint::from_vec(&focus).as_lat_long()
+		);
+		self.flat_map = Some(flat_map);
+	}
+
+	// Shows where on the globe the cursor is over in the title, and the city closest to there,
+	// along with the selected city
+	fn update_title(&mut self) {
+		let state = self.world.get_state();
+		let mut title = format!("Viewing the world of {}", state.name);
+		if let Some(lat_long) = self.cursor_lat_long() {
+			title += &format!(" - {}", lat_long);
+			if let Some(city) = closest_city_to(&lat_long, &state.cities) {
+				title += &format!(", near {}", city.name);
+			}
+		}
+		if let Some(ref selected_city) = self.selected_city {
+			title += &format!(" - Selected {}", selected_city.name);
+		}
+		if title != self.title {
+			self.view.set_title(&title);
+			self.title = title;
+		}
+	}
+
 	// For setting the view up from scratch
 	fn init_view(&mut self, state: &WorldState) {
 		self.view.set_shaders(VERT_SHADER, FRAG_SHADER);
 		self.view
 			.set_title(&format!("Viewing the world of {}", state.name));
-		self.view
-			.set_texture_array(vec![conv_image_to_raw_image(&state.map.missing_image)]);
+	}
+
+	// Puts all of the map's textures in the view's texture array at once. Returns which
+	// layer each of them ended up in.
+	fn init_textures(&mut self, state: &WorldState) -> TextureLayers {
+		let (texture_layers, images) =
+			TextureLayers::new(&state.map, self.settings.texture_gutter_px);
+		let raw_images = images.into_iter().map(conv_image_to_raw_image).collect();
+		if !self
+			.view
+			.set_texture_array(raw_images, self.settings.texture_anisotropy)
+		{
+			error!("Presenter: Couldn't set texture array");
+		}
+		texture_layers
 	}
 }
@@ -0,0 +1,218 @@
+use cgmath::{Point2, Vector2};
+
+use image::{Rgba, RgbaImage};
+
+use rusttype::{point, Font, GlyphId, Scale};
+
+use std::collections::HashMap;
+
+// Blank pixels left around each glyph, so filtering never picks up its neighbours
+const GLYPH_PADDING_PX: u32 = 1;
+
+// Pixels across the solid block packed in for drawing plain shapes. Only its middle pixel's
+// drawn from, so filtering never picks up the blank ones around it.
+const SOLID_PX: u32 = 3;
+
+/// Where one glyph of some text goes, in pixels from the text's top left, and which part of
+/// the atlas it's drawn from
+#[derive(Clone, Copy, Debug)]
+pub struct GlyphQuad {
+	pub min: Point2<f64>,
+	pub max: Point2<f64>,
+	pub tex_min: Point2<f64>, // Tex coords, with v going up from the atlas' bottom row
+	pub tex_max: Point2<f64>,
+}
+
+/// Some text laid out in a line, ready to be drawn from the atlas
+#[derive(Clone, Debug)]
+pub struct TextLayout {
+	pub quads: Vec<GlyphQuad>,
+	pub size: Vector2<f64>, // Width and height of the line, in pixels
+}
+
+// Where a glyph was put in the atlas, and the offset to its top left from its origin on the
+// baseline. None for glyphs with nothing to draw, like spaces.
+type AtlasGlyph = Option<(Point2<u32>, Vector2<u32>, Vector2<i32>)>;
+
+/// An image of every glyph text has been laid out with so far, for the view's overlay
+/// texture. Glyphs are rendered the first time they're needed, at each font and size
+/// they're used at, and packed into rows along the image. Glyphs that no longer fit aren't
+/// drawn.
+///
+/// Glyphs are white, with their coverage in the alpha channel, so they can be drawn in
+/// any colour. A solid white block is packed in with them too, the first time one's needed,
+/// for drawing plain shapes.
+pub struct GlyphAtlas {
+	image: RgbaImage,
+	glyphs: HashMap<(String, u32, GlyphId), AtlasGlyph>, // By font, size and glyph
+	row_top: u32,                                        // Of the row glyphs are being packed into
+	row_height: u32,                                     // Of its tallest glyph so far
+	row_x: u32,                                          // Where the next glyph in it goes
+	solid: Option<Point2<u32>>, // Where the solid block went, once it's been needed
+	changed: bool,
+	full: bool,
+}
+impl GlyphAtlas {
+	/// An atlas of size x size pixels with no glyphs in it yet
+	pub fn new(size: u32) -> GlyphAtlas {
+		GlyphAtlas {
+			image: RgbaImage::from_pixel(size, size, Rgba([255, 255, 255, 0])),
+			glyphs: HashMap::new(),
+			row_top: 0,
+			row_height: 0,
+			row_x: 0,
+			solid: None,
+			changed: true,
+			full: false,
+		}
+	}
+
+	/// Tex coords of a solid white pixel, with v going up from the atlas' bottom row, adding
+	/// the solid block if it isn't in the atlas yet. None if there's no room for it.
+	pub fn solid(&mut self) -> Option<Point2<f64>> {
+		if self.solid.is_none() {
+			let solid = self.allocate(SOLID_PX, SOLID_PX)?;
+			for y in 0..SOLID_PX {
+				for x in 0..SOLID_PX {
+					self.image
+						.put_pixel(solid.x + x, solid.y + y, Rgba([255, 255, 255, 255]));
+				}
+			}
+			self.solid = Some(solid);
+			self.changed = true;
+		}
+		let solid = self.solid?;
+		let (atlas_width, atlas_height) = self.image.dimensions();
+		let middle = SOLID_PX as f64 / 2_f64;
+		Some(Point2::new(
+			(solid.x as f64 + middle) / atlas_width as f64,
+			1_f64 - (solid.y as f64 + middle) / atlas_height as f64,
+		))
+	}
+
+	/// Lays text out in font_name's font, size_px pixels from its ascent to its descent,
+	/// adding any of its glyphs that aren't in the atlas yet
+	pub fn layout(&mut self, font_name: &str, font: &Font, size_px: f64, text: &str) -> TextLayout {
+		let scale = Scale::uniform(size_px as f32);
+		let v_metrics = font.v_metrics(scale);
+		let mut quads = Vec::with_capacity(text.len());
+		let mut width = 0_f64;
+
+		for glyph in font.layout(text, scale, point(0_f32, v_metrics.ascent)) {
+			let position = glyph.position();
+			width = (position.x + glyph.unpositioned().h_metrics().advance_width) as f64;
+
+			let (atlas_pos, size, offset) = match self.glyph(font_name, font, size_px, glyph.id()) {
+				Some(glyph) => glyph,
+				None => continue,
+			};
+			// Snapped to whole pixels, so glyphs are drawn as crisply as they were rendered
+			let min = Point2::new(
+				position.x.round() as f64 + offset.x as f64,
+				position.y.round() as f64 + offset.y as f64,
+			);
+			let (atlas_width, atlas_height) = self.image.dimensions();
+			let tex_at = |x: u32, y: u32| {
+				Point2::new(
+					x as f64 / atlas_width as f64,
+					1_f64 - y as f64 / atlas_height as f64,
+				)
+			};
+			quads.push(GlyphQuad {
+				min,
+				max: min + Vector2::new(size.x as f64, size.y as f64),
+				tex_min: tex_at(atlas_pos.x, atlas_pos.y),
+				tex_max: tex_at(atlas_pos.x + size.x, atlas_pos.y + size.y),
+			});
+		}
+
+		TextLayout {
+			quads,
+			size: Vector2::new(width, (v_metrics.ascent - v_metrics.descent) as f64),
+		}
+	}
+
+	/// The atlas, if glyphs have been added to it since this was last called
+	pub fn take_changed(&mut self) -> Option<&RgbaImage> {
+		if !self.changed {
+			return None;
+		}
+		self.changed = false;
+		Some(&self.image)
+	}
+
+	// Where glyph id is in the atlas, rendering it in first if it's not there yet
+	fn glyph(&mut self, font_name: &str, font: &Font, size_px: f64, id: GlyphId) -> AtlasGlyph {
+		// Sizes are keyed to the hundredth of a pixel
+		let key = (
+			font_name.to_string(),
+			(size_px * 100_f64).round() as u32,
+			id,
+		);
+		if let Some(&glyph) = self.glyphs.get(&key) {
+			return glyph;
+		}
+
+		let glyph = font
+			.glyph(id)
+			.scaled(Scale::uniform(size_px as f32))
+			.positioned(point(0_f32, 0_f32));
+		let bounds = match glyph.pixel_bounding_box() {
+			Some(bounds) => bounds,
+			None => {
+				self.glyphs.insert(key, None);
+				return None;
+			}
+		};
+		let (width, height) = (bounds.width() as u32, bounds.height() as u32);
+		let atlas_pos = match self.allocate(width, height) {
+			Some(atlas_pos) => atlas_pos,
+			None => {
+				if !self.full {
+					warn!("Glyph atlas is full, some text won't be drawn");
+					self.full = true;
+				}
+				return None;
+			}
+		};
+		let image = &mut self.image;
+		glyph.draw(|x, y, coverage| {
+			let alpha = (coverage.min(1_f32) * 255_f32).round() as u8;
+			image.put_pixel(
+				atlas_pos.x + x,
+				atlas_pos.y + y,
+				Rgba([255, 255, 255, alpha]),
+			);
+		});
+		self.changed = true;
+
+		let atlas_glyph = Some((
+			atlas_pos,
+			Vector2::new(width, height),
+			Vector2::new(bounds.min.x, bounds.min.y),
+		));
+		self.glyphs.insert(key, atlas_glyph);
+		atlas_glyph
+	}
+
+	// Finds room for a width x height glyph, starting a new row if this one's out of room
+	fn allocate(&mut self, width: u32, height: u32) -> Option<Point2<u32>> {
+		let (atlas_width, atlas_height) = self.image.dimensions();
+		let (width, height) = (width + GLYPH_PADDING_PX, height + GLYPH_PADDING_PX);
+		if width > atlas_width {
+			return None;
+		}
+		if self.row_x + width > atlas_width {
+			self.row_top += self.row_height;
+			self.row_height = 0;
+			self.row_x = 0;
+		}
+		if self.row_top + height > atlas_height {
+			return None;
+		}
+		let atlas_pos = Point2::new(self.row_x, self.row_top);
+		self.row_x += width;
+		self.row_height = self.row_height.max(height);
+		Some(atlas_pos)
+	}
+}
@@ -0,0 +1,479 @@
+use super::city_labels::{add_text, screen_pos, LabelStyle};
+use super::glyph_atlas::{GlyphAtlas, TextLayout};
+use super::label_placement::{place_labels, LabelCandidate};
+use super::{
+	pick_lat_long, pick_middle, MapProjection, Mesh, MeshObject, Shading, Vertex, View, ViewParams,
+};
+use crate::entities::{LatLong, SphericalPoint};
+
+use cgmath::prelude::*;
+use cgmath::{Deg, Point2, Point3, Rad, Vector2, Vector3};
+
+use rusttype::Font;
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::rc::Rc;
+
+// Degrees between lines, coarsest first. Lines are as close together as they can be while
+// still at least MIN_SPACING_PX apart in the middle of the screen.
+const SPACINGS_DEG: [f64; 9] = [
+	30_f64, 15_f64, 10_f64, 5_f64, 2_f64, 1_f64, 0.5_f64, 0.2_f64, 0.1_f64,
+];
+const MIN_SPACING_PX: f64 = 80_f64;
+
+// Most lines drawn each way from the middle of the screen
+const MAX_LINES_EACH_WAY: i64 = 64;
+
+// Lines are drawn in straight segments at most this long. Segments longer than
+// MAX_SEGMENT_RADII world radii are torn across the edge of a flat map, and aren't drawn.
+const MAX_SEGMENT_DEG: f64 = 1_f64;
+const MAX_SEGMENT_RADII: f64 = 0.5_f64;
+
+// Steps per doubling of the camera's altitude that lines are resized at, as with markers
+const SIZE_STEPS_PER_DOUBLING: f64 = 8_f64;
+
+const LINE_WIDTH_PX: f64 = 1.5_f64;
+const LINE_COLOR: [f64; 3] = [0.8_f64, 0.9_f64, 1_f64];
+const LINE_OPACITY: f64 = 0.5_f64;
+
+// Pixels kept clear between where two lines cross and the label beside it
+const LABEL_MARGIN_PX: f64 = 3_f64;
+
+/// Which lines of the graticule are drawn, in multiples of the spacing
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Window {
+	spacing: usize,            // Into SPACINGS_DEG
+	lats: (i64, i64),          // Of the first and last parallels
+	longs: Option<(i64, i64)>, // Of the first and last meridians, None for all the way around
+	middle: (i64, i64),        // Of the lines nearest the middle of the screen, labels go along
+}
+
+/// Lines of latitude and longitude over the globe, or the flat map, with their degrees.
+/// Parallels are small circles and meridians great circles, lying on the surface and facing
+/// out of it, so the ones over the horizon aren't drawn. They're spaced out more the further
+/// away the camera is, and only drawn around what it can see.
+///
+/// Each line's labelled where it crosses the line nearest the middle of the screen, if there's
+/// room. Labels are laid out with the glyph atlas they're given, which must be the same one
+/// every time.
+pub struct Graticule {
+	label_style: LabelStyle,
+	layouts: HashMap<String, TextLayout>, // By their text
+	missing_font: bool,                   // Already warned about
+	mesh: Option<Rc<Mesh>>,
+	labels: Vec<(Vector3<f64>, String)>, // Where each line's label goes, as a unit vector
+	window: Option<Window>,              // Which lines the mesh was built with
+	size_step: Option<i64>,              // Which altitude step it was built for
+	map: Option<MapProjection>,          // Which flat map it was laid out on, if any
+}
+impl Graticule {
+	pub fn new(label_style: LabelStyle) -> Graticule {
+		Graticule {
+			label_style,
+			layouts: HashMap::new(),
+			missing_font: false,
+			mesh: None,
+			labels: Vec::new(),
+			window: None,
+			size_step: None,
+			map: None,
+		}
+	}
+
+	/// The objects to draw the lines with as seen with params, then those to draw the labels
+	/// that fit on screen with. Any glyphs they need that weren't in atlas are added to it.
+	pub fn update(
+		&mut self,
+		view: &dyn View,
+		atlas: &mut GlyphAtlas,
+		fonts: &HashMap<String, Font<'static>>,
+		params: &ViewParams,
+	) -> (Vec<MeshObject>, Vec<MeshObject>) {
+		let window = graticule_window(params);
+		let altitude = params.altitude();
+		let size_step = (altitude.max(f64::EPSILON).log2() * SIZE_STEPS_PER_DOUBLING).round();
+		if self.window != Some(window)
+			|| self.size_step != Some(size_step as i64)
+			|| self.map != params.map
+		{
+			self.window = Some(window);
+			self.size_step = Some(size_step as i64);
+			self.map = params.map;
+			// World units per pixel, at the surface right below the camera
+			let step_altitude = (size_step / SIZE_STEPS_PER_DOUBLING).exp2() * params.world_radius;
+			let world_per_px = step_altitude / params.px_per_rad;
+			let (mesh, labels) = graticule_mesh(view, params, &window, world_per_px);
+			self.mesh = mesh.map(Rc::new);
+			self.labels = labels;
+		}
+
+		let lines = self
+			.mesh
+			.iter()
+			.map(|mesh| {
+				let mut object = MeshObject::from_shared(mesh.clone());
+				object.shading = Shading::Flat {
+					opacity: LINE_OPACITY,
+				};
+				object
+			})
+			.collect();
+		(lines, self.label_objects(view, atlas, fonts, params))
+	}
+
+	// The objects to draw the labels that fit on screen with
+	fn label_objects(
+		&mut self,
+		view: &dyn View,
+		atlas: &mut GlyphAtlas,
+		fonts: &HashMap<String, Font<'static>>,
+		params: &ViewParams,
+	) -> Vec<MeshObject> {
+		let style = &self.label_style;
+		let font = match fonts.get(&style.font) {
+			Some(font) => font,
+			None => {
+				if !self.missing_font {
+					warn!(
+						"No font {} for the graticule, the world's [fonts] should name it",
+						style.font
+					);
+					self.missing_font = true;
+				}
+				return Vec::new();
+			}
+		};
+		let screen_size = Vector2::new(params.viewport.0 as f64, params.viewport.1 as f64);
+
+		let mut candidates = Vec::new();
+		let mut candidate_texts = Vec::new();
+		for &(dir, ref text) in &self.labels {
+			let anchor = match screen_pos(params, dir, screen_size) {
+				Some(anchor) => anchor,
+				None => continue,
+			};
+			let layout = self
+				.layouts
+				.entry(text.clone())
+				.or_insert_with(|| atlas.layout(&style.font, font, style.size_px, text));
+			candidates.push(LabelCandidate {
+				anchor,
+				size: layout.size,
+				margin: LABEL_MARGIN_PX,
+				priority: 0,
+			});
+			candidate_texts.push(text);
+		}
+
+		let (mut vertices, mut triangles) = (Vec::new(), Vec::new());
+		for placed in place_labels(&candidates, screen_size) {
+			add_text(
+				&mut vertices,
+				&mut triangles,
+				&self.layouts[candidate_texts[placed.candidate]],
+				placed.top_left,
+				style.color,
+				screen_size,
+			);
+		}
+		if vertices.is_empty() {
+			return Vec::new();
+		}
+		let mut object = MeshObject::new(Mesh::new(view.get_facade(), &vertices, &triangles));
+		object.shading = Shading::Overlay { opacity: 1_f64 };
+		vec![object]
+	}
+}
+
+// Which lines to draw to cover everything seen with params, as far apart as the camera's
+// distance from the middle of the screen calls for
+fn graticule_window(params: &ViewParams) -> Window {
+	let (middle_dir, distance) = pick_middle(params);
+	let rad_per_px = distance / params.world_radius / params.px_per_rad;
+	let spacing = SPACINGS_DEG
+		.iter()
+		.rposition(|&spacing| Rad::from(Deg(spacing)).0 / rad_per_px >= MIN_SPACING_PX)
+		.unwrap_or(0);
+	let spacing_deg = SPACINGS_DEG[spacing];
+	let middle = SphericalPoint::from_vec(&middle_dir).as_lat_long();
+
+	// How far around the globe from the middle the screen reaches, at its corners. When the
+	// globe doesn't fill it, that's out to the horizon.
+	let (width, height) = (params.viewport.0 as f64, params.viewport.1 as f64);
+	let corners = [
+		(0_f64, 0_f64),
+		(width, 0_f64),
+		(0_f64, height),
+		(width, height),
+	];
+	let corner_reaches: Option<Vec<f64>> = corners
+		.iter()
+		.map(|&(x, y)| {
+			pick_lat_long(params, Point2::new(x, y))
+				.map(|corner| middle.great_circle_distance(&corner).0)
+		})
+		.collect();
+	let reach = match (corner_reaches, params.map) {
+		(Some(reaches), _) => reaches.iter().cloned().fold(0_f64, f64::max),
+		(None, Some(_)) => PI,
+		(None, None) => {
+			let from_center = params.cam_pos - params.world_center;
+			let horizon = (params.world_radius / from_center.magnitude())
+				.min(1_f64)
+				.acos();
+			from_center.angle(middle_dir).0 + horizon
+		}
+	};
+
+	// Whole powers of two of the spacing, so they don't change every frame while zooming
+	let lines_each_way = |reach_deg: f64| {
+		(((reach_deg / spacing_deg).ceil() as i64).max(1) as u64)
+			.next_power_of_two()
+			.min(MAX_LINES_EACH_WAY as u64) as i64
+	};
+	let reach_deg = Deg::from(Rad(reach.min(PI))).0;
+	let lines_to_pole = (90_f64 / spacing_deg).round() as i64;
+	let lines_around = (360_f64 / spacing_deg).round() as i64;
+	let middle_lat = (Deg::from(middle.lat).0 / spacing_deg).round() as i64;
+	let middle_long = (Deg::from(middle.long).0 / spacing_deg).round() as i64;
+
+	let lats_each_way = lines_each_way(reach_deg);
+	let lats = (
+		(middle_lat - lats_each_way).max(-lines_to_pole),
+		(middle_lat + lats_each_way).min(lines_to_pole),
+	);
+	// Meridians spread further apart on the screen towards the poles, so more are needed
+	let furthest_lat = lats.0.abs().max(lats.1.abs()) as f64 * spacing_deg;
+	let longs = match furthest_lat < 90_f64 {
+		true => {
+			let longs_each_way = lines_each_way(reach_deg / Deg(furthest_lat).cos());
+			match 2 * longs_each_way < lines_around {
+				true => Some((middle_long - longs_each_way, middle_long + longs_each_way)),
+				false => None,
+			}
+		}
+		false => None,
+	};
+	Window {
+		spacing,
+		lats,
+		longs,
+		middle: (
+			middle_lat.max(1 - lines_to_pole).min(lines_to_pole - 1),
+			middle_long,
+		),
+	}
+}
+
+// A single mesh of the lines in window seen with params, world_per_px world units to a pixel,
+// and where each line's label goes, nearest the middle of the screen first. None if none of
+// the lines are drawn.
+fn graticule_mesh(
+	view: &dyn View,
+	params: &ViewParams,
+	window: &Window,
+	world_per_px: f64,
+) -> (Option<Mesh>, Vec<(Vector3<f64>, String)>) {
+	let spacing_deg = SPACINGS_DEG[window.spacing];
+	let decimals = if spacing_deg < 1_f64 { 1 } else { 0 };
+	let half_width = LINE_WIDTH_PX * world_per_px / 2_f64;
+	let (mut vertices, mut triangles) = (Vec::new(), Vec::new());
+	let mut labels = Vec::new();
+
+	let lat_range = (
+		(window.lats.0 as f64 * spacing_deg).max(-90_f64),
+		(window.lats.1 as f64 * spacing_deg).min(90_f64),
+	);
+	let (long_lines, long_range) = match window.longs {
+		Some((first, last)) => (
+			(first..=last).collect::<Vec<i64>>(),
+			(first as f64 * spacing_deg, last as f64 * spacing_deg),
+		),
+		None => {
+			let half_around = (180_f64 / spacing_deg).round() as i64;
+			((-half_around..half_around).collect(), (-180_f64, 180_f64))
+		}
+	};
+	let (middle_lat, middle_long) = (
+		window.middle.0 as f64 * spacing_deg,
+		window.middle.1 as f64 * spacing_deg,
+	);
+
+	// Parallels, leaving out the poles
+	for i in window.lats.0..=window.lats.1 {
+		let lat = i as f64 * spacing_deg;
+		if lat.abs() >= 90_f64 {
+			continue;
+		}
+		let dirs = sample_line(long_range).map(|long| lat_long_dir(lat, long));
+		add_line(&mut vertices, &mut triangles, params, dirs, half_width);
+		let text = lat_text(lat, decimals);
+		labels.push((
+			(i - window.middle.0).abs(),
+			lat_long_dir(lat, middle_long),
+			text,
+		));
+	}
+	// Meridians
+	for &i in &long_lines {
+		let long = i as f64 * spacing_deg;
+		let dirs = sample_line(lat_range).map(|lat| lat_long_dir(lat, long));
+		add_line(&mut vertices, &mut triangles, params, dirs, half_width);
+		let text = long_text(long, decimals);
+		labels.push((
+			(i - window.middle.1).abs(),
+			lat_long_dir(middle_lat, long),
+			text,
+		));
+	}
+
+	labels.sort_by_key(|&(from_middle, _, _)| from_middle);
+	let labels = labels
+		.into_iter()
+		.map(|(_, dir, text)| (dir, text))
+		.collect();
+	if triangles.is_empty() {
+		return (None, labels);
+	}
+	(
+		Some(Mesh::new(view.get_facade(), &vertices, &triangles)),
+		labels,
+	)
+}
+
+// Evenly spaced degrees from the start to the end of range, no more than MAX_SEGMENT_DEG apart
+fn sample_line(range: (f64, f64)) -> impl Iterator<Item = f64> {
+	let (start, end) = range;
+	let segments = ((end - start) / MAX_SEGMENT_DEG).ceil().max(1_f64) as u32;
+	(0..=segments).map(move |i| start + (end - start) * i as f64 / segments as f64)
+}
+
+// Adds a line through the points on the surface straight out along dirs, unit vectors, as a
+// strip half_width world units to each side of it, facing out of the surface
+fn add_line<I>(
+	vertices: &mut Vec<Vertex>,
+	triangles: &mut Vec<u32>,
+	params: &ViewParams,
+	dirs: I,
+	half_width: f64,
+) where
+	I: Iterator<Item = Vector3<f64>>,
+{
+	let points: Vec<(Point3<f64>, Vector3<f64>)> =
+		dirs.map(|dir| params.surface_pos(dir)).collect();
+	for pair in points.windows(2) {
+		let ((start, start_up), (end, end_up)) = (pair[0], pair[1]);
+		let along = end - start;
+		let length = along.magnitude();
+		if length <= f64::EPSILON || length > MAX_SEGMENT_RADII * params.world_radius {
+			continue;
+		}
+		let up = (start_up + end_up).normalize();
+		let left = match up.cross(along / length) {
+			left if left.magnitude2() > f64::EPSILON => left.normalize() * half_width,
+			_ => continue,
+		};
+
+		let vertex = |position: Point3<f64>| Vertex {
+			position: position.to_homogeneous().into(),
+			color: LINE_COLOR,
+			normal: up.into(),
+			..Default::default()
+		};
+		let first = vertices.len() as u32;
+		vertices.extend_from_slice(&[
+			vertex(start + left),
+			vertex(end + left),
+			vertex(end - left),
+			vertex(start - left),
+		]);
+		// Clockwise seen from above, so they're front facing
+		triangles.extend_from_slice(&[first, first + 1, first + 2, first, first + 2, first + 3]);
+	}
+}
+
+// Unit vector from the world's center through lat/long, in degrees
+fn lat_long_dir(lat: f64, long: f64) -> Vector3<f64> {
+	LatLong::new(Deg(lat), Deg(long))
+		.as_sph_point(1_f64)
+		.to_vec()
+}
+
+// Degrees north or south of the equator, to decimals places
+fn lat_text(lat: f64, decimals: usize) -> String {
+	let hemisphere = match lat {
+		lat if lat > 0_f64 => "N",
+		lat if lat < 0_f64 => "S",
+		_ => "",
+	};
+	format!("{:.*}°{}", decimals, lat.abs(), hemisphere)
+}
+
+// Degrees east or west of the prime meridian, to decimals places, wrapped around to within
+// 180° of it
+fn long_text(long: f64, decimals: usize) -> String {
+	let long = long - 360_f64 * ((long + 180_f64) / 360_f64).floor();
+	let long = (long * 1e6_f64).round() / 1e6_f64;
+	let hemisphere = match long {
+		long if long == 0_f64 || long == -180_f64 => "",
+		long if long > 0_f64 => "E",
+		_ => "W",
+	};
+	format!("{:.*}°{}", decimals, long.abs(), hemisphere)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn lines_get_closer_together_closer_to_the_ground() {
+		let spacings: Vec<f64> = [3_f64, 1_f64, 0.5_f64, 0.1_f64, 0.01_f64]
+			.iter()
+			.map(|&altitude| {
+				SPACINGS_DEG[graticule_window(&ViewParams::looking_down_at(
+					20_f64, 40_f64, altitude,
+				))
+				.spacing]
+			})
+			.collect();
+		assert_eq!(spacings, [30_f64, 10_f64, 5_f64, 1_f64, 0.1_f64]);
+	}
+
+	#[test]
+	fn lines_are_as_close_as_they_can_be_while_far_enough_apart() {
+		for &altitude in &[2_f64, 0.7_f64, 0.3_f64, 0.05_f64, 0.02_f64] {
+			let params = ViewParams::looking_down_at(-10_f64, 100_f64, altitude);
+			let spacing = graticule_window(&params).spacing;
+			// Pixels apart at the middle of the screen, straight below the camera
+			let spacing_px =
+				|spacing: f64| Rad::from(Deg(spacing)).0 / altitude * params.px_per_rad;
+			assert!(
+				spacing_px(SPACINGS_DEG[spacing]) >= MIN_SPACING_PX,
+				"at {}",
+				altitude
+			);
+			assert!(
+				spacing_px(SPACINGS_DEG[spacing + 1]) < MIN_SPACING_PX,
+				"at {}",
+				altitude
+			);
+		}
+	}
+
+	#[test]
+	fn window_is_around_the_middle_of_the_screen() {
+		let window = graticule_window(&ViewParams::looking_down_at(35_f64, 5_f64, 0.1_f64));
+		// 1° apart
+		assert_eq!(window.middle, (35, 5));
+		assert!(window.lats.0 < 35 && 35 < window.lats.1);
+		let longs = window.longs.unwrap();
+		assert!(longs.0 < 5 && 5 < longs.1);
+		// Far enough out, lines go all the way around
+		assert_eq!(
+			graticule_window(&ViewParams::looking_down_at(35_f64, 5_f64, 3_f64)).longs,
+			None
+		);
+	}
+}
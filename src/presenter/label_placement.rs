@@ -0,0 +1,208 @@
+use cgmath::{Point2, Vector2};
+
+use std::collections::HashMap;
+
+// Size of the cells placed labels are bucketed into, for finding which ones might overlap
+const GRID_CELL_PX: f64 = 64_f64;
+
+// Space kept clear between labels
+const LABEL_GAP_PX: f64 = 2_f64;
+
+/// A label that wants a place on screen, beside the point it's anchored to. Screen coords
+/// are in pixels, with y going down from the top left.
+#[derive(Clone, Copy, Debug)]
+pub struct LabelCandidate {
+	pub anchor: Point2<f64>,
+	pub size: Vector2<f64>,
+	pub margin: f64, // Pixels kept clear between the anchor and the label, like for a marker
+	pub priority: u32, // Higher priority labels are placed first
+}
+
+/// Where a candidate was placed on screen
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlacedLabel {
+	pub candidate: usize, // Index into the candidates
+	pub top_left: Point2<f64>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Rect {
+	min: Point2<f64>,
+	max: Point2<f64>,
+}
+impl Rect {
+	fn overlaps(&self, other: &Rect) -> bool {
+		self.min.x < other.max.x
+			&& other.min.x < self.max.x
+			&& self.min.y < other.max.y
+			&& other.min.y < self.max.y
+	}
+
+	// The grid cells the rect touches
+	fn cells(&self) -> Vec<(i64, i64)> {
+		let cell = |value: f64| (value / GRID_CELL_PX).floor() as i64;
+		let mut cells = Vec::new();
+		for x in cell(self.min.x)..=cell(self.max.x) {
+			for y in cell(self.min.y)..=cell(self.max.y) {
+				cells.push((x, y));
+			}
+		}
+		cells
+	}
+}
+
+/// Places as many of candidates on a screen of screen_size pixels as fit without any two
+/// overlapping, highest priority first, with ties going to whichever comes first. Each is
+/// tried to the right of its anchor, then the left, above and below, and left out if none
+/// of those are clear and entirely on screen.
+pub fn place_labels(candidates: &[LabelCandidate], screen_size: Vector2<f64>) -> Vec<PlacedLabel> {
+	let mut order: Vec<usize> = (0..candidates.len()).collect();
+	order.sort_by_key(|&i| std::cmp::Reverse(candidates[i].priority));
+
+	let mut placed = Vec::new();
+	let mut grid: HashMap<(i64, i64), Vec<Rect>> = HashMap::new();
+	for i in order {
+		let candidate = &candidates[i];
+		let (anchor, size, margin) = (candidate.anchor, candidate.size, candidate.margin);
+		let spots = [
+			Point2::new(anchor.x + margin, anchor.y - size.y / 2_f64),
+			Point2::new(anchor.x - margin - size.x, anchor.y - size.y / 2_f64),
+			Point2::new(anchor.x - size.x / 2_f64, anchor.y - margin - size.y),
+			Point2::new(anchor.x - size.x / 2_f64, anchor.y + margin),
+		];
+
+		let spot = spots.iter().find(|&&top_left| {
+			let rect = Rect {
+				min: top_left,
+				max: top_left + size,
+			};
+			let on_screen = rect.min.x >= 0_f64
+				&& rect.min.y >= 0_f64
+				&& rect.max.x <= screen_size.x
+				&& rect.max.y <= screen_size.y;
+			on_screen
+				&& !rect.cells().iter().any(|cell| {
+					grid.get(cell)
+						.is_some_and(|rects| rects.iter().any(|other| rect.overlaps(other)))
+				})
+		});
+		if let Some(&top_left) = spot {
+			// Grown by the gap, so the next labels keep clear of it
+			let gap = Vector2::new(LABEL_GAP_PX, LABEL_GAP_PX);
+			let rect = Rect {
+				min: top_left - gap,
+				max: top_left + size + gap,
+			};
+			for cell in rect.cells() {
+				grid.entry(cell).or_default().push(rect);
+			}
+			placed.push(PlacedLabel {
+				candidate: i,
+				top_left,
+			});
+		}
+	}
+	placed
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const SCREEN: Vector2<f64> = Vector2 {
+		x: 800_f64,
+		y: 600_f64,
+	};
+
+	// A 60x12 label, for a city of population
+	fn candidate(x: f64, y: f64, population: u32) -> LabelCandidate {
+		LabelCandidate {
+			anchor: Point2::new(x, y),
+			size: Vector2::new(60_f64, 12_f64),
+			margin: 4_f64,
+			priority: population,
+		}
+	}
+
+	fn placed_candidates(placed: &[PlacedLabel]) -> Vec<usize> {
+		let mut candidates: Vec<usize> = placed.iter().map(|label| label.candidate).collect();
+		candidates.sort();
+		candidates
+	}
+
+	#[test]
+	fn overlapping_labels_are_left_out() {
+		// Three at the same spot leave no room around it for the third, and one far off
+		let candidates = [
+			candidate(400_f64, 300_f64, 100),
+			candidate(400_f64, 300_f64, 100),
+			candidate(401_f64, 301_f64, 100),
+			candidate(100_f64, 100_f64, 100),
+		];
+		let placed = place_labels(&candidates, SCREEN);
+		assert_eq!(placed_candidates(&placed), vec![0, 1, 3]);
+		assert_eq!(placed[0].top_left, Point2::new(404_f64, 294_f64));
+		assert_eq!(placed[1].top_left, Point2::new(336_f64, 294_f64));
+
+		let rects: Vec<Rect> = placed
+			.iter()
+			.map(|label| Rect {
+				min: label.top_left,
+				max: label.top_left + candidates[label.candidate].size,
+			})
+			.collect();
+		for (i, rect) in rects.iter().enumerate() {
+			for other in &rects[i + 1..] {
+				assert!(!rect.overlaps(other));
+			}
+		}
+	}
+
+	#[test]
+	fn bigger_settlements_are_placed_first() {
+		// A village, then a city fighting it for the same spot, and a town for the next best,
+		// leaving no room for the village
+		let candidates = [
+			candidate(400_f64, 300_f64, 200),
+			candidate(400_f64, 300_f64, 20_000),
+			candidate(400_f64, 300_f64, 3_000),
+		];
+		let placed = place_labels(&candidates, SCREEN);
+		assert_eq!(placed[0].candidate, 1);
+		assert_eq!(placed[0].top_left, Point2::new(404_f64, 294_f64));
+		assert_eq!(placed[1].candidate, 2);
+		assert_eq!(placed[1].top_left, Point2::new(336_f64, 294_f64));
+		assert_eq!(placed.len(), 2);
+
+		// Ties go to whichever came first
+		let candidates = [
+			candidate(400_f64, 300_f64, 500),
+			candidate(400_f64, 300_f64, 500),
+		];
+		assert_eq!(place_labels(&candidates, SCREEN)[0].candidate, 0);
+	}
+
+	#[test]
+	fn labels_off_screen_are_culled() {
+		let candidates = [
+			candidate(-50_f64, 300_f64, 100),
+			candidate(400_f64, 700_f64, 100),
+			candidate(400_f64, 300_f64, 100),
+			// Too close to the right edge for the right, so placed on the left
+			candidate(790_f64, 300_f64, 100),
+			// Fits only below the anchor, in the top left corner
+			candidate(35_f64, 2_f64, 100),
+		];
+		let placed = place_labels(&candidates, SCREEN);
+		assert_eq!(placed_candidates(&placed), vec![2, 3, 4]);
+		let top_left = |i: usize| {
+			placed
+				.iter()
+				.find(|label| label.candidate == i)
+				.unwrap()
+				.top_left
+		};
+		assert_eq!(top_left(3), Point2::new(726_f64, 294_f64));
+		assert_eq!(top_left(4), Point2::new(5_f64, 6_f64));
+	}
+}
@@ -1,10 +1,14 @@
-use cgmath::{InnerSpace, Rad, Vector3};
+use cgmath::{Deg, InnerSpace, Rad, Vector3};
 use glium::backend::Facade;
 
 use rand::rngs::ThreadRng;
 use rand::Rng;
 
-use crate::entities::SphericalPoint;
+use super::globe_mesh::{cube_sphere, icosphere, model_triangles, texture_triangles};
+use super::{GlobeMesh, TextureLayers};
+use crate::entities::{LatLong, MapBounds, MapPieceKey, Model, SphericalPoint};
+
+use std::collections::HashMap;
 
 #[derive(Copy, Clone)]
 pub struct Vertex {
@@ -46,43 +50,108 @@ impl std::fmt::Debug for Vertex {
 }
 glium::implement_vertex!(Vertex, position, color, normal, tex_coords);
 
-pub struct Mesh {
-	//pub vertices: Vec<Vertex>,
-	//pub triangles_list: Vec<u32>,
+/// A mesh's vertices and triangles uploaded for drawing with GL
+pub struct MeshBuffers {
 	pub vertex_buffer: glium::VertexBuffer<Vertex>,
 	pub index_buffer: glium::index::IndexBuffer<u32>,
 }
+
+pub struct Mesh {
+	pub vertices: Vec<Vertex>,
+	pub triangles_list: Vec<u32>,
+	pub buffers: Option<MeshBuffers>, // Only for views that draw with GL
+}
 impl Mesh {
-	pub fn new<F: ?Sized>(facade: &F, vertices: &[Vertex], triangles: &[u32]) -> Mesh
+	/// A mesh of vertices and triangles, uploaded to facade if there is one
+	pub fn new<F>(facade: Option<&F>, vertices: &[Vertex], triangles: &[u32]) -> Mesh
 	where
-		F: Facade,
+		F: Facade + ?Sized,
 	{
 		Mesh {
-			vertex_buffer: glium::VertexBuffer::new(facade, vertices).unwrap(),
-			index_buffer: glium::index::IndexBuffer::new(
-				facade,
-				glium::index::PrimitiveType::TrianglesList,
-				triangles,
-			)
-			.unwrap(),
+			vertices: vertices.to_vec(),
+			triangles_list: triangles.to_vec(),
+			buffers: facade.map(|facade| MeshBuffers {
+				vertex_buffer: glium::VertexBuffer::new(facade, vertices).unwrap(),
+				index_buffer: glium::index::IndexBuffer::new(
+					facade,
+					glium::index::PrimitiveType::TrianglesList,
+					triangles,
+				)
+				.unwrap(),
+			}),
 		}
 	}
+
+	/// Generates the globe as the kind of mesh asked for, textured from texture_layers. A
+	/// model not in models falls back to a UV sphere.
+	pub fn gen_globe_mesh<F>(
+		facade: Option<&F>,
+		kind: &GlobeMesh,
+		radius: f64,
+		texture_layers: &TextureLayers,
+		models: &HashMap<String, Model>,
+	) -> Mesh
+	where
+		F: Facade + ?Sized,
+	{
+		let triangles = match *kind {
+			GlobeMesh::UvSphere { deg_resolution } => {
+				return Self::gen_sphere_mesh(facade, deg_resolution, radius, texture_layers);
+			}
+			GlobeMesh::Icosphere { subdivisions } => icosphere(subdivisions),
+			GlobeMesh::CubeSphere { subdivisions } => cube_sphere(subdivisions),
+			GlobeMesh::Model { ref name } => match models.get(name) {
+				Some(model) => model_triangles(model),
+				None => {
+					warn!("No model {} for the globe, using a UV sphere", name);
+					return Self::gen_sphere_mesh(facade, 1_u32, radius, texture_layers);
+				}
+			},
+		};
+		let (vertices, triangles_list) = texture_triangles(triangles, radius, texture_layers);
+		info!(
+			"Generated {:?} globe mesh, with {} triangles after cutting along textures",
+			kind,
+			triangles_list.len() / 3
+		);
+		Self::new(facade, &vertices, &triangles_list)
+	}
+
 	// TODO clean this up, it's unbelievably bad
-	pub fn gen_sphere_mesh<F: ?Sized>(facade: &F, deg_resolution: u32, radius: f64) -> Mesh
+	pub fn gen_sphere_mesh<F>(
+		facade: Option<&F>,
+		deg_resolution: u32,
+		radius: f64,
+		texture_layers: &TextureLayers,
+	) -> Mesh
 	where
-		F: Facade,
+		F: Facade + ?Sized,
 	{
 		let lat_divs = 180u32 / deg_resolution;
 		let lat_inc = std::f64::consts::PI / (lat_divs as f64);
 
 		let lon_divs = 360u32 / deg_resolution;
 		let lon_inc = 2f64 * std::f64::consts::PI / (lon_divs as f64);
-		// TODO Only generates texture coords for the missing texture atm
+
+		let tex_size_deg = texture_layers.texture_size_deg();
+		if tex_size_deg % deg_resolution != 0 {
+			warn!(
+				"Sphere resolution {} doesn't divide texture size {}, textures won't line up",
+				deg_resolution, tex_size_deg
+			);
+		}
 
 		let mut vertices = Vec::new();
 		let mut triangles_list = Vec::new();
 		let mut rng = rand::thread_rng();
 
+		// Lat/long in degrees of a point given in (fractional) lat and lon divs
+		let lat_long_deg = |lat: f64, lon: f64| {
+			let lat_long =
+				SphericalPoint::new(radius, Rad(lat * lat_inc), Rad(lon * lon_inc)).as_lat_long();
+			(Deg::from(lat_long.lat).0, Deg::from(lat_long.long).0)
+		};
+
 		fn add_vertex_fn(
 			lat: f64,
 			lon: f64,
@@ -103,16 +172,27 @@ impl Mesh {
 
 			verts.push(vert);
 		}
-		let mut add_vertex = |lat: u32, lon: u32, tex: [f64; 3], verts: &mut Vec<Vertex>| {
-			add_vertex_fn(
-				(lat as f64) * lat_inc,
-				(lon as f64) * lon_inc,
-				tex,
-				radius,
-				&mut rng,
-				verts,
-			);
-		};
+		// Adds a vertex with coords into the texture for key, which is at layer tex_id
+		let mut add_vertex =
+			|lat: u32, lon: u32, key: &MapPieceKey, tex_id: f64, verts: &mut Vec<Vertex>| {
+				let (lat_deg, long_deg) = lat_long_deg(lat as f64, lon as f64);
+				// Longitude wraps around, so measure it from the closest copy of the texture
+				let long_from_key =
+					(long_deg - key.min_long as f64 + 180f64).rem_euclid(360f64) - 180f64;
+				let (u, v) = texture_layers.inset_tex_coords(
+					long_from_key / tex_size_deg as f64,
+					(lat_deg - key.min_lat as f64) / tex_size_deg as f64,
+				);
+				let tex = [u, v, tex_id];
+				add_vertex_fn(
+					(lat as f64) * lat_inc,
+					(lon as f64) * lon_inc,
+					tex,
+					radius,
+					&mut rng,
+					verts,
+				);
+			};
 
 		let add_triangle = |base, offset_a, offset_b, offset_c, tris: &mut Vec<u32>| {
 			tris.push(base + offset_a);
@@ -123,61 +203,31 @@ impl Mesh {
 		for lat in 0..lat_divs {
 			for lon in 0..lon_divs {
 				let verts_len = vertices.len() as u32;
-				let lat_deg: i32 = 90i32 - (deg_resolution as i32 * lat as i32);
-				let lon_deg: i32 = (deg_resolution as i32 * lon as i32) - 180i32;
-				let (min_lat, rows, min_lon, cols) = (23i32, 31i32, -26i32, 39i32);
-
-				// TODO support resolutions > 1?
-				let tex_id: f64 = if lat_deg >= min_lat
-					&& lat_deg < min_lat + rows
-					&& lon_deg >= min_lon
-					&& lon_deg < min_lon + cols
-				{
-					let row = lat_deg - min_lat;
-					let col = lon_deg - min_lon;
-					1f64 + (col as f64) + ((row * cols) as f64)
-				} else {
-					0f64
-				};
+
+				// The texture used for the whole quad is the one under its center
+				let (center_lat, center_long) =
+					lat_long_deg(lat as f64 + 0.5f64, lon as f64 + 0.5f64);
+				let key = MapPieceKey::containing(center_lat, center_long, tex_size_deg);
+				let tex_id = texture_layers.get_layer(&key) as f64;
 
 				if lat == 0 {
 					// First ring, 3 verts, one triangle for each lon
-					add_vertex(lat, lon, [0f64, 1f64, tex_id], &mut vertices);
-					add_vertex(
-						lat + 1,
-						(lon + 1) % lon_divs,
-						[1f64, 0f64, tex_id],
-						&mut vertices,
-					);
-					add_vertex(lat + 1, lon, [0f64, 0f64, tex_id], &mut vertices);
+					add_vertex(lat, lon, &key, tex_id, &mut vertices);
+					add_vertex(lat + 1, (lon + 1) % lon_divs, &key, tex_id, &mut vertices);
+					add_vertex(lat + 1, lon, &key, tex_id, &mut vertices);
 					add_triangle(verts_len, 0, 1, 2, &mut triangles_list);
 				} else if lat == lat_divs - 1 {
 					// Last ring, 3 verts, one triangle for each lon
-					add_vertex(lat, lon, [0f64, 1f64, tex_id], &mut vertices);
-					add_vertex(
-						lat,
-						(lon + 1) % lon_divs,
-						[1f64, 1f64, tex_id],
-						&mut vertices,
-					);
-					add_vertex(lat + 1, lon, [0f64, 0f64, tex_id], &mut vertices);
+					add_vertex(lat, lon, &key, tex_id, &mut vertices);
+					add_vertex(lat, (lon + 1) % lon_divs, &key, tex_id, &mut vertices);
+					add_vertex(lat + 1, lon, &key, tex_id, &mut vertices);
 					add_triangle(verts_len, 0, 1, 2, &mut triangles_list);
 				} else {
 					// Middle rings, 4 verts, two triangles for each lon
-					add_vertex(lat, lon, [0f64, 1f64, tex_id], &mut vertices);
-					add_vertex(
-						lat,
-						(lon + 1) % lon_divs,
-						[1f64, 1f64, tex_id],
-						&mut vertices,
-					);
-					add_vertex(lat + 1, lon, [0f64, 0f64, tex_id], &mut vertices);
-					add_vertex(
-						lat + 1,
-						(lon + 1) % lon_divs,
-						[1f64, 0f64, tex_id],
-						&mut vertices,
-					);
+					add_vertex(lat, lon, &key, tex_id, &mut vertices);
+					add_vertex(lat, (lon + 1) % lon_divs, &key, tex_id, &mut vertices);
+					add_vertex(lat + 1, lon, &key, tex_id, &mut vertices);
+					add_vertex(lat + 1, (lon + 1) % lon_divs, &key, tex_id, &mut vertices);
 					add_triangle(verts_len, 0, 1, 2, &mut triangles_list);
 					add_triangle(verts_len, 1, 3, 2, &mut triangles_list);
 				}
@@ -187,18 +237,119 @@ impl Mesh {
 		// Clean up and give our caller a beautiful mesh!
 		Self::new(facade, &vertices, &triangles_list)
 	}
+
+	/// Generates the part of a sphere inside bounds, as a grid of divs by divs quads. Texture
+	/// coords go from 0 to 1 across bounds, west to east and south to north, all on layer 0.
+	pub fn gen_patch_mesh<F>(facade: Option<&F>, bounds: &MapBounds, divs: u32, radius: f64) -> Mesh
+	where
+		F: Facade + ?Sized,
+	{
+		let mut vertices = Vec::new();
+		// Rows go from north to south and columns from west to east, like the sphere's
+		for row in 0..=divs {
+			let v = 1_f64 - row as f64 / divs as f64;
+			let lat = bounds.min_lat + v * (bounds.max_lat - bounds.min_lat);
+			for column in 0..=divs {
+				let u = column as f64 / divs as f64;
+				let long = bounds.min_long + u * (bounds.max_long - bounds.min_long);
+				let pos = LatLong::new(Deg(lat), Deg(long))
+					.as_sph_point(radius)
+					.to_point();
+				vertices.push(Vertex {
+					position: [pos.x, pos.y, pos.z, 1_f64],
+					normal: Vector3::new(pos.x, pos.y, pos.z).normalize().into(),
+					tex_coords: [u, v, 0_f64],
+					..Default::default()
+				});
+			}
+		}
+
+		let mut triangles_list = Vec::new();
+		let index = |row: u32, column: u32| row * (divs + 1) + column;
+		for row in 0..divs {
+			for column in 0..divs {
+				triangles_list.extend_from_slice(&[
+					index(row, column),
+					index(row, column + 1),
+					index(row + 1, column),
+					index(row, column + 1),
+					index(row + 1, column + 1),
+					index(row + 1, column),
+				]);
+			}
+		}
+		Self::new(facade, &vertices, &triangles_list)
+	}
 }
 impl std::fmt::Debug for Mesh {
 	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-		write!(f, "Mesh[{:?}, {:?}]", self.vertex_buffer, self.index_buffer)
+		write!(
+			f,
+			"Mesh[{} vertices, {} triangles]",
+			self.vertices.len(),
+			self.triangles_list.len() / 3
+		)
 		/*writeln!(f, "Vertices (position, normal, tex_coords, color):")?;
-		for (i, vertex) in self.vertex_buffer.data.iter().enumerate() {
+		for (i, vertex) in self.vertices.iter().enumerate() {
 			writeln!(f, "{}: {:?}", i, vertex)?;
 		}
 		writeln!(f, "Indices:")?;
-		for (i, index) in self.index_buffer.data.iter().enumerate() {
+		for (i, index) in self.triangles_list.iter().enumerate() {
 			writeln!(f, "{}: {:?}", i, index)?;
 		}
 		Ok(())*/
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::entities::Map;
+
+	use image::{DynamicImage, RgbaImage};
+
+	#[test]
+	fn sphere_mesh_is_a_textured_uv_sphere() {
+		// One texture for every 30° square of the sphere, with only the one at 0, 0 loaded
+		let blank = DynamicImage::ImageRgba8(RgbaImage::new(4, 4));
+		let mut map = Map::new("test", vec![], blank.clone(), 30);
+		let key = MapPieceKey {
+			min_lat: 0,
+			min_long: 0,
+		};
+		map.textures.insert(key, blank);
+		let (texture_layers, _) = TextureLayers::new(&map, 0);
+		let mesh = Mesh::gen_sphere_mesh(None::<&dyn Facade>, 30, 2_f64, &texture_layers);
+
+		// 12 triangles around each pole, and 12 quads around each of the 4 rings between
+		assert_eq!(mesh.vertices.len(), 2 * 12 * 3 + 4 * 12 * 4);
+		assert_eq!(mesh.triangles_list.len(), (2 * 12 + 4 * 12 * 2) * 3);
+		assert!(mesh
+			.triangles_list
+			.iter()
+			.all(|&index| (index as usize) < mesh.vertices.len()));
+
+		for vertex in &mesh.vertices {
+			let [x, y, z, _] = vertex.position;
+			assert!((Vector3::new(x, y, z).magnitude() - 2_f64).abs() < 1e-9_f64);
+
+			// Each quad spans exactly one texture, so its corners are at the texture's
+			// corners, even where it crosses the antimeridian or meets a pole
+			let [u, v, layer] = vertex.tex_coords;
+			for coord in &[u, v] {
+				assert!(
+					coord.abs() < 1e-9_f64 || (coord - 1_f64).abs() < 1e-9_f64,
+					"{:?}",
+					vertex
+				);
+			}
+			assert!(layer == 0_f64 || layer == 1_f64);
+		}
+		// Only the quad over 0, 0 has its texture, the rest are missing
+		let textured = mesh
+			.vertices
+			.iter()
+			.filter(|vertex| vertex.tex_coords[2] == 1_f64);
+		assert_eq!(textured.count(), 4);
+	}
+}
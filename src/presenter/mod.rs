@@ -4,8 +4,47 @@ pub use self::glpresenter::GLPresenter;
 mod view;
 pub use self::view::View;
 
+mod city_labels;
+pub use self::city_labels::{CityLabels, LabelStyle};
+
+mod city_markers;
+pub use self::city_markers::CityMarkers;
+
+mod flat_map;
+pub use self::flat_map::{FlatMap, MapProjection};
+
+mod flight;
+pub use self::flight::Flight;
+
+mod globe_mesh;
+pub use self::globe_mesh::GlobeMesh;
+
+mod glyph_atlas;
+pub use self::glyph_atlas::GlyphAtlas;
+
+mod graticule;
+pub use self::graticule::Graticule;
+
+mod label_placement;
+
 mod mesh;
 pub use self::mesh::{Mesh, Vertex};
 
+mod picking;
+pub use self::picking::{pick_lat_long, pick_middle, pick_point};
+
+mod scale_bar;
+pub use self::scale_bar::ScaleBar;
+
+mod settings;
+
 mod objects;
-pub use self::objects::{ AmbientLight, Camera, MeshObject, WorldLight, };
+pub use self::objects::{
+	AmbientLight, Camera, CameraLimits, MeshObject, Shading, TexTransform, WorldLight,
+};
+
+mod texture_layers;
+pub use self::texture_layers::TextureLayers;
+
+mod tile_streamer;
+pub use self::tile_streamer::{TileStreamer, ViewParams};
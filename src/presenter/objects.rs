@@ -1,21 +1,60 @@
-use super::Mesh;
-use crate::entities::SphericalPoint;
+use super::{Flight, Mesh};
+use crate::entities::{CameraView, LatLong, SphericalPoint};
 
 use cgmath::prelude::*;
-use cgmath::{Matrix4, Point2, Point3, Rad, Vector3, Vector4};
+use cgmath::{Matrix3, Matrix4, Point2, Point3, Rad, Vector3, Vector4};
+
+use std::rc::Rc;
+
+/// Where a mesh's texture coords land in the texture array: scaled, then offset, then moved
+/// up by layer layers. Lets a mesh be drawn with whichever texture is loaded at the time,
+/// rather than having it baked into the mesh's vertices.
+#[derive(Clone, Copy, Debug)]
+pub struct TexTransform {
+	pub offset: [f64; 2],
+	pub scale: [f64; 2],
+	pub layer: u32,
+}
+impl Default for TexTransform {
+	fn default() -> TexTransform {
+		TexTransform {
+			offset: [0_f64, 0_f64],
+			scale: [1_f64, 1_f64],
+			layer: 0_u32,
+		}
+	}
+}
+
+/// How an object's surface is coloured
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shading {
+	Textured,              // From the texture array, lit by the lights
+	Flat { opacity: f64 }, // From its vertices' colours, unlit, and blended over what's behind
+	// Like flat, but with the overlay texture's alpha, and with vertices already in clip
+	// space rather than the world, for things drawn on the screen like text
+	Overlay { opacity: f64 },
+}
 
 /// An object in the game world, which has a mesh
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct MeshObject {
-	pub mesh: Mesh,
+	pub mesh: Rc<Mesh>, // Shared between objects drawing the same mesh
+	pub tex_transform: TexTransform,
+	pub shading: Shading,
 	scale_mat: Matrix4<f64>,
 	rotation_mat: Matrix4<f64>,
 	translation_mat: Matrix4<f64>,
 }
 impl MeshObject {
 	pub fn new(mesh: Mesh) -> MeshObject {
+		MeshObject::from_shared(Rc::new(mesh))
+	}
+
+	pub fn from_shared(mesh: Rc<Mesh>) -> MeshObject {
 		MeshObject {
-			mesh: mesh,
+			mesh,
+			tex_transform: TexTransform::default(),
+			shading: Shading::Textured,
 			scale_mat: Matrix4::identity(),
 			rotation_mat: Matrix4::identity(),
 			translation_mat: Matrix4::identity(),
@@ -74,10 +113,58 @@ pub struct WorldLight {
 	pub color: Vector4<f64>,
 }
 
-#[derive(Debug)]
+// How near the camera can orbit to straight over either pole of what it's locked to, where it
+// couldn't tell which way's up
+const POLE_MARGIN: Rad<f64> = Rad(0.00001_f64);
+
+/// Where the camera can go: how near to and far from the world's sphere, so it can't end up
+/// inside it or lost far away from it, and how far over it can tilt orbiting a point on the
+/// surface. Altitudes are above the surface, in world units.
+#[derive(Clone, Copy, Debug)]
+pub struct CameraLimits {
+	pub world_center: Point3<f64>,
+	pub world_radius: f64,
+	pub min_altitude: f64,
+	pub max_altitude: f64,
+	pub max_tilt: Rad<f64>, // From looking straight down
+}
+impl CameraLimits {
+	fn clamp_altitude(&self, altitude: f64) -> f64 {
+		altitude.max(self.min_altitude).min(self.max_altitude)
+	}
+}
+impl Default for CameraLimits {
+	fn default() -> CameraLimits {
+		CameraLimits {
+			world_center: Point3::new(0_f64, 0_f64, 0_f64),
+			world_radius: 0_f64,
+			min_altitude: 0_f64,
+			max_altitude: f64::INFINITY,
+			max_tilt: Rad::turn_div_4(),
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug)]
 struct LockedData {
 	pub rotate_point: Point3<f64>,
 	pub zoom_dist: f64,
+	// Turns directions from the locked point's own frame, where theta's measured from its up
+	// and phi from its south, into the world's
+	pub frame: Matrix3<f64>,
+	pub on_surface: bool, // Orbiting a point on the surface, rather than the whole sphere
+}
+
+// A flight the camera's on, around the sphere of radius around about
+#[derive(Debug)]
+struct CameraFlight {
+	flight: Flight,
+	secs: f64, // How far into it the camera is
+	about: Point3<f64>,
+	radius: f64,
+	from_look: (Point3<f64>, Vector3<f64>), // What it was looking at and which way was up
+	to_pos: Point3<f64>,
+	landing: LockedData, // How it's locked once it gets there
 }
 
 #[derive(Debug)]
@@ -86,35 +173,167 @@ pub struct Camera {
 	dir: Vector3<f64>,
 	up: Vector3<f64>,
 	lock: Option<LockedData>,
+	flight: Option<CameraFlight>,
+	limits: CameraLimits,
 
 	move_speed: f64, // World units per second when moving unlocked
 	pan_speed: f64,  // Rad/s to rotate panning locked (at one radius dist at equator)
 	zoom_speed: f64, // Zoom factor per second when zooming locked
 }
 impl Camera {
-	pub fn new(pos: Point3<f64>, dir: Vector3<f64>, up: Vector3<f64>, move_speed: f64, pan_speed: f64, zoom_speed: f64) -> Camera {
+	pub fn new(
+		pos: Point3<f64>,
+		dir: Vector3<f64>,
+		up: Vector3<f64>,
+		move_speed: f64,
+		pan_speed: f64,
+		zoom_speed: f64,
+	) -> Camera {
 		info!("Camera initialized, pos {:?}, dir {:?}", pos, dir);
 		Camera {
-			pos: pos,
-			dir: dir,
-			up: up,
+			pos,
+			dir,
+			up,
 			lock: None,
-			move_speed: move_speed,
-			pan_speed: pan_speed,
-			zoom_speed: zoom_speed,
+			flight: None,
+			limits: Default::default(),
+			move_speed,
+			pan_speed,
+			zoom_speed,
 		}
 	}
 
+	pub fn set_speeds(&mut self, move_speed: f64, pan_speed: f64, zoom_speed: f64) {
+		self.move_speed = move_speed;
+		self.pan_speed = pan_speed;
+		self.zoom_speed = zoom_speed;
+	}
+	/// Keeps the camera within limits from now on, moving it there if it's not
+	pub fn set_limits(&mut self, limits: CameraLimits) {
+		self.limits = limits;
+		self.keep_in_limits();
+	}
+
+	/// Locks the camera orbiting the sphere of radius zoom_dist around to_point, with north up
 	pub fn lock(&mut self, to_point: Point3<f64>, zoom_dist: f64) {
-		self.lock = Some(LockedData { rotate_point: to_point, zoom_dist: zoom_dist });
+		self.lock = Some(LockedData {
+			rotate_point: to_point,
+			zoom_dist,
+			frame: Matrix3::identity(),
+			on_surface: false,
+		});
+		self.flight = None;
+		self.up = Vector3::unit_y();
 		self.dir = (to_point - self.pos).normalize();
 	}
+	/// Locks the camera orbiting target on the surface of the world's sphere (as its limits
+	/// have it), altitude world units straight over it, with north up. Panning then turns it
+	/// around target and tilts it over, and zooming takes it nearer to or further from target.
+	pub fn lock_to_surface(&mut self, target: &LatLong, altitude: f64) {
+		info!("Camera orbiting {}, {} over it", target, altitude);
+		self.set_view(&CameraView {
+			target: target.clone(),
+			distance: altitude / self.limits.world_radius,
+			orbit: true,
+			heading: Rad(0_f64),
+			tilt: Rad(0_f64),
+		});
+	}
 	pub fn unlock(&mut self) {
 		self.lock = None;
+		self.flight = None;
 	}
 	pub fn is_locked(&self) -> bool {
 		self.lock.is_some()
 	}
+	pub fn is_orbiting_surface(&self) -> bool {
+		self.lock.is_some_and(|lock| lock.on_surface)
+	}
+
+	/// Where the camera's looking from (or will be, once it's done flying), to be put back
+	/// with set_view or fly_to_view. Unlocked, it's over where it is, looking straight down.
+	pub fn view(&self) -> CameraView {
+		let (center, radius) = (self.limits.world_center, self.limits.world_radius);
+		let (pos, lock) = match self.flight {
+			Some(ref flying) => (flying.to_pos, Some(flying.landing)),
+			None => (self.pos, self.lock),
+		};
+		match lock {
+			Some(LockedData {
+				rotate_point,
+				frame,
+				on_surface: true,
+				..
+			}) => {
+				let sph_disp =
+					SphericalPoint::from_vec(&(frame.transpose() * (pos - rotate_point)));
+				CameraView {
+					target: SphericalPoint::from_vec(&(rotate_point - center)).as_lat_long(),
+					distance: sph_disp.radius / radius,
+					orbit: true,
+					heading: (-sph_disp.phi).normalize(),
+					tilt: sph_disp.theta,
+				}
+			}
+			_ => {
+				let sph_pos = SphericalPoint::from_vec(&(pos - center));
+				CameraView {
+					target: sph_pos.as_lat_long(),
+					distance: sph_pos.radius / radius - 1_f64,
+					orbit: false,
+					heading: Rad(0_f64),
+					tilt: Rad(0_f64),
+				}
+			}
+		}
+	}
+	/// Puts the camera where it sees view from, locked orbiting the world's sphere (as its
+	/// limits have it) or view's target
+	pub fn set_view(&mut self, view: &CameraView) {
+		let (lock, pos) = self.view_lock(view);
+		self.lock = Some(lock);
+		self.flight = None;
+		self.up = lock.frame.y;
+		self.pos = pos;
+		self.rotate_locked(lock.rotate_point, Point2::new(Rad(0_f64), Rad(0_f64)));
+	}
+
+	// How the camera's locked to see view, and where from
+	fn view_lock(&self, view: &CameraView) -> (LockedData, Point3<f64>) {
+		let (center, radius) = (self.limits.world_center, self.limits.world_radius);
+		let normal = view.target.as_sph_point(1_f64).to_vec();
+		let distance = view.distance * radius;
+		if view.orbit {
+			let east = match Vector3::unit_y().cross(normal) {
+				east if east.magnitude2() > 1e-18_f64 => east.normalize(),
+				_ => Vector3::unit_x(), // On a pole, where every way's south
+			};
+			let rotate_point = center + normal * radius;
+			let frame = Matrix3::from_cols(east, normal, east.cross(normal));
+			// Never quite straight over it, so it can tell which way it's heading
+			let tilt = Rad(view.tilt.0.max(POLE_MARGIN.0));
+			let disp = SphericalPoint::new(distance, tilt, -view.heading).to_vec();
+			(
+				LockedData {
+					rotate_point,
+					zoom_dist: 0_f64,
+					frame,
+					on_surface: true,
+				},
+				rotate_point + frame * disp,
+			)
+		} else {
+			(
+				LockedData {
+					rotate_point: center,
+					zoom_dist: radius,
+					frame: Matrix3::identity(),
+					on_surface: false,
+				},
+				center + normal * (radius + distance),
+			)
+		}
+	}
 
 	pub fn view_mat(&self) -> Matrix4<f64> {
 		Matrix4::look_at(self.pos, self.pos + self.dir, self.up)
@@ -123,6 +342,13 @@ impl Camera {
 		self.pos
 	}
 
+	/// Puts the camera disp away from about, looking at it, as near there as its limits let it
+	pub fn look_from(&mut self, about: Point3<f64>, disp: Vector3<f64>) {
+		self.pos = about + disp;
+		// Rotating by nothing still keeps it off the poles, where it couldn't tell which way's up
+		self.rotate_locked(about, Point2::new(Rad(0_f64), Rad(0_f64)));
+	}
+
 	/// If the camera is unlocked, will 'move' vec distance in world-space relative to the
 	/// direction the camera is facing.
 	///
@@ -132,57 +358,89 @@ impl Camera {
 	/// +vec.x = right, +vec.y = up, +vec.z = backwards XXX
 	///
 	/// The camera will move using the appropriate speed for the type of movement,
-	/// using the amount of time elapsed supplied.
+	/// using the amount of time elapsed supplied. It stops at its limits.
 	pub fn move_cam(&mut self, vec: Vector3<f64>, elapsed: f64) {
 		match self.lock {
-			Some(LockedData {rotate_point, zoom_dist}) => {
+			Some(LockedData {
+				rotate_point,
+				zoom_dist,
+				..
+			}) => {
 				// "Pan" movement
-				let angles = self.correct_rot(rotate_point, zoom_dist,
-					Point2::new(Rad(elapsed * self.pan_speed * vec.x),
-					            Rad(elapsed * self.pan_speed * vec.z)));
-				debug!("Rotating phi={:?}, theta={:?} over {}s", angles.x, angles.y, elapsed);
+				let angles = self.correct_rot(
+					rotate_point,
+					zoom_dist,
+					Point2::new(
+						Rad(elapsed * self.pan_speed * vec.x),
+						Rad(elapsed * self.pan_speed * vec.z),
+					),
+				);
+				debug!(
+					"Rotating phi={:?}, theta={:?} over {}s",
+					angles.x, angles.y, elapsed
+				);
 				self.rotate_locked(rotate_point, angles);
 
 				// "Zoom" movement (avoid div by 0)
 				if vec.y.abs() > 0.01_f64 {
 					let zoom_factor = if vec.y < 0_f64 {
 						1_f64 / (self.zoom_speed * -vec.y).powf(elapsed)
-					}
-					else {
+					} else {
 						(self.zoom_speed * vec.y).powf(elapsed)
 					};
 					debug!("Zooming a factor of {} over {}s", zoom_factor, elapsed);
-					self.zoom(rotate_point, zoom_dist, zoom_factor);
+					self.zoom(zoom_factor);
 				}
-			},
+			}
 			None => {
 				// "Move" movement
 				let right = self.dir.cross(self.up).normalize();
-				self.pos += self.move_speed * elapsed *
-					(right * vec.x + self.up * vec.y - self.dir * vec.z)
-			},
+				self.pos += self.move_speed
+					* elapsed * (right * vec.x + self.up * vec.y - self.dir * vec.z);
+				self.keep_in_limits();
+			}
 		}
 	}
 
 	// Sets the (longitudinal) rotation multiplier due to latitude.
 	// Basically, rotating x radians at the equator is faster compared to rotating the
 	// same angle near the poles, more ground moves beneath you
-	fn correct_rot(&self, about: Point3<f64>, zoom_dist: f64, angles: Point2<Rad<f64>>) -> Point2<Rad<f64>> {
-		let sph_disp = SphericalPoint::from_vec(&(self.pos - about));
+	fn correct_rot(
+		&self,
+		about: Point3<f64>,
+		zoom_dist: f64,
+		angles: Point2<Rad<f64>>,
+	) -> Point2<Rad<f64>> {
+		let (frame, _) = self.orbit_frame();
+		let sph_disp = SphericalPoint::from_vec(&(frame.transpose() * (self.pos - about)));
 		let long_rot_mult = 1_f64 / sph_disp.theta.sin().max(0.25f64); // XXX Hardcoded max speedup
-		// Slows down rotation closer the camera is to the sphere
+																 // Slows down rotation closer the camera is to the sphere
 		let dist_mult = sph_disp.radius - zoom_dist;
 		Point2::new(angles.x * long_rot_mult * dist_mult, angles.y * dist_mult)
 	}
 
+	// The frame the camera orbits in, and how far down from its up it can orbit to
+	fn orbit_frame(&self) -> (Matrix3<f64>, Rad<f64>) {
+		match self.lock {
+			Some(LockedData {
+				frame,
+				on_surface: true,
+				..
+			}) => (frame, self.limits.max_tilt),
+			Some(LockedData { frame, .. }) => (frame, Rad::turn_div_2() - POLE_MARGIN),
+			None => (Matrix3::identity(), Rad::turn_div_2() - POLE_MARGIN),
+		}
+	}
+
 	// Rotates around "about" by the given angles (raw, no corrections except stopping at
-	// min/max latitude)
+	// min/max latitude, or max tilt orbiting the surface, and the camera's limits)
 	fn rotate_locked(&mut self, about: Point3<f64>, angles: Point2<Rad<f64>>) {
-		let mut sph_disp = SphericalPoint::from_vec(&(self.pos - about));
+		let (frame, max_theta) = self.orbit_frame();
+		let mut sph_disp = SphericalPoint::from_vec(&(frame.transpose() * (self.pos - about)));
 
 		sph_disp.theta += angles.y;
-		let min_theta = Rad(0.00001_f64); // magic number
-		let max_theta = Rad::turn_div_2() - min_theta;
+		let min_theta = POLE_MARGIN;
+		let max_theta = Rad(max_theta.0.max(min_theta.0));
 		if sph_disp.theta < min_theta {
 			sph_disp.theta = min_theta;
 		} else if sph_disp.theta > max_theta {
@@ -192,24 +450,236 @@ impl Camera {
 		sph_disp.phi = (sph_disp.phi + angles.x).normalize();
 
 		if !sph_disp.is_ok() {
-			// TODO set to a normal default
 			error!(
 				"Resetting camera, bad spherical coord values: r {} | theta {:?} | phi {:?}",
 				sph_disp.radius, sph_disp.theta, sph_disp.phi
 			);
-			sph_disp.radius = 2_f64;
-			sph_disp.theta = Rad::turn_div_4();
+			// Back over the equator, or straight over the point it's orbiting, a radius up
+			let zoom_dist = self.lock.map_or(0_f64, |lock| lock.zoom_dist);
+			sph_disp.radius = zoom_dist + self.limits.clamp_altitude(self.limits.world_radius);
+			sph_disp.theta = if self.is_orbiting_surface() {
+				min_theta
+			} else {
+				Rad::turn_div_4()
+			};
 			sph_disp.phi = Rad(0_f64);
 		}
 
-		let new_disp = sph_disp.to_vec();
+		let new_disp = frame * sph_disp.to_vec();
 
 		self.pos = about + new_disp;
 		self.dir = -new_disp.normalize();
+		self.keep_in_limits();
+	}
+
+	// Moves the camera straight up or down to between its min and max altitudes, if it's not,
+	// still looking at what it's locked to
+	fn keep_in_limits(&mut self) {
+		let disp = self.pos - self.limits.world_center;
+		let dist = disp.magnitude();
+		let altitude = dist - self.limits.world_radius;
+		let clamped = self.limits.clamp_altitude(altitude);
+		if clamped == altitude || dist <= 0_f64 {
+			return;
+		}
+		debug!(
+			"Camera kept at altitude {}, rather than {}",
+			clamped, altitude
+		);
+		self.pos = self.limits.world_center + disp * ((self.limits.world_radius + clamped) / dist);
+		if let Some(LockedData { rotate_point, .. }) = self.lock {
+			if rotate_point != self.pos {
+				self.dir = (rotate_point - self.pos).normalize();
+			}
+		}
+	}
+
+	/// Starts the camera flying around the sphere it's locked orbiting to over target,
+	/// altitude world units above the surface. If it isn't locked orbiting a sphere, it flies
+	/// around the world's instead, and ends up locked orbiting that. It gets there as
+	/// update_flight is called.
+	pub fn fly_to(&mut self, target: &LatLong, altitude: f64) {
+		match self.lock {
+			Some(
+				lock @ LockedData {
+					rotate_point,
+					zoom_dist,
+					on_surface: false,
+					..
+				},
+			) => {
+				let to_pos = rotate_point + target.as_sph_point(zoom_dist + altitude).to_vec();
+				info!("Flying to {}", target);
+				self.fly(rotate_point, zoom_dist, to_pos, lock);
+			}
+			_ => self.fly_to_view(&CameraView {
+				target: target.clone(),
+				distance: altitude / self.limits.world_radius,
+				orbit: false,
+				heading: Rad(0_f64),
+				tilt: Rad(0_f64),
+			}),
+		}
+	}
+	/// Starts the camera flying around the world's sphere (as its limits have it) to where it
+	/// sees view from, turning to look the way it does there along the way. It gets there as
+	/// update_flight is called.
+	pub fn fly_to_view(&mut self, view: &CameraView) {
+		let (landing, to_pos) = self.view_lock(view);
+		info!("Flying to view {}", view.target);
+		let (center, radius) = (self.limits.world_center, self.limits.world_radius);
+		self.fly(center, radius, to_pos, landing);
+	}
+
+	// Starts the camera flying around the sphere of radius around about to to_pos, where it
+	// ends up locked as landing. It's locked orbiting the sphere on the way.
+	fn fly(&mut self, about: Point3<f64>, radius: f64, to_pos: Point3<f64>, landing: LockedData) {
+		let (from_disp, to_disp) = (self.pos - about, to_pos - about);
+		let flight = Flight::new(
+			from_disp,
+			from_disp.magnitude() - radius,
+			to_disp,
+			to_disp.magnitude() - radius,
+			radius,
+		);
+		debug!("Flight takes {}s", flight.duration());
+		let from_look = match self.lock {
+			Some(LockedData { rotate_point, .. }) => (rotate_point, self.up),
+			None => (self.pos + self.dir, self.up),
+		};
+		self.lock = Some(LockedData {
+			rotate_point: about,
+			zoom_dist: radius,
+			frame: Matrix3::identity(),
+			on_surface: false,
+		});
+		self.flight = Some(CameraFlight {
+			flight,
+			secs: 0_f64,
+			about,
+			radius,
+			from_look,
+			to_pos,
+			landing,
+		});
+	}
+	/// Moves the camera elapsed seconds further along its flight, if it's flying
+	pub fn update_flight(&mut self, elapsed: f64) {
+		let mut flying = match self.flight.take() {
+			Some(flying) => flying,
+			None => return,
+		};
+		flying.secs += elapsed;
+		if flying.secs >= flying.flight.duration() {
+			// Landed, looking just as it would locked there
+			self.lock = Some(flying.landing);
+			self.up = flying.landing.frame.y;
+			self.pos = flying.to_pos;
+			self.rotate_locked(
+				flying.landing.rotate_point,
+				Point2::new(Rad(0_f64), Rad(0_f64)),
+			);
+			return;
+		}
+
+		let (dir, altitude) = flying.flight.sample(flying.secs);
+		self.look_from(flying.about, dir * (flying.radius + altitude));
+		// Turning from what it was looking at to what it'll be locked looking at
+		let progress = flying.flight.progress(flying.secs);
+		let (from_point, from_up) = flying.from_look;
+		let look_at = from_point + (flying.landing.rotate_point - from_point) * progress;
+		if look_at != self.pos {
+			self.dir = (look_at - self.pos).normalize();
+		}
+		let up = from_up.lerp(flying.landing.frame.y, progress);
+		if up.magnitude2() > 1e-18_f64 {
+			self.up = up.normalize();
+		}
+		self.flight = Some(flying);
+	}
+	/// Stops the camera where it is, if it's flying
+	pub fn cancel_flight(&mut self) {
+		if self.flight.take().is_some() {
+			debug!("Flight cancelled");
+		}
+	}
+
+	/// If the camera is locked orbiting the sphere, rotates it around the locked point so that
+	/// grabbed, which was under some spot on the screen, ends up where under is. Dragging a
+	/// point on the globe keeps it under the cursor this way, with north kept up.
+	pub fn drag_locked(&mut self, grabbed: Point3<f64>, under: Point3<f64>) {
+		if let Some(LockedData {
+			rotate_point,
+			on_surface: false,
+			..
+		}) = self.lock
+		{
+			let grabbed = SphericalPoint::from_vec(&(grabbed - rotate_point));
+			let under = SphericalPoint::from_vec(&(under - rotate_point));
+			let angles = Point2::new(
+				(grabbed.phi - under.phi).normalize_signed(),
+				grabbed.theta - under.theta,
+			);
+			debug!("Dragging phi={:?}, theta={:?}", angles.x, angles.y);
+			self.rotate_locked(rotate_point, angles);
+		}
 	}
 
-	fn zoom(&mut self, to_point: Point3<f64>, to_dist: f64, factor: f64) {
-		let zoom_target = to_point + to_dist * (self.pos - to_point).normalize();
-		self.pos = zoom_target + (self.pos - zoom_target) / factor;
+	/// If the camera is locked, turns it by angles.x around the locked point and tilts it by
+	/// angles.y over it, as far as its limits let it
+	pub fn orbit(&mut self, angles: Point2<Rad<f64>>) {
+		if let Some(LockedData { rotate_point, .. }) = self.lock {
+			self.rotate_locked(rotate_point, angles);
+		}
+	}
+
+	/// If the camera is locked, divides how far it is from the locked sphere's surface, or the
+	/// point it's orbiting, by factor, so more than 1 zooms in. It stops at its limits. Does
+	/// nothing unlocked.
+	pub fn zoom(&mut self, factor: f64) {
+		if let Some(LockedData {
+			rotate_point,
+			zoom_dist,
+			..
+		}) = self.lock
+		{
+			let zoom_target = rotate_point + zoom_dist * (self.pos - rotate_point).normalize();
+			self.pos = zoom_target + (self.pos - zoom_target) / factor;
+			self.keep_in_limits();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use cgmath::Deg;
+
+	#[test]
+	fn unlocked_camera_flies_to_the_globe() {
+		let mut camera = Camera::new(
+			Point3::new(0_f64, 0_f64, 5_f64),
+			-Vector3::unit_z(),
+			Vector3::unit_y(),
+			1_f64,
+			1_f64,
+			1_f64,
+		);
+		camera.set_limits(CameraLimits {
+			world_radius: 2_f64,
+			..Default::default()
+		});
+		assert!(!camera.is_locked());
+
+		let target = LatLong::new(Deg(35_f64), Deg(5_f64));
+		camera.fly_to(&target, 0.5_f64);
+		camera.update_flight(1e6_f64);
+
+		let view = camera.view();
+		assert!(camera.is_locked() && !camera.is_orbiting_surface());
+		assert!(!view.orbit);
+		assert!(target.great_circle_distance(&view.target).0 < 1e-9_f64);
+		assert!((view.distance - 0.25_f64).abs() < 1e-9_f64);
 	}
 }
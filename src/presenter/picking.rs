@@ -0,0 +1,245 @@
+use super::ViewParams;
+use crate::entities::{LatLong, SphericalPoint};
+
+use cgmath::prelude::*;
+use cgmath::{Matrix4, Point2, Point3, Vector3, Vector4};
+
+/// The ray from the camera out through screen_pos, in pixels from the top left of a screen
+/// viewport pixels wide and high, as where it starts on the near plane and which way it goes.
+/// None if view_proj_mat can't be inverted.
+pub fn screen_ray(
+	view_proj_mat: Matrix4<f64>,
+	viewport: (u32, u32),
+	screen_pos: Point2<f64>,
+) -> Option<(Point3<f64>, Vector3<f64>)> {
+	let inverse = view_proj_mat.invert()?;
+	let ndc_x = screen_pos.x / viewport.0 as f64 * 2_f64 - 1_f64;
+	let ndc_y = 1_f64 - screen_pos.y / viewport.1 as f64 * 2_f64;
+	let unproject =
+		|ndc_z: f64| Point3::from_homogeneous(inverse * Vector4::new(ndc_x, ndc_y, ndc_z, 1_f64));
+
+	let near = unproject(-1_f64);
+	let far = unproject(1_f64);
+	Some((near, (far - near).normalize()))
+}
+
+/// Where a ray from origin going along dir, a unit vector, first hits the sphere of radius
+/// around center. Rays starting inside the sphere hit it on their way out. None if it
+/// misses, or the sphere's behind it.
+pub fn ray_sphere_hit(
+	origin: Point3<f64>,
+	dir: Vector3<f64>,
+	center: Point3<f64>,
+	radius: f64,
+) -> Option<Point3<f64>> {
+	let to_center = center - origin;
+	let along = to_center.dot(dir); // To the point on the ray closest to the center
+	let miss_dist2 = to_center.magnitude2() - along * along;
+	if miss_dist2 > radius * radius {
+		return None;
+	}
+	let half_chord = (radius * radius - miss_dist2).sqrt();
+	let dist = if along - half_chord >= 0_f64 {
+		along - half_chord
+	} else {
+		along + half_chord
+	};
+	if dist < 0_f64 {
+		return None;
+	}
+	Some(origin + dir * dist)
+}
+
+/// Where a ray from origin going along dir first hits the plane through point facing normal,
+/// from either side. None if it's parallel to the plane, or the plane's behind it.
+pub fn ray_plane_hit(
+	origin: Point3<f64>,
+	dir: Vector3<f64>,
+	point: Point3<f64>,
+	normal: Vector3<f64>,
+) -> Option<Point3<f64>> {
+	let towards = dir.dot(normal);
+	if towards.abs() < f64::EPSILON {
+		return None;
+	}
+	let dist = (point - origin).dot(normal) / towards;
+	if dist < 0_f64 {
+		return None;
+	}
+	Some(origin + dir * dist)
+}
+
+/// The point on the globe's surface, or the flat map, under screen_pos, in pixels from the
+/// top left of the screen, as seen with params. None if it's off the globe or the map.
+pub fn pick_point(params: &ViewParams, screen_pos: Point2<f64>) -> Option<Point3<f64>> {
+	let (origin, dir) = screen_ray(params.view_proj_mat, params.viewport, screen_pos)?;
+	match params.map {
+		Some(ref projection) => {
+			let hit = ray_plane_hit(origin, dir, params.world_center, Vector3::unit_z())?;
+			projection.map_dir(hit, params.world_center, params.world_radius)?;
+			Some(hit)
+		}
+		None => ray_sphere_hit(origin, dir, params.world_center, params.world_radius),
+	}
+}
+
+/// Where on the globe is under screen_pos, in pixels from the top left of the screen, as
+/// seen with params. None if it's off the globe or the map.
+pub fn pick_lat_long(params: &ViewParams, screen_pos: Point2<f64>) -> Option<LatLong> {
+	let hit = pick_point(params, screen_pos)?;
+	let from_center = match params.map {
+		Some(ref projection) => {
+			projection.map_dir(hit, params.world_center, params.world_radius)?
+		}
+		None => hit - params.world_center,
+	};
+	Some(SphericalPoint::from_vec(&from_center).as_lat_long())
+}
+
+/// The point on the surface in the middle of the screen seen with params, as a unit vector
+/// from the world's center, and how far it is from the camera. Looking past the globe or off
+/// the map, it's the point straight under the camera instead.
+pub fn pick_middle(params: &ViewParams) -> (Vector3<f64>, f64) {
+	let middle = Point2::new(
+		params.viewport.0 as f64 / 2_f64,
+		params.viewport.1 as f64 / 2_f64,
+	);
+	let hit = pick_point(params, middle).and_then(|hit| {
+		let dir = match params.map {
+			Some(ref projection) => {
+				projection.map_dir(hit, params.world_center, params.world_radius)?
+			}
+			None => (hit - params.world_center).normalize(),
+		};
+		Some((dir, (hit - params.cam_pos).magnitude()))
+	});
+	hit.unwrap_or_else(|| {
+		let altitude = params.altitude() * params.world_radius;
+		let dir = match params.map {
+			Some(ref projection) => {
+				let under = Point3::new(params.cam_pos.x, params.cam_pos.y, params.world_center.z);
+				projection
+					.map_dir(under, params.world_center, params.world_radius)
+					.unwrap_or_else(Vector3::unit_z)
+			}
+			None => (params.cam_pos - params.world_center).normalize(),
+		};
+		(dir, altitude)
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::presenter::MapProjection;
+
+	use cgmath::{Deg, Rad};
+
+	const VIEWPORT: (u32, u32) = (600, 400);
+
+	// Seen from cam_pos looking at target, the way the presenter projects it
+	fn view_params(
+		cam_pos: Point3<f64>,
+		target: Point3<f64>,
+		map: Option<MapProjection>,
+	) -> ViewParams {
+		let aspect_ratio = VIEWPORT.0 as f64 / VIEWPORT.1 as f64;
+		let proj_mat = cgmath::perspective(Deg(60_f64), aspect_ratio, 0.00001_f64, 100000_f64);
+		let view_mat = Matrix4::look_at(cam_pos, target, Vector3::unit_y());
+		ViewParams {
+			cam_pos,
+			view_proj_mat: proj_mat * view_mat,
+			world_center: Point3::origin(),
+			world_radius: 1_f64,
+			px_per_rad: VIEWPORT.1 as f64 / Rad::from(Deg(60_f64)).0,
+			viewport: VIEWPORT,
+			map,
+		}
+	}
+
+	fn middle() -> Point2<f64> {
+		Point2::new(VIEWPORT.0 as f64 / 2_f64, VIEWPORT.1 as f64 / 2_f64)
+	}
+
+	fn assert_near(a: f64, b: f64) {
+		assert!((a - b).abs() < 1e-6_f64, "{} isn't {}", a, b);
+	}
+
+	#[test]
+	fn middle_of_screen_picks_point_under_camera() {
+		let under = LatLong::new(Deg(35_f64), Deg(5_f64));
+		let cam_pos = Point3::from_vec(under.as_sph_point(3_f64).to_vec());
+		let params = view_params(cam_pos, Point3::origin(), None);
+
+		let (origin, dir) = screen_ray(params.view_proj_mat, VIEWPORT, middle()).unwrap();
+		assert!((origin - cam_pos).magnitude() < 1e-3_f64);
+		assert!((dir + cam_pos.to_vec().normalize()).magnitude() < 1e-9_f64);
+
+		let picked = pick_lat_long(&params, middle()).unwrap();
+		assert_near(picked.lat.0, under.lat.0);
+		assert_near(picked.long.0, under.long.0);
+	}
+
+	#[test]
+	fn ray_past_the_limb_misses() {
+		let cam_pos = Point3::new(0_f64, 0_f64, 3_f64);
+		let params = view_params(cam_pos, Point3::origin(), None);
+		assert!(pick_point(&params, Point2::new(0_f64, 0_f64)).is_none());
+		assert!(pick_lat_long(&params, Point2::new(VIEWPORT.0 as f64, 0_f64)).is_none());
+
+		// Just outside and just inside the limb, along a ray parallel to z
+		let dir = -Vector3::unit_z();
+		assert!(ray_sphere_hit(
+			Point3::new(1.001_f64, 0_f64, 3_f64),
+			dir,
+			Point3::origin(),
+			1_f64
+		)
+		.is_none());
+		let grazing = ray_sphere_hit(
+			Point3::new(0.999_f64, 0_f64, 3_f64),
+			dir,
+			Point3::origin(),
+			1_f64,
+		)
+		.unwrap();
+		assert_near(grazing.to_vec().magnitude(), 1_f64);
+		assert!(grazing.z > 0_f64);
+
+		// Behind the ray, and from inside the sphere
+		assert!(ray_sphere_hit(
+			Point3::new(0_f64, 0_f64, 3_f64),
+			-dir,
+			Point3::origin(),
+			1_f64
+		)
+		.is_none());
+		let exit = ray_sphere_hit(Point3::origin(), dir, Point3::origin(), 1_f64).unwrap();
+		assert_near(exit.z, -1_f64);
+	}
+
+	#[test]
+	fn flat_map_picks_point_under_camera() {
+		let cam_pos = Point3::new(0.5_f64, 0.3_f64, 1_f64);
+		let target = Point3::new(cam_pos.x, cam_pos.y, 0_f64);
+		let params = view_params(cam_pos, target, Some(MapProjection::Equirectangular));
+
+		let hit = pick_point(&params, middle()).unwrap();
+		assert!((hit - target).magnitude() < 1e-9_f64);
+		let picked = pick_lat_long(&params, middle()).unwrap();
+		assert_near(picked.lat.0, 0.3_f64);
+		assert_near(picked.long.0, 0.5_f64);
+
+		// From above or below, but never along the plane or away from it
+		let normal = Vector3::unit_z();
+		let hit = ray_plane_hit(
+			Point3::new(0_f64, 0_f64, -2_f64),
+			normal,
+			Point3::origin(),
+			normal,
+		);
+		assert_near(hit.unwrap().z, 0_f64);
+		assert!(ray_plane_hit(cam_pos, Vector3::unit_x(), Point3::origin(), normal).is_none());
+		assert!(ray_plane_hit(cam_pos, normal, Point3::origin(), normal).is_none());
+	}
+}
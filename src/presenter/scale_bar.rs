@@ -0,0 +1,242 @@
+use super::city_labels::{add_quad, add_text, LabelStyle};
+use super::glyph_atlas::{GlyphAtlas, TextLayout};
+use super::{pick_middle, Mesh, MeshObject, Shading, View, ViewParams};
+
+use cgmath::{Point2, Vector2};
+
+use rusttype::Font;
+
+use std::collections::HashMap;
+
+// Longest the bar's drawn. It measures the longest round distance that fits.
+const MAX_LENGTH_PX: f64 = 150_f64;
+
+// From the bottom left of the screen to the bottom left of the bar
+const MARGIN_PX: f64 = 16_f64;
+
+// How thick the bar is, and the ticks at its ends, and how high the ticks reach
+const BAR_PX: f64 = 3_f64;
+const TICK_WIDTH_PX: f64 = 2_f64;
+const TICK_HEIGHT_PX: f64 = 9_f64;
+const BAR_COLOR: [f64; 3] = [1_f64, 1_f64, 1_f64];
+
+// Pixels of dark outline around the bar, so it stands out on any terrain
+const OUTLINE_PX: f64 = 1_f64;
+const OUTLINE_COLOR: [f64; 3] = [0.05_f64, 0.05_f64, 0.05_f64];
+
+// Between the top of the ticks and the label
+const LABEL_GAP_PX: f64 = 2_f64;
+
+/// A bar in the bottom left of the screen, a round distance on the ground long at the middle
+/// of the screen, labelled with it. On flat maps it's measured along the map's north to
+/// south scale there.
+///
+/// Labels are laid out with the glyph atlas it's given, which must be the same one every
+/// time.
+pub struct ScaleBar {
+	label_style: LabelStyle,
+	layouts: HashMap<String, TextLayout>, // By their text
+	missing_font: bool,                   // Already warned about
+}
+impl ScaleBar {
+	pub fn new(label_style: LabelStyle) -> ScaleBar {
+		ScaleBar {
+			label_style,
+			layouts: HashMap::new(),
+			missing_font: false,
+		}
+	}
+
+	/// The objects to draw the bar with as seen with params, measuring distances in unit for
+	/// a world radius long. Any glyphs its label needs that weren't in atlas are added to it.
+	pub fn update(
+		&mut self,
+		view: &dyn View,
+		atlas: &mut GlyphAtlas,
+		fonts: &HashMap<String, Font<'static>>,
+		params: &ViewParams,
+		radius: f64,
+		unit: &str,
+	) -> Vec<MeshObject> {
+		let (length, length_px) = bar_length(params, radius);
+		if !length_px.is_finite() {
+			return Vec::new();
+		}
+
+		let screen_size = Vector2::new(params.viewport.0 as f64, params.viewport.1 as f64);
+		let (left, bottom) = (MARGIN_PX, screen_size.y - MARGIN_PX);
+		let right = left + length_px;
+		let rects = [
+			(
+				Point2::new(left, bottom - BAR_PX),
+				Point2::new(right, bottom),
+			),
+			(
+				Point2::new(left, bottom - TICK_HEIGHT_PX),
+				Point2::new(left + TICK_WIDTH_PX, bottom),
+			),
+			(
+				Point2::new(right - TICK_WIDTH_PX, bottom - TICK_HEIGHT_PX),
+				Point2::new(right, bottom),
+			),
+		];
+		let (mut vertices, mut triangles) = (Vec::new(), Vec::new());
+		if let Some(solid) = atlas.solid() {
+			for &(outline_px, color) in &[(OUTLINE_PX, OUTLINE_COLOR), (0_f64, BAR_COLOR)] {
+				let outline = Vector2::new(outline_px, outline_px);
+				for &(min, max) in &rects {
+					let rect = (min - outline, max + outline);
+					add_quad(
+						&mut vertices,
+						&mut triangles,
+						rect,
+						(solid, solid),
+						color,
+						screen_size,
+					);
+				}
+			}
+		}
+
+		let text = format!("{} {}", length_text(length), unit);
+		let color = self.label_style.color;
+		if let Some(layout) = self.layout(atlas, fonts, text) {
+			let top_left =
+				Point2::new(left, bottom - TICK_HEIGHT_PX - LABEL_GAP_PX - layout.size.y);
+			add_text(
+				&mut vertices,
+				&mut triangles,
+				layout,
+				top_left,
+				color,
+				screen_size,
+			);
+		}
+
+		if vertices.is_empty() {
+			return Vec::new();
+		}
+		let mut object = MeshObject::new(Mesh::new(view.get_facade(), &vertices, &triangles));
+		object.shading = Shading::Overlay { opacity: 1_f64 };
+		vec![object]
+	}
+
+	// text laid out in the label style, None if its font's missing
+	fn layout(
+		&mut self,
+		atlas: &mut GlyphAtlas,
+		fonts: &HashMap<String, Font<'static>>,
+		text: String,
+	) -> Option<&TextLayout> {
+		let style = &self.label_style;
+		let font = match fonts.get(&style.font) {
+			Some(font) => font,
+			None => {
+				if !self.missing_font {
+					warn!(
+						"No font {} for the scale bar, the world's [fonts] should name it",
+						style.font
+					);
+					self.missing_font = true;
+				}
+				return None;
+			}
+		};
+		let layout = self
+			.layouts
+			.entry(text)
+			.or_insert_with_key(|text| atlas.layout(&style.font, font, style.size_px, text));
+		Some(layout)
+	}
+}
+
+// How long the bar is seen with params, in units of which a world radius is radius long, and
+// in pixels. The pixels aren't finite if the bar can't be measured.
+fn bar_length(params: &ViewParams, radius: f64) -> (f64, f64) {
+	let (middle_dir, distance) = pick_middle(params);
+	let map_scale = params
+		.map
+		.map_or(1_f64, |projection| projection.scale_at(middle_dir));
+	let units_per_px = distance / params.px_per_rad / map_scale / params.world_radius * radius;
+	let length = round_length(MAX_LENGTH_PX * units_per_px);
+	(length, length / units_per_px)
+}
+
+// The longest of 1, 2 or 5 times a power of ten that's no longer than max_length
+fn round_length(max_length: f64) -> f64 {
+	let power = 10_f64.powf(max_length.log10().floor());
+	[5_f64, 2_f64, 1_f64]
+		.iter()
+		.map(|&multiple| multiple * power)
+		.find(|&length| length <= max_length)
+		.unwrap_or(power)
+}
+
+// A round length, without the rounding errors from working it out
+fn length_text(length: f64) -> String {
+	let decimals = (-length.log10().floor()).max(0_f64) as usize;
+	format!("{:.*}", decimals, length)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn lengths_round_down_to_one_two_or_five_times_a_power_of_ten() {
+		let cases = [
+			(1_f64, 1_f64),
+			(1.99_f64, 1_f64),
+			(2_f64, 2_f64),
+			(4.99_f64, 2_f64),
+			(5_f64, 5_f64),
+			(9.99_f64, 5_f64),
+			(10_f64, 10_f64),
+			(37_f64, 20_f64),
+			(740_f64, 500_f64),
+			(0.3_f64, 0.2_f64),
+			(0.06_f64, 0.05_f64),
+		];
+		for &(max_length, length) in &cases {
+			assert!(
+				(round_length(max_length) - length).abs() < 1e-12_f64,
+				"{} rounds to {}",
+				max_length,
+				round_length(max_length)
+			);
+		}
+	}
+
+	#[test]
+	fn lengths_are_written_without_rounding_errors() {
+		assert_eq!(length_text(round_length(5000_f64)), "5000");
+		assert_eq!(length_text(round_length(20_f64)), "20");
+		assert_eq!(length_text(round_length(1_f64)), "1");
+		assert_eq!(length_text(round_length(0.3_f64)), "0.2");
+		assert_eq!(length_text(round_length(0.06_f64)), "0.05");
+		assert_eq!(length_text(round_length(0.001_f64)), "0.001");
+	}
+
+	#[test]
+	fn bar_is_as_long_as_the_round_length_it_measures() {
+		for &altitude in &[2_f64, 0.5_f64, 0.1_f64, 0.003_f64] {
+			let params = ViewParams::looking_down_at(35_f64, 5_f64, altitude);
+			let radius = 3959_f64;
+			let (length, length_px) = bar_length(&params, radius);
+			// Straight below the camera, a pixel covers altitude / px_per_rad world radii
+			let expected_px = length / radius / altitude * params.px_per_rad;
+			assert!(
+				(length_px - expected_px).abs() < 1e-6_f64,
+				"at {}",
+				altitude
+			);
+			// Each round length is at most 2.5 times the one before
+			assert!(
+				(MAX_LENGTH_PX / 2.5_f64..=MAX_LENGTH_PX).contains(&length_px),
+				"at {}: {} px",
+				altitude,
+				length_px
+			);
+		}
+	}
+}
@@ -0,0 +1,690 @@
+use super::{GlobeMesh, LabelStyle, MapProjection};
+use crate::entities::{edit_set, same_value, SettlementTier};
+
+use glium::glutin::{MouseButton, VirtualKeyCode};
+
+use cgmath::{Deg, Point3};
+
+use toml::Value;
+use toml_edit::DocumentMut;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+#[derive(Hash, Eq, PartialEq, Clone, Copy, Debug)]
+pub enum InputType {
+	Key(VirtualKeyCode),
+	Mouse(MouseButton),
+	// Inputs with an amount, rather than being pressed and released
+	Scroll,            // By the pixels scrolled, up being positive y
+	Drag(MouseButton), // Moving the cursor with the button held, by the pixels it moved
+}
+
+#[derive(Hash, Eq, PartialEq, Clone, Copy, Debug)]
+pub enum ContinualAction {
+	MoveForward,
+	MoveBackward,
+	MoveLeft,
+	MoveRight,
+	MoveUp,
+	MoveDown,
+	MoveFast,
+	SetBookmark, // Held while going to a bookmark sets it to where the camera is instead
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum InstantAction {
+	Quit,
+	//ToggleDebug,
+	MoveLight,
+	//ToggleCursorGrab,
+	ToggleCameraLock,
+	SelectCity,
+	OrbitSelectedCity,
+	GoToBookmark(u32), // Its slot, from 1 to BOOKMARK_SLOTS
+	ToggleFlatMap,
+	CycleMapProjection,
+	ToggleGraticule,
+	ToggleScaleBar,
+	Log,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum AnalogAction {
+	Zoom,
+	DragGlobe,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum ActionType {
+	Continual(ContinualAction),
+	Instant(InstantAction),
+	Analog(AnalogAction),
+}
+
+// How many bookmarks there are keys to set and go to
+const BOOKMARK_SLOTS: u32 = 9;
+
+// Every action except going to bookmarks, in the order they're saved in, before them
+const ACTIONS: &[ActionType] = &[
+	ActionType::Continual(ContinualAction::MoveForward),
+	ActionType::Continual(ContinualAction::MoveBackward),
+	ActionType::Continual(ContinualAction::MoveLeft),
+	ActionType::Continual(ContinualAction::MoveRight),
+	ActionType::Continual(ContinualAction::MoveUp),
+	ActionType::Continual(ContinualAction::MoveDown),
+	ActionType::Continual(ContinualAction::MoveFast),
+	ActionType::Continual(ContinualAction::SetBookmark),
+	ActionType::Instant(InstantAction::Quit),
+	ActionType::Instant(InstantAction::MoveLight),
+	ActionType::Instant(InstantAction::ToggleCameraLock),
+	ActionType::Instant(InstantAction::SelectCity),
+	ActionType::Instant(InstantAction::OrbitSelectedCity),
+	ActionType::Instant(InstantAction::ToggleFlatMap),
+	ActionType::Instant(InstantAction::CycleMapProjection),
+	ActionType::Instant(InstantAction::ToggleGraticule),
+	ActionType::Instant(InstantAction::ToggleScaleBar),
+	ActionType::Instant(InstantAction::Log),
+	ActionType::Analog(AnalogAction::Zoom),
+	ActionType::Analog(AnalogAction::DragGlobe),
+];
+
+// Keys by the names settings files give them, which are their VirtualKeyCode names
+macro_rules! key_names {
+	($($key:ident),*) => { &[$((stringify!($key), VirtualKeyCode::$key)),*] };
+}
+const KEY_NAMES: &[(&str, VirtualKeyCode)] = key_names! {
+	Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0, A, B, C, D, E, F, G, H, I, J, K,
+	L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z, Escape, F1, F2, F3, F4, F5, F6, F7, F8, F9, F10,
+	F11, F12, F13, F14, F15, F16, F17, F18, F19, F20, F21, F22, F23, F24, Snapshot, Scroll, Pause,
+	Insert, Home, Delete, End, PageDown, PageUp, Left, Up, Right, Down, Back, Return, Space,
+	Compose, Caret, Numlock, Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6,
+	Numpad7, Numpad8, Numpad9, AbntC1, AbntC2, Add, Apostrophe, Apps, At, Ax, Backslash,
+	Calculator, Capital, Colon, Comma, Convert, Decimal, Divide, Equals, Grave, Kana, Kanji, LAlt,
+	LBracket, LControl, LShift, LWin, Mail, MediaSelect, MediaStop, Minus, Multiply, Mute,
+	MyComputer, NavigateForward, NavigateBackward, NextTrack, NoConvert, NumpadComma, NumpadEnter,
+	NumpadEquals, OEM102, Period, PlayPause, Power, PrevTrack, RAlt, RBracket, RControl, RShift,
+	RWin, Semicolon, Slash, Sleep, Stop, Subtract, Sysrq, Tab, Underline, Unlabeled, VolumeDown,
+	VolumeUp, Wake, WebBack, WebFavorites, WebForward, WebHome, WebRefresh, WebSearch, WebStop,
+	Yen, Copy, Paste, Cut
+};
+
+fn all_actions() -> Vec<ActionType> {
+	let bookmarks =
+		(1..=BOOKMARK_SLOTS).map(|slot| ActionType::Instant(InstantAction::GoToBookmark(slot)));
+	ACTIONS.iter().cloned().chain(bookmarks).collect()
+}
+
+fn action_name(action: &ActionType) -> String {
+	match action {
+		ActionType::Continual(action) => format!("{:?}", action),
+		ActionType::Instant(InstantAction::GoToBookmark(slot)) => format!("GoToBookmark{}", slot),
+		ActionType::Instant(action) => format!("{:?}", action),
+		ActionType::Analog(action) => format!("{:?}", action),
+	}
+}
+
+fn parse_action(name: &str) -> Option<ActionType> {
+	all_actions()
+		.into_iter()
+		.find(|action| action_name(action) == name)
+}
+
+fn button_name(button: &MouseButton) -> String {
+	match button {
+		MouseButton::Other(n) => format!("{}", n),
+		button => format!("{:?}", button),
+	}
+}
+
+fn parse_button(name: &str) -> Option<MouseButton> {
+	match name {
+		"Left" => Some(MouseButton::Left),
+		"Right" => Some(MouseButton::Right),
+		"Middle" => Some(MouseButton::Middle),
+		_ => name.parse::<u8>().ok().map(MouseButton::Other),
+	}
+}
+
+/// Keys by their VirtualKeyCode names, like "W" or "LShift", mouse buttons as "Mouse" then
+/// the button, like "MouseLeft" or "Mouse4", dragging with them as "Drag" then the button,
+/// and the scroll wheel as "MouseWheel"
+fn input_name(input: &InputType) -> String {
+	match input {
+		InputType::Key(key) => KEY_NAMES
+			.iter()
+			.find(|&&(_, named_key)| named_key == *key)
+			.map_or_else(|| format!("{:?}", key), |&(name, _)| name.to_string()),
+		InputType::Mouse(button) => format!("Mouse{}", button_name(button)),
+		InputType::Drag(button) => format!("Drag{}", button_name(button)),
+		InputType::Scroll => "MouseWheel".to_string(),
+	}
+}
+
+fn parse_input(name: &str) -> Option<InputType> {
+	if name == "MouseWheel" {
+		return Some(InputType::Scroll);
+	}
+	if let Some(&(_, key)) = KEY_NAMES.iter().find(|&&(key_name, _)| key_name == name) {
+		return Some(InputType::Key(key));
+	}
+	if let Some(button) = name.strip_prefix("Mouse") {
+		return parse_button(button).map(InputType::Mouse);
+	}
+	if let Some(button) = name.strip_prefix("Drag") {
+		return parse_button(button).map(InputType::Drag);
+	}
+	None
+}
+
+#[derive(Debug)]
+pub enum SettingsError {
+	IOError(std::io::Error),
+	ParseError(toml::de::Error),
+	EditError(toml_edit::TomlError),
+	UnknownSetting(String),
+	UnknownAction(String),
+	UnknownInput(String, String), // The input, and the action it was bound to
+	BadValue(String, &'static str), // The setting, and what it should have been
+}
+impl fmt::Display for SettingsError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			SettingsError::IOError(e) => write!(f, "{}", e),
+			SettingsError::ParseError(e) => write!(f, "{}", e),
+			SettingsError::EditError(e) => write!(f, "{}", e),
+			SettingsError::UnknownSetting(key) => write!(f, "unknown setting {:?}", key),
+			SettingsError::UnknownAction(name) => {
+				write!(f, "unknown action {:?} in [bindings]", name)
+			}
+			SettingsError::UnknownInput(name, action) => {
+				write!(f, "unknown input {:?} bound to {}", name, action)
+			}
+			SettingsError::BadValue(key, expected) => write!(f, "{} should be {}", key, expected),
+		}
+	}
+}
+
+#[derive(Debug)]
+pub struct Settings {
+	pub bindings: HashMap<InputType, Vec<ActionType>>, // Inputs can fire several actions
+	pub max_fps: f64,
+
+	pub world_center: Point3<f64>,
+	pub world_radius: f64,
+
+	pub light_frac_ambient: f64,
+	pub light_distance: f64,
+
+	pub move_speed: f64,
+	pub pan_speed: f64,
+	pub zoom_speed: f64,
+	pub scroll_line_px: f64, // Pixels scrolled per line, for mice that scroll in lines
+	pub scroll_zoom_px: f64, // Pixels scrolled to zoom in or out by a factor of 2
+	pub fov: Deg<f64>,
+	pub select_radius_px: f64, // How near the cursor cities must be to be clicked on
+	pub city_altitude: f64,    // World radii above the surface cities are flown to, at most
+	// World radii above the surface the camera's kept between, and orbits cities from
+	pub min_altitude: f64,
+	pub max_altitude: f64,
+	pub orbit_altitude: f64,
+	pub max_orbit_tilt: Deg<f64>, // From looking straight down, orbiting a city
+	pub map_projection: MapProjection, // How the flat map's laid out
+
+	pub texture_gutter_px: u32,
+	pub texture_anisotropy: u16,
+
+	// For maps without a tile pyramid
+	pub globe_mesh: GlobeMesh,
+
+	// For maps with a tile pyramid
+	pub tile_cache_layers: u32,
+	pub tile_loads_per_frame: usize,
+
+	// For labelling cities, by tier. Tiers without a style aren't labelled.
+	pub label_styles: BTreeMap<SettlementTier, LabelStyle>,
+	pub glyph_atlas_px: u32,
+
+	// Layers drawn over the globe, and how their labels look
+	pub show_graticule: bool,
+	pub show_scale_bar: bool,
+	pub graticule_label_style: LabelStyle,
+	pub scale_bar_label_style: LabelStyle,
+
+	pub quitting: bool,
+}
+impl Default for Settings {
+	fn default() -> Settings {
+		use self::AnalogAction::*;
+		use self::ContinualAction::*;
+		use self::InputType::{Drag, Key, Mouse, Scroll};
+		use self::InstantAction::*;
+
+		let mut bindings: HashMap<InputType, Vec<ActionType>> = HashMap::new();
+		for &(input, action) in &[
+			(Key(VirtualKeyCode::W), ActionType::Continual(MoveForward)),
+			(Key(VirtualKeyCode::S), ActionType::Continual(MoveBackward)),
+			(Key(VirtualKeyCode::A), ActionType::Continual(MoveLeft)),
+			(Key(VirtualKeyCode::D), ActionType::Continual(MoveRight)),
+			(Key(VirtualKeyCode::R), ActionType::Continual(MoveUp)),
+			(Key(VirtualKeyCode::F), ActionType::Continual(MoveDown)),
+			(Key(VirtualKeyCode::LShift), ActionType::Continual(MoveFast)),
+			(
+				Key(VirtualKeyCode::LControl),
+				ActionType::Continual(SetBookmark),
+			),
+			(
+				Key(VirtualKeyCode::RControl),
+				ActionType::Continual(SetBookmark),
+			),
+			(Key(VirtualKeyCode::Escape), ActionType::Instant(Quit)),
+			//(Key(VirtualKeyCode::Q), ActionType::Instant(ToggleDebug)),
+			(Key(VirtualKeyCode::L), ActionType::Instant(MoveLight)),
+			//(Key(VirtualKeyCode::G), ActionType::Instant(ToggleCursorGrab)),
+			(
+				Key(VirtualKeyCode::C),
+				ActionType::Instant(ToggleCameraLock),
+			),
+			(Mouse(MouseButton::Left), ActionType::Instant(SelectCity)),
+			(
+				Key(VirtualKeyCode::O),
+				ActionType::Instant(OrbitSelectedCity),
+			),
+			(Key(VirtualKeyCode::M), ActionType::Instant(ToggleFlatMap)),
+			(
+				Key(VirtualKeyCode::P),
+				ActionType::Instant(CycleMapProjection),
+			),
+			(Key(VirtualKeyCode::G), ActionType::Instant(ToggleGraticule)),
+			(Key(VirtualKeyCode::B), ActionType::Instant(ToggleScaleBar)),
+			(Mouse(MouseButton::Middle), ActionType::Instant(Log)),
+			(Mouse(MouseButton::Right), ActionType::Instant(Log)),
+			(Drag(MouseButton::Left), ActionType::Analog(DragGlobe)),
+			(Scroll, ActionType::Analog(Zoom)),
+		] {
+			bindings.entry(input).or_default().push(action);
+		}
+		let slot_keys = [
+			VirtualKeyCode::Key1,
+			VirtualKeyCode::Key2,
+			VirtualKeyCode::Key3,
+			VirtualKeyCode::Key4,
+			VirtualKeyCode::Key5,
+			VirtualKeyCode::Key6,
+			VirtualKeyCode::Key7,
+			VirtualKeyCode::Key8,
+			VirtualKeyCode::Key9,
+		];
+		for (slot, &key) in (1..=BOOKMARK_SLOTS).zip(slot_keys.iter()) {
+			bindings
+				.entry(Key(key))
+				.or_default()
+				.push(ActionType::Instant(GoToBookmark(slot)));
+		}
+
+		let label_style = |font: &str, size_px: f64, color: [f64; 3]| LabelStyle {
+			font: font.to_string(),
+			size_px,
+			color,
+		};
+		let label_styles: BTreeMap<SettlementTier, LabelStyle> = [
+			(
+				SettlementTier::City,
+				label_style("sans_bold", 15_f64, [1_f64, 1_f64, 1_f64]),
+			),
+			(
+				SettlementTier::Town,
+				label_style("sans", 13_f64, [1_f64, 0.95_f64, 0.85_f64]),
+			),
+			(
+				SettlementTier::Village,
+				label_style("sans", 11_f64, [1_f64, 0.95_f64, 0.85_f64]),
+			),
+			(
+				SettlementTier::Thorp,
+				label_style("sans", 10_f64, [1_f64, 0.95_f64, 0.85_f64]),
+			),
+			(
+				SettlementTier::Ruins,
+				label_style("sans", 10_f64, [0.75_f64, 0.75_f64, 0.75_f64]),
+			),
+		]
+		.iter()
+		.cloned()
+		.collect();
+
+		Settings {
+			bindings,
+			max_fps: 60_f64,
+
+			world_center: Point3::new(0_f64, 0_f64, 0_f64),
+			world_radius: 1_f64,
+
+			light_frac_ambient: 0.05_f64,
+			light_distance: 10_000_f64,
+
+			move_speed: 1_f64,
+			pan_speed: 1_f64,
+			zoom_speed: 2_f64,
+			scroll_line_px: 15_f64,
+			scroll_zoom_px: 300_f64,
+			fov: Deg(90_f64),
+			select_radius_px: 16_f64,
+			city_altitude: 0.1_f64,
+			min_altitude: 0.0005_f64,
+			max_altitude: 20_f64,
+			orbit_altitude: 0.01_f64,
+			max_orbit_tilt: Deg(80_f64),
+			map_projection: MapProjection::Equirectangular,
+
+			texture_gutter_px: 8_u32,
+			texture_anisotropy: 16_u16,
+
+			globe_mesh: GlobeMesh::UvSphere {
+				deg_resolution: 1_u32,
+			},
+
+			tile_cache_layers: 255_u32,
+			tile_loads_per_frame: 4_usize,
+
+			label_styles,
+			glyph_atlas_px: 1024_u32,
+
+			show_graticule: false,
+			show_scale_bar: false,
+			graticule_label_style: label_style("sans", 10_f64, [0.8_f64, 0.9_f64, 1_f64]),
+			scale_bar_label_style: label_style("sans", 11_f64, [1_f64, 1_f64, 1_f64]),
+
+			quitting: false,
+		}
+	}
+}
+impl Settings {
+	/// The default settings, with any in the settings file at path in place of them. Each
+	/// action the file binds has only the inputs it gives, and the rest keep their default
+	/// inputs. A missing file leaves every setting at its default.
+	pub fn load(path: &str) -> Result<Settings, SettingsError> {
+		let mut settings: Settings = Default::default();
+		let contents = match std::fs::read_to_string(path) {
+			Ok(contents) => contents,
+			Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => {
+				info!("No settings file at {}, using the defaults", path);
+				return Ok(settings);
+			}
+			Err(err) => return Err(SettingsError::IOError(err)),
+		};
+		let value = contents
+			.parse::<Value>()
+			.map_err(SettingsError::ParseError)?;
+		let table = value
+			.as_table()
+			.ok_or_else(|| SettingsError::BadValue("The settings".to_string(), "a table"))?;
+
+		for (key, value) in table {
+			let float = |check: fn(f64) -> bool, expected: &'static str| {
+				value
+					.as_float()
+					.or_else(|| value.as_integer().map(|value| value as f64))
+					.filter(|&value| check(value))
+					.ok_or_else(|| SettingsError::BadValue(key.clone(), expected))
+			};
+			let int = |max: i64| {
+				value
+					.as_integer()
+					.filter(|&value| value > 0 && value <= max)
+					.ok_or_else(|| SettingsError::BadValue(key.clone(), "a positive whole number"))
+			};
+			let boolean = || {
+				value
+					.as_bool()
+					.ok_or_else(|| SettingsError::BadValue(key.clone(), "true or false"))
+			};
+			let positive = |value: f64| value > 0_f64;
+			match key.as_str() {
+				"max_fps" => settings.max_fps = float(positive, "positive")?,
+				"fov_deg" => {
+					settings.fov = Deg(float(|fov| fov > 0_f64 && fov < 180_f64, "0-180")?)
+				}
+				"light_frac_ambient" => {
+					settings.light_frac_ambient =
+						float(|frac| (0_f64..=1_f64).contains(&frac), "0-1")?
+				}
+				"move_speed" => settings.move_speed = float(positive, "positive")?,
+				"pan_speed" => settings.pan_speed = float(positive, "positive")?,
+				"zoom_speed" => settings.zoom_speed = float(positive, "positive")?,
+				"scroll_line_px" => settings.scroll_line_px = float(positive, "positive")?,
+				"scroll_zoom_px" => settings.scroll_zoom_px = float(positive, "positive")?,
+				"select_radius_px" => settings.select_radius_px = float(positive, "positive")?,
+				"city_altitude" => settings.city_altitude = float(positive, "positive")?,
+				"min_altitude" => settings.min_altitude = float(positive, "positive")?,
+				"max_altitude" => settings.max_altitude = float(positive, "positive")?,
+				"orbit_altitude" => settings.orbit_altitude = float(positive, "positive")?,
+				"max_orbit_tilt_deg" => {
+					settings.max_orbit_tilt =
+						Deg(float(|tilt| (0_f64..90_f64).contains(&tilt), "0-90")?)
+				}
+				"map_projection" => {
+					settings.map_projection = value
+						.as_str()
+						.and_then(MapProjection::from_name)
+						.ok_or_else(|| {
+							SettingsError::BadValue(
+								key.clone(),
+								"equirectangular, mercator or azimuthal",
+							)
+						})?
+				}
+				"texture_gutter_px" => {
+					settings.texture_gutter_px = value
+						.as_integer()
+						.filter(|&px| px >= 0 && px <= u32::MAX as i64)
+						.ok_or_else(|| SettingsError::BadValue(key.clone(), "a whole number"))?
+						as u32
+				}
+				"texture_anisotropy" => settings.texture_anisotropy = int(u16::MAX as i64)? as u16,
+				"tile_cache_layers" => settings.tile_cache_layers = int(u32::MAX as i64)? as u32,
+				"tile_loads_per_frame" => {
+					settings.tile_loads_per_frame = int(u32::MAX as i64)? as usize
+				}
+				"glyph_atlas_px" => settings.glyph_atlas_px = int(u32::MAX as i64)? as u32,
+				"show_graticule" => settings.show_graticule = boolean()?,
+				"show_scale_bar" => settings.show_scale_bar = boolean()?,
+				"bindings" => settings.load_bindings(value)?,
+				_ => return Err(SettingsError::UnknownSetting(key.clone())),
+			}
+		}
+		if settings.max_altitude <= settings.min_altitude {
+			return Err(SettingsError::BadValue(
+				"max_altitude".to_string(),
+				"more than min_altitude",
+			));
+		}
+		info!("Loaded settings from {}", path);
+		Ok(settings)
+	}
+
+	// Binds each action bindings names to the inputs it gives, in place of its defaults
+	fn load_bindings(&mut self, bindings: &Value) -> Result<(), SettingsError> {
+		let bindings = bindings
+			.as_table()
+			.ok_or_else(|| SettingsError::BadValue("bindings".to_string(), "a table"))?;
+		for (name, inputs) in bindings {
+			let action =
+				parse_action(name).ok_or_else(|| SettingsError::UnknownAction(name.clone()))?;
+			let bad_inputs = || SettingsError::BadValue(name.clone(), "a list of input names");
+			let inputs = inputs
+				.as_array()
+				.ok_or_else(bad_inputs)?
+				.iter()
+				.map(|input| {
+					let input_name = input.as_str().ok_or_else(bad_inputs)?;
+					parse_input(input_name).ok_or_else(|| {
+						SettingsError::UnknownInput(input_name.to_string(), name.clone())
+					})
+				})
+				.collect::<Result<Vec<InputType>, SettingsError>>()?;
+
+			for actions in self.bindings.values_mut() {
+				actions.retain(|&bound| action_name(&bound) != *name);
+			}
+			for input in inputs {
+				self.bindings.entry(input).or_default().push(action);
+			}
+		}
+		self.bindings.retain(|_, actions| !actions.is_empty());
+		Ok(())
+	}
+
+	/// Saves the settings into the settings file at path, making it if it's not there. Only
+	/// settings the file already gives or that aren't their defaults are written. Settings the
+	/// file already has the same values for are left as they were written, with their
+	/// comments, and the file isn't written at all if nothing's changed.
+	pub fn save(&self, path: &str) -> Result<(), SettingsError> {
+		let contents = match std::fs::read_to_string(path) {
+			Ok(contents) => contents,
+			Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+			Err(err) => return Err(SettingsError::IOError(err)),
+		};
+		let mut doc = contents
+			.parse::<DocumentMut>()
+			.map_err(SettingsError::EditError)?;
+
+		// Settings are only written once they're not the defaults, or if the file already
+		// gives them, so the file only ever has the ones the user set
+		let defaults = Settings::default();
+		let root = doc.as_table_mut();
+		for ((key, value), (_, default)) in self.values().into_iter().zip(defaults.values()) {
+			if root.contains_key(key) || !same_value(&value, &default) {
+				edit_set(root, key, value);
+			}
+		}
+
+		for action in &all_actions() {
+			let name = action_name(action);
+			let inputs = self.bound_inputs(action);
+			let in_file = root
+				.get("bindings")
+				.and_then(|bindings| bindings.as_table())
+				.is_some_and(|bindings| bindings.contains_key(&name));
+			if !in_file && inputs == defaults.bound_inputs(action) {
+				continue;
+			}
+			let bindings = root
+				.entry("bindings")
+				.or_insert(toml_edit::table())
+				.as_table_mut()
+				.ok_or_else(|| SettingsError::BadValue("bindings".to_string(), "a table"))?;
+			let inputs: toml_edit::Array = inputs.iter().map(String::as_str).collect();
+			edit_set(bindings, &name, inputs.into());
+		}
+
+		let new_contents = doc.to_string();
+		if new_contents == contents {
+			return Ok(());
+		}
+		// Written beside it and moved over it, so a failed save never leaves half a file
+		let tmp_path = format!("{}.tmp", path);
+		std::fs::write(&tmp_path, new_contents).map_err(SettingsError::IOError)?;
+		std::fs::rename(&tmp_path, path).map_err(SettingsError::IOError)?;
+		info!("Saved settings to {}", path);
+		Ok(())
+	}
+
+	// Every setting that's saved, by its key in settings files, in the order they're saved in
+	fn values(&self) -> Vec<(&'static str, toml_edit::Value)> {
+		vec![
+			("max_fps", self.max_fps.into()),
+			("fov_deg", self.fov.0.into()),
+			("light_frac_ambient", self.light_frac_ambient.into()),
+			("move_speed", self.move_speed.into()),
+			("pan_speed", self.pan_speed.into()),
+			("zoom_speed", self.zoom_speed.into()),
+			("scroll_line_px", self.scroll_line_px.into()),
+			("scroll_zoom_px", self.scroll_zoom_px.into()),
+			("select_radius_px", self.select_radius_px.into()),
+			("city_altitude", self.city_altitude.into()),
+			("min_altitude", self.min_altitude.into()),
+			("max_altitude", self.max_altitude.into()),
+			("orbit_altitude", self.orbit_altitude.into()),
+			("max_orbit_tilt_deg", self.max_orbit_tilt.0.into()),
+			("map_projection", self.map_projection.name().into()),
+			("texture_gutter_px", (self.texture_gutter_px as i64).into()),
+			(
+				"texture_anisotropy",
+				(self.texture_anisotropy as i64).into(),
+			),
+			("tile_cache_layers", (self.tile_cache_layers as i64).into()),
+			(
+				"tile_loads_per_frame",
+				(self.tile_loads_per_frame as i64).into(),
+			),
+			("glyph_atlas_px", (self.glyph_atlas_px as i64).into()),
+			("show_graticule", self.show_graticule.into()),
+			("show_scale_bar", self.show_scale_bar.into()),
+		]
+	}
+
+	// Names of the inputs bound to action, sorted
+	fn bound_inputs(&self, action: &ActionType) -> Vec<String> {
+		let mut inputs: Vec<String> = self
+			.bindings
+			.iter()
+			.filter(|(_, actions)| {
+				actions
+					.iter()
+					.any(|bound| action_name(bound) == action_name(action))
+			})
+			.map(|(input, _)| input_name(input))
+			.collect();
+		inputs.sort();
+		inputs
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn scratch_path(name: &str) -> String {
+		let path = std::env::temp_dir().join(format!(
+			"hm_world_settings_{}_{}.toml",
+			name,
+			std::process::id()
+		));
+		let _ = std::fs::remove_file(&path);
+		path.to_str().unwrap().to_string()
+	}
+
+	#[test]
+	fn defaults_arent_saved() {
+		let path = scratch_path("defaults");
+		Settings::default().save(&path).unwrap();
+		assert!(std::fs::metadata(&path).is_err());
+	}
+
+	#[test]
+	fn only_settings_the_user_set_are_saved() {
+		let path = scratch_path("user_set");
+		std::fs::write(&path, "# Slower\nmove_speed = 1.0\ntexture_gutter_px = 0\n").unwrap();
+		let mut settings = Settings::load(&path).unwrap();
+		assert_eq!(settings.texture_gutter_px, 0);
+
+		settings.show_graticule = true;
+		settings.bindings.insert(
+			InputType::Key(VirtualKeyCode::Q),
+			vec![ActionType::Instant(InstantAction::Quit)],
+		);
+		settings.save(&path).unwrap();
+		let saved = std::fs::read_to_string(&path).unwrap();
+		assert!(saved.starts_with("# Slower\nmove_speed = 1.0\ntexture_gutter_px = 0\n"));
+		assert!(saved.contains("show_graticule = true\n"));
+		assert!(saved.contains("Quit = [\"Escape\", \"Q\"]\n"));
+		assert!(!saved.contains("max_fps"));
+		assert!(!saved.contains("MoveForward"));
+
+		let reloaded = Settings::load(&path).unwrap();
+		assert!(reloaded.show_graticule);
+		assert_eq!(reloaded.texture_gutter_px, 0);
+		let _ = std::fs::remove_file(&path);
+	}
+}
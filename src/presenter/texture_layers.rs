@@ -0,0 +1,117 @@
+use crate::entities::{Map, MapPieceKey};
+
+use image::{DynamicImage, GenericImage, GenericImageView, RgbaImage};
+
+use std::collections::HashMap;
+
+/// Which layer of the view's texture array holds each of the map's textures. Layer 0 is
+/// always the missing texture, which is used anywhere the map has no texture.
+///
+/// Each layer has a gutter of gutter_px around its texture, copied from the textures next to
+/// it, so that filtering and mipmapping near a texture's edge blend into its neighbours
+/// rather than leaving a seam.
+#[derive(Debug)]
+pub struct TextureLayers {
+	texture_size_deg: u32,
+	gutter_px: u32,
+	texture_dims: (u32, u32), // Of each texture, without its gutter
+	layers: HashMap<MapPieceKey, u32>,
+}
+impl TextureLayers {
+	/// Lays out map's textures, returning the layout along with the image for each layer,
+	/// gutter included
+	pub fn new(map: &Map, gutter_px: u32) -> (TextureLayers, Vec<RgbaImage>) {
+		// Sorted, so the layout is the same every run
+		let mut keys: Vec<&MapPieceKey> = map.textures.keys().collect();
+		keys.sort();
+
+		let mut texture_layers = TextureLayers {
+			texture_size_deg: map.texture_size_deg,
+			gutter_px,
+			texture_dims: map.missing_image.dimensions(),
+			layers: HashMap::new(),
+		};
+		let mut images = vec![texture_layers.with_gutter(map, None)];
+		for key in keys {
+			texture_layers.layers.insert(*key, images.len() as u32);
+			images.push(texture_layers.with_gutter(map, Some(key)));
+		}
+		info!(
+			"Laid out {} texture layers, with {}px gutters",
+			images.len(),
+			gutter_px
+		);
+		(texture_layers, images)
+	}
+
+	// The texture for key (or the missing texture), surrounded by a gutter copied from the
+	// textures around it. Where there's no texture, the gutter comes from the missing texture.
+	fn with_gutter(&self, map: &Map, key: Option<&MapPieceKey>) -> RgbaImage {
+		let (width, height) = self.texture_dims;
+		let gutter = self.gutter_px;
+		let texture_at = |lat_offset: i32, long_offset: i32| -> &DynamicImage {
+			let key = match key {
+				Some(key) => key,
+				None => return &map.missing_image,
+			};
+			let size = self.texture_size_deg as i32;
+			let neighbour = MapPieceKey {
+				min_lat: key.min_lat + lat_offset * size,
+				// Longitude wraps around the globe
+				min_long: (key.min_long + long_offset * size + 180).rem_euclid(360) - 180,
+			};
+			map.textures.get(&neighbour).unwrap_or(&map.missing_image)
+		};
+
+		let mut image = RgbaImage::new(width + 2 * gutter, height + 2 * gutter);
+		if !image.copy_from(&texture_at(0, 0).to_rgba(), gutter, gutter) {
+			warn!("Texture {:?} isn't {}x{}", key, width, height);
+		}
+		if gutter == 0 {
+			return image;
+		}
+
+		// Every gutter pixel is from whichever texture would be there, north being up
+		for y in 0..height + 2 * gutter {
+			for x in 0..width + 2 * gutter {
+				let inside_x = x >= gutter && x < width + gutter;
+				let inside_y = y >= gutter && y < height + gutter;
+				if inside_x && inside_y {
+					continue;
+				}
+
+				let (tex_x, tex_y) = (x as i64 - gutter as i64, y as i64 - gutter as i64);
+				let long_offset = tex_x.div_euclid(width as i64) as i32;
+				let lat_offset = -(tex_y.div_euclid(height as i64) as i32);
+				let neighbour = texture_at(lat_offset, long_offset);
+				let (neighbour_x, neighbour_y) = (
+					tex_x.rem_euclid(width as i64) as u32,
+					tex_y.rem_euclid(height as i64) as u32,
+				);
+				if neighbour.in_bounds(neighbour_x, neighbour_y) {
+					image.put_pixel(x, y, neighbour.get_pixel(neighbour_x, neighbour_y));
+				}
+			}
+		}
+		image
+	}
+
+	pub fn texture_size_deg(&self) -> u32 {
+		self.texture_size_deg
+	}
+
+	/// The layer holding the texture for key, or the missing texture's layer if there isn't one
+	pub fn get_layer(&self, key: &MapPieceKey) -> u32 {
+		self.layers.get(key).cloned().unwrap_or(0_u32)
+	}
+
+	/// Maps coords from 0 to 1 across a texture onto its layer, skipping over the gutter
+	pub fn inset_tex_coords(&self, u: f64, v: f64) -> (f64, f64) {
+		let (width, height) = (self.texture_dims.0 as f64, self.texture_dims.1 as f64);
+		let gutter = self.gutter_px as f64;
+		(
+			(gutter + u * width) / (width + 2_f64 * gutter),
+			(gutter + v * height) / (height + 2_f64 * gutter),
+		)
+	}
+}
@@ -0,0 +1,602 @@
+use super::{MapProjection, Mesh, MeshObject, TexTransform, View};
+use crate::entities::{LatLong, TileId, TilePyramid};
+use crate::world::World;
+
+use cgmath::prelude::*;
+use cgmath::{Deg, Matrix4, Point2, Point3, Vector2, Vector3, Vector4};
+use glium::backend::Facade;
+
+use image::{DynamicImage, FilterType};
+
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+// Quads along each side of a tile's mesh
+const PATCH_DIVS: u32 = 16;
+
+// Frames a tile's mesh is kept around for after it was last drawn
+const MESH_KEEP_FRAMES: u64 = 300;
+
+// Points along each side of a tile projected to find where it is on azimuthal maps, which
+// curve its edges
+const AZIMUTHAL_RECT_SAMPLES: u32 = 8;
+
+/// Where the camera is and what it can see, for choosing which tiles and labels to draw
+#[derive(Debug)]
+pub struct ViewParams {
+	pub cam_pos: Point3<f64>,
+	pub view_proj_mat: Matrix4<f64>,
+	pub world_center: Point3<f64>,
+	pub world_radius: f64,
+	pub px_per_rad: f64, // Screen pixels per radian, at the middle of the screen
+	pub viewport: (u32, u32), // Width and height of the screen, in pixels
+	pub map: Option<MapProjection>, // How the world's laid out flat, if it's not a globe
+}
+impl ViewParams {
+	/// How high the camera is over the surface, or the flat map, in world radii
+	pub fn altitude(&self) -> f64 {
+		match self.map {
+			Some(_) => (self.cam_pos.z - self.world_center.z) / self.world_radius,
+			None => {
+				((self.cam_pos - self.world_center).magnitude() - self.world_radius)
+					/ self.world_radius
+			}
+		}
+	}
+
+	/// Where the point on the surface straight out along dir, a unit vector, is drawn, and
+	/// which way it faces there: on the globe, or the flat map
+	pub fn surface_pos(&self, dir: Vector3<f64>) -> (Point3<f64>, Vector3<f64>) {
+		match self.map {
+			Some(ref projection) => (
+				projection.map_pos(dir, self.world_center, self.world_radius),
+				Vector3::unit_z(),
+			),
+			None => (self.world_center + dir * self.world_radius, dir),
+		}
+	}
+}
+#[cfg(test)]
+impl ViewParams {
+	/// Looking straight down at lat/long from altitude world radii over a world of radius 1,
+	/// on an 800x600 screen 60° high
+	pub fn looking_down_at(lat_deg: f64, long_deg: f64, altitude: f64) -> ViewParams {
+		let viewport = (800, 600);
+		let cam_pos = Point3::from_vec(lat_long_dir(lat_deg, long_deg) * (1_f64 + altitude));
+		let up = lat_long_dir(lat_deg + 90_f64, long_deg);
+		let aspect_ratio = viewport.0 as f64 / viewport.1 as f64;
+		let proj_mat = cgmath::perspective(Deg(60_f64), aspect_ratio, 0.00001_f64, 100000_f64);
+		ViewParams {
+			cam_pos,
+			view_proj_mat: proj_mat * Matrix4::look_at(cam_pos, Point3::origin(), up),
+			world_center: Point3::origin(),
+			world_radius: 1_f64,
+			px_per_rad: viewport.1 as f64 / 2_f64 / 30_f64.to_radians().tan(),
+			viewport,
+			map: None,
+		}
+	}
+}
+
+// Unit vector from the world's center through lat/long
+fn lat_long_dir(lat_deg: f64, long_deg: f64) -> Vector3<f64> {
+	LatLong::new(Deg(lat_deg), Deg(long_deg))
+		.as_sph_point(1_f64)
+		.to_vec()
+}
+
+// Unit vector through the middle of id, and the angle from there to its furthest point
+fn tile_cap(id: &TileId) -> (Vector3<f64>, f64) {
+	let bounds = id.bounds();
+	let centre = lat_long_dir(
+		(bounds.min_lat + bounds.max_lat) / 2_f64,
+		(bounds.min_long + bounds.max_long) / 2_f64,
+	);
+	// Along every edge of a lat/long rect, the distance from its middle only grows towards
+	// the corners, so the corners are the furthest points
+	let radius = [
+		(bounds.min_lat, bounds.min_long),
+		(bounds.min_lat, bounds.max_long),
+		(bounds.max_lat, bounds.min_long),
+		(bounds.max_lat, bounds.max_long),
+	]
+	.iter()
+	.map(|&(lat, long)| centre.angle(lat_long_dir(lat, long)).0)
+	.fold(0_f64, f64::max);
+	(centre, radius)
+}
+
+// Whether a sphere is completely outside one of the planes of the frustum view_proj_mat
+// projects into clip space
+fn outside_frustum(view_proj_mat: &Matrix4<f64>, centre: Point3<f64>, radius: f64) -> bool {
+	let row = |i: usize| {
+		Vector4::new(
+			view_proj_mat.x[i],
+			view_proj_mat.y[i],
+			view_proj_mat.z[i],
+			view_proj_mat.w[i],
+		)
+	};
+	let centre = centre.to_homogeneous();
+	(0..3).any(|i| {
+		[row(3) + row(i), row(3) - row(i)].iter().any(|plane| {
+			let normal_len = plane.truncate().magnitude();
+			normal_len > 0_f64 && plane.dot(centre) / normal_len < -radius
+		})
+	})
+}
+
+/// The tiles to draw for params, covering the whole visible part of the world. Each is
+/// split into its children until it's either no bigger on screen than a tile's pixels, or
+/// the pyramid goes no deeper. Tiles the camera can't see aren't included.
+pub fn select_tiles(pyramid: &TilePyramid, params: &ViewParams) -> Vec<TileId> {
+	let mut selected = Vec::new();
+	let mut to_visit: Vec<TileId> = TileId::roots().to_vec();
+
+	let cam_disp = params.cam_pos - params.world_center;
+	let (cam_dist, cam_dir) = (cam_disp.magnitude(), cam_disp.normalize());
+	let radius = params.world_radius;
+	// Angle from the point below the camera to the horizon
+	let horizon = if cam_dist > radius {
+		(radius / cam_dist).acos()
+	} else {
+		std::f64::consts::PI
+	};
+
+	while let Some(id) = to_visit.pop() {
+		let (cap_dir, cap_radius) = tile_cap(&id);
+		let cap_angle = cam_dir.angle(cap_dir).0;
+		if cap_angle - cap_radius > horizon {
+			continue;
+		}
+		// Caps under a hemisphere fit in a sphere around their base, otherwise in the world
+		let (bound_centre, bound_radius) = if cap_radius < std::f64::consts::FRAC_PI_2 {
+			(
+				params.world_center + cap_dir * radius * cap_radius.cos(),
+				radius * cap_radius.sin(),
+			)
+		} else {
+			(params.world_center, radius)
+		};
+		if outside_frustum(&params.view_proj_mat, bound_centre, bound_radius) {
+			continue;
+		}
+
+		// How big the tile is on screen, from the closest point of it to the camera
+		let closest_angle = (cap_angle - cap_radius).max(0_f64);
+		let closest_dist = (cam_dist * cam_dist + radius * radius
+			- 2_f64 * cam_dist * radius * closest_angle.cos())
+		.sqrt()
+		.max(f64::EPSILON);
+		let size = radius * TileId::size_deg(id.level).to_radians();
+		let screen_px = size / closest_dist * params.px_per_rad;
+
+		if id.level < pyramid.max_level && screen_px > pyramid.tile_px as f64 {
+			to_visit.extend_from_slice(&id.children());
+		} else {
+			selected.push(id);
+		}
+	}
+	selected
+}
+
+// Where id is on projection's map, as the min and max corners of the rect around it
+fn tile_map_rect(projection: &MapProjection, id: &TileId) -> (Point2<f64>, Point2<f64>) {
+	let bounds = id.bounds();
+	let samples = match *projection {
+		MapProjection::Azimuthal { center } => {
+			// Tiles around the point opposite the middle go all the way around the edge
+			let (cap_dir, cap_radius) = tile_cap(id);
+			if (-center).angle(cap_dir).0 <= cap_radius {
+				let extent = projection.extent();
+				return (Point2::from_vec(-extent), Point2::from_vec(extent));
+			}
+			AZIMUTHAL_RECT_SAMPLES
+		}
+		// Across and up only grow with longitude and latitude, so the corners are enough
+		_ => 1,
+	};
+
+	let mut min = Point2::new(f64::INFINITY, f64::INFINITY);
+	let mut max = Point2::new(f64::NEG_INFINITY, f64::NEG_INFINITY);
+	for i in 0..=samples {
+		let lat = bounds.min_lat + (bounds.max_lat - bounds.min_lat) * i as f64 / samples as f64;
+		for j in 0..=samples {
+			let long =
+				bounds.min_long + (bounds.max_long - bounds.min_long) * j as f64 / samples as f64;
+			let point = projection.project_lat_long(&LatLong::new(Deg(lat), Deg(long)));
+			min = Point2::new(min.x.min(point.x), min.y.min(point.y));
+			max = Point2::new(max.x.max(point.x), max.y.max(point.y));
+		}
+	}
+	(min, max)
+}
+
+/// The tiles to draw for params, looking down on the world laid out flat with projection,
+/// split like select_tiles does. Tiles whose part of the map is off screen aren't included.
+pub fn select_map_tiles(
+	pyramid: &TilePyramid,
+	params: &ViewParams,
+	projection: &MapProjection,
+) -> Vec<TileId> {
+	let mut selected = Vec::new();
+	let mut to_visit: Vec<TileId> = TileId::roots().to_vec();
+
+	// The rect of the map on screen, in map coords
+	let altitude = params.altitude().max(f64::EPSILON);
+	let cam_disp = (params.cam_pos - params.world_center) / params.world_radius;
+	let under_cam = Point2::new(cam_disp.x, cam_disp.y);
+	let half_screen = Vector2::new(params.viewport.0 as f64, params.viewport.1 as f64)
+		* (altitude / 2_f64 / params.px_per_rad);
+	let (screen_min, screen_max) = (under_cam - half_screen, under_cam + half_screen);
+
+	while let Some(id) = to_visit.pop() {
+		let (min, max) = tile_map_rect(projection, &id);
+		if max.x < screen_min.x
+			|| min.x > screen_max.x
+			|| max.y < screen_min.y
+			|| min.y > screen_max.y
+		{
+			continue;
+		}
+
+		// How big the tile is on screen, from how much of the map it covers
+		let size = max - min;
+		let screen_px = (size.x * size.y).sqrt() / altitude * params.px_per_rad;
+
+		if id.level < pyramid.max_level && screen_px > pyramid.tile_px as f64 {
+			to_visit.extend_from_slice(&id.children());
+		} else {
+			selected.push(id);
+		}
+	}
+	selected
+}
+
+/// Which texture array layer each loaded tile is in. Once every layer is taken, loading
+/// another tile replaces whichever was used longest ago, so long as it wasn't this frame.
+#[derive(Debug)]
+pub struct TileCache {
+	layers: HashMap<TileId, (u32, u64)>, // Layer, and the frame it was last used in
+	free_layers: Vec<u32>,
+}
+impl TileCache {
+	/// A cache of layer_count layers, starting at first_layer
+	pub fn new(first_layer: u32, layer_count: u32) -> TileCache {
+		TileCache {
+			layers: HashMap::new(),
+			free_layers: (first_layer..first_layer + layer_count).rev().collect(),
+		}
+	}
+
+	pub fn contains(&self, id: &TileId) -> bool {
+		self.layers.contains_key(id)
+	}
+
+	/// The layer id is in, marking it as used in frame
+	pub fn get(&mut self, id: &TileId, frame: u64) -> Option<u32> {
+		let (layer, last_used) = self.layers.get_mut(id)?;
+		*last_used = frame;
+		Some(*layer)
+	}
+
+	/// Finds a layer for id to be loaded into, as used in frame. None if every layer's been
+	/// used this frame.
+	pub fn insert(&mut self, id: TileId, frame: u64) -> Option<u32> {
+		if let Some(layer) = self.get(&id, frame) {
+			return Some(layer);
+		}
+		let layer = match self.free_layers.pop() {
+			Some(layer) => layer,
+			None => {
+				let (&oldest, &(layer, last_used)) = self
+					.layers
+					.iter()
+					.min_by_key(|&(_, &(_, last_used))| last_used)?;
+				if last_used >= frame {
+					return None;
+				}
+				self.layers.remove(&oldest);
+				layer
+			}
+		};
+		self.layers.insert(id, (layer, frame));
+		Some(layer)
+	}
+}
+
+/// Streams a map's tile pyramid into the view's texture array as the camera moves around,
+/// so only the tiles being looked at need loading, at the detail they're seen at.
+///
+/// Layer 0 of the texture array is the missing texture, for anywhere the pyramid doesn't
+/// cover. Until a tile is loaded, it's drawn with the part of its closest loaded ancestor
+/// that covers it.
+pub struct TileStreamer {
+	pyramid: TilePyramid,
+	world_radius: f64,
+	loads_per_frame: usize,
+	cache: TileCache,
+	unavailable: HashSet<TileId>, // Failed to load, so aren't tried again
+	meshes: HashMap<TileId, (Rc<Mesh>, u64)>, // Mesh, and the frame it was last drawn in
+	map: Option<MapProjection>,   // How the meshes are laid out flat, if they are
+	frame: u64,
+	settled: bool,
+}
+impl TileStreamer {
+	/// Sets up view's texture array with cache_layers layers for tiles, after the missing
+	/// texture. At most loads_per_frame tiles are loaded each frame, so the frame rate
+	/// holds up while zooming in.
+	pub fn new(
+		view: &mut dyn View,
+		pyramid: &TilePyramid,
+		missing_image: &DynamicImage,
+		world_radius: f64,
+		cache_layers: u32,
+		loads_per_frame: usize,
+		max_anisotropy: u16,
+	) -> Option<TileStreamer> {
+		let image_px = pyramid.image_px();
+		if !view.set_empty_texture_array(image_px, image_px, cache_layers + 1, max_anisotropy) {
+			return None;
+		}
+		let missing = missing_image.resize_exact(image_px, image_px, FilterType::Lanczos3);
+		view.set_texture_layer(0_u32, &missing.to_rgba());
+		info!(
+			"Streaming a {} level tile pyramid through {} texture layers",
+			pyramid.max_level + 1,
+			cache_layers
+		);
+
+		Some(TileStreamer {
+			pyramid: pyramid.clone(),
+			world_radius,
+			loads_per_frame,
+			cache: TileCache::new(1_u32, cache_layers),
+			unavailable: HashSet::new(),
+			meshes: HashMap::new(),
+			map: None,
+			frame: 0_u64,
+			settled: false,
+		})
+	}
+
+	/// Loads what's needed to draw the world as seen with params, on the globe or the flat
+	/// map, and returns the objects to draw it with
+	pub fn update(
+		&mut self,
+		view: &dyn View,
+		world: &dyn World,
+		params: &ViewParams,
+	) -> Vec<MeshObject> {
+		self.frame += 1;
+		if params.map != self.map {
+			self.meshes.clear();
+			self.map = params.map;
+		}
+		let tiles = match params.map {
+			Some(ref projection) => select_map_tiles(&self.pyramid, params, projection),
+			None => select_tiles(&self.pyramid, params),
+		};
+
+		// Least detailed first, so there's soon something close to right everywhere. The
+		// roots are always wanted, as the last resort for anything not loaded yet.
+		let mut wanted: Vec<TileId> = TileId::roots().to_vec();
+		for id in &tiles {
+			let mut ancestor = Some(*id);
+			while let Some(id) = ancestor {
+				wanted.push(id);
+				ancestor = id.parent();
+			}
+		}
+		wanted.sort();
+		wanted.dedup();
+		for id in &wanted {
+			self.cache.get(id, self.frame);
+		}
+
+		let mut loads = 0;
+		self.settled = true;
+		for id in wanted {
+			if !self.pyramid.contains(&id)
+				|| self.cache.contains(&id)
+				|| self.unavailable.contains(&id)
+			{
+				continue;
+			}
+			if loads >= self.loads_per_frame {
+				self.settled = false;
+				break;
+			}
+			loads += 1;
+			let tile = match world.load_pyramid_tile(&id) {
+				Some(tile) => tile,
+				None => {
+					self.unavailable.insert(id);
+					continue;
+				}
+			};
+			match self.cache.insert(id, self.frame) {
+				Some(layer) => view.set_texture_layer(layer, &tile.to_rgba()),
+				None => {
+					debug!("Tile cache is full, can't load {:?}", id);
+					break;
+				}
+			}
+		}
+
+		let objects = tiles
+			.iter()
+			.map(|id| {
+				let mut object = MeshObject::from_shared(self.mesh(view, params, id));
+				object.tex_transform = self.tex_transform(id);
+				object
+			})
+			.collect();
+
+		let frame = self.frame;
+		self.meshes
+			.retain(|_, &mut (_, last_drawn)| frame - last_drawn < MESH_KEEP_FRAMES);
+		objects
+	}
+
+	/// Whether the last update loaded everything it could for its view, so another with the
+	/// same view would draw just the same
+	pub fn is_settled(&self) -> bool {
+		self.settled
+	}
+
+	fn mesh(&mut self, view: &dyn View, params: &ViewParams, id: &TileId) -> Rc<Mesh> {
+		let (world_radius, frame) = (self.world_radius, self.frame);
+		let (mesh, last_drawn) = self.meshes.entry(*id).or_insert_with(|| {
+			let mesh = match params.map {
+				Some(ref projection) => {
+					let mesh = Mesh::gen_patch_mesh(
+						None::<&dyn Facade>,
+						&id.bounds(),
+						PATCH_DIVS,
+						world_radius,
+					);
+					projection.flatten(view.get_facade(), &mesh, params.world_center, world_radius)
+				}
+				None => {
+					Mesh::gen_patch_mesh(view.get_facade(), &id.bounds(), PATCH_DIVS, world_radius)
+				}
+			};
+			(Rc::new(mesh), frame)
+		});
+		*last_drawn = frame;
+		mesh.clone()
+	}
+
+	// Maps id's texture coords onto the closest tile to it that's loaded: itself, or the
+	// part of an ancestor covering it
+	fn tex_transform(&mut self, id: &TileId) -> TexTransform {
+		if !self.pyramid.contains(id) {
+			return TexTransform::default();
+		}
+		let mut source = Some(*id);
+		while let Some(source_id) = source {
+			if let Some(layer) = self.cache.get(&source_id, self.frame) {
+				let (bounds, source_bounds) = (id.bounds(), source_id.bounds());
+				let source_size = source_bounds.max_lat - source_bounds.min_lat;
+				let scale = (bounds.max_lat - bounds.min_lat) / source_size;
+				let (u, v) = (
+					(bounds.min_long - source_bounds.min_long) / source_size,
+					(bounds.min_lat - source_bounds.min_lat) / source_size,
+				);
+				let (min_u, min_v) = self.pyramid.inset_tex_coords(u, v);
+				let (max_u, max_v) = self.pyramid.inset_tex_coords(u + scale, v + scale);
+				return TexTransform {
+					offset: [min_u, min_v],
+					scale: [max_u - min_u, max_v - min_v],
+					layer,
+				};
+			}
+			source = source_id.parent();
+		}
+		TexTransform::default()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::entities::MapBounds;
+
+	fn pyramid() -> TilePyramid {
+		let world = MapBounds::new(-90_f64, 90_f64, -180_f64, 180_f64);
+		TilePyramid::new(256, 0, world, 1000_f64)
+	}
+
+	// The selected tile covering lat/long, if any
+	fn selected_at(tiles: &[TileId], lat: f64, long: f64) -> Option<TileId> {
+		tiles.iter().cloned().find(|id| {
+			let bounds = id.bounds();
+			bounds.min_lat <= lat
+				&& lat < bounds.max_lat
+				&& bounds.min_long <= long
+				&& long < bounds.max_long
+		})
+	}
+
+	#[test]
+	fn tiles_are_chosen_by_what_the_camera_sees() {
+		let pyramid = pyramid();
+		let far = select_tiles(
+			&pyramid,
+			&ViewParams::looking_down_at(20_f64, 10_f64, 2_f64),
+		);
+		let near = select_tiles(
+			&pyramid,
+			&ViewParams::looking_down_at(20_f64, 10_f64, 0.01_f64),
+		);
+
+		for tiles in &[&far, &near] {
+			// No tile is drawn over another
+			for (i, a) in tiles.iter().enumerate() {
+				for b in &tiles[i + 1..] {
+					assert!(!a.bounds().intersects(&b.bounds()), "{:?} {:?}", a, b);
+				}
+			}
+			// Below the camera is covered, the other side of the world isn't
+			assert!(selected_at(tiles, 20.5_f64, 10.5_f64).is_some());
+			assert_eq!(selected_at(tiles, -20.5_f64, -169.5_f64), None);
+		}
+
+		// The closer the camera, the more detailed the tiles under it, and the fewer there
+		// are in the distance
+		let far_under = selected_at(&far, 20.5_f64, 10.5_f64).unwrap();
+		let near_under = selected_at(&near, 20.5_f64, 10.5_f64).unwrap();
+		assert!(near_under.level > far_under.level + 3);
+		assert_eq!(selected_at(&near, 20.5_f64, 60.5_f64), None);
+		assert!(selected_at(&far, 20.5_f64, 60.5_f64).is_some());
+	}
+
+	#[test]
+	fn tiles_stop_at_the_deepest_level() {
+		let pyramid = TilePyramid::new(
+			256,
+			0,
+			MapBounds::new(-90_f64, 90_f64, -180_f64, 180_f64),
+			5_f64,
+		);
+		let tiles = select_tiles(
+			&pyramid,
+			&ViewParams::looking_down_at(0_f64, 0_f64, 0.001_f64),
+		);
+		assert!(tiles.iter().all(|id| id.level <= pyramid.max_level));
+		assert_eq!(
+			selected_at(&tiles, 0.5_f64, 0.5_f64).map(|id| id.level),
+			Some(pyramid.max_level)
+		);
+	}
+
+	#[test]
+	fn cache_replaces_the_tile_used_longest_ago() {
+		let (a, b, c, d, e) = (
+			TileId::roots()[0],
+			TileId::roots()[1],
+			TileId::roots()[0].children()[0],
+			TileId::roots()[0].children()[1],
+			TileId::roots()[0].children()[2],
+		);
+		let mut cache = TileCache::new(1, 2);
+		assert_eq!(cache.insert(a, 1), Some(1));
+		assert_eq!(cache.insert(b, 2), Some(2));
+		assert_eq!(cache.insert(a, 2), Some(1));
+
+		// a was used more recently than b, so b goes first
+		assert_eq!(cache.get(&a, 3), Some(1));
+		assert_eq!(cache.insert(c, 4), Some(2));
+		assert!(!cache.contains(&b));
+		assert_eq!(cache.insert(d, 4), Some(1));
+		assert!(!cache.contains(&a));
+
+		// Everything's been used this frame, so nothing can be replaced
+		assert_eq!(cache.insert(e, 4), None);
+		assert!(cache.contains(&c) && cache.contains(&d) && !cache.contains(&e));
+		assert_eq!(cache.get(&d, 5), Some(1));
+		assert_eq!(cache.insert(e, 5), Some(2));
+		assert!(!cache.contains(&c));
+	}
+}
@@ -13,11 +13,22 @@ out vec3 v_tex_coords;
 uniform dmat4 u_model_mat;
 uniform dmat4 u_view_mat;
 uniform dmat4 u_proj_mat;
+uniform dvec2 u_tex_offset;
+uniform dvec2 u_tex_scale;
+uniform double u_tex_layer;
+uniform bool u_overlay;
 
 void main() {
+	if (u_overlay) {
+		gl_Position = vec4(position);
+		v_color = vec4(color, 1);
+		v_tex_coords = vec3(tex_coords);
+		return;
+	}
+
 	gl_Position = vec4(u_proj_mat * u_view_mat * u_model_mat * position);
 	v_position = vec3(u_model_mat * position);
 	v_normal = vec3(normal);
 	v_color = vec4(color, 1);
-	v_tex_coords = vec3(tex_coords);
+	v_tex_coords = vec3(tex_coords.xy * u_tex_scale + u_tex_offset, tex_coords.z + u_tex_layer);
 }
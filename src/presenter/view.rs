@@ -1,15 +1,33 @@
 use cgmath::Matrix4;
-use glium::texture::texture2d_array::Texture2dArray;
+use glium::backend::Facade;
 
 use super::{AmbientLight, MeshObject, WorldLight};
 
 pub trait View {
-	fn set_shaders(&mut self, &str, &str);
+	fn set_shaders(&mut self, vert_shader: &str, frag_shader: &str);
+	/// Sets the layers of the texture array objects are drawn with, which is mipmapped and
+	/// sampled with up to max_anisotropy anisotropic filtering (1 for just trilinear). False
+	/// if it couldn't be made.
 	fn set_texture_array(
 		&mut self,
-		Vec<glium::texture::RawImage2d<'static, u8>>,
-	) -> Option<&Texture2dArray>;
-	fn set_title(&self, &str);
+		images: Vec<glium::texture::RawImage2d<'static, u8>>,
+		max_anisotropy: u16,
+	) -> bool;
+	/// Sets the texture array to layers blank layers of width x height, to be filled in
+	/// later by set_texture_layer. False if it couldn't be made.
+	fn set_empty_texture_array(
+		&mut self,
+		width: u32,
+		height: u32,
+		layers: u32,
+		max_anisotropy: u16,
+	) -> bool;
+	/// Replaces one layer of the texture array, mipmaps and all
+	fn set_texture_layer(&self, layer: u32, image: &image::RgbaImage);
+	/// Sets the texture overlay objects are drawn with, which isn't mipmapped. Its bottom row
+	/// is at v 0, as with the texture array. False if it couldn't be made.
+	fn set_overlay_texture(&mut self, image: &image::RgbaImage) -> bool;
+	fn set_title(&self, title: &str);
 	fn draw(
 		&self,
 		view_mat: Matrix4<f64>,
@@ -18,7 +36,14 @@ pub trait View {
 		world_light: &WorldLight,
 		objects: &[MeshObject],
 	);
+	/// The last frame drawn, top row first. None if it couldn't be read back.
+	fn read_image(&self) -> Option<image::RgbaImage>;
 	fn poll_events(&mut self) -> Vec<glium::glutin::WindowEvent>;
 	fn get_aspect_ratio(&self) -> f64;
-	fn get_facade(&self) -> &glium::Display;
+	/// Width and height of what's drawn to, in pixels
+	fn get_dimensions(&self) -> (u32, u32);
+	/// Pixels drawn to per logical pixel, which window events measure positions in
+	fn get_hidpi_factor(&self) -> f64;
+	/// The GL context meshes are uploaded to, None if the view doesn't draw with GL
+	fn get_facade(&self) -> Option<&dyn Facade>;
 }
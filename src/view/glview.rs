@@ -1,17 +1,23 @@
+use glium::backend::Facade;
 use glium::glutin::dpi::LogicalSize;
 use glium::glutin::{ContextBuilder, EventsLoop, WindowBuilder, WindowEvent};
-use glium::texture::texture2d_array::Texture2dArray;
-use glium::{Display, Program, Surface};
+use glium::texture::{RawImage2d, Texture2d};
+use glium::uniforms::MagnifySamplerFilter;
+use glium::{Display, Surface};
 
-use cgmath::{Matrix4, Point3, Vector4};
+use cgmath::Matrix4;
 
+use image::RgbaImage;
+
+use super::rasterizer::Rasterizer;
+use super::renderer::{image_from_gl, Renderer};
 use crate::presenter::{AmbientLight, MeshObject, View, WorldLight};
 
 pub struct GLView {
 	display: Display,
 	events_loop: EventsLoop,
-	program: Option<Program>,
-	texture_array: Option<Texture2dArray>,
+	renderer: Renderer,
+	rasterizer: Option<Rasterizer>, // Draws on the CPU instead, if GL can't run the shaders
 }
 impl GLView {
 	pub fn new() -> Result<GLView, String> {
@@ -27,30 +33,86 @@ impl GLView {
 		};
 
 		Ok(GLView {
-			display: display,
+			display,
 			events_loop: el,
-			program: None,
-			texture_array: None,
+			renderer: Renderer::new(),
+			rasterizer: None,
 		})
 	}
+
+	// Shows image, which is as big as the window
+	fn show_image(&self, image: RgbaImage) {
+		let dimensions = image.dimensions();
+		let raw = RawImage2d::from_raw_rgba_reversed(&image.into_raw(), dimensions);
+		let texture = match Texture2d::new(&self.display, raw) {
+			Ok(texture) => texture,
+			Err(err) => {
+				error!("glview::show_image: {:?}", err);
+				return;
+			}
+		};
+		let target = self.display.draw();
+		texture
+			.as_surface()
+			.fill(&target, MagnifySamplerFilter::Nearest);
+		target.finish().unwrap();
+	}
 }
 impl View for GLView {
 	fn set_shaders(&mut self, vert_shader: &str, frag_shader: &str) {
-		match Program::from_source(&self.display, vert_shader, frag_shader, None) {
-			Ok(program) => self.program = Some(program),
-			Err(err) => {
-				self.program = None;
-				error!("glview::init_program: {}", err);
-			}
+		if !self
+			.renderer
+			.set_shaders(&self.display, vert_shader, frag_shader)
+		{
+			warn!("GL can't run the shaders, so drawing on the CPU instead");
+			self.rasterizer = Some(Rasterizer::new());
 		}
 	}
 
 	fn set_texture_array(
 		&mut self,
-		images: Vec<glium::texture::RawImage2d<'static, u8>>,
-	) -> Option<&Texture2dArray> {
-		self.texture_array = Texture2dArray::new(&self.display, images).ok();
-		self.texture_array.as_ref()
+		images: Vec<RawImage2d<'static, u8>>,
+		max_anisotropy: u16,
+	) -> bool {
+		match self.rasterizer {
+			Some(ref mut rasterizer) => rasterizer.set_texture_array(images),
+			None => self
+				.renderer
+				.set_texture_array(&self.display, images, max_anisotropy),
+		}
+	}
+
+	fn set_empty_texture_array(
+		&mut self,
+		width: u32,
+		height: u32,
+		layers: u32,
+		max_anisotropy: u16,
+	) -> bool {
+		match self.rasterizer {
+			Some(ref mut rasterizer) => rasterizer.set_empty_texture_array(width, height, layers),
+			None => self.renderer.set_empty_texture_array(
+				&self.display,
+				width,
+				height,
+				layers,
+				max_anisotropy,
+			),
+		}
+	}
+
+	fn set_texture_layer(&self, layer: u32, image: &RgbaImage) {
+		match self.rasterizer {
+			Some(ref rasterizer) => rasterizer.set_texture_layer(layer, image),
+			None => self.renderer.set_texture_layer(&self.display, layer, image),
+		}
+	}
+
+	fn set_overlay_texture(&mut self, image: &RgbaImage) -> bool {
+		match self.rasterizer {
+			Some(ref mut rasterizer) => rasterizer.set_overlay_texture(image),
+			None => self.renderer.set_overlay_texture(&self.display, image),
+		}
 	}
 
 	fn set_title(&self, title: &str) {
@@ -65,39 +127,38 @@ impl View for GLView {
 		world_light: &WorldLight,
 		objects: &[MeshObject],
 	) {
-		let textures = self.texture_array.as_ref().unwrap();
-
-		let mut target = self.display.draw();
-		let draw_params = glium::DrawParameters {
-			backface_culling: glium::draw_parameters::BackfaceCullingMode::CullCounterClockwise,
-			..Default::default()
-		};
-		target.clear_color(0.0, 0.0, 1.0, 1.0);
-
-		for object in objects {
-			let uniforms = glium::uniform! {
-				u_view_mat: <Matrix4<f64> as Into<[[f64; 4]; 4]>>::into(view_mat),
-				u_proj_mat: <Matrix4<f64> as Into<[[f64; 4]; 4]>>::into(proj_mat),
-				u_model_mat: <Matrix4<f64> as Into<[[f64; 4]; 4]>>::into(object.model_mat()),
-				u_light_pos: <Point3<f64> as Into<[f64; 3]>>::into(world_light.pos),
-				u_light_color: <Vector4<f64> as Into<[f64; 4]>>::into(world_light.color),
-				u_light_ambient: <Vector4<f64> as Into<[f64; 4]>>::into(ambient_light.color),
-				u_tex: textures,
-			};
-
-			target
-				.draw(
-					&object.mesh.vertex_buffer,
-					&object.mesh.index_buffer,
-					self.program.as_ref().unwrap(),
-					&uniforms,
-					&draw_params,
-				)
-				.unwrap();
+		if let Some(ref rasterizer) = self.rasterizer {
+			let image = rasterizer.draw(
+				self.get_dimensions(),
+				view_mat,
+				proj_mat,
+				ambient_light,
+				world_light,
+				objects,
+			);
+			self.show_image(image);
+			return;
 		}
 
+		let mut target = self.display.draw();
+		self.renderer.draw(
+			&mut target,
+			view_mat,
+			proj_mat,
+			ambient_light,
+			world_light,
+			objects,
+		);
 		target.finish().unwrap();
 	}
+	fn read_image(&self) -> Option<RgbaImage> {
+		let raw = self
+			.display
+			.read_front_buffer()
+			.map_err(|err| error!("glview::read_image: {:?}", err))
+			.ok()?;
+		image_from_gl(raw)
+	}
 	fn poll_events(&mut self) -> Vec<WindowEvent> {
 		let mut events = Vec::new();
 		self.events_loop.poll_events(|event| {
@@ -115,7 +176,17 @@ impl View for GLView {
 		let (win_width, win_height) = self.display.get_framebuffer_dimensions();
 		(win_width as f64) / (win_height as f64)
 	}
-	fn get_facade(&self) -> &glium::Display {
-		&self.display
+	fn get_dimensions(&self) -> (u32, u32) {
+		self.display.get_framebuffer_dimensions()
+	}
+	fn get_hidpi_factor(&self) -> f64 {
+		self.display.gl_window().window().get_hidpi_factor()
+	}
+	// Meshes aren't uploaded when drawing on the CPU
+	fn get_facade(&self) -> Option<&dyn Facade> {
+		match self.rasterizer {
+			Some(_) => None,
+			None => Some(&self.display),
+		}
 	}
 }
@@ -0,0 +1,136 @@
+use glium::backend::{Context, Facade};
+use glium::framebuffer::SimpleFrameBuffer;
+use glium::glutin::WindowEvent;
+use glium::texture::{MipmapsOption, RawImage2d, Texture2d, UncompressedFloatFormat};
+
+use cgmath::Matrix4;
+
+use image::RgbaImage;
+
+use std::rc::Rc;
+
+use super::renderer::{image_from_gl, Renderer};
+use super::surfaceless::surfaceless_context;
+use crate::presenter::{AmbientLight, MeshObject, View, WorldLight};
+
+/// A view with no window, which draws into an image instead, for rendering snapshots of the
+/// world. It works without a GPU or display server, so long as EGL's surfaceless platform
+/// is there, as it is with Mesa.
+pub struct HeadlessView {
+	context: Rc<Context>,
+	target: Texture2d,
+	renderer: Renderer,
+}
+impl HeadlessView {
+	/// A view that draws images width x height
+	pub fn new(width: u32, height: u32) -> Result<HeadlessView, String> {
+		let context = match surfaceless_context((width, height)) {
+			Ok(context) => context,
+			Err(err) => {
+				error!("headless_view::init_context: {}", err);
+				return Err(err);
+			}
+		};
+		info!(
+			"Drawing offscreen with {} {}",
+			context.get_opengl_vendor_string(),
+			context.get_opengl_renderer_string()
+		);
+		let target = Texture2d::empty_with_format(
+			&context,
+			UncompressedFloatFormat::U8U8U8U8,
+			MipmapsOption::NoMipmap,
+			width,
+			height,
+		)
+		.map_err(|err| format!("{:?}", err))?;
+
+		Ok(HeadlessView {
+			context,
+			target,
+			renderer: Renderer::new(),
+		})
+	}
+}
+impl View for HeadlessView {
+	fn set_shaders(&mut self, vert_shader: &str, frag_shader: &str) {
+		self.renderer
+			.set_shaders(&self.context, vert_shader, frag_shader);
+	}
+
+	fn set_texture_array(
+		&mut self,
+		images: Vec<RawImage2d<'static, u8>>,
+		max_anisotropy: u16,
+	) -> bool {
+		self.renderer
+			.set_texture_array(&self.context, images, max_anisotropy)
+	}
+
+	fn set_empty_texture_array(
+		&mut self,
+		width: u32,
+		height: u32,
+		layers: u32,
+		max_anisotropy: u16,
+	) -> bool {
+		self.renderer
+			.set_empty_texture_array(&self.context, width, height, layers, max_anisotropy)
+	}
+
+	fn set_texture_layer(&self, layer: u32, image: &RgbaImage) {
+		self.renderer.set_texture_layer(&self.context, layer, image);
+	}
+
+	fn set_overlay_texture(&mut self, image: &RgbaImage) -> bool {
+		self.renderer.set_overlay_texture(&self.context, image)
+	}
+
+	// No window to title
+	fn set_title(&self, _: &str) {}
+	fn draw(
+		&self,
+		view_mat: Matrix4<f64>,
+		proj_mat: Matrix4<f64>,
+		ambient_light: &AmbientLight,
+		world_light: &WorldLight,
+		objects: &[MeshObject],
+	) {
+		let mut target = match SimpleFrameBuffer::new(&self.context, &self.target) {
+			Ok(target) => target,
+			Err(err) => {
+				error!("headless_view::draw: {:?}", err);
+				return;
+			}
+		};
+		self.renderer.draw(
+			&mut target,
+			view_mat,
+			proj_mat,
+			ambient_light,
+			world_light,
+			objects,
+		);
+	}
+	fn read_image(&self) -> Option<RgbaImage> {
+		image_from_gl(self.target.read())
+	}
+	// Nothing to get events from
+	fn poll_events(&mut self) -> Vec<WindowEvent> {
+		Vec::new()
+	}
+	fn get_aspect_ratio(&self) -> f64 {
+		let (width, height) = self.get_dimensions();
+		(width as f64) / (height as f64)
+	}
+	fn get_dimensions(&self) -> (u32, u32) {
+		self.target.dimensions()
+	}
+	// There's no window for events to come from
+	fn get_hidpi_factor(&self) -> f64 {
+		1_f64
+	}
+	fn get_facade(&self) -> Option<&dyn Facade> {
+		Some(&self.context)
+	}
+}
@@ -1,2 +1,12 @@
 mod glview;
 pub use self::glview::GLView;
+
+mod headless_view;
+pub use self::headless_view::HeadlessView;
+
+mod software_view;
+pub use self::software_view::SoftwareView;
+
+mod rasterizer;
+mod renderer;
+mod surfaceless;
@@ -0,0 +1,513 @@
+use glium::texture::RawImage2d;
+
+use cgmath::prelude::*;
+use cgmath::{Matrix4, Vector2, Vector3, Vector4};
+
+use image::{imageops, FilterType, Rgba, RgbaImage};
+
+use std::cell::RefCell;
+
+use crate::presenter::{AmbientLight, MeshObject, Shading, Vertex, WorldLight};
+
+// What the GL views clear to before drawing
+const CLEAR_COLOR: Rgba<u8> = Rgba([0, 0, 255, 255]);
+
+// A corner of a triangle after the vertex shader, with what's interpolated across it
+#[derive(Clone, Copy, Debug)]
+struct ShadedVertex {
+	clip_pos: Vector4<f64>,
+	world_pos: Vector3<f64>,
+	normal: Vector3<f64>,
+	color: Vector3<f64>,
+	tex_coords: Vector3<f64>,
+}
+impl ShadedVertex {
+	// What vertex.glsl outputs for vertex of object
+	fn new(object: &MeshObject, view_proj_mat: &Matrix4<f64>, vertex: &Vertex) -> ShadedVertex {
+		if let Shading::Overlay { .. } = object.shading {
+			return ShadedVertex {
+				clip_pos: Vector4::from(vertex.position),
+				world_pos: Vector3::zero(),
+				normal: Vector3::from(vertex.normal),
+				color: Vector3::from(vertex.color),
+				tex_coords: Vector3::from(vertex.tex_coords),
+			};
+		}
+		let model_pos = object.model_mat() * Vector4::from(vertex.position);
+		let transform = &object.tex_transform;
+		let [u, v, layer] = vertex.tex_coords;
+		ShadedVertex {
+			clip_pos: view_proj_mat * model_pos,
+			world_pos: model_pos.truncate(),
+			normal: Vector3::from(vertex.normal),
+			color: Vector3::from(vertex.color),
+			tex_coords: Vector3::new(
+				u * transform.scale[0] + transform.offset[0],
+				v * transform.scale[1] + transform.offset[1],
+				layer + transform.layer as f64,
+			),
+		}
+	}
+
+	fn lerp(&self, other: &ShadedVertex, amount: f64) -> ShadedVertex {
+		ShadedVertex {
+			clip_pos: self.clip_pos.lerp(other.clip_pos, amount),
+			world_pos: self.world_pos.lerp(other.world_pos, amount),
+			normal: self.normal.lerp(other.normal, amount),
+			color: self.color.lerp(other.color, amount),
+			tex_coords: self.tex_coords.lerp(other.tex_coords, amount),
+		}
+	}
+
+	// How far inside the near plane the vertex is, negative if it's in front of it
+	fn near_dist(&self) -> f64 {
+		self.clip_pos.z + self.clip_pos.w
+	}
+}
+
+// Cuts away the part of a triangle in front of the near plane, which includes everything
+// behind the camera. What's left is a convex polygon.
+fn clip_near(triangle: [ShadedVertex; 3]) -> Vec<ShadedVertex> {
+	let mut polygon = Vec::with_capacity(4);
+	for i in 0..3 {
+		let (from, to) = (&triangle[i], &triangle[(i + 1) % 3]);
+		let (from_dist, to_dist) = (from.near_dist(), to.near_dist());
+		if from_dist >= 0_f64 {
+			polygon.push(*from);
+		}
+		if (from_dist >= 0_f64) != (to_dist >= 0_f64) {
+			polygon.push(from.lerp(to, from_dist / (from_dist - to_dist)));
+		}
+	}
+	polygon
+}
+
+// Twice the signed area of the triangle a, b, c. With y going down, as on screen, it's
+// positive if they're clockwise, which is front facing.
+fn edge(a: Vector2<f64>, b: Vector2<f64>, c: Vector2<f64>) -> f64 {
+	(b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x)
+}
+
+// Averages each 2x2 block of image, as GL does when generating mipmaps
+fn halve(image: &RgbaImage) -> RgbaImage {
+	let (width, height) = image.dimensions();
+	RgbaImage::from_fn((width / 2).max(1), (height / 2).max(1), |x, y| {
+		let mut sum = [0_u32; 4];
+		for &(dx, dy) in &[(0, 0), (1, 0), (0, 1), (1, 1)] {
+			let pixel = image.get_pixel((x * 2 + dx).min(width - 1), (y * 2 + dy).min(height - 1));
+			for channel in 0..4 {
+				sum[channel] += pixel[channel] as u32;
+			}
+		}
+		Rgba([
+			((sum[0] + 2) / 4) as u8,
+			((sum[1] + 2) / 4) as u8,
+			((sum[2] + 2) / 4) as u8,
+			((sum[3] + 2) / 4) as u8,
+		])
+	})
+}
+
+// A layer's mipmaps, most detailed first, each half the size of the one before, down to
+// 1x1. They're made the same way the GL views make them: by GL for whole texture arrays,
+// and shrunk with filter for single layers.
+fn mipmaps(image: RgbaImage, filter: Option<FilterType>) -> Vec<RgbaImage> {
+	let mut mipmaps = vec![image];
+	loop {
+		let last = &mipmaps[mipmaps.len() - 1];
+		let (width, height) = last.dimensions();
+		if width == 1 && height == 1 {
+			return mipmaps;
+		}
+		let smaller = match filter {
+			Some(filter) => imageops::resize(last, (width / 2).max(1), (height / 2).max(1), filter),
+			None => halve(last),
+		};
+		mipmaps.push(smaller);
+	}
+}
+
+// Bilinearly filtered colour at u, v, clamped to the edges
+fn sample_bilinear(image: &RgbaImage, u: f64, v: f64) -> Vector4<f64> {
+	let (width, height) = image.dimensions();
+	let (x, y) = (u * width as f64 - 0.5_f64, v * height as f64 - 0.5_f64);
+	let (x0, y0) = (x.floor(), y.floor());
+	let (fx, fy) = (x - x0, y - y0);
+	let texel = |x: f64, y: f64| {
+		let x = x.clamp(0_f64, width as f64 - 1_f64) as u32;
+		let y = y.clamp(0_f64, height as f64 - 1_f64) as u32;
+		let pixel = image.get_pixel(x, y);
+		Vector4::new(
+			pixel[0] as f64,
+			pixel[1] as f64,
+			pixel[2] as f64,
+			pixel[3] as f64,
+		) / 255_f64
+	};
+	let top = texel(x0, y0).lerp(texel(x0 + 1_f64, y0), fx);
+	let bottom = texel(x0, y0 + 1_f64).lerp(texel(x0 + 1_f64, y0 + 1_f64), fx);
+	top.lerp(bottom, fy)
+}
+
+/// Draws objects on the CPU the way the shaders do, for machines whose GL can't run them,
+/// and as a reference to check GL's drawing against.
+///
+/// Like the GL views, triangles are drawn in order, with their back faces culled and no
+/// depth test, and flat shaded and overlay objects are blended over what's behind them.
+/// Textures are filtered trilinearly, but never anisotropically.
+pub struct Rasterizer {
+	// Each layer's mipmaps. Rows go from the bottom up, as in GL, so v indexes them directly.
+	layers: RefCell<Vec<Vec<RgbaImage>>>,
+	overlay: Option<RgbaImage>, // Rows from the bottom up too
+}
+impl Rasterizer {
+	pub fn new() -> Rasterizer {
+		Rasterizer {
+			layers: RefCell::new(Vec::new()),
+			overlay: None,
+		}
+	}
+
+	pub fn set_texture_array(&mut self, images: Vec<RawImage2d<'static, u8>>) -> bool {
+		let mut layers = Vec::with_capacity(images.len());
+		for raw in images {
+			match RgbaImage::from_raw(raw.width, raw.height, raw.data.into_owned()) {
+				Some(image) => layers.push(mipmaps(image, None)),
+				None => {
+					error!("rasterizer::set_texture_array: layer isn't RGBA");
+					return false;
+				}
+			}
+		}
+		self.layers = RefCell::new(layers);
+		true
+	}
+
+	pub fn set_empty_texture_array(&mut self, width: u32, height: u32, layers: u32) -> bool {
+		let blank = mipmaps(RgbaImage::new(width, height), None);
+		self.layers = RefCell::new(vec![blank; layers as usize]);
+		true
+	}
+
+	pub fn set_texture_layer(&self, layer: u32, image: &RgbaImage) {
+		let mut layers = self.layers.borrow_mut();
+		match layers.get_mut(layer as usize) {
+			Some(mipmaps_of_layer) => {
+				*mipmaps_of_layer =
+					mipmaps(imageops::flip_vertical(image), Some(FilterType::Triangle))
+			}
+			None => error!("rasterizer::set_texture_layer: no layer {}", layer),
+		}
+	}
+
+	pub fn set_overlay_texture(&mut self, image: &RgbaImage) -> bool {
+		self.overlay = Some(imageops::flip_vertical(image));
+		true
+	}
+
+	/// Draws objects into a width x height image, top row first
+	pub fn draw(
+		&self,
+		(width, height): (u32, u32),
+		view_mat: Matrix4<f64>,
+		proj_mat: Matrix4<f64>,
+		ambient_light: &AmbientLight,
+		world_light: &WorldLight,
+		objects: &[MeshObject],
+	) -> RgbaImage {
+		let mut target = RgbaImage::from_pixel(width, height, CLEAR_COLOR);
+		let view_proj_mat = proj_mat * view_mat;
+		for object in objects {
+			let mesh = &object.mesh;
+			let shaded: Vec<ShadedVertex> = mesh
+				.vertices
+				.iter()
+				.map(|vertex| ShadedVertex::new(object, &view_proj_mat, vertex))
+				.collect();
+			for triangle in mesh.triangles_list.chunks(3) {
+				let corners = [
+					shaded[triangle[0] as usize],
+					shaded[triangle[1] as usize],
+					shaded[triangle[2] as usize],
+				];
+				let polygon = clip_near(corners);
+				for i in 1..polygon.len().saturating_sub(1) {
+					self.draw_triangle(
+						&mut target,
+						[&polygon[0], &polygon[i], &polygon[i + 1]],
+						object.shading,
+						ambient_light,
+						world_light,
+					);
+				}
+			}
+		}
+		target
+	}
+
+	// Fills the pixels whose centers are inside triangle, if it's facing the camera
+	fn draw_triangle(
+		&self,
+		target: &mut RgbaImage,
+		triangle: [&ShadedVertex; 3],
+		shading: Shading,
+		ambient_light: &AmbientLight,
+		world_light: &WorldLight,
+	) {
+		let (width, height) = (target.width() as f64, target.height() as f64);
+		// Screen coords have y going down from the top row, as in the image
+		let screen: Vec<Vector2<f64>> = triangle
+			.iter()
+			.map(|vertex| {
+				let ndc = vertex.clip_pos.truncate() / vertex.clip_pos.w;
+				Vector2::new(
+					(ndc.x + 1_f64) / 2_f64 * width,
+					(1_f64 - ndc.y) / 2_f64 * height,
+				)
+			})
+			.collect();
+		let area = edge(screen[0], screen[1], screen[2]);
+		if area.is_nan() || area <= 0_f64 {
+			return;
+		}
+		let inv_w: Vec<f64> = triangle
+			.iter()
+			.map(|vertex| 1_f64 / vertex.clip_pos.w)
+			.collect();
+
+		// Perspective correct weights of each corner at a point on screen, which may be
+		// outside the triangle
+		let weights = |point: Vector2<f64>| {
+			let linear = [
+				edge(screen[1], screen[2], point) / area,
+				edge(screen[2], screen[0], point) / area,
+				edge(screen[0], screen[1], point) / area,
+			];
+			let sum: f64 = (0..3).map(|i| linear[i] * inv_w[i]).sum();
+			(
+				[
+					linear[0] * inv_w[0] / sum,
+					linear[1] * inv_w[1] / sum,
+					linear[2] * inv_w[2] / sum,
+				],
+				linear,
+			)
+		};
+		let tex_coords_at = |weights: [f64; 3]| {
+			(0..3)
+				.map(|i| triangle[i].tex_coords * weights[i])
+				.fold(Vector3::zero(), |sum, tex_coords| sum + tex_coords)
+		};
+
+		let min_x = screen.iter().map(|point| point.x).fold(width, f64::min);
+		let max_x = screen.iter().map(|point| point.x).fold(0_f64, f64::max);
+		let min_y = screen.iter().map(|point| point.y).fold(height, f64::min);
+		let max_y = screen.iter().map(|point| point.y).fold(0_f64, f64::max);
+		let (first_x, last_x) = (min_x.max(0_f64) as u32, max_x.min(width - 1_f64) as u32);
+		let (first_y, last_y) = (min_y.max(0_f64) as u32, max_y.min(height - 1_f64) as u32);
+
+		let layers = self.layers.borrow();
+		for y in first_y..=last_y {
+			for x in first_x..=last_x {
+				let center = Vector2::new(x as f64 + 0.5_f64, y as f64 + 0.5_f64);
+				let (weights_here, linear) = weights(center);
+				if linear.iter().any(|&weight| weight < 0_f64) {
+					continue;
+				}
+				let color = match shading {
+					Shading::Textured => {
+						let world_pos = (0..3)
+							.map(|i| triangle[i].world_pos * weights_here[i])
+							.fold(Vector3::zero(), |sum, world_pos| sum + world_pos);
+						let normal = (0..3)
+							.map(|i| triangle[i].normal * weights_here[i])
+							.fold(Vector3::zero(), |sum, normal| sum + normal);
+						let tex_coords = tex_coords_at(weights_here);
+						// How fast the tex coords change across the pixel, for picking mipmaps
+						let tex_dx =
+							tex_coords_at(weights(center + Vector2::unit_x()).0) - tex_coords;
+						let tex_dy =
+							tex_coords_at(weights(center + Vector2::unit_y()).0) - tex_coords;
+
+						let color = match self.sample(&layers, tex_coords, tex_dx, tex_dy) {
+							Some(color) => color,
+							None => continue,
+						};
+						// As in fragment.glsl
+						let to_light = (world_light.pos.to_vec() - world_pos).normalize();
+						let diffuse = color.mul_element_wise(world_light.color)
+							* normal.dot(to_light).max(0_f64);
+						let ambient = color.mul_element_wise(ambient_light.color);
+						diffuse + ambient
+					}
+					Shading::Flat { .. } | Shading::Overlay { .. } => {
+						let color = (0..3)
+							.map(|i| triangle[i].color * weights_here[i])
+							.fold(Vector3::zero(), |sum, color| sum + color);
+						let opacity = match (shading, &self.overlay) {
+							(Shading::Overlay { opacity }, Some(overlay)) => {
+								let tex_coords = tex_coords_at(weights_here);
+								sample_bilinear(overlay, tex_coords.x, tex_coords.y).w * opacity
+							}
+							(Shading::Flat { opacity }, _) => opacity,
+							_ => continue,
+						};
+						// Blended the way GL's alpha blending does, alpha included
+						let behind = target.get_pixel(x, y);
+						let behind = Vector4::new(
+							behind[0] as f64,
+							behind[1] as f64,
+							behind[2] as f64,
+							behind[3] as f64,
+						) / 255_f64;
+						color.extend(opacity) * opacity + behind * (1_f64 - opacity)
+					}
+				};
+				let channel = |value: f64| (value.clamp(0_f64, 1_f64) * 255_f64).round() as u8;
+				target.put_pixel(
+					x,
+					y,
+					Rgba([
+						channel(color.x),
+						channel(color.y),
+						channel(color.z),
+						channel(color.w),
+					]),
+				);
+			}
+		}
+	}
+
+	// The texture array's colour at tex_coords, sampled trilinearly from the mipmaps that
+	// best fit how much tex_dx and tex_dy say a pixel covers. None without a texture array.
+	fn sample(
+		&self,
+		layers: &[Vec<RgbaImage>],
+		tex_coords: Vector3<f64>,
+		tex_dx: Vector3<f64>,
+		tex_dy: Vector3<f64>,
+	) -> Option<Vector4<f64>> {
+		if layers.is_empty() {
+			return None;
+		}
+		let layer = tex_coords
+			.z
+			.round()
+			.clamp(0_f64, layers.len() as f64 - 1_f64) as usize;
+		let mipmaps = &layers[layer];
+		let (width, height) = mipmaps[0].dimensions();
+		let texels = |delta: Vector3<f64>| {
+			Vector2::new(delta.x * width as f64, delta.y * height as f64).magnitude()
+		};
+		let lod = texels(tex_dx).max(texels(tex_dy)).log2();
+
+		let (u, v) = (tex_coords.x, tex_coords.y);
+		if lod.is_nan() || lod <= 0_f64 {
+			return Some(sample_bilinear(&mipmaps[0], u, v));
+		}
+		let max_level = (mipmaps.len() - 1) as f64;
+		let lod = lod.min(max_level);
+		let level = lod.floor();
+		let finer = sample_bilinear(&mipmaps[level as usize], u, v);
+		let coarser = sample_bilinear(&mipmaps[(level + 1_f64).min(max_level) as usize], u, v);
+		Some(finer.lerp(coarser, lod - level))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::presenter::Mesh;
+	use cgmath::{Point3, Vector4};
+	use glium::backend::Facade;
+
+	fn vertex(x: f64, y: f64) -> Vertex {
+		Vertex {
+			position: [x, y, 0_f64, 1_f64],
+			color: [1_f64, 0_f64, 0_f64],
+			tex_coords: [(x + 1_f64) / 2_f64, (y + 1_f64) / 2_f64, 0_f64],
+			..Vertex::default()
+		}
+	}
+
+	// Only the ambient light, and all of it, so textured pixels are their texture's colour
+	fn draw(rasterizer: &Rasterizer, size: u32, object: MeshObject) -> RgbaImage {
+		rasterizer.draw(
+			(size, size),
+			Matrix4::identity(),
+			Matrix4::identity(),
+			&AmbientLight {
+				color: Vector4::new(1_f64, 1_f64, 1_f64, 1_f64),
+			},
+			&WorldLight {
+				pos: Point3::new(0_f64, 0_f64, 1_f64),
+				color: Vector4::zero(),
+			},
+			&[object],
+		)
+	}
+
+	// Half of the screen, below the diagonal from its top left to its bottom right. It faces
+	// the camera if triangle goes round it clockwise, as the GL views cull the others.
+	fn lower_left(triangle: &[u32]) -> MeshObject {
+		let vertices = [
+			vertex(-1_f64, -1_f64),
+			vertex(1_f64, -1_f64),
+			vertex(-1_f64, 1_f64),
+		];
+		let mut object = MeshObject::new(Mesh::new(None::<&dyn Facade>, &vertices, triangle));
+		object.shading = Shading::Flat { opacity: 1_f64 };
+		object
+	}
+
+	#[test]
+	fn triangles_cover_the_pixels_whose_centres_they_contain() {
+		let image = draw(&Rasterizer::new(), 4, lower_left(&[0, 2, 1]));
+		for (x, y, pixel) in image.enumerate_pixels() {
+			let expected = if x <= y {
+				Rgba([255, 0, 0, 255])
+			} else {
+				CLEAR_COLOR
+			};
+			assert_eq!(*pixel, expected, "pixel ({}, {})", x, y);
+		}
+	}
+
+	#[test]
+	fn triangles_facing_away_are_culled() {
+		let image = draw(&Rasterizer::new(), 4, lower_left(&[0, 1, 2]));
+		assert!(image.pixels().all(|pixel| *pixel == CLEAR_COLOR));
+	}
+
+	#[test]
+	fn textures_are_drawn_the_right_way_up() {
+		let vertices = [
+			vertex(-1_f64, -1_f64),
+			vertex(1_f64, -1_f64),
+			vertex(1_f64, 1_f64),
+			vertex(-1_f64, 1_f64),
+		];
+		let quad = MeshObject::new(Mesh::new(
+			None::<&dyn Facade>,
+			&vertices,
+			&[0, 3, 2, 0, 2, 1],
+		));
+		let (top_left, top_right) = (Rgba([255, 0, 0, 255]), Rgba([0, 255, 0, 255]));
+		let (bottom_left, bottom_right) = (Rgba([0, 0, 255, 255]), Rgba([255, 255, 255, 255]));
+		let texture = RgbaImage::from_fn(2, 2, |x, y| match (x, y) {
+			(0, 0) => top_left,
+			(1, 0) => top_right,
+			(0, _) => bottom_left,
+			_ => bottom_right,
+		});
+
+		// Layers are set from images top row first
+		let mut rasterizer = Rasterizer::new();
+		rasterizer.set_empty_texture_array(2, 2, 1);
+		rasterizer.set_texture_layer(0, &texture);
+		let image = draw(&rasterizer, 2, quad);
+		for (x, y, pixel) in image.enumerate_pixels() {
+			assert_eq!(pixel, texture.get_pixel(x, y), "pixel ({}, {})", x, y);
+		}
+	}
+}
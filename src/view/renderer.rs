@@ -0,0 +1,216 @@
+use glium::backend::Facade;
+use glium::texture::pixel_buffer::PixelBuffer;
+use glium::texture::texture2d_array::Texture2dArray;
+use glium::texture::{MipmapsOption, RawImage2d, Texture2d};
+use glium::uniforms::{MagnifySamplerFilter, MinifySamplerFilter, SamplerWrapFunction};
+use glium::{Program, Surface};
+
+use cgmath::{Matrix4, Point3, Vector4};
+
+use image::{imageops, FilterType, RgbaImage};
+
+use crate::presenter::{AmbientLight, MeshObject, Shading, WorldLight};
+
+/// The shaders and textures the world is drawn with, and the drawing itself, for any view
+/// with a GL context. Views pass in their context, and what to draw to.
+pub struct Renderer {
+	program: Option<Program>,
+	texture_array: Option<Texture2dArray>,
+	overlay_texture: Option<Texture2d>,
+	max_anisotropy: u16,
+}
+impl Renderer {
+	pub fn new() -> Renderer {
+		Renderer {
+			program: None,
+			texture_array: None,
+			overlay_texture: None,
+			max_anisotropy: 1_u16,
+		}
+	}
+
+	/// False if the shaders couldn't be built, like when GL's too old for them
+	pub fn set_shaders<F: ?Sized + Facade>(
+		&mut self,
+		facade: &F,
+		vert_shader: &str,
+		frag_shader: &str,
+	) -> bool {
+		match Program::from_source(facade, vert_shader, frag_shader, None) {
+			Ok(program) => self.program = Some(program),
+			Err(err) => {
+				self.program = None;
+				error!("renderer::init_program: {}", err);
+			}
+		}
+		self.program.is_some()
+	}
+
+	pub fn set_texture_array<F: ?Sized + Facade>(
+		&mut self,
+		facade: &F,
+		images: Vec<RawImage2d<'static, u8>>,
+		max_anisotropy: u16,
+	) -> bool {
+		self.texture_array =
+			Texture2dArray::with_mipmaps(facade, images, MipmapsOption::AutoGeneratedMipmaps)
+				.map_err(|err| error!("renderer::set_texture_array: {:?}", err))
+				.ok();
+		self.max_anisotropy = max_anisotropy;
+		self.texture_array.is_some()
+	}
+
+	pub fn set_empty_texture_array<F: ?Sized + Facade>(
+		&mut self,
+		facade: &F,
+		width: u32,
+		height: u32,
+		layers: u32,
+		max_anisotropy: u16,
+	) -> bool {
+		self.texture_array = Texture2dArray::empty_with_mipmaps(
+			facade,
+			MipmapsOption::EmptyMipmaps,
+			width,
+			height,
+			layers,
+		)
+		.map_err(|err| error!("renderer::set_empty_texture_array: {:?}", err))
+		.ok();
+		self.max_anisotropy = max_anisotropy;
+		self.texture_array.is_some()
+	}
+
+	pub fn set_texture_layer<F: ?Sized + Facade>(&self, facade: &F, layer: u32, image: &RgbaImage) {
+		let texture_array = match self.texture_array {
+			Some(ref texture_array) => texture_array,
+			None => return,
+		};
+		// GL's rows go from the bottom up. Each mipmap is shrunk from the one before it.
+		let mut mipmap_image = imageops::flip_vertical(image);
+		for level in 0..texture_array.get_mipmap_levels() {
+			let mipmap = match texture_array.mipmap(level) {
+				Some(mipmap) => mipmap,
+				None => break,
+			};
+			let (width, height) = (mipmap.width(), mipmap.height());
+			if mipmap_image.dimensions() != (width, height) {
+				mipmap_image = imageops::resize(&mipmap_image, width, height, FilterType::Triangle);
+			}
+
+			let pixels: Vec<(u8, u8, u8, u8)> = mipmap_image
+				.pixels()
+				.map(|pixel| (pixel[0], pixel[1], pixel[2], pixel[3]))
+				.collect();
+			let buffer = PixelBuffer::new_empty(facade, pixels.len());
+			buffer.write(&pixels);
+			mipmap.raw_upload_from_pixel_buffer(
+				buffer.as_slice(),
+				0..width,
+				0..height,
+				layer..layer + 1,
+			);
+		}
+	}
+
+	pub fn set_overlay_texture<F: ?Sized + Facade>(
+		&mut self,
+		facade: &F,
+		image: &RgbaImage,
+	) -> bool {
+		// GL's rows go from the bottom up
+		let raw = RawImage2d::from_raw_rgba_reversed(&image.clone().into_raw(), image.dimensions());
+		self.overlay_texture = Texture2d::with_mipmaps(facade, raw, MipmapsOption::NoMipmap)
+			.map_err(|err| error!("renderer::set_overlay_texture: {:?}", err))
+			.ok();
+		self.overlay_texture.is_some()
+	}
+
+	/// Clears target and draws objects to it
+	pub fn draw<S: Surface>(
+		&self,
+		target: &mut S,
+		view_mat: Matrix4<f64>,
+		proj_mat: Matrix4<f64>,
+		ambient_light: &AmbientLight,
+		world_light: &WorldLight,
+		objects: &[MeshObject],
+	) {
+		// Textures are clamped, since each has a gutter rather than wrapping around
+		let textures = self
+			.texture_array
+			.as_ref()
+			.unwrap()
+			.sampled()
+			.wrap_function(SamplerWrapFunction::Clamp)
+			.minify_filter(MinifySamplerFilter::LinearMipmapLinear)
+			.magnify_filter(MagnifySamplerFilter::Linear)
+			.anisotropy(self.max_anisotropy);
+		let overlay_texture = self
+			.overlay_texture
+			.as_ref()
+			.unwrap()
+			.sampled()
+			.wrap_function(SamplerWrapFunction::Clamp)
+			.minify_filter(MinifySamplerFilter::Linear)
+			.magnify_filter(MagnifySamplerFilter::Linear);
+
+		let draw_params = glium::DrawParameters {
+			backface_culling: glium::draw_parameters::BackfaceCullingMode::CullCounterClockwise,
+			..Default::default()
+		};
+		let blended_draw_params = glium::DrawParameters {
+			blend: glium::Blend::alpha_blending(),
+			..draw_params.clone()
+		};
+		target.clear_color(0.0, 0.0, 1.0, 1.0);
+
+		for object in objects {
+			let buffers = match object.mesh.buffers {
+				Some(ref buffers) => buffers,
+				None => continue,
+			};
+			let (flat, overlay, opacity) = match object.shading {
+				Shading::Textured => (false, false, 1_f64),
+				Shading::Flat { opacity } => (true, false, opacity),
+				Shading::Overlay { opacity } => (false, true, opacity),
+			};
+			let uniforms = glium::uniform! {
+				u_view_mat: <Matrix4<f64> as Into<[[f64; 4]; 4]>>::into(view_mat),
+				u_proj_mat: <Matrix4<f64> as Into<[[f64; 4]; 4]>>::into(proj_mat),
+				u_model_mat: <Matrix4<f64> as Into<[[f64; 4]; 4]>>::into(object.model_mat()),
+				u_light_pos: <Point3<f64> as Into<[f64; 3]>>::into(world_light.pos),
+				u_light_color: <Vector4<f64> as Into<[f64; 4]>>::into(world_light.color),
+				u_light_ambient: <Vector4<f64> as Into<[f64; 4]>>::into(ambient_light.color),
+				u_tex_offset: object.tex_transform.offset,
+				u_tex_scale: object.tex_transform.scale,
+				u_tex_layer: object.tex_transform.layer as f64,
+				u_tex: textures,
+				u_overlay_tex: overlay_texture,
+				u_flat: flat,
+				u_overlay: overlay,
+				u_opacity: opacity,
+			};
+
+			target
+				.draw(
+					&buffers.vertex_buffer,
+					&buffers.index_buffer,
+					self.program.as_ref().unwrap(),
+					&uniforms,
+					if flat || overlay {
+						&blended_draw_params
+					} else {
+						&draw_params
+					},
+				)
+				.unwrap();
+		}
+	}
+}
+
+/// Turns pixels read back from GL, which go from the bottom row up, into an image
+pub fn image_from_gl(raw: RawImage2d<u8>) -> Option<RgbaImage> {
+	let image = RgbaImage::from_raw(raw.width, raw.height, raw.data.into_owned())?;
+	Some(imageops::flip_vertical(&image))
+}
@@ -0,0 +1,93 @@
+use glium::backend::Facade;
+use glium::glutin::WindowEvent;
+use glium::texture::RawImage2d;
+
+use cgmath::Matrix4;
+
+use image::RgbaImage;
+
+use std::cell::RefCell;
+
+use super::rasterizer::Rasterizer;
+use crate::presenter::{AmbientLight, MeshObject, View, WorldLight};
+
+/// A view with no window that draws on the CPU, into an image, so needs no GL at all. It's
+/// much slower than a GL view, but draws the same thing.
+pub struct SoftwareView {
+	dimensions: (u32, u32),
+	rasterizer: Rasterizer,
+	frame: RefCell<Option<RgbaImage>>, // The last one drawn
+}
+impl SoftwareView {
+	/// A view that draws images width x height
+	pub fn new(width: u32, height: u32) -> SoftwareView {
+		info!("Drawing on the CPU");
+		SoftwareView {
+			dimensions: (width, height),
+			rasterizer: Rasterizer::new(),
+			frame: RefCell::new(None),
+		}
+	}
+}
+impl View for SoftwareView {
+	// The rasterizer does what the shaders do itself
+	fn set_shaders(&mut self, _: &str, _: &str) {}
+
+	fn set_texture_array(&mut self, images: Vec<RawImage2d<'static, u8>>, _: u16) -> bool {
+		self.rasterizer.set_texture_array(images)
+	}
+
+	fn set_empty_texture_array(&mut self, width: u32, height: u32, layers: u32, _: u16) -> bool {
+		self.rasterizer
+			.set_empty_texture_array(width, height, layers)
+	}
+
+	fn set_texture_layer(&self, layer: u32, image: &RgbaImage) {
+		self.rasterizer.set_texture_layer(layer, image);
+	}
+
+	fn set_overlay_texture(&mut self, image: &RgbaImage) -> bool {
+		self.rasterizer.set_overlay_texture(image)
+	}
+
+	// No window to title
+	fn set_title(&self, _: &str) {}
+	fn draw(
+		&self,
+		view_mat: Matrix4<f64>,
+		proj_mat: Matrix4<f64>,
+		ambient_light: &AmbientLight,
+		world_light: &WorldLight,
+		objects: &[MeshObject],
+	) {
+		let image = self.rasterizer.draw(
+			self.dimensions,
+			view_mat,
+			proj_mat,
+			ambient_light,
+			world_light,
+			objects,
+		);
+		*self.frame.borrow_mut() = Some(image);
+	}
+	fn read_image(&self) -> Option<RgbaImage> {
+		self.frame.borrow().clone()
+	}
+	// Nothing to get events from
+	fn poll_events(&mut self) -> Vec<WindowEvent> {
+		Vec::new()
+	}
+	fn get_aspect_ratio(&self) -> f64 {
+		(self.dimensions.0 as f64) / (self.dimensions.1 as f64)
+	}
+	fn get_dimensions(&self) -> (u32, u32) {
+		self.dimensions
+	}
+	// There's no window for events to come from
+	fn get_hidpi_factor(&self) -> f64 {
+		1_f64
+	}
+	fn get_facade(&self) -> Option<&dyn Facade> {
+		None
+	}
+}
@@ -0,0 +1,181 @@
+use glium::backend::{Backend, Context};
+use glium::debug::DebugCallbackBehavior;
+use glium::SwapBuffersError;
+
+use glutin_egl_sys::egl;
+use glutin_egl_sys::egl::types::{EGLContext, EGLDisplay, EGLenum, EGLint};
+
+use libloading::Library;
+
+use std::ffi::CString;
+use std::os::raw::c_void;
+use std::rc::Rc;
+
+// From EGL_MESA_platform_surfaceless, which the bindings don't cover
+const PLATFORM_SURFACELESS_MESA: EGLenum = 0x31DD;
+
+// The shaders need GL 4.4
+const GL_VERSION: (EGLint, EGLint) = (4, 4);
+
+/// A GL context that isn't tied to any window, from EGL's surfaceless platform. It can only
+/// draw into framebuffers made in it, but needs no display server or GPU: without a GPU,
+/// Mesa renders with llvmpipe on the CPU.
+struct SurfacelessBackend {
+	egl: egl::Egl,
+	display: EGLDisplay,
+	context: EGLContext,
+	dimensions: (u32, u32),
+	_library: Library, // Holds the EGL functions in place
+}
+impl SurfacelessBackend {
+	fn new(dimensions: (u32, u32)) -> Result<SurfacelessBackend, String> {
+		let library = Library::new("libEGL.so.1")
+			.or_else(|_| Library::new("libEGL.so"))
+			.map_err(|err| format!("couldn't load libEGL: {}", err))?;
+		let egl = unsafe {
+			let get_proc_address = library
+				.get::<unsafe extern "C" fn(*const i8) -> *const c_void>(b"eglGetProcAddress\0")
+				.map_err(|err| format!("couldn't load eglGetProcAddress: {}", err))?;
+			// Extension functions mightn't be exported by the library itself
+			egl::Egl::load_with(|name| {
+				let symbol = CString::new(name).unwrap();
+				match library.get::<*const c_void>(symbol.as_bytes_with_nul()) {
+					Ok(function) => *function,
+					Err(_) => get_proc_address(symbol.as_ptr()),
+				}
+			})
+		};
+
+		unsafe {
+			let display = if egl.GetPlatformDisplayEXT.is_loaded() {
+				egl.GetPlatformDisplayEXT(
+					PLATFORM_SURFACELESS_MESA,
+					egl::DEFAULT_DISPLAY as *mut c_void,
+					std::ptr::null(),
+				)
+			} else {
+				egl::NO_DISPLAY
+			};
+			if display == egl::NO_DISPLAY {
+				return Err("no surfaceless EGL display".to_string());
+			}
+			let (mut major, mut minor) = (0, 0);
+			if egl.Initialize(display, &mut major, &mut minor) == egl::FALSE {
+				return Err(format!("couldn't initialize EGL: {:#x}", egl.GetError()));
+			}
+			info!("Initialized surfaceless EGL {}.{}", major, minor);
+
+			let context = match SurfacelessBackend::create_context(&egl, display) {
+				Ok(context) => context,
+				Err(err) => {
+					egl.Terminate(display);
+					return Err(err);
+				}
+			};
+			Ok(SurfacelessBackend {
+				egl,
+				display,
+				context,
+				dimensions,
+				_library: library,
+			})
+		}
+	}
+
+	unsafe fn create_context(egl: &egl::Egl, display: EGLDisplay) -> Result<EGLContext, String> {
+		if egl.BindAPI(egl::OPENGL_API) == egl::FALSE {
+			return Err(format!("couldn't bind OpenGL: {:#x}", egl.GetError()));
+		}
+		let config_attribs = [
+			egl::SURFACE_TYPE as EGLint,
+			egl::PBUFFER_BIT as EGLint,
+			egl::RENDERABLE_TYPE as EGLint,
+			egl::OPENGL_BIT as EGLint,
+			egl::NONE as EGLint,
+		];
+		let mut config = std::ptr::null();
+		let mut config_count = 0;
+		if egl.ChooseConfig(
+			display,
+			config_attribs.as_ptr(),
+			&mut config,
+			1,
+			&mut config_count,
+		) == egl::FALSE
+			|| config_count == 0
+		{
+			return Err("no EGL config for OpenGL".to_string());
+		}
+
+		let context_attribs = [
+			egl::CONTEXT_MAJOR_VERSION as EGLint,
+			GL_VERSION.0,
+			egl::CONTEXT_MINOR_VERSION as EGLint,
+			GL_VERSION.1,
+			egl::CONTEXT_OPENGL_PROFILE_MASK as EGLint,
+			egl::CONTEXT_OPENGL_CORE_PROFILE_BIT as EGLint,
+			egl::NONE as EGLint,
+		];
+		let context = egl.CreateContext(display, config, egl::NO_CONTEXT, context_attribs.as_ptr());
+		if context == egl::NO_CONTEXT {
+			return Err(format!(
+				"couldn't create an OpenGL {}.{} context: {:#x}",
+				GL_VERSION.0,
+				GL_VERSION.1,
+				egl.GetError()
+			));
+		}
+
+		Ok(context)
+	}
+}
+unsafe impl Backend for SurfacelessBackend {
+	// There's no surface, so nothing to swap
+	fn swap_buffers(&self) -> Result<(), SwapBuffersError> {
+		Ok(())
+	}
+
+	unsafe fn get_proc_address(&self, symbol: &str) -> *const c_void {
+		let symbol = CString::new(symbol).unwrap();
+		self.egl.GetProcAddress(symbol.as_ptr()) as *const c_void
+	}
+
+	fn get_framebuffer_dimensions(&self) -> (u32, u32) {
+		self.dimensions
+	}
+
+	fn is_current(&self) -> bool {
+		unsafe { self.egl.GetCurrentContext() == self.context }
+	}
+
+	unsafe fn make_current(&self) {
+		if self
+			.egl
+			.MakeCurrent(self.display, egl::NO_SURFACE, egl::NO_SURFACE, self.context)
+			== egl::FALSE
+		{
+			error!("surfaceless::make_current: {:#x}", self.egl.GetError());
+		}
+	}
+}
+impl Drop for SurfacelessBackend {
+	fn drop(&mut self) {
+		unsafe {
+			self.egl.MakeCurrent(
+				self.display,
+				egl::NO_SURFACE,
+				egl::NO_SURFACE,
+				egl::NO_CONTEXT,
+			);
+			self.egl.DestroyContext(self.display, self.context);
+			self.egl.Terminate(self.display);
+		}
+	}
+}
+
+/// A glium context for drawing offscreen, with framebuffers of dimensions by default
+pub fn surfaceless_context(dimensions: (u32, u32)) -> Result<Rc<Context>, String> {
+	let backend = SurfacelessBackend::new(dimensions)?;
+	unsafe { Context::new(backend, true, DebugCallbackBehavior::default()) }
+		.map_err(|err| format!("{}", err))
+}
@@ -1,31 +1,50 @@
 use super::{Database, DatabaseError, World, WorldState};
+use crate::entities::TileId;
+
+use image::DynamicImage;
 
 extern crate log;
 
 // =============================================================================
 
 pub struct ConcreteWorld {
-	database: Box<Database>,
+	database: Box<dyn Database>,
 	state: WorldState,
 }
 
 impl ConcreteWorld {
-	pub fn new(database: Box<Database>) -> Result<ConcreteWorld, DatabaseError> {
+	pub fn new(database: Box<dyn Database>) -> Result<ConcreteWorld, DatabaseError> {
 		let state = database.load()?;
 		info!("World loaded");
 
-		Ok(ConcreteWorld {
-			database: database,
-			state: state,
-		})
+		Ok(ConcreteWorld { database, state })
 	}
 }
 impl World for ConcreteWorld {
 	fn get_state(&self) -> &WorldState {
 		&self.state
 	}
+	fn get_state_mut(&mut self) -> &mut WorldState {
+		&mut self.state
+	}
+
+	fn save(&self) -> Result<(), DatabaseError> {
+		self.database.save(&self.state)?;
+		info!("World saved");
+		Ok(())
+	}
 
-	fn save(&self) {
-		self.database.save();
+	fn load_pyramid_tile(&self, id: &TileId) -> Option<DynamicImage> {
+		let pyramid = self.state.map.pyramid.as_ref()?;
+		if !pyramid.contains(id) {
+			return None;
+		}
+		match self.database.load_pyramid_tile(pyramid, id) {
+			Ok(tile) => Some(tile),
+			Err(err) => {
+				warn!("Couldn't load pyramid tile {:?}: {}", id, err);
+				None
+			}
+		}
 	}
 }
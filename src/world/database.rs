@@ -1,30 +1,45 @@
 use super::WorldState;
+use crate::entities::{CityParseError, ObjParseError, TileId, TilePyramid};
 
 use std::fmt;
 
 #[derive(Debug)]
 pub enum DatabaseError {
-	ConfigParseError(Box<std::error::Error>),
+	CitiesParseError(CityParseError),
+	ConfigParseError(Box<dyn std::error::Error>),
 	ConfigMissingValue,
 	ConfigValueWrongType,
+	FontParseError(String, rusttype::Error), // File name, and what's wrong with it
 	ImageError(image::ImageError),
 	IOError(std::io::Error),
+	ModelParseError(String, ObjParseError), // File name, and what's wrong with it
 }
 impl fmt::Display for DatabaseError {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		write!(f,
-		       "DatabaseError({})",
-		       match self {
-			       DatabaseError::ConfigParseError(e) => format!("{}", e),
-			       DatabaseError::ConfigMissingValue => "ConfigMissingValue".to_string(),
-			       DatabaseError::ConfigValueWrongType => "ConfigValueWrongType".to_string(),
-			       DatabaseError::ImageError(e) => format!("{}", e),
-			       DatabaseError::IOError(e) => format!("{}", e),
-		       })
+		write!(
+			f,
+			"DatabaseError({})",
+			match self {
+				DatabaseError::CitiesParseError(e) => format!("{}", e),
+				DatabaseError::ConfigParseError(e) => format!("{}", e),
+				DatabaseError::ConfigMissingValue => "ConfigMissingValue".to_string(),
+				DatabaseError::ConfigValueWrongType => "ConfigValueWrongType".to_string(),
+				DatabaseError::FontParseError(file, e) => format!("{}: {}", file, e),
+				DatabaseError::ImageError(e) => format!("{}", e),
+				DatabaseError::IOError(e) => format!("{}", e),
+				DatabaseError::ModelParseError(file, e) => format!("{}: {}", file, e),
+			}
+		)
 	}
 }
 
 pub trait Database {
 	fn load(&self) -> Result<WorldState, DatabaseError>;
-	fn save(&self);
+	fn save(&self, state: &WorldState) -> Result<(), DatabaseError>;
+	/// Loads the image for one of pyramid's tiles, which is only read when it's needed
+	fn load_pyramid_tile(
+		&self,
+		pyramid: &TilePyramid,
+		id: &TileId,
+	) -> Result<image::DynamicImage, DatabaseError>;
 }
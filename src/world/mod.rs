@@ -1,3 +1,4 @@
+#[allow(clippy::module_inception)]
 mod world;
 pub use self::world::{World, WorldState};
 
@@ -1,12 +1,29 @@
-use crate::entities::Map;
+use super::DatabaseError;
+use crate::entities::{Bookmark, CameraView, City, Map, Model, TileId};
+
+use image::DynamicImage;
+use rusttype::Font;
+
+use std::collections::HashMap;
 
 #[derive(Clone, Debug)]
 pub struct WorldState {
 	pub name: String,
+	pub radius: Option<f64>, // In distance_unit, if the world's size is given
+	pub distance_unit: String,
 	pub map: Box<Map>,
+	pub cities: Vec<City>,
+	pub models: HashMap<String, Model>, // By the name the config gives them
+	pub fonts: HashMap<String, Font<'static>>, // By the name the config gives them
+	pub bookmarks: Vec<Bookmark>,
+	pub camera: Option<CameraView>, // Where it was left looking from, saved apart from the world
 }
 
 pub trait World {
 	fn get_state(&self) -> &WorldState;
-	fn save(&self);
+	fn get_state_mut(&mut self) -> &mut WorldState;
+	fn save(&self) -> Result<(), DatabaseError>;
+	/// The image for a tile of the map's pyramid, None if the map has no such tile or it
+	/// couldn't be loaded
+	fn load_pyramid_tile(&self, id: &TileId) -> Option<DynamicImage>;
 }